@@ -0,0 +1,263 @@
+//! Runs the lock, channel and `Arc` benchmarks for a range of thread
+//! counts, and prints the results as CSV or JSON, to collect them from
+//! different machines and compare them with a script rather than by eye.
+//!
+//! ```text
+//! cargo bench --bench report -- [--json] [--threads 1,2,4] [--millis 200] [filter...]
+//! ```
+//!
+//! Without `--threads`, it doubles the count from 1 up to the number of
+//! CPUs. Only the benchmarks whose names contain one of the filters run,
+//! or all of them without any. Every row holds the architecture, OS,
+//! CPU count and `platform::wait` backend, so rows from different machines
+//! can go in one file. Progress goes to stderr, so stdout is only the
+//! report.
+//!
+//! Each benchmark has every thread repeat one operation, like taking a
+//! lock and incrementing what it guards, for `--millis`. Throughput counts
+//! the operations of all threads. Every 32nd operation is timed on its
+//! own, for the latency percentiles, which includes reading the clock.
+//! The `std_` benchmarks are the same with std's types, as a baseline.
+
+mod output;
+
+use std::env;
+use std::hint::black_box;
+use std::process;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Barrier;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use atomics_and_locks::channels::mpsc;
+use atomics_and_locks::channels::Channel;
+use atomics_and_locks::locks::{Mutex, RawFutexRwLock, RwLock};
+use atomics_and_locks::platform::wait;
+use atomics_and_locks::{Arc, SpinLock};
+
+use crate::output::{Format, Machine, Row};
+
+/// One in this many operations is timed for the latency percentiles.
+const SAMPLE_EVERY: u64 = 32;
+
+/// How many messages the bounded channels hold.
+const BOUND: usize = 1024;
+
+struct Args {
+    format: Format,
+    threads: Vec<usize>,
+    duration: Duration,
+    filters: Vec<String>,
+}
+
+fn usage(problem: &str) -> ! {
+    eprintln!("{problem}");
+    eprintln!("usage: report [--json] [--threads 1,2,4] [--millis 200] [filter...]");
+    process::exit(2);
+}
+
+fn parse_args(cpus: usize) -> Args {
+    let mut args = Args {
+        format: Format::Csv,
+        threads: (0..)
+            .map(|i| 1 << i)
+            .take_while(|&n| n < cpus)
+            .chain([cpus])
+            .collect(),
+        duration: Duration::from_millis(200),
+        filters: Vec::new(),
+    };
+    let mut it = env::args().skip(1);
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            // `cargo bench` passes this on to every benchmark.
+            "--bench" => {}
+            "--json" => args.format = Format::Json,
+            "--threads" => {
+                let list = it.next().unwrap_or_else(|| usage("--threads needs a list"));
+                args.threads = list
+                    .split(',')
+                    .map(|n| match n.parse() {
+                        Ok(n) if n > 0 => n,
+                        _ => usage(&format!("not a thread count: {n}")),
+                    })
+                    .collect();
+            }
+            "--millis" => {
+                let millis = it.next().and_then(|m| m.parse().ok());
+                let millis = millis.unwrap_or_else(|| usage("--millis needs a number"));
+                args.duration = Duration::from_millis(millis);
+            }
+            flag if flag.starts_with("--") => usage(&format!("unknown flag: {flag}")),
+            _ => args.filters.push(arg),
+        }
+    }
+    args
+}
+
+/// What the threads of one run did together.
+struct Sample {
+    ops: u64,
+    elapsed: Duration,
+    /// Sorted.
+    latencies: Vec<Duration>,
+}
+
+impl Sample {
+    fn percentile(&self, p: usize) -> Duration {
+        self.latencies
+            .get(self.latencies.len() * p / 100)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// Runs an operation on `threads` threads for `duration`. Each thread
+/// calls `setup` once to get its operation, so it can hold something of
+/// its own, like a sender.
+fn measure<F, O>(threads: usize, duration: Duration, setup: F) -> Sample
+where
+    F: Fn() -> O + Sync,
+    O: FnMut(),
+{
+    let stop = AtomicBool::new(false);
+    // The threads, and this one to time it.
+    let start = Barrier::new(threads + 1);
+    let (elapsed, results) = thread::scope(|s| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut op = setup();
+                    let mut latencies = Vec::new();
+                    let mut ops = 0u64;
+                    start.wait();
+                    while !stop.load(Relaxed) {
+                        if ops.is_multiple_of(SAMPLE_EVERY) {
+                            let t = Instant::now();
+                            op();
+                            latencies.push(t.elapsed());
+                        } else {
+                            op();
+                        }
+                        ops += 1;
+                    }
+                    (ops, latencies)
+                })
+            })
+            .collect();
+        start.wait();
+        let t = Instant::now();
+        thread::sleep(duration);
+        stop.store(true, Relaxed);
+        let results: Vec<_> = workers.into_iter().map(|w| w.join().unwrap()).collect();
+        (t.elapsed(), results)
+    });
+    let ops = results.iter().map(|(ops, _)| ops).sum();
+    let mut latencies: Vec<Duration> = results.into_iter().flat_map(|(_, l)| l).collect();
+    latencies.sort_unstable();
+    Sample {
+        ops,
+        elapsed,
+        latencies,
+    }
+}
+
+type Bench = fn(usize, Duration) -> Sample;
+
+const BENCHES: &[(&str, Bench)] = &[
+    ("mutex", |threads, duration| {
+        let lock = Mutex::new(0u64);
+        measure(threads, duration, || || *lock.lock() += 1)
+    }),
+    ("spin_lock", |threads, duration| {
+        let lock = SpinLock::new(0u64);
+        measure(threads, duration, || || *lock.lock() += 1)
+    }),
+    ("rwlock_read", |threads, duration| {
+        let lock = RwLock::<_, RawFutexRwLock>::new(0u64);
+        measure(threads, duration, || || _ = black_box(*lock.read()))
+    }),
+    ("std_mutex", |threads, duration| {
+        let lock = std::sync::Mutex::new(0u64);
+        measure(threads, duration, || || *lock.lock().unwrap() += 1)
+    }),
+    // Every thread sends one message, then receives one, which may be
+    // another thread's. A thread waiting to receive hasn't received its
+    // own message yet, so there's always one for it.
+    ("channel", |threads, duration| {
+        let channel = Channel::new();
+        measure(threads, duration, || {
+            || {
+                channel.send(1u64);
+                black_box(channel.receive());
+            }
+        })
+    }),
+    // Every thread sends, and one more receives everything.
+    ("mpsc_sync", |threads, duration| {
+        let (tx, rx) = mpsc::sync_channel(BOUND);
+        thread::scope(|s| {
+            s.spawn(move || while rx.recv().is_ok() {});
+            let sample = measure(threads, duration, || {
+                let tx = tx.clone();
+                move || tx.send(1u64).unwrap()
+            });
+            drop(tx);
+            sample
+        })
+    }),
+    ("std_mpsc_sync", |threads, duration| {
+        let (tx, rx) = std::sync::mpsc::sync_channel(BOUND);
+        thread::scope(|s| {
+            s.spawn(move || while rx.recv().is_ok() {});
+            let sample = measure(threads, duration, || {
+                let tx = tx.clone();
+                move || tx.send(1u64).unwrap()
+            });
+            drop(tx);
+            sample
+        })
+    }),
+    ("arc_clone", |threads, duration| {
+        let arc = Arc::new(0u64);
+        measure(threads, duration, || || drop(black_box(arc.clone())))
+    }),
+    ("std_arc_clone", |threads, duration| {
+        let arc = std::sync::Arc::new(0u64);
+        measure(threads, duration, || || drop(black_box(arc.clone())))
+    }),
+];
+
+fn main() {
+    let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+    let args = parse_args(cpus);
+    let machine = Machine {
+        arch: env::consts::ARCH,
+        os: env::consts::OS,
+        cpus,
+        wait_backend: wait::BACKEND,
+    };
+
+    let mut rows = Vec::new();
+    for &(name, bench) in BENCHES {
+        if !args.filters.is_empty() && !args.filters.iter().any(|f| name.contains(f.as_str())) {
+            continue;
+        }
+        for &threads in &args.threads {
+            eprint!("{name} on {threads} threads... ");
+            let sample = bench(threads, args.duration);
+            let row = Row {
+                bench: name,
+                threads,
+                ops: sample.ops,
+                seconds: sample.elapsed.as_secs_f64(),
+                p50: sample.percentile(50),
+                p99: sample.percentile(99),
+            };
+            eprintln!("{:.0} ops/s", row.ops_per_sec());
+            rows.push(row);
+        }
+    }
+    print!("{}", args.format.render(&machine, &rows));
+}
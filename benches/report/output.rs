@@ -0,0 +1,92 @@
+//! The report, as CSV or JSON. The values are all numbers or names without
+//! quotes, commas or newlines in them, so neither needs escaping.
+
+use std::fmt::Write;
+use std::time::Duration;
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    /// A header, then one line per benchmark and thread count, with the
+    /// machine repeated on every line.
+    Csv,
+    /// An object with the machine, and the rows in `results`.
+    Json,
+}
+
+/// What the numbers were measured on.
+pub struct Machine {
+    pub arch: &'static str,
+    pub os: &'static str,
+    pub cpus: usize,
+    pub wait_backend: &'static str,
+}
+
+/// One benchmark at one thread count.
+pub struct Row {
+    pub bench: &'static str,
+    pub threads: usize,
+    pub ops: u64,
+    pub seconds: f64,
+    pub p50: Duration,
+    pub p99: Duration,
+}
+
+impl Row {
+    pub fn ops_per_sec(&self) -> f64 {
+        self.ops as f64 / self.seconds
+    }
+}
+
+impl Format {
+    pub fn render(self, machine: &Machine, rows: &[Row]) -> String {
+        let mut out = String::new();
+        match self {
+            Format::Csv => {
+                out.push_str(
+                    "arch,os,cpus,wait_backend,bench,threads,ops,seconds,ops_per_sec,p50_ns,p99_ns\n",
+                );
+                for row in rows {
+                    let _ = writeln!(
+                        out,
+                        "{},{},{},{},{},{},{},{:.6},{:.0},{},{}",
+                        machine.arch,
+                        machine.os,
+                        machine.cpus,
+                        machine.wait_backend,
+                        row.bench,
+                        row.threads,
+                        row.ops,
+                        row.seconds,
+                        row.ops_per_sec(),
+                        row.p50.as_nanos(),
+                        row.p99.as_nanos(),
+                    );
+                }
+            }
+            Format::Json => {
+                let _ = write!(
+                    out,
+                    "{{\"arch\":\"{}\",\"os\":\"{}\",\"cpus\":{},\"wait_backend\":\"{}\",\"results\":[",
+                    machine.arch, machine.os, machine.cpus, machine.wait_backend,
+                );
+                for (i, row) in rows.iter().enumerate() {
+                    let _ = write!(
+                        out,
+                        "{}\n  {{\"bench\":\"{}\",\"threads\":{},\"ops\":{},\"seconds\":{:.6},\
+                         \"ops_per_sec\":{:.0},\"p50_ns\":{},\"p99_ns\":{}}}",
+                        if i == 0 { "" } else { "," },
+                        row.bench,
+                        row.threads,
+                        row.ops,
+                        row.seconds,
+                        row.ops_per_sec(),
+                        row.p50.as_nanos(),
+                        row.p99.as_nanos(),
+                    );
+                }
+                out.push_str("\n]}\n");
+            }
+        }
+        out
+    }
+}
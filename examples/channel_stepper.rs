@@ -0,0 +1,164 @@
+//! Steps through a send and a receive on a `OneShotChannel` by hand, one
+//! step at a time, and shows the channel's state after each.
+//!
+//! ```text
+//! cargo run --example channel_stepper --features chaos
+//! ```
+//!
+//! A producer thread sends a message, and a consumer thread keeps trying to
+//! receive it. Both stop at every `chaos` point in the channel, and only go
+//! on to the next one when told to:
+//!
+//! - `step producer`, or `p`
+//! - `step consumer`, or `c`
+//! - `quit`, or `q`
+//!
+//! Let the consumer try while the producer is still writing: it sees the
+//! state isn't `ready`, and doesn't touch the message.
+
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread;
+
+use atomics_and_locks::channels::OneShotChannel;
+use atomics_and_locks::chaos;
+
+static CHANNEL: OneShotChannel<&str> = OneShotChannel::new();
+
+const ROLES: [&str; 2] = ["producer", "consumer"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Where {
+    Starting,
+    At(&'static str),
+    Done,
+}
+
+struct Threads {
+    at: [Where; 2],
+    /// How often each thread stopped, to see it stopped again even at the
+    /// same point, like the consumer retrying.
+    stops: [u32; 2],
+    go: [bool; 2],
+}
+
+static THREADS: Mutex<Threads> = Mutex::new(Threads {
+    at: [Where::Starting; 2],
+    stops: [0; 2],
+    go: [false; 2],
+});
+static CHANGED: Condvar = Condvar::new();
+
+fn threads() -> MutexGuard<'static, Threads> {
+    THREADS.lock().unwrap()
+}
+
+fn role() -> Option<usize> {
+    let name = thread::current().name()?.to_owned();
+    ROLES.iter().position(|&r| r == name)
+}
+
+/// The hook, called at every point: tells the main thread where we are,
+/// and waits to be stepped.
+fn stop(point: &'static str) {
+    let Some(i) = role() else { return };
+    let mut t = threads();
+    t.at[i] = Where::At(point);
+    t.stops[i] += 1;
+    CHANGED.notify_all();
+    while !t.go[i] {
+        t = CHANGED.wait(t).unwrap();
+    }
+    t.go[i] = false;
+}
+
+fn done() {
+    let i = role().unwrap();
+    threads().at[i] = Where::Done;
+    CHANGED.notify_all();
+}
+
+/// What a thread stopped at `point` does next.
+fn next_step(point: &str) -> &'static str {
+    match point {
+        "one_shot::before_send" => "claim the channel: compare_exchange(empty, writing, Relaxed)",
+        "one_shot::writing" => "write the message, which nobody else touches while writing",
+        "one_shot::send" => "publish it: store(ready, Release)",
+        "one_shot::before_receive" => "take it: compare_exchange(ready, reading, Acquire)",
+        "one_shot::reading" => "read the message, which the Acquire made visible",
+        "one_shot::read" => "store(consumed, Relaxed)",
+        _ => "go on",
+    }
+}
+
+fn show(t: &Threads) {
+    println!("  channel:  {CHANNEL:?}");
+    for (role, at) in ROLES.iter().zip(t.at) {
+        match at {
+            Where::Starting => println!("  {role}: starting"),
+            Where::At(point) => println!("  {role}: at {point}, next: {}", next_step(point)),
+            Where::Done => println!("  {role}: done"),
+        }
+    }
+}
+
+/// Lets the thread go on, and waits until it stopped again or finished.
+fn step(i: usize) {
+    let mut t = threads();
+    if t.at[i] == Where::Done {
+        println!("  the {} is done already", ROLES[i]);
+        return;
+    }
+    let stops = t.stops[i];
+    t.go[i] = true;
+    CHANGED.notify_all();
+    while t.stops[i] == stops && t.at[i] != Where::Done {
+        t = CHANGED.wait(t).unwrap();
+    }
+    show(&t);
+}
+
+fn spawn(i: usize, f: impl FnOnce() + Send + 'static) {
+    thread::Builder::new()
+        .name(ROLES[i].into())
+        .spawn(move || {
+            f();
+            done();
+        })
+        .unwrap();
+}
+
+fn main() {
+    chaos::set_hook(Some(stop));
+    spawn(0, || CHANNEL.send("hello").unwrap());
+    spawn(1, || loop {
+        if let Ok(message) = CHANNEL.receive() {
+            println!("  consumer received {message:?}");
+            break;
+        }
+    });
+
+    let mut t = threads();
+    while t.stops.contains(&0) {
+        t = CHANGED.wait(t).unwrap();
+    }
+    show(&t);
+    drop(t);
+
+    let mut lines = io::stdin().lock().lines();
+    while threads().at != [Where::Done; 2] {
+        print!("> ");
+        io::stdout().flush().unwrap();
+        let Some(Ok(line)) = lines.next() else { break };
+        match line.trim() {
+            "step producer" | "p" => step(0),
+            "step consumer" | "c" => step(1),
+            "quit" | "q" => break,
+            "" => {}
+            _ => println!("  step producer (p), step consumer (c) or quit (q)"),
+        }
+    }
+    // The threads may still be waiting to be stepped.
+    process::exit(0);
+}
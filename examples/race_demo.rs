@@ -0,0 +1,156 @@
+//! Two deliberately broken one-shot channels, with `chaos` points where
+//! their races are, run until the races show.
+//!
+//! ```text
+//! cargo run --release --example race_demo --features chaos -- [check_then_take|flag_first] [rounds]
+//! ```
+//!
+//! The points pause at random, as configured by `CHAOS_RACE` (see
+//! `chaos::RaceConfig`), or by a default that makes most rounds go wrong.
+//! With `CHAOS_RACE=` (empty), they never pause, and the same bugs only
+//! show up once in a while, if at all. Which is how they get past tests.
+//!
+//! The messages are atomics, so going wrong here means getting the wrong
+//! message, not undefined behavior.
+
+use std::env;
+use std::process;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64};
+use std::thread;
+use std::time::Duration;
+
+use atomics_and_locks::chaos::{self, Pause, RaceConfig};
+
+const MESSAGE: u64 = 42;
+
+const DEFAULT: RaceConfig = RaceConfig::new()
+    .yield_percent(30)
+    .sleep_percent(40)
+    .max_sleep(Duration::from_micros(200));
+
+/// Checks the flag, and clears it in a separate step, so two receivers can
+/// both get past the check, and both receive the message.
+#[derive(Default)]
+struct CheckThenTake {
+    message: AtomicU64,
+    ready: AtomicBool,
+}
+
+impl CheckThenTake {
+    fn send(&self, message: u64) {
+        self.message.store(message, Relaxed);
+        self.ready.store(true, Release);
+    }
+
+    fn receive(&self) -> Option<u64> {
+        if !self.ready.load(Acquire) {
+            return None;
+        }
+        chaos::point("race_demo::checked");
+        // Should have been a swap.
+        self.ready.store(false, Relaxed);
+        Some(self.message.load(Relaxed))
+    }
+}
+
+/// Sets the flag before writing the message. With Relaxed on both, that's
+/// an order other threads are allowed to see even if the code says
+/// otherwise, and this makes it happen on every CPU.
+#[derive(Default)]
+struct FlagFirst {
+    message: AtomicU64,
+    ready: AtomicBool,
+}
+
+impl FlagFirst {
+    fn send(&self, message: u64) {
+        self.ready.store(true, Relaxed);
+        chaos::point("race_demo::flagged");
+        self.message.store(message, Relaxed);
+    }
+
+    fn receive(&self) -> Option<u64> {
+        self.ready.load(Relaxed).then(|| self.message.load(Relaxed))
+    }
+}
+
+/// Two receivers racing for one message. Wrong if both got it.
+fn check_then_take() -> bool {
+    let channel = CheckThenTake::default();
+    let received = AtomicU32::new(0);
+    thread::scope(|s| {
+        for _ in 0..2 {
+            s.spawn(|| {
+                while received.load(Relaxed) == 0 {
+                    if channel.receive().is_some() {
+                        received.fetch_add(1, Relaxed);
+                    }
+                }
+            });
+        }
+        channel.send(MESSAGE);
+    });
+    received.into_inner() > 1
+}
+
+/// One receiver. Wrong if it got something else than what was sent.
+fn flag_first() -> bool {
+    let channel = FlagFirst::default();
+    thread::scope(|s| {
+        let receiver = s.spawn(|| loop {
+            if let Some(message) = channel.receive() {
+                return message;
+            }
+            std::hint::spin_loop();
+        });
+        channel.send(MESSAGE);
+        receiver.join().unwrap() != MESSAGE
+    })
+}
+
+struct Demo {
+    name: &'static str,
+    /// Where the race is.
+    point: &'static str,
+    /// Whether this round went wrong.
+    run: fn() -> bool,
+}
+
+const DEMOS: [Demo; 2] = [
+    Demo {
+        name: "check_then_take",
+        point: "race_demo::checked",
+        run: check_then_take,
+    },
+    Demo {
+        name: "flag_first",
+        point: "race_demo::flagged",
+        run: flag_first,
+    },
+];
+
+fn main() {
+    let demo = env::args().nth(1);
+    let rounds: u32 = env::args()
+        .nth(2)
+        .map(|a| a.parse().expect("rounds must be a number"))
+        .unwrap_or(1000);
+    let config = RaceConfig::from_env().unwrap_or(DEFAULT);
+    println!("{config:?}");
+
+    let mut ran = false;
+    for d in DEMOS {
+        if demo.as_deref().is_some_and(|name| name != d.name) {
+            continue;
+        }
+        ran = true;
+        chaos::pause_at(d.point, Pause::Random(config));
+        let wrong = (0..rounds).filter(|_| (d.run)()).count();
+        println!("{}: {wrong} of {rounds} rounds went wrong", d.name);
+    }
+    if !ran {
+        eprintln!("no demo called {:?}", demo.unwrap());
+        process::exit(1);
+    }
+}
@@ -0,0 +1,77 @@
+//! Races receivers for the messages of many one-shot channels, and counts
+//! the messages that were received more than once, or arrived torn.
+//!
+//! ```text
+//! cargo run --release --example stress_one_shot -- [receivers] [seconds]
+//! ```
+
+use std::env;
+use std::process;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering::Relaxed;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use atomics_and_locks::channels::OneShotChannel;
+
+/// Channels per round. Every round starts with fresh ones, since a one-shot
+/// channel can only be used once.
+const ROUND: usize = 1000;
+
+/// More words than a single instruction can write, so a message read
+/// before it's fully written shows up as a mix.
+const WORDS: usize = 16;
+
+fn arg(n: usize, default: u64) -> u64 {
+    env::args()
+        .nth(n)
+        .map(|a| a.parse().expect("arguments must be numbers"))
+        .unwrap_or(default)
+}
+
+fn main() {
+    let receivers = arg(1, 4);
+    let seconds = arg(2, 5);
+
+    let deadline = Instant::now() + Duration::from_secs(seconds);
+    let (mut messages, mut double, mut torn) = (0u64, 0u64, 0u64);
+
+    while Instant::now() < deadline {
+        let channels: Vec<OneShotChannel<[u64; WORDS]>> =
+            (0..ROUND).map(|_| OneShotChannel::new()).collect();
+        let received: Vec<AtomicU32> = (0..ROUND).map(|_| AtomicU32::new(0)).collect();
+        let round_torn = AtomicU32::new(0);
+        thread::scope(|s| {
+            for _ in 0..receivers {
+                s.spawn(|| {
+                    for (i, channel) in channels.iter().enumerate() {
+                        // Keep trying until someone got it, so a second
+                        // receive has every chance to happen.
+                        while received[i].load(Relaxed) == 0 {
+                            if let Ok(message) = channel.receive() {
+                                if message.iter().any(|w| *w != i as u64) {
+                                    round_torn.fetch_add(1, Relaxed);
+                                }
+                                received[i].fetch_add(1, Relaxed);
+                            }
+                        }
+                    }
+                });
+            }
+            for (i, channel) in channels.iter().enumerate() {
+                channel.send([i as u64; WORDS]).unwrap();
+            }
+        });
+        messages += ROUND as u64;
+        double += received.iter().filter(|r| r.load(Relaxed) > 1).count() as u64;
+        torn += u64::from(round_torn.into_inner());
+    }
+
+    println!(
+        "{receivers} receivers, {seconds}s: {messages} messages, \
+         {double} received more than once, {torn} torn"
+    );
+    if double > 0 || torn > 0 {
+        process::exit(1);
+    }
+}
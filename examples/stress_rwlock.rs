@@ -0,0 +1,81 @@
+//! Hammers a `RwLock` from reader and writer threads, and counts the reads
+//! that saw a torn value: one a writer was halfway through writing.
+//!
+//! ```text
+//! cargo run --release --example stress_rwlock -- [readers] [writers] [seconds]
+//! ```
+
+use std::env;
+use std::process;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::thread;
+use std::time::Duration;
+
+use atomics_and_locks::locks::{RawSpinRwLock, RwLock};
+
+/// More words than a single instruction can write, so an unprotected read
+/// can see a mix of two writes.
+const WORDS: usize = 16;
+
+fn arg(n: usize, default: u64) -> u64 {
+    env::args()
+        .nth(n)
+        .map(|a| a.parse().expect("arguments must be numbers"))
+        .unwrap_or(default)
+}
+
+fn main() {
+    let readers = arg(1, 4);
+    let writers = arg(2, 2);
+    let seconds = arg(3, 5);
+
+    let rwlock = RwLock::<_, RawSpinRwLock>::new([0u64; WORDS]);
+    let stop = AtomicBool::new(false);
+    let reads = AtomicU64::new(0);
+    let writes = AtomicU64::new(0);
+    let torn = AtomicU64::new(0);
+
+    thread::scope(|s| {
+        for _ in 0..readers {
+            s.spawn(|| {
+                let (mut n, mut bad) = (0, 0);
+                while !stop.load(Relaxed) {
+                    let value = rwlock.read();
+                    if value.iter().any(|w| *w != value[0]) {
+                        bad += 1;
+                    }
+                    n += 1;
+                }
+                reads.fetch_add(n, Relaxed);
+                torn.fetch_add(bad, Relaxed);
+            });
+        }
+        for _ in 0..writers {
+            s.spawn(|| {
+                let mut n = 0;
+                while !stop.load(Relaxed) {
+                    let mut value = rwlock.write();
+                    let next = value[0] + 1;
+                    for w in value.iter_mut() {
+                        *w = next;
+                    }
+                    n += 1;
+                }
+                writes.fetch_add(n, Relaxed);
+            });
+        }
+        thread::sleep(Duration::from_secs(seconds));
+        stop.store(true, Relaxed);
+    });
+
+    let torn = torn.into_inner();
+    println!(
+        "{readers} readers, {writers} writers, {seconds}s: {} reads, {} writes, {torn} torn reads",
+        reads.into_inner(),
+        writes.into_inner(),
+    );
+    if torn > 0 {
+        process::exit(1);
+    }
+}
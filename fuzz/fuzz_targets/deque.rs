@@ -0,0 +1,103 @@
+//! Runs a random sequence of owner operations against random sequences of
+//! steals from other threads, and checks the result against a model: every
+//! item is taken at most once, and dropped exactly once, whether it was
+//! taken, rejected by a full deque, or still in it when it's dropped.
+//!
+//! ```text
+//! cargo +nightly fuzz run deque
+//! ```
+
+#![no_main]
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use atomics_and_locks::lockfree::{deque, Steal};
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+enum OwnerOp {
+    Push,
+    Pop,
+}
+
+#[derive(Arbitrary, Debug)]
+enum ThiefOp {
+    Steal,
+    Yield,
+    /// Drops this thief's `Stealer` (and so its reference to the deque),
+    /// ending the thread.
+    Quit,
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    capacity_log2: u8,
+    owner: Vec<OwnerOp>,
+    thieves: Vec<Vec<ThiefOp>>,
+}
+
+struct Item {
+    id: usize,
+    drops: Arc<AtomicUsize>,
+}
+
+impl Drop for Item {
+    fn drop(&mut self) {
+        self.drops.fetch_add(1, Relaxed);
+    }
+}
+
+fuzz_target!(|input: Input| {
+    let (worker, stealer) = deque::<Item>(1 << (input.capacity_log2 % 5));
+    let drops = Arc::new(AtomicUsize::new(0));
+    let taken = Mutex::new(Vec::new());
+    let mut created = 0;
+    thread::scope(|s| {
+        for ops in input.thieves.iter().take(3) {
+            let (stealer, taken) = (stealer.clone(), &taken);
+            s.spawn(move || {
+                for op in ops {
+                    match op {
+                        ThiefOp::Steal => {
+                            if let Steal::Success(item) = stealer.steal() {
+                                taken.lock().unwrap().push(item.id);
+                            }
+                        }
+                        ThiefOp::Yield => thread::yield_now(),
+                        ThiefOp::Quit => return,
+                    }
+                }
+            });
+        }
+        for op in &input.owner {
+            match op {
+                OwnerOp::Push => {
+                    // When the deque is full, the item comes back and is
+                    // dropped right here.
+                    drop(worker.push(Item {
+                        id: created,
+                        drops: drops.clone(),
+                    }));
+                    created += 1;
+                }
+                OwnerOp::Pop => {
+                    if let Some(item) = worker.pop() {
+                        taken.lock().unwrap().push(item.id);
+                    }
+                }
+            }
+        }
+    });
+    let mut taken = taken.into_inner().unwrap();
+    let taken_count = taken.len();
+    taken.sort_unstable();
+    taken.dedup();
+    assert_eq!(taken.len(), taken_count, "an item was taken twice");
+    // The rest are dropped along with the deque.
+    drop((worker, stealer));
+    assert_eq!(drops.load(Relaxed), created);
+});
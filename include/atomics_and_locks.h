@@ -0,0 +1,54 @@
+/*
+ * C declarations for the functions in src/ffi.rs. Build the library with
+ *
+ *     cargo rustc --release --lib --features ffi --crate-type staticlib
+ *
+ * and link target/release/libatomics_and_locks.a.
+ */
+
+#ifndef ATOMICS_AND_LOCKS_H
+#define ATOMICS_AND_LOCKS_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define AAL_OK 0
+#define AAL_FULL 1
+#define AAL_EMPTY 2
+#define AAL_TIMEOUT 3
+
+typedef struct AalMutex AalMutex;
+typedef struct AalChannel AalChannel;
+typedef struct AalEvent AalEvent;
+
+AalMutex *aal_mutex_new(void);
+void aal_mutex_free(AalMutex *mutex);
+void aal_mutex_lock(const AalMutex *mutex);
+bool aal_mutex_trylock(const AalMutex *mutex);
+void aal_mutex_unlock(const AalMutex *mutex);
+
+AalChannel *aal_channel_new(size_t capacity);
+void aal_channel_free(AalChannel *channel);
+int aal_channel_send(const AalChannel *channel, void *message);
+int aal_channel_try_send(const AalChannel *channel, void *message);
+int aal_channel_recv(const AalChannel *channel, void **message);
+int aal_channel_try_recv(const AalChannel *channel, void **message);
+int aal_channel_recv_timeout(const AalChannel *channel, void **message, uint64_t timeout_ms);
+
+AalEvent *aal_event_new(void);
+void aal_event_free(AalEvent *event);
+void aal_event_set(const AalEvent *event);
+void aal_event_reset(const AalEvent *event);
+bool aal_event_is_set(const AalEvent *event);
+void aal_event_wait(const AalEvent *event);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,99 @@
+//! How the last drop of an `Arc` or `Weak` sees everything the others did
+//! before it frees the data.
+//!
+//! Every drop decrements the count with Release, and the one that takes it
+//! to zero has to acquire all of those. It can do that with a separate
+//! `fence(Acquire)`, only when it's the last one, which is what the book
+//! and `std` do. Or every decrement can be AcqRel, which is a single
+//! instruction but makes every drop pay for acquiring. On x86 there's no
+//! difference, as every read-modify-write is a full barrier already. On
+//! AArch64 it's `ldaddal` instead of `ldaddl` on every drop, against a
+//! `dmb ishld` on the last one. Whether that's measurable is up to the
+//! core, which is what `bench_drop_strategies` is for.
+//!
+//! The fence is the default, and the `arc_acqrel_drop` feature switches to
+//! AcqRel.
+
+use crate::sync_shim::atomic::Ordering::{AcqRel, Acquire, Release};
+use crate::sync_shim::atomic::{fence, AtomicUsize};
+
+/// Gives up one count, and returns whether it was the last one, in which
+/// case every earlier drop happened before this returns.
+#[cfg(not(feature = "arc_acqrel_drop"))]
+pub(crate) use fence_drop as release;
+
+#[cfg(feature = "arc_acqrel_drop")]
+pub(crate) use acq_rel_drop as release;
+
+// Only one of these is `release`, but the benchmark compares both.
+#[cfg_attr(feature = "arc_acqrel_drop", allow(dead_code))]
+#[inline]
+pub(crate) fn fence_drop(count: &AtomicUsize) -> bool {
+    if count.fetch_sub(1, Release) == 1 {
+        fence(Acquire);
+        return true;
+    }
+    false
+}
+
+#[cfg_attr(not(feature = "arc_acqrel_drop"), allow(dead_code))]
+#[inline]
+pub(crate) fn acq_rel_drop(count: &AtomicUsize) -> bool {
+    count.fetch_sub(1, AcqRel) == 1
+}
+
+#[cfg(all(test, not(any(loom, feature = "shuttle"))))]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::arc::drop_strategy::{acq_rel_drop, fence_drop};
+    use crate::sync_shim::atomic::AtomicUsize;
+
+    #[test]
+    fn test_both_report_the_last_drop() {
+        for release in [fence_drop, acq_rel_drop] {
+            let count = AtomicUsize::new(2);
+            assert!(!release(&count));
+            assert!(release(&count));
+        }
+    }
+
+    const DROPS: usize = 10_000_000;
+
+    /// `threads` threads dropping their share of `DROPS` counts of one
+    /// counter. Returns the time per drop.
+    fn drop_all(release: fn(&AtomicUsize) -> bool, threads: usize) -> Duration {
+        let count = AtomicUsize::new(DROPS);
+        let start = Instant::now();
+        thread::scope(|s| {
+            for _ in 0..threads {
+                s.spawn(|| {
+                    for _ in 0..DROPS / threads {
+                        std::hint::black_box(release(&count));
+                    }
+                });
+            }
+        });
+        start.elapsed() / DROPS as u32
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_drop_strategies`,
+    /// on each target you care about.
+    #[test]
+    #[ignore]
+    fn bench_drop_strategies() {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        println!("on {}:", std::env::consts::ARCH);
+        for (name, release) in [
+            ("fence(Acquire)", fence_drop as fn(&AtomicUsize) -> bool),
+            ("AcqRel", acq_rel_drop),
+        ] {
+            println!("{name:>14}: {:?} alone", drop_all(release, 1));
+            println!(
+                "{name:>14}: {:?} on {threads} threads",
+                drop_all(release, threads)
+            );
+        }
+    }
+}
@@ -0,0 +1,250 @@
+//! Cheaper clones of an `Arc`, for code that clones one per message.
+//!
+//! Every `Arc::clone` and drop is an atomic read-modify-write on the same
+//! counter, and when several threads do that at the same time, that
+//! counter's cache line bounces between their cores. Two ways around it:
+//!
+//! - A [`LocalArc`] holds one strong count for the thread it's on, and its
+//!   clones only count among themselves, non-atomically. The last one
+//!   gives the strong count back. They can't leave the thread, but
+//!   [`LocalArc::to_arc`] makes a real `Arc` that can.
+//! - An [`ArcPool`] takes strong counts in batches, with one `fetch_add`,
+//!   and hands them out as real `Arc`s that can be sent anywhere. What's
+//!   left of the batch is given back, again at once, when it's dropped.
+//!   The drops of those `Arc`s are still atomic, but they often happen on
+//!   the receiving end.
+
+use alloc::rc::Rc;
+use core::fmt;
+use core::ops::Deref;
+
+use crate::arc::Arc;
+
+/// A handle to an `Arc` whose clones stay on this thread, and cost no
+/// atomic operations.
+pub struct LocalArc<T> {
+    // The `Rc` keeps this `!Send` and `!Sync`, and dropping the last one
+    // drops the `Arc`, so there's no drop order to get wrong.
+    arc: Rc<Arc<T>>,
+}
+
+impl<T> LocalArc<T> {
+    pub fn new(arc: Arc<T>) -> Self {
+        Self { arc: Rc::new(arc) }
+    }
+
+    /// A real `Arc`, to send to another thread. That's an atomic increment.
+    pub fn to_arc(this: &Self) -> Arc<T> {
+        Arc::clone(&this.arc)
+    }
+
+    /// The number of `LocalArc`s sharing this one's strong count.
+    pub fn local_count(this: &Self) -> usize {
+        Rc::strong_count(&this.arc)
+    }
+}
+
+impl<T> Clone for LocalArc<T> {
+    fn clone(&self) -> Self {
+        Self {
+            arc: Rc::clone(&self.arc),
+        }
+    }
+}
+
+impl<T> Deref for LocalArc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.arc
+    }
+}
+
+impl<T> From<Arc<T>> for LocalArc<T> {
+    fn from(arc: Arc<T>) -> Self {
+        Self::new(arc)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for LocalArc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalArc")
+            .field("data", &**self)
+            .field("local", &LocalArc::local_count(self))
+            .finish()
+    }
+}
+
+pub const DEFAULT_BATCH: usize = 64;
+/// The most counts a pool takes at once. More than that would overflow the
+/// strong count on its own.
+pub const MAX_BATCH: usize = usize::MAX / 2;
+
+/// Hands out `Arc`s to the same data, taking strong counts for them in
+/// batches.
+pub struct ArcPool<T> {
+    arc: Arc<T>,
+    batch: usize,
+    /// Counts taken for `Arc`s that haven't been handed out yet.
+    reserved: usize,
+}
+
+impl<T> ArcPool<T> {
+    pub fn new(arc: Arc<T>) -> Self {
+        Self::with_batch(arc, DEFAULT_BATCH)
+    }
+
+    /// Panics if `batch` is zero, or more than `MAX_BATCH`.
+    pub fn with_batch(arc: Arc<T>, batch: usize) -> Self {
+        assert!(batch > 0, "batch must not be zero");
+        assert!(batch <= MAX_BATCH, "batch must be at most {MAX_BATCH}");
+        Self {
+            arc,
+            batch,
+            reserved: 0,
+        }
+    }
+
+    /// Another `Arc`. Only every `batch`th one touches the counter.
+    pub fn get(&mut self) -> Arc<T> {
+        if self.reserved == 0 {
+            Arc::reserve(&self.arc, self.batch);
+            self.reserved = self.batch;
+        }
+        self.reserved -= 1;
+        // Safety: We've just given up one of the reserved counts.
+        unsafe { Arc::from_reserved(&self.arc) }
+    }
+}
+
+impl<T> Deref for ArcPool<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.arc
+    }
+}
+
+impl<T> fmt::Debug for ArcPool<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArcPool")
+            .field("batch", &self.batch)
+            .field("reserved", &self.reserved)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> Drop for ArcPool<T> {
+    fn drop(&mut self) {
+        if self.reserved > 0 {
+            // Safety: These were never handed out, and `self.arc`, which
+            // is only dropped after this, keeps the count above zero.
+            unsafe { Arc::unreserve(&self.arc, self.reserved) };
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::arc::local::{ArcPool, LocalArc};
+    use crate::arc::Arc;
+
+    static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    struct DetectDrop;
+
+    impl Drop for DetectDrop {
+        fn drop(&mut self) {
+            NUM_DROPS.fetch_add(1, Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_local_clones_share_one_count() {
+        let arc = Arc::new(5);
+        let local = LocalArc::new(arc.clone());
+        let clones: Vec<_> = (0..10).map(|_| local.clone()).collect();
+        assert_eq!(Arc::strong_count(&arc), 2);
+        assert_eq!(LocalArc::local_count(&local), 11);
+        assert_eq!(clones.iter().map(|c| **c).sum::<i32>(), 50);
+        let sent = LocalArc::to_arc(&local);
+        assert_eq!(Arc::strong_count(&arc), 3);
+        thread::spawn(move || assert_eq!(*sent, 5)).join().unwrap();
+        drop(clones);
+        drop(local);
+        assert_eq!(Arc::strong_count(&arc), 1);
+    }
+
+    #[test]
+    fn test_pool_gives_back_what_it_did_not_hand_out() {
+        NUM_DROPS.store(0, Relaxed);
+        let arc = Arc::new(DetectDrop);
+        let mut pool = ArcPool::with_batch(arc.clone(), 4);
+        let handed_out: Vec<_> = (0..5).map(|_| pool.get()).collect();
+        // The original, the pool's own, and two batches.
+        assert_eq!(Arc::strong_count(&arc), 2 + 8);
+        drop(pool);
+        assert_eq!(Arc::strong_count(&arc), 1 + 5);
+        thread::scope(|s| {
+            for a in handed_out {
+                s.spawn(move || drop(a));
+            }
+        });
+        assert_eq!(Arc::strong_count(&arc), 1);
+        assert_eq!(NUM_DROPS.load(Relaxed), 0);
+        drop(arc);
+        assert_eq!(NUM_DROPS.load(Relaxed), 1);
+    }
+
+    #[test]
+    #[should_panic = "batch must be at most"]
+    fn test_pool_rejects_a_batch_that_would_overflow() {
+        ArcPool::with_batch(Arc::new(()), usize::MAX);
+    }
+
+    const CLONES: u32 = 1_000_000;
+
+    /// Four threads each running `clones` for their share of the clones.
+    /// Returns the time per clone.
+    fn on_four_threads(clones: impl Fn(u32) + Sync) -> Duration {
+        let start = Instant::now();
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| clones(CLONES / 4));
+            }
+        });
+        start.elapsed() / CLONES
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_local_arc`.
+    #[test]
+    #[ignore]
+    fn bench_local_arc() {
+        let arc = Arc::new([0u8; 64]);
+        let arc_clone = on_four_threads(|n| {
+            for _ in 0..n {
+                std::hint::black_box(arc.clone());
+            }
+        });
+        let local_clone = on_four_threads(|n| {
+            let local = LocalArc::new(arc.clone());
+            for _ in 0..n {
+                std::hint::black_box(local.clone());
+            }
+        });
+        let pool_get = on_four_threads(|n| {
+            let mut pool = ArcPool::new(arc.clone());
+            for _ in 0..n {
+                std::hint::black_box(pool.get());
+            }
+        });
+        println!("     Arc::clone: {arc_clone:?}");
+        println!("LocalArc::clone: {local_clone:?}");
+        println!("   ArcPool::get: {pool_get:?} (still an atomic drop)");
+    }
+}
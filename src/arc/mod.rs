@@ -1 +1,20 @@
+mod drop_strategy;
+pub mod local;
+pub mod packed;
 pub mod reference_counting;
+
+pub use local::{ArcPool, LocalArc};
+pub use reference_counting::better_weak::{Arc, Wake, Weak};
+
+/// Called when a reference count is about to overflow, which can only
+/// happen if `Arc`s are being leaked. Without `std` there's no way to
+/// abort, so it panics instead.
+#[cfg(feature = "std")]
+fn abort() -> ! {
+    std::process::abort()
+}
+
+#[cfg(not(feature = "std"))]
+fn abort() -> ! {
+    panic!("reference count overflow")
+}
@@ -0,0 +1,381 @@
+//! An `Arc` whose strong and weak counts share one `AtomicUsize`.
+//!
+//! The low half of the word counts the `Arc`s, and the high half the
+//! `Weak`s, plus one for as long as there are any `Arc`s, like
+//! `better_weak`'s `alloc_ref_count`. Cloning and dropping only touch the
+//! low half, so that costs the same. What's cheaper is the end: when the
+//! last `Arc` goes while there are no `Weak`s, its decrement alone shows
+//! that (the word was exactly one of each), and nothing else can reach the
+//! allocation anymore, so it frees it without the second decrement the
+//! two-counter design needs. And `get_mut` is a single load, since both
+//! counts are read at once. The header is one word instead of two, too.
+//!
+//! The price is range: each count gets half the bits, so on 32-bit targets
+//! there can be at most 2^15 of each before it aborts.
+
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem::ManuallyDrop;
+use core::ops::Deref;
+use core::ptr::NonNull;
+
+use crate::platform::backoff::Backoff;
+use crate::sync_shim::atomic::Ordering::{Acquire, Relaxed, Release};
+use crate::sync_shim::atomic::{fence, AtomicUsize};
+
+const HALF: u32 = usize::BITS / 2;
+const STRONG_ONE: usize = 1;
+const WEAK_ONE: usize = 1 << HALF;
+const STRONG_MASK: usize = WEAK_ONE - 1;
+/// Past this, either half is about to run into the other, or off the end.
+const MAX_COUNT: usize = STRONG_MASK / 2;
+
+fn strong(counts: usize) -> usize {
+    counts & STRONG_MASK
+}
+
+fn weak(counts: usize) -> usize {
+    counts >> HALF
+}
+
+struct ArcData<T> {
+    /// `Arc`s in the low half, `Weak`s (plus one while there are `Arc`s) in
+    /// the high half.
+    counts: AtomicUsize,
+    /// Dropped when the strong count reaches zero.
+    data: UnsafeCell<ManuallyDrop<T>>,
+}
+
+pub struct Arc<T> {
+    ptr: NonNull<ArcData<T>>,
+}
+
+unsafe impl<T: Send + Sync> Send for Arc<T> {}
+
+unsafe impl<T: Send + Sync> Sync for Arc<T> {}
+
+pub struct Weak<T> {
+    ptr: NonNull<ArcData<T>>,
+}
+
+unsafe impl<T: Send + Sync> Send for Weak<T> {}
+
+unsafe impl<T: Send + Sync> Sync for Weak<T> {}
+
+impl<T> Arc<T> {
+    pub fn new(data: T) -> Arc<T> {
+        Arc {
+            ptr: NonNull::from(Box::leak(Box::new(ArcData {
+                counts: AtomicUsize::new(STRONG_ONE | WEAK_ONE),
+                data: UnsafeCell::new(ManuallyDrop::new(data)),
+            }))),
+        }
+    }
+
+    fn data(&self) -> &ArcData<T> {
+        unsafe { self.ptr.as_ref() }
+    }
+
+    pub fn downgrade(arc: &Self) -> Weak<T> {
+        let counts = arc.data().counts.fetch_add(WEAK_ONE, Relaxed);
+        if weak(counts) > MAX_COUNT {
+            crate::arc::abort();
+        }
+        Weak { ptr: arc.ptr }
+    }
+
+    /// The number of `Arc`s, which may change right after.
+    pub fn strong_count(arc: &Self) -> usize {
+        strong(arc.data().counts.load(Relaxed))
+    }
+
+    /// The number of `Weak`s, which may change right after.
+    pub fn weak_count(arc: &Self) -> usize {
+        weak(arc.data().counts.load(Relaxed)) - 1
+    }
+
+    pub fn get_mut(arc: &mut Self) -> Option<&mut T> {
+        // Acquire matches the Release decrements of the other `Arc`s and
+        // `Weak`s, so nothing they did to the data is still going on. Both
+        // counts are in this one load, so neither can change in between:
+        // without other `Weak`s, nobody can upgrade.
+        if arc.data().counts.load(Acquire) != STRONG_ONE | WEAK_ONE {
+            return None;
+        }
+        // Safety: Nothing else can access the data.
+        unsafe { Some(&mut *arc.data().data.get()) }
+    }
+}
+
+impl<T> Deref for Arc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: Since there's an Arc to the data,
+        // the data exists and may be shared.
+        unsafe { &*self.data().data.get() }
+    }
+}
+
+impl<T> Clone for Arc<T> {
+    fn clone(&self) -> Self {
+        if strong(self.data().counts.fetch_add(STRONG_ONE, Relaxed)) > MAX_COUNT {
+            crate::arc::abort();
+        }
+        Arc { ptr: self.ptr }
+    }
+}
+
+impl<T> Drop for Arc<T> {
+    fn drop(&mut self) {
+        let counts = self.data().counts.fetch_sub(STRONG_ONE, Release);
+        if strong(counts) != 1 {
+            return;
+        }
+        fence(Acquire);
+        // Safety: The strong count is zero, so nothing will access the data
+        // anymore.
+        unsafe {
+            ManuallyDrop::drop(&mut *self.data().data.get());
+        }
+        if counts == STRONG_ONE | WEAK_ONE {
+            // There were no `Weak`s, and without `Arc`s nobody can make
+            // one, so nothing else can reach the allocation.
+            // Safety: See above.
+            drop(unsafe { Box::from_raw(self.ptr.as_ptr()) });
+        } else {
+            // Give up the weak count that all `Arc`s shared.
+            drop(Weak { ptr: self.ptr });
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Arc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counts = self.data().counts.load(Relaxed);
+        f.debug_struct("Arc")
+            .field("data", &**self)
+            .field("strong", &strong(counts))
+            .field("weak", &(weak(counts) - 1))
+            .finish()
+    }
+}
+
+impl<T: Default> Default for Arc<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> Weak<T> {
+    fn data(&self) -> &ArcData<T> {
+        unsafe { self.ptr.as_ref() }
+    }
+
+    pub fn upgrade(&self) -> Option<Arc<T>> {
+        let mut counts = self.data().counts.load(Relaxed);
+        let mut backoff = Backoff::new();
+        loop {
+            if strong(counts) == 0 {
+                return None;
+            }
+            assert!(strong(counts) <= MAX_COUNT);
+            if let Err(e) = self.data().counts.compare_exchange_weak(
+                counts,
+                counts + STRONG_ONE,
+                Relaxed,
+                Relaxed,
+            ) {
+                counts = e;
+                backoff.spin();
+                continue;
+            }
+            return Some(Arc { ptr: self.ptr });
+        }
+    }
+}
+
+impl<T> Clone for Weak<T> {
+    fn clone(&self) -> Self {
+        if weak(self.data().counts.fetch_add(WEAK_ONE, Relaxed)) > MAX_COUNT {
+            crate::arc::abort();
+        }
+        Weak { ptr: self.ptr }
+    }
+}
+
+impl<T> Drop for Weak<T> {
+    fn drop(&mut self) {
+        // The weak count includes one for the `Arc`s, so it only reaches
+        // zero once the strong count has too.
+        if self.data().counts.fetch_sub(WEAK_ONE, Release) == WEAK_ONE {
+            fence(Acquire);
+            // Safety: Both counts are zero, so this was the last pointer.
+            drop(unsafe { Box::from_raw(self.ptr.as_ptr()) });
+        }
+    }
+}
+
+impl<T> fmt::Debug for Weak<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("(Weak)")
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::mem::size_of;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::arc::packed::{self, ArcData};
+    use crate::arc::Arc;
+
+    static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    struct DetectDrop;
+
+    impl Drop for DetectDrop {
+        fn drop(&mut self) {
+            NUM_DROPS.fetch_add(1, Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_weak_outlives_data() {
+        NUM_DROPS.store(0, Relaxed);
+        let x = packed::Arc::new(("hello", DetectDrop));
+        let y = packed::Arc::downgrade(&x);
+        let z = y.clone();
+        assert_eq!(
+            (packed::Arc::strong_count(&x), packed::Arc::weak_count(&x)),
+            (1, 2)
+        );
+        let t = thread::spawn(move || {
+            let y = y.upgrade().unwrap();
+            assert_eq!(y.0, "hello");
+        });
+        t.join().unwrap();
+        assert_eq!(NUM_DROPS.load(Relaxed), 0);
+        assert!(z.upgrade().is_some());
+        drop(x);
+        assert_eq!(NUM_DROPS.load(Relaxed), 1);
+        assert!(z.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_get_mut() {
+        let mut x = packed::Arc::new(1);
+        *packed::Arc::get_mut(&mut x).unwrap() += 1;
+        let y = x.clone();
+        assert!(packed::Arc::get_mut(&mut x).is_none());
+        drop(y);
+        let w = packed::Arc::downgrade(&x);
+        assert!(packed::Arc::get_mut(&mut x).is_none());
+        drop(w);
+        assert_eq!(*packed::Arc::get_mut(&mut x).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_header_is_one_word() {
+        assert_eq!(size_of::<ArcData<()>>(), size_of::<usize>());
+    }
+
+    const OPS: u32 = 1_000_000;
+
+    fn time(op: impl Fn()) -> Duration {
+        let start = Instant::now();
+        (0..OPS).for_each(|_| op());
+        start.elapsed() / OPS
+    }
+
+    /// All threads cloning and dropping one `Arc`. Returns the time per
+    /// clone and drop.
+    fn contended<A: Clone + Sync>(arc: &A) -> Duration {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get()) as u32;
+        let start = Instant::now();
+        thread::scope(|s| {
+            for _ in 0..threads {
+                s.spawn(|| {
+                    for _ in 0..OPS / threads {
+                        drop(std::hint::black_box(arc.clone()));
+                    }
+                });
+            }
+        });
+        start.elapsed() / OPS
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_packed_arc`.
+    #[test]
+    #[ignore]
+    fn bench_packed_arc() {
+        println!("new and drop:");
+        println!(
+            "   better_weak: {:?}",
+            time(|| drop(std::hint::black_box(Arc::new(0))))
+        );
+        println!(
+            "        packed: {:?}",
+            time(|| drop(std::hint::black_box(packed::Arc::new(0))))
+        );
+        println!("clone and drop, on all threads:");
+        println!("   better_weak: {:?}", contended(&Arc::new(0)));
+        println!("        packed: {:?}", contended(&packed::Arc::new(0)));
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use crate::arc::packed::Arc;
+    use crate::sync_shim::atomic::AtomicUsize;
+    use crate::sync_shim::atomic::Ordering::Relaxed;
+    use crate::sync_shim::thread;
+
+    struct Data(AtomicUsize);
+
+    impl Drop for Data {
+        fn drop(&mut self) {
+            // Relaxed, so only the Arc's orderings make the store visible.
+            assert_eq!(self.0.load(Relaxed), 1);
+        }
+    }
+
+    /// Fails if the last drop, through the single-decrement path, doesn't
+    /// acquire the other drop.
+    #[test]
+    fn loom_last_drop_sees_all_uses() {
+        crate::sync_shim::model(|| {
+            let x = Arc::new(Data(AtomicUsize::new(0)));
+            let y = x.clone();
+            let t = thread::spawn(move || {
+                y.0.store(1, Relaxed);
+                drop(y);
+            });
+            drop(x);
+            t.join().unwrap();
+        });
+    }
+
+    /// A `Weak` upgrading or dropping while the last `Arc` goes: the data
+    /// and the allocation are each freed exactly once, and only after every
+    /// use.
+    #[test]
+    fn loom_upgrade_races_last_drop() {
+        crate::sync_shim::model(|| {
+            let x = Arc::new(Data(AtomicUsize::new(0)));
+            let w = Arc::downgrade(&x);
+            let t = thread::spawn(move || {
+                if let Some(y) = w.upgrade() {
+                    y.0.store(1, Relaxed);
+                }
+            });
+            x.0.store(1, Relaxed);
+            drop(x);
+            t.join().unwrap();
+        });
+    }
+}
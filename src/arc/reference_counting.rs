@@ -1,8 +1,11 @@
-mod basic {
-    use std::ops::Deref;
-    use std::ptr::NonNull;
-    use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
-    use std::sync::atomic::{fence, AtomicUsize};
+pub mod basic {
+    use alloc::boxed::Box;
+    use core::fmt;
+    use core::ops::Deref;
+    use core::ptr::NonNull;
+
+    use crate::sync_shim::atomic::Ordering::{Acquire, Relaxed, Release};
+    use crate::sync_shim::atomic::{fence, AtomicUsize};
 
     struct ArcData<T> {
         ref_count: AtomicUsize,
@@ -50,12 +53,21 @@ mod basic {
         }
     }
 
+    impl<T: fmt::Debug> fmt::Debug for Arc<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Arc")
+                .field("data", &**self)
+                .field("ref_count", &self.data().ref_count.load(Relaxed))
+                .finish()
+        }
+    }
+
     impl<T> Clone for Arc<T> {
         fn clone(&self) -> Self {
             // TODO: Handle overflows.
             let current_rc = self.data().ref_count.fetch_add(1, Relaxed);
             if current_rc > usize::MAX / 2 {
-                std::process::abort();
+                crate::arc::abort();
             }
             Self { ptr: self.ptr }
         }
@@ -74,6 +86,7 @@ mod basic {
     }
 
     #[test]
+    #[cfg(not(loom))]
     fn test() {
         static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
         struct DetectDrop;
@@ -82,9 +95,12 @@ mod basic {
                 NUM_DROPS.fetch_add(1, Relaxed);
             }
         }
+        // Fails when it's dropped, at the end, if the allocation is still
+        // around.
+        let leaks = crate::leak_check::LeakCheck::new();
         // Create two Arcs sharing an object containing a string
         // and a DetectDrop, to detect when it's dropped.
-        let x = Arc::new(("hello", DetectDrop));
+        let x = leaks.track(|| Arc::new(("hello", DetectDrop)));
         let y = x.clone();
         // Send x to another thread, and use it there.
         let t = std::thread::spawn(move || {
@@ -106,12 +122,17 @@ mod basic {
     }
 }
 
-mod with_weak {
-    use std::cell::UnsafeCell;
-    use std::ops::Deref;
-    use std::ptr::NonNull;
-    use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
-    use std::sync::atomic::{fence, AtomicUsize};
+pub mod with_weak {
+    use alloc::boxed::Box;
+    use core::cell::UnsafeCell;
+    use core::fmt;
+    use core::ops::Deref;
+    use core::ptr::NonNull;
+
+    use crate::platform::backoff::Backoff;
+    use crate::sync_shim::atomic::Ordering::{Acquire, Relaxed, Release};
+    use crate::sync_shim::atomic::{fence, AtomicUsize};
+    use crate::sync_shim::chaos_point;
 
     struct ArcData<T> {
         /// Number of `Arc`s.
@@ -176,6 +197,9 @@ mod with_weak {
 
         pub fn upgrade(&self) -> Option<Arc<T>> {
             let mut n = self.data().data_ref_count.load(Relaxed);
+            // Many threads upgrading at once mostly fail their CAS, and
+            // retrying right away only makes the next one fail too.
+            let mut backoff = Backoff::new();
             loop {
                 if n == 0 {
                     return None;
@@ -187,8 +211,10 @@ mod with_weak {
                         .compare_exchange_weak(n, n + 1, Relaxed, Relaxed)
                 {
                     n = e;
+                    backoff.spin();
                     continue;
                 }
+                chaos_point!("arc::upgrade");
                 return Some(Arc { weak: self.clone() });
             }
         }
@@ -205,10 +231,33 @@ mod with_weak {
         }
     }
 
+    impl<T: fmt::Debug> fmt::Debug for Arc<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let data = self.weak.data();
+            // Other threads can change the counts between the two loads,
+            // so the difference can come out negative.
+            let strong = data.data_ref_count.load(Relaxed);
+            f.debug_struct("Arc")
+                .field("data", &**self)
+                .field("strong", &strong)
+                .field(
+                    "weak",
+                    &data.alloc_ref_count.load(Relaxed).saturating_sub(strong),
+                )
+                .finish()
+        }
+    }
+
+    impl<T> fmt::Debug for Weak<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("(Weak)")
+        }
+    }
+
     impl<T> Clone for Weak<T> {
         fn clone(&self) -> Self {
             if self.data().alloc_ref_count.fetch_add(1, Relaxed) > usize::MAX / 2 {
-                std::process::abort();
+                crate::arc::abort();
             }
             Weak { ptr: self.ptr }
         }
@@ -218,7 +267,7 @@ mod with_weak {
         fn clone(&self) -> Self {
             let weak = self.weak.clone();
             if weak.data().data_ref_count.fetch_add(1, Relaxed) > usize::MAX / 2 {
-                std::process::abort();
+                crate::arc::abort();
             }
             Arc { weak }
         }
@@ -250,6 +299,7 @@ mod with_weak {
     }
 
     #[test]
+    #[cfg(not(loom))]
     fn test() {
         static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
         struct DetectDrop;
@@ -258,8 +308,9 @@ mod with_weak {
                 NUM_DROPS.fetch_add(1, Relaxed);
             }
         }
+        let leaks = crate::leak_check::LeakCheck::new();
         // Create an Arc with two weak pointers.
-        let x = Arc::new(("hello", DetectDrop));
+        let x = leaks.track(|| Arc::new(("hello", DetectDrop)));
         let y = Arc::downgrade(&x);
         let z = Arc::downgrade(&x);
         let t = std::thread::spawn(move || {
@@ -279,16 +330,83 @@ mod with_weak {
         // weak pointer should no longer be upgradable.
         assert_eq!(NUM_DROPS.load(Relaxed), 1);
         assert!(z.upgrade().is_none());
+        // Only the last weak pointer frees the allocation.
+        assert_eq!(leaks.live(), 1);
+        drop(z);
+        assert_eq!(leaks.live(), 0);
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_upgrade_contention`,
+    /// on a machine with a few cores to spare.
+    #[test]
+    #[ignore]
+    #[cfg(not(loom))]
+    fn bench_upgrade_contention() {
+        use std::sync::atomic::AtomicUsize;
+        use std::thread;
+        use std::time::{Duration, Instant};
+
+        use crate::platform::backoff::Backoff;
+
+        const OPS: u32 = 1_000_000;
+        let threads = thread::available_parallelism().map_or(2, |n| n.get()) as u32;
+        let on_all_threads = |op: &(dyn Fn() + Sync)| -> Duration {
+            let start = Instant::now();
+            thread::scope(|s| {
+                for _ in 0..threads {
+                    s.spawn(|| (0..OPS / threads).for_each(|_| op()));
+                }
+            });
+            start.elapsed() / OPS
+        };
+        // The loop in `upgrade`, on a bare counter, with and without the backoff.
+        let counter = AtomicUsize::new(1);
+        let increment = |backoff_on_failure: bool| {
+            let mut backoff = Backoff::new();
+            let mut n = counter.load(Relaxed);
+            while let Err(e) = counter.compare_exchange_weak(n, n + 1, Relaxed, Relaxed) {
+                n = e;
+                if backoff_on_failure {
+                    backoff.spin();
+                }
+            }
+        };
+        let arc = Arc::new(0);
+        let weak = Arc::downgrade(&arc);
+        println!("{threads} threads:");
+        println!(
+            " immediate retry: {:?}",
+            on_all_threads(&|| increment(false))
+        );
+        println!(
+            "         Backoff: {:?}",
+            on_all_threads(&|| increment(true))
+        );
+        println!(
+            "   Weak::upgrade: {:?} (with the drop)",
+            on_all_threads(&|| drop(weak.upgrade()))
+        );
     }
 }
 
-mod better_weak {
-    use std::cell::UnsafeCell;
-    use std::mem::ManuallyDrop;
-    use std::ops::Deref;
-    use std::ptr::NonNull;
-    use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
-    use std::sync::atomic::{fence, AtomicUsize};
+pub mod better_weak {
+    use alloc::boxed::Box;
+    use core::fmt;
+    use core::marker::PhantomData;
+    use core::mem::{ManuallyDrop, MaybeUninit};
+    use core::ops::Deref;
+    use core::ptr::{self, NonNull};
+
+    use crate::arc::drop_strategy;
+    use crate::errors::AllocError;
+    use crate::oom;
+    use crate::platform::backoff::Backoff;
+    #[cfg(feature = "stats")]
+    use crate::stats::{ArcRecorder, ArcStats};
+    use crate::sync_shim::atomic::Ordering::{Acquire, Relaxed, Release};
+    use crate::sync_shim::atomic::{fence, AtomicUsize};
+    use crate::sync_shim::{chaos_point, trace_event};
+    use core::task::{RawWaker, RawWakerVTable, Waker};
 
     struct ArcData<T> {
         /// Number of `Arc`s.
@@ -296,17 +414,99 @@ mod better_weak {
         /// Number of `Weak`s, plus one if there are any `Arc`s.
         alloc_ref_count: AtomicUsize,
         /// The data. Dropped if there are only weak pointers left.
-        data: UnsafeCell<ManuallyDrop<T>>,
+        ///
+        /// Not in an `UnsafeCell`, which would make `Arc<T>` invariant in
+        /// `T`. It's only changed through `ptr`, in `get_mut` and the last
+        /// `Arc`'s drop, when no references to it are left. So nothing
+        /// makes a `&ArcData<T>`, which would cover it: the counts are
+        /// reached through [`Counts`].
+        data: ManuallyDrop<T>,
+        #[cfg(feature = "stats")]
+        stats: ArcRecorder,
+    }
+
+    /// The parts of an `ArcData` that live as long as the allocation,
+    /// like std's `WeakInner`.
+    struct Counts<'a> {
+        data_ref_count: &'a AtomicUsize,
+        alloc_ref_count: &'a AtomicUsize,
+        #[cfg(feature = "stats")]
+        stats: &'a ArcRecorder,
+    }
+
+    /// Safety: `ptr` must point to an `ArcData` that lives for `'a`.
+    unsafe fn counts<'a, T>(ptr: NonNull<ArcData<T>>) -> Counts<'a> {
+        let ptr = ptr.as_ptr();
+        Counts {
+            data_ref_count: &*ptr::addr_of!((*ptr).data_ref_count),
+            alloc_ref_count: &*ptr::addr_of!((*ptr).alloc_ref_count),
+            #[cfg(feature = "stats")]
+            stats: &*ptr::addr_of!((*ptr).stats),
+        }
     }
 
+    /// Covariant in `T`, and, with the `nightly` feature, its drop can
+    /// outlive borrows in `T`, like std's `Arc`:
+    ///
+    /// ```
+    /// use atomics_and_locks::Arc;
+    ///
+    /// fn shorten<'a>(arc: Arc<&'static str>) -> Arc<&'a str> {
+    ///     arc
+    /// }
+    /// ```
+    ///
+    #[cfg_attr(feature = "nightly", doc = "```")]
+    #[cfg_attr(not(feature = "nightly"), doc = "```compile_fail,E0597")]
+    /// use atomics_and_locks::Arc;
+    ///
+    /// let (arc, s);
+    /// s = String::from("dropped before `arc`");
+    /// arc = Arc::new(&s);
+    /// ```
+    ///
+    /// Unless `T`'s own drop may use those borrows:
+    ///
+    /// ```compile_fail,E0597
+    /// use atomics_and_locks::Arc;
+    ///
+    /// struct Print<'a>(&'a String);
+    ///
+    /// impl Drop for Print<'_> {
+    ///     fn drop(&mut self) {
+    ///         println!("{}", self.0);
+    ///     }
+    /// }
+    ///
+    /// let (arc, s);
+    /// s = String::from("dropped before `arc`");
+    /// arc = Arc::new(Print(&s));
+    /// ```
+    ///
+    /// A `T` that's invariant itself stays invariant:
+    ///
+    /// ```compile_fail
+    /// use atomics_and_locks::Arc;
+    /// use std::cell::Cell;
+    ///
+    /// fn shorten<'a>(arc: Arc<Cell<&'static str>>) -> Arc<Cell<&'a str>> {
+    ///     arc
+    /// }
+    /// ```
     pub struct Arc<T> {
         ptr: NonNull<ArcData<T>>,
+        /// Tells the drop checker that dropping an `Arc<T>` may drop a `T`.
+        /// Not `ArcData<T>`, which only holds it in a `ManuallyDrop`.
+        phantom: PhantomData<T>,
     }
 
     unsafe impl<T: Sync + Send> Send for Arc<T> {}
 
     unsafe impl<T: Sync + Send> Sync for Arc<T> {}
 
+    /// Moving an `Arc` doesn't move the data, whatever `phantom` says.
+    impl<T> Unpin for Arc<T> {}
+
     pub struct Weak<T> {
         ptr: NonNull<ArcData<T>>,
     }
@@ -316,85 +516,254 @@ mod better_weak {
     unsafe impl<T: Sync + Send> Sync for Weak<T> {}
 
     impl<T> Arc<T> {
+        /// Does what the `OomPolicy` says if it can't allocate.
         pub fn new(data: T) -> Arc<T> {
-            Arc {
-                ptr: NonNull::from(Box::leak(Box::new(ArcData {
+            Self::try_new(data).unwrap_or_else(|e| oom::handle(e))
+        }
+
+        /// Returns an error instead of failing to allocate, dropping
+        /// `data`.
+        pub fn try_new(data: T) -> Result<Arc<T>, AllocError> {
+            // Allocated before `data` goes in, so it's dropped on failure.
+            let uninit = oom::try_box(MaybeUninit::uninit())?;
+            let data = Box::write(
+                uninit,
+                ArcData {
                     alloc_ref_count: AtomicUsize::new(1),
                     data_ref_count: AtomicUsize::new(1),
-                    data: UnsafeCell::new(ManuallyDrop::new(data)),
-                }))),
-            }
+                    data: ManuallyDrop::new(data),
+                    #[cfg(feature = "stats")]
+                    stats: ArcRecorder::new(),
+                },
+            );
+            Ok(Arc::from_ptr(NonNull::from(Box::leak(data))))
         }
 
-        fn data(&self) -> &ArcData<T> {
-            unsafe { self.ptr.as_ref() }
+        fn from_ptr(ptr: NonNull<ArcData<T>>) -> Arc<T> {
+            Arc {
+                ptr,
+                phantom: PhantomData,
+            }
         }
-    }
-
-    impl<T> Deref for Arc<T> {
-        type Target = T;
 
-        fn deref(&self) -> &T {
-            // Safety: Since there's an Arc to the data,
-            // the data exists and may be shared.
-            unsafe { &*self.data().data.get() }
+        fn counts(&self) -> Counts<'_> {
+            // Safety: The `Arc` keeps the allocation alive.
+            unsafe { counts(self.ptr) }
         }
-    }
 
-    impl<T> Weak<T> {
-        fn data(&self) -> &ArcData<T> {
-            unsafe { self.ptr.as_ref() }
+        /// The number of `Arc`s, which may change right after.
+        pub fn strong_count(arc: &Self) -> usize {
+            arc.counts().data_ref_count.load(Relaxed)
         }
 
-        pub fn upgrade(&self) -> Option<Arc<T>> {
-            let mut n = self.data().data_ref_count.load(Relaxed);
+        /// Adds `n` strong counts at once, for `from_reserved` to hand out
+        /// without touching the counter again.
+        ///
+        /// Unlike `clone`, this checks before adding: a large enough `n`
+        /// would wrap the counter around to a small value, which no later
+        /// check would catch.
+        pub(crate) fn reserve(arc: &Self, n: usize) {
+            let count = arc.counts().data_ref_count;
+            let mut old = count.load(Relaxed);
             loop {
-                if n == 0 {
-                    return None;
-                }
-                assert!(n < usize::MAX);
-                if let Err(e) =
-                    self.data()
-                        .data_ref_count
-                        .compare_exchange_weak(n, n + 1, Relaxed, Relaxed)
-                {
-                    n = e;
-                    continue;
+                let new = match old.checked_add(n) {
+                    Some(new) if new <= usize::MAX / 2 => new,
+                    _ => crate::arc::abort(),
+                };
+                match count.compare_exchange_weak(old, new, Relaxed, Relaxed) {
+                    Ok(_) => break,
+                    Err(e) => old = e,
                 }
-                return Some(Arc { ptr: self.ptr });
             }
+            #[cfg(feature = "stats")]
+            arc.counts().stats.strong(old + n);
+        }
+
+        /// # Safety
+        ///
+        /// Gives up one of the counts added by `reserve`.
+        pub(crate) unsafe fn from_reserved(arc: &Self) -> Self {
+            Arc::from_ptr(arc.ptr)
+        }
+
+        /// Gives back `n` counts added by `reserve`, at once.
+        ///
+        /// # Safety
+        ///
+        /// None of those may have been handed out, and `arc` itself keeps
+        /// the count above zero.
+        pub(crate) unsafe fn unreserve(arc: &Self, n: usize) {
+            // Release, like dropping those `Arc`s one by one would.
+            arc.counts().data_ref_count.fetch_sub(n, Release);
         }
 
         pub fn get_mut(arc: &mut Self) -> Option<&mut T> {
             // Acquire matches Weak::drop's Release decrement, to make sure any
             // upgraded pointers are visible in the next data_ref_count.load.
             if arc
-                .data()
+                .counts()
                 .alloc_ref_count
                 .compare_exchange(1, usize::MAX, Acquire, Relaxed)
                 .is_err()
             {
+                #[cfg(feature = "stats")]
+                arc.counts().stats.failed_get_muts.fetch_add(1, Relaxed);
                 return None;
             }
-            let is_unique = arc.data().data_ref_count.load(Relaxed) == 1;
+            let is_unique = arc.counts().data_ref_count.load(Relaxed) == 1;
             // Release matches Acquire increment in `downgrade`, to make sure any
             // changes to the data_ref_count that come after `downgrade` don't
             // change the is_unique result above.
-            arc.data().alloc_ref_count.store(1, Release);
+            arc.counts().alloc_ref_count.store(1, Release);
             if !is_unique {
+                #[cfg(feature = "stats")]
+                arc.counts().stats.failed_get_muts.fetch_add(1, Relaxed);
                 return None;
             }
             // Acquire to match Arc::drop's Release decrement, to make sure nothing
             // else is accessing the data.
             fence(Acquire);
-            unsafe { Some(&mut *arc.data().data.get()) }
+            // Through `ptr`, which may write.
+            unsafe { Some(&mut *ptr::addr_of_mut!((*arc.ptr.as_ptr()).data)) }
+        }
+
+        pub fn downgrade(arc: &Self) -> Weak<T> {
+            let mut n = arc.counts().alloc_ref_count.load(Relaxed);
+            loop {
+                // `get_mut` is checking for uniqueness.
+                if n == usize::MAX {
+                    core::hint::spin_loop();
+                    n = arc.counts().alloc_ref_count.load(Relaxed);
+                    continue;
+                }
+                if n > usize::MAX / 2 {
+                    crate::arc::abort();
+                }
+                // Acquire matches `get_mut`'s Release store.
+                if let Err(e) =
+                    arc.counts()
+                        .alloc_ref_count
+                        .compare_exchange_weak(n, n + 1, Acquire, Relaxed)
+                {
+                    n = e;
+                    continue;
+                }
+                return Weak { ptr: arc.ptr };
+            }
+        }
+
+        /// A snapshot of the statistics of the allocation, shared by all
+        /// its `Arc`s and `Weak`s, since it was created or last reset.
+        #[cfg(feature = "stats")]
+        pub fn stats(arc: &Self) -> ArcStats {
+            arc.counts().stats.snapshot()
+        }
+
+        #[cfg(feature = "stats")]
+        pub fn reset_stats(arc: &Self) {
+            arc.counts().stats.reset();
+        }
+
+        /// Panics if the counts can't be right while `arc` exists: no
+        /// strong count left for it, a count past where `clone` aborts, or
+        /// no weak count left for the `Arc`s. Between operations, that
+        /// catches a count that was decremented once too often while the
+        /// allocation is still around, before the last `Arc` frees it
+        /// under the others.
+        pub fn debug_validate(arc: &Self) {
+            let strong = arc.counts().data_ref_count.load(Relaxed);
+            assert!(strong != 0, "an Arc exists, but the strong count is 0");
+            assert!(
+                strong <= usize::MAX / 2,
+                "strong count overflowed: {strong}"
+            );
+            // usize::MAX while `get_mut` checks for uniqueness.
+            let weak = arc.counts().alloc_ref_count.load(Relaxed);
+            assert!(weak != 0, "an Arc exists, but the weak count is 0");
+            assert!(
+                weak <= usize::MAX / 2 || weak == usize::MAX,
+                "weak count overflowed: {weak}"
+            );
+        }
+    }
+
+    impl<T> Deref for Arc<T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            // Safety: Since there's an Arc to the data,
+            // the data exists and may be shared.
+            unsafe { &*ptr::addr_of!((*self.ptr.as_ptr()).data) }
+        }
+    }
+
+    impl<T: fmt::Debug> fmt::Debug for Arc<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            // The weak count is usize::MAX while `get_mut` checks for uniqueness.
+            let weak = match self.counts().alloc_ref_count.load(Relaxed) {
+                usize::MAX => 0,
+                n => n - 1,
+            };
+            f.debug_struct("Arc")
+                .field("data", &**self)
+                .field("strong", &self.counts().data_ref_count.load(Relaxed))
+                .field("weak", &weak)
+                .finish()
+        }
+    }
+
+    impl<T: Default> Default for Arc<T> {
+        fn default() -> Self {
+            Self::new(T::default())
+        }
+    }
+
+    impl<T> fmt::Debug for Weak<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("(Weak)")
+        }
+    }
+
+    impl<T> Weak<T> {
+        fn counts(&self) -> Counts<'_> {
+            // Safety: The `Weak` keeps the allocation alive.
+            unsafe { counts(self.ptr) }
+        }
+
+        pub fn upgrade(&self) -> Option<Arc<T>> {
+            let mut n = self.counts().data_ref_count.load(Relaxed);
+            // Many threads upgrading at once mostly fail their CAS, and
+            // retrying right away only makes the next one fail too.
+            let mut backoff = Backoff::new();
+            loop {
+                if n == 0 {
+                    return None;
+                }
+                assert!(n < usize::MAX);
+                if let Err(e) =
+                    self.counts()
+                        .data_ref_count
+                        .compare_exchange_weak(n, n + 1, Relaxed, Relaxed)
+                {
+                    #[cfg(feature = "stats")]
+                    self.counts().stats.failed_upgrades.fetch_add(1, Relaxed);
+                    n = e;
+                    backoff.spin();
+                    continue;
+                }
+                #[cfg(feature = "stats")]
+                self.counts().stats.strong(n + 1);
+                chaos_point!("arc::upgrade");
+                trace_event!(RefIncrement, self.ptr.as_ptr().addr(), Relaxed);
+                return Some(Arc::from_ptr(self.ptr));
+            }
         }
     }
 
     impl<T> Clone for Weak<T> {
         fn clone(&self) -> Self {
-            if self.data().alloc_ref_count.fetch_add(1, Relaxed) > usize::MAX / 2 {
-                std::process::abort();
+            if self.counts().alloc_ref_count.fetch_add(1, Relaxed) > usize::MAX / 2 {
+                crate::arc::abort();
             }
             Weak { ptr: self.ptr }
         }
@@ -402,8 +771,7 @@ mod better_weak {
 
     impl<T> Drop for Weak<T> {
         fn drop(&mut self) {
-            if self.data().alloc_ref_count.fetch_sub(1, Release) == 1 {
-                fence(Acquire);
+            if drop_strategy::release(self.counts().alloc_ref_count) {
                 unsafe {
                     drop(Box::from_raw(self.ptr.as_ptr()));
                 }
@@ -413,21 +781,42 @@ mod better_weak {
 
     impl<T> Clone for Arc<T> {
         fn clone(&self) -> Self {
-            if self.data().data_ref_count.fetch_add(1, Relaxed) > usize::MAX / 2 {
-                std::process::abort();
+            let old = self.counts().data_ref_count.fetch_add(1, Relaxed);
+            if old > usize::MAX / 2 {
+                crate::arc::abort();
             }
-            Arc { ptr: self.ptr }
+            #[cfg(feature = "stats")]
+            self.counts().stats.strong(old + 1);
+            trace_event!(RefIncrement, self.ptr.as_ptr().addr(), Relaxed);
+            Arc::from_ptr(self.ptr)
+        }
+    }
+
+    // Safety, with `may_dangle`: The drop only drops the `T`, which
+    // `phantom` tells the drop checker about, and doesn't otherwise use it.
+    #[cfg(feature = "nightly")]
+    unsafe impl<#[may_dangle] T> Drop for Arc<T> {
+        fn drop(&mut self) {
+            self.drop_ref();
         }
     }
 
+    #[cfg(not(feature = "nightly"))]
     impl<T> Drop for Arc<T> {
         fn drop(&mut self) {
-            if self.data().data_ref_count.fetch_sub(1, Release) == 1 {
-                fence(Acquire);
+            self.drop_ref();
+        }
+    }
+
+    impl<T> Arc<T> {
+        fn drop_ref(&mut self) {
+            trace_event!(RefDecrement, self.ptr.as_ptr().addr(), Release);
+            if drop_strategy::release(self.counts().data_ref_count) {
+                trace_event!(RefZero, self.ptr.as_ptr().addr(), Acquire);
                 // Safety: The data reference counter is zero,
                 // so nothing will access the data anymore.
                 unsafe {
-                    ManuallyDrop::drop(&mut *self.data().data.get());
+                    ManuallyDrop::drop(&mut *ptr::addr_of_mut!((*self.ptr.as_ptr()).data));
                 }
                 // Now that there's no `Arc<T>`s left,
                 // drop the implicit weak pointer that represented all `Arc<T>`s.
@@ -435,4 +824,189 @@ mod better_weak {
             }
         }
     }
+
+    /// Like `std::task::Wake`, but for our own `Arc`.
+    pub trait Wake: Sized {
+        fn wake(this: Arc<Self>);
+
+        fn wake_by_ref(this: &Arc<Self>) {
+            Self::wake(this.clone());
+        }
+    }
+
+    impl<W: Wake + Send + Sync + 'static> From<Arc<W>> for Waker {
+        fn from(arc: Arc<W>) -> Waker {
+            // Safety: The vtable functions below uphold the RawWaker contract,
+            // and `W: Send + Sync` makes it fine to wake from any thread.
+            unsafe { Waker::from_raw(raw_waker(arc)) }
+        }
+    }
+
+    /// The waker's data pointer is the `ArcData` pointer, which owns
+    /// one `data_ref_count`, just like an `Arc` would.
+    fn raw_waker<W: Wake + Send + Sync + 'static>(arc: Arc<W>) -> RawWaker {
+        let arc = ManuallyDrop::new(arc);
+        RawWaker::new(arc.ptr.cast().as_ptr(), &WakerVTable::<W>::VTABLE)
+    }
+
+    struct WakerVTable<W>(PhantomData<W>);
+
+    impl<W: Wake + Send + Sync + 'static> WakerVTable<W> {
+        const VTABLE: RawWakerVTable = RawWakerVTable::new(
+            Self::clone_waker,
+            Self::wake,
+            Self::wake_by_ref,
+            Self::drop_waker,
+        );
+
+        /// Safety: `data` must come from `raw_waker::<W>`, and its reference
+        /// must not have been given up yet.
+        unsafe fn borrow_arc(data: *const ()) -> ManuallyDrop<Arc<W>> {
+            ManuallyDrop::new(Arc::from_ptr(NonNull::new_unchecked(
+                data.cast_mut().cast(),
+            )))
+        }
+
+        unsafe fn clone_waker(data: *const ()) -> RawWaker {
+            raw_waker(Arc::clone(&Self::borrow_arc(data)))
+        }
+
+        unsafe fn wake(data: *const ()) {
+            W::wake(ManuallyDrop::into_inner(Self::borrow_arc(data)));
+        }
+
+        unsafe fn wake_by_ref(data: *const ()) {
+            W::wake_by_ref(&Self::borrow_arc(data));
+        }
+
+        unsafe fn drop_waker(data: *const ()) {
+            drop(ManuallyDrop::into_inner(Self::borrow_arc(data)));
+        }
+    }
+
+    #[test]
+    #[cfg(not(loom))]
+    fn test_waker_refcounting() {
+        static NUM_WAKES: AtomicUsize = AtomicUsize::new(0);
+        struct CountWakes;
+        impl Wake for CountWakes {
+            fn wake(_this: Arc<Self>) {
+                NUM_WAKES.fetch_add(1, Relaxed);
+            }
+        }
+        let leaks = crate::leak_check::LeakCheck::new();
+        let arc = leaks.track(|| Arc::new(CountWakes));
+        let waker = Waker::from(arc.clone());
+        assert_eq!(arc.counts().data_ref_count.load(Relaxed), 2);
+        let waker2 = waker.clone();
+        assert_eq!(arc.counts().data_ref_count.load(Relaxed), 3);
+        // The default wake_by_ref clones, wakes, and drops the clone.
+        waker.wake_by_ref();
+        assert_eq!(arc.counts().data_ref_count.load(Relaxed), 3);
+        waker.wake();
+        assert_eq!(arc.counts().data_ref_count.load(Relaxed), 2);
+        drop(waker2);
+        assert_eq!(arc.counts().data_ref_count.load(Relaxed), 1);
+        assert_eq!(NUM_WAKES.load(Relaxed), 2);
+    }
+
+    #[test]
+    #[cfg(not(loom))]
+    fn test_downgrade_and_get_mut() {
+        let leaks = crate::leak_check::LeakCheck::new();
+        let mut a = leaks.track(|| Arc::new(1));
+        let weak = Arc::downgrade(&a);
+        assert!(Arc::get_mut(&mut a).is_none());
+        assert_eq!(*weak.upgrade().unwrap(), 1);
+        drop(weak);
+        *Arc::get_mut(&mut a).unwrap() += 1;
+        assert_eq!(*a, 2);
+        let weak = Arc::downgrade(&a);
+        drop(a);
+        assert!(weak.upgrade().is_none());
+        drop(weak);
+        assert_eq!(leaks.live(), 0);
+    }
+
+    #[test]
+    #[cfg(not(loom))]
+    fn test_covariance() {
+        fn shorten<'a>(
+            arc: Arc<&'static str>,
+            weak: Weak<&'static str>,
+        ) -> (Arc<&'a str>, Weak<&'a str>) {
+            (arc, weak)
+        }
+        let s = String::from("short");
+        let arc = Arc::new("static");
+        let weak = Arc::downgrade(&arc);
+        let (mut arc, weak) = shorten(arc, weak);
+        drop(weak);
+        *Arc::get_mut(&mut arc).unwrap() = &s;
+        assert_eq!(*arc, "short");
+    }
+
+    #[test]
+    #[cfg(not(loom))]
+    fn test_upgrade_while_dropping() {
+        // Under Miri, dropping the data raced with a `Weak` that made a
+        // reference covering it.
+        const N: usize = if cfg!(miri) { 20 } else { 1000 };
+        let leaks = crate::leak_check::LeakCheck::new();
+        for _ in 0..N {
+            let arc = leaks.track(|| Arc::new(std::vec![1u8]));
+            let weak = Arc::downgrade(&arc);
+            std::thread::scope(|s| {
+                s.spawn(move || drop(arc));
+                s.spawn(move || {
+                    if let Some(arc) = weak.upgrade() {
+                        assert_eq!(*arc, [1]);
+                    }
+                    drop(weak);
+                });
+            });
+        }
+        assert_eq!(leaks.live(), 0);
+    }
+
+    #[test]
+    #[cfg(not(loom))]
+    fn test_debug_validate() {
+        let a = Arc::new(1);
+        let b = a.clone();
+        Arc::debug_validate(&a);
+        drop(a);
+        Arc::debug_validate(&b);
+        // A drop too many, with `b` still around.
+        b.counts().data_ref_count.fetch_sub(1, Relaxed);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            Arc::debug_validate(&b);
+        }));
+        assert!(result.is_err());
+        b.counts().data_ref_count.fetch_add(1, Relaxed);
+    }
+
+    #[test]
+    #[cfg(loom)]
+    fn loom_last_drop_sees_all_uses() {
+        use crate::sync_shim::thread;
+
+        crate::sync_shim::model(|| {
+            struct Data(AtomicUsize);
+            impl Drop for Data {
+                fn drop(&mut self) {
+                    // Relaxed, so only the Arc's orderings make the store visible.
+                    assert_eq!(self.0.load(Relaxed), 1);
+                }
+            }
+            let x = Arc::new(Data(AtomicUsize::new(0)));
+            let y = x.clone();
+            let t = thread::spawn(move || {
+                y.0.store(1, Relaxed);
+                drop(y);
+            });
+            drop(x);
+            t.join().unwrap();
+        });
+    }
 }
@@ -0,0 +1,192 @@
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync;
+use std::task::{Context, Poll, Waker};
+
+/// Lets a fixed number of tasks wait for each other.
+///
+/// The `wait()` futures resolve once `n` of them have arrived, after which
+/// the barrier starts over for the next round. A `Wait` that's dropped
+/// before its round completes doesn't count as arrived.
+pub struct Barrier {
+    n: usize,
+    state: sync::Mutex<State>,
+}
+
+struct State {
+    /// The tasks waiting in the current round.
+    waiters: Vec<(u64, Waker)>,
+    /// Bumped every time a round completes.
+    generation: u64,
+    next_id: u64,
+}
+
+pub struct Wait<'a> {
+    barrier: &'a Barrier,
+    /// The round we arrived in, and our id in it, once we have arrived.
+    arrived: Option<(u64, u64)>,
+}
+
+/// Exactly one of the tasks in each round is the leader.
+#[derive(Debug)]
+pub struct BarrierWaitResult(bool);
+
+impl Barrier {
+    /// A barrier for zero tasks behaves like one for a single task.
+    pub const fn new(n: usize) -> Self {
+        Self {
+            n,
+            state: sync::Mutex::new(State {
+                waiters: Vec::new(),
+                generation: 0,
+                next_id: 0,
+            }),
+        }
+    }
+
+    pub fn wait(&self) -> Wait<'_> {
+        Wait {
+            barrier: self,
+            arrived: None,
+        }
+    }
+}
+
+impl Future for Wait<'_> {
+    type Output = BarrierWaitResult;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<BarrierWaitResult> {
+        let barrier = self.barrier;
+        let mut state = barrier.state.lock().unwrap();
+        if let Some((generation, id)) = self.arrived {
+            if state.generation != generation {
+                self.arrived = None;
+                return Poll::Ready(BarrierWaitResult(false));
+            }
+            if let Some((_, waker)) = state.waiters.iter_mut().find(|(i, _)| *i == id) {
+                waker.clone_from(cx.waker());
+            }
+            return Poll::Pending;
+        }
+        if state.waiters.len() + 1 >= barrier.n {
+            // We're the last one to arrive: release everyone and start over.
+            state.generation += 1;
+            let waiters = std::mem::take(&mut state.waiters);
+            drop(state);
+            waiters.into_iter().for_each(|(_, waker)| waker.wake());
+            return Poll::Ready(BarrierWaitResult(true));
+        }
+        let id = state.next_id;
+        state.next_id += 1;
+        state.waiters.push((id, cx.waker().clone()));
+        self.arrived = Some((state.generation, id));
+        Poll::Pending
+    }
+}
+
+impl Drop for Wait<'_> {
+    fn drop(&mut self) {
+        let Some((generation, id)) = self.arrived else {
+            return;
+        };
+        let mut state = self.barrier.state.lock().unwrap();
+        if state.generation == generation {
+            state.waiters.retain(|(i, _)| *i != id);
+        }
+    }
+}
+
+impl BarrierWaitResult {
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+impl fmt::Debug for Barrier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("Barrier");
+        d.field("n", &self.n);
+        match self.state.try_lock() {
+            Ok(state) => d
+                .field("waiting", &state.waiters.len())
+                .field("generation", &state.generation),
+            Err(_) => d.field("waiting", &format_args!("<locked>")),
+        };
+        d.finish()
+    }
+}
+
+impl fmt::Debug for Wait<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Wait")
+            .field("arrived", &self.arrived.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::task::{Context, Waker};
+    use std::thread;
+
+    use crate::async_sync::barrier::Barrier;
+    use crate::exec::executor::block_on;
+
+    #[test]
+    fn test_releases_when_all_arrived() {
+        let barrier = Barrier::new(3);
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut a = pin!(barrier.wait());
+        let mut b = pin!(barrier.wait());
+        assert!(a.as_mut().poll(&mut cx).is_pending());
+        assert!(b.as_mut().poll(&mut cx).is_pending());
+        assert!(a.as_mut().poll(&mut cx).is_pending());
+        let leader = block_on(barrier.wait());
+        assert!(leader.is_leader());
+        assert!(!block_on(a).is_leader());
+        assert!(!block_on(b).is_leader());
+    }
+
+    #[test]
+    fn test_dropped_wait_does_not_count() {
+        let barrier = Barrier::new(2);
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut gone = Box::pin(barrier.wait());
+        assert!(gone.as_mut().poll(&mut cx).is_pending());
+        drop(gone);
+        let mut a = pin!(barrier.wait());
+        assert!(a.as_mut().poll(&mut cx).is_pending());
+        assert!(block_on(barrier.wait()).is_leader());
+        assert!(a.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn test_reuse_over_many_rounds() {
+        let barrier = Barrier::new(4);
+        let leaders = AtomicUsize::new(0);
+        let arrived = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    block_on(async {
+                        for round in 0..100 {
+                            arrived.fetch_add(1, Relaxed);
+                            if barrier.wait().await.is_leader() {
+                                leaders.fetch_add(1, Relaxed);
+                            }
+                            // Nobody can be a round ahead of us.
+                            assert!(arrived.load(Relaxed) >= (round + 1) * 4);
+                            barrier.wait().await;
+                        }
+                    })
+                });
+            }
+        });
+        assert_eq!(leaders.load(Relaxed), 100);
+    }
+}
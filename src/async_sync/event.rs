@@ -0,0 +1,251 @@
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync;
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, Thread};
+
+use crate::sync_shim::plain_atomic::AtomicBool;
+use crate::sync_shim::plain_atomic::Ordering::{Acquire, Release};
+
+/// A manual-reset event that both tasks and threads can wait for.
+///
+/// Once `set`, every `wait()` future and `wait_sync()` call completes right
+/// away, until the event is `reset` again. Everyone waiting at the time of
+/// a `set` is released, even if the event is reset before they get to run.
+pub struct AsyncEvent {
+    set: AtomicBool,
+    state: sync::Mutex<State>,
+}
+
+struct State {
+    waiters: Vec<(u64, Waiter)>,
+    /// Bumped by every `set`, so waiters can tell they were released.
+    generation: u64,
+    next_id: u64,
+}
+
+enum Waiter {
+    Task(Waker),
+    Thread(Thread),
+}
+
+pub struct Wait<'a> {
+    event: &'a AsyncEvent,
+    /// The generation we started waiting in, and our id, once registered.
+    registered: Option<(u64, u64)>,
+}
+
+impl AsyncEvent {
+    pub const fn new() -> Self {
+        Self {
+            set: AtomicBool::new(false),
+            state: sync::Mutex::new(State {
+                waiters: Vec::new(),
+                generation: 0,
+                next_id: 0,
+            }),
+        }
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.set.load(Acquire)
+    }
+
+    pub fn set(&self) {
+        self.set.store(true, Release);
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+        let waiters = std::mem::take(&mut state.waiters);
+        drop(state);
+        for (_, waiter) in waiters {
+            match waiter {
+                Waiter::Task(waker) => waker.wake(),
+                Waiter::Thread(thread) => thread.unpark(),
+            }
+        }
+    }
+
+    pub fn reset(&self) {
+        self.set.store(false, Release);
+    }
+
+    pub fn wait(&self) -> Wait<'_> {
+        Wait {
+            event: self,
+            registered: None,
+        }
+    }
+
+    /// Blocks the current thread until the event is set.
+    pub fn wait_sync(&self) {
+        if self.is_set() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        // Check again, in case it was set before we took the lock.
+        if self.is_set() {
+            return;
+        }
+        let generation = state.generation;
+        let id = state.next_id;
+        state.next_id += 1;
+        state.waiters.push((id, Waiter::Thread(thread::current())));
+        drop(state);
+        loop {
+            thread::park();
+            // Unparks can be spurious, so only `set` bumping the generation counts.
+            let mut state = self.state.lock().unwrap();
+            if state.generation != generation {
+                return;
+            }
+            if self.is_set() {
+                state.waiters.retain(|(i, _)| *i != id);
+                return;
+            }
+        }
+    }
+}
+
+impl Default for AsyncEvent {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Future for Wait<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let event = self.event;
+        if event.is_set() {
+            self.unregister();
+            return Poll::Ready(());
+        }
+        let mut state = event.state.lock().unwrap();
+        match self.registered {
+            Some((generation, _)) if state.generation != generation => {
+                self.registered = None;
+                Poll::Ready(())
+            }
+            Some((_, id)) => {
+                if let Some((_, Waiter::Task(waker))) =
+                    state.waiters.iter_mut().find(|(i, _)| *i == id)
+                {
+                    waker.clone_from(cx.waker());
+                }
+                Poll::Pending
+            }
+            None => {
+                // Check again, in case it was set before we took the lock.
+                if event.is_set() {
+                    return Poll::Ready(());
+                }
+                let id = state.next_id;
+                state.next_id += 1;
+                state.waiters.push((id, Waiter::Task(cx.waker().clone())));
+                self.registered = Some((state.generation, id));
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Wait<'_> {
+    /// Forgets our registration, if we still have one.
+    fn unregister(&mut self) {
+        let Some((generation, id)) = self.registered.take() else {
+            return;
+        };
+        let mut state = self.event.state.lock().unwrap();
+        if state.generation == generation {
+            state.waiters.retain(|(i, _)| *i != id);
+        }
+    }
+}
+
+impl Drop for Wait<'_> {
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
+
+impl fmt::Debug for AsyncEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("AsyncEvent");
+        d.field("set", &self.is_set());
+        match self.state.try_lock() {
+            Ok(state) => d.field("waiters", &state.waiters.len()),
+            Err(_) => d.field("waiters", &format_args!("<locked>")),
+        };
+        d.finish()
+    }
+}
+
+impl fmt::Debug for Wait<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Wait")
+            .field("registered", &self.registered.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Waker};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::async_sync::event::AsyncEvent;
+    use crate::exec::executor::block_on;
+
+    #[test]
+    fn test_set_and_reset() {
+        let event = AsyncEvent::new();
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(pin!(event.wait()).poll(&mut cx).is_pending());
+        event.set();
+        assert!(event.is_set());
+        assert!(pin!(event.wait()).poll(&mut cx).is_ready());
+        event.wait_sync();
+        event.reset();
+        assert!(pin!(event.wait()).poll(&mut cx).is_pending());
+    }
+
+    #[test]
+    fn test_waiter_released_by_set_then_reset() {
+        let event = AsyncEvent::new();
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut wait = pin!(event.wait());
+        assert!(wait.as_mut().poll(&mut cx).is_pending());
+        event.set();
+        event.reset();
+        assert!(wait.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn test_threads_and_tasks_wait_together() {
+        let event = AsyncEvent::new();
+        thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| event.wait_sync());
+                s.spawn(|| block_on(event.wait()));
+            }
+            thread::sleep(Duration::from_millis(10));
+            event.set();
+        });
+        assert!(event.state.lock().unwrap().waiters.is_empty());
+    }
+
+    #[test]
+    fn test_static() {
+        static EVENT: AsyncEvent = AsyncEvent::new();
+        thread::scope(|s| {
+            s.spawn(|| block_on(EVENT.wait()));
+            s.spawn(|| EVENT.wait_sync());
+            EVENT.set();
+        });
+    }
+}
@@ -0,0 +1,390 @@
+//! A lock per key, without a `Mutex` per key.
+//!
+//! Locking a key puts it in a map, and unlocking it takes it out again
+//! unless someone is waiting for it, so there's only an entry for the keys
+//! that are locked right now. The map is split into shards by the hash of
+//! the key, each with its own `std::sync::Mutex` that's only held to look
+//! up the entry, so locking different keys rarely contends even when they
+//! end up in the same shard.
+//!
+//! Waiters for a key queue up like for the async [`Mutex`], and are handed
+//! the key in FIFO order. Blocking a thread on a key runs the same future
+//! with [`block_on`], so the async and the blocking side share the queue.
+//!
+//! [`Mutex`]: crate::async_sync::mutex::Mutex
+
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::hash::{BuildHasher, Hash};
+use std::pin::Pin;
+use std::sync;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use crate::cache_padded::CachePadded;
+use crate::errors::LockTimeout;
+use crate::exec::executor::{block_on, block_on_until};
+
+/// How many shards `LockMap::new` makes.
+pub const DEFAULT_SHARDS: usize = 64;
+
+/// Hands out a lock for every key: locking the same key serializes,
+/// different keys don't wait for each other.
+pub struct LockMap<K, S = RandomState> {
+    shards: Box<[CachePadded<sync::Mutex<Shard<K>>>]>,
+    hasher: S,
+}
+
+struct Shard<K> {
+    /// The keys that are locked.
+    locked: HashMap<K, Waiters>,
+    next_id: u64,
+}
+
+#[derive(Default)]
+struct Waiters {
+    queue: VecDeque<(u64, Waker)>,
+    /// The waiter that was handed the key but hasn't been polled since.
+    handed_to: Option<u64>,
+}
+
+/// Holds the lock of a key, and unlocks it when dropped.
+pub struct KeyGuard<'a, K: Hash + Eq, S: BuildHasher = RandomState> {
+    map: &'a LockMap<K, S>,
+    key: K,
+}
+
+pub struct LockKey<'a, K: Hash + Eq, S: BuildHasher = RandomState> {
+    map: &'a LockMap<K, S>,
+    /// Moved into the guard once we have the lock.
+    key: Option<K>,
+    /// Our place in the waiter queue of the key, once we have one.
+    id: Option<u64>,
+}
+
+// Never pinned structurally: the key is only moved out by value.
+impl<K: Hash + Eq, S: BuildHasher> Unpin for LockKey<'_, K, S> {}
+
+impl<K: Hash + Eq + Clone> LockMap<K> {
+    pub fn new() -> Self {
+        Self::with_shards(DEFAULT_SHARDS)
+    }
+
+    /// Fewer shards take less memory, more make it less likely that
+    /// threads locking different keys contend on a shard.
+    pub fn with_shards(shards: usize) -> Self {
+        Self::with_shards_and_hasher(shards, RandomState::new())
+    }
+}
+
+impl<K: Hash + Eq + Clone, S: BuildHasher> LockMap<K, S> {
+    pub fn with_shards_and_hasher(shards: usize, hasher: S) -> Self {
+        assert!(shards > 0, "a LockMap needs at least one shard");
+        Self {
+            shards: (0..shards)
+                .map(|_| {
+                    CachePadded::new(sync::Mutex::new(Shard {
+                        locked: HashMap::new(),
+                        next_id: 0,
+                    }))
+                })
+                .collect(),
+            hasher,
+        }
+    }
+
+    /// Blocks the thread until `key` is unlocked, and locks it.
+    pub fn lock(&self, key: K) -> KeyGuard<'_, K, S> {
+        block_on(self.lock_async(key))
+    }
+
+    /// Waits for `key` to be unlocked without blocking the thread. Wrap it
+    /// in `exec::timer::timeout` to give up after a while: dropping the
+    /// future leaves the queue, or passes the key on if it was handed it.
+    pub fn lock_async(&self, key: K) -> LockKey<'_, K, S> {
+        LockKey {
+            map: self,
+            key: Some(key),
+            id: None,
+        }
+    }
+
+    /// Fails if `key` is locked, or if others are already waiting for it.
+    pub fn try_lock(&self, key: K) -> Option<KeyGuard<'_, K, S>> {
+        let mut shard = self.shard(&key);
+        if shard.locked.contains_key(&key) {
+            return None;
+        }
+        shard.locked.insert(key.clone(), Waiters::default());
+        Some(KeyGuard { map: self, key })
+    }
+
+    /// Blocks for at most `timeout` waiting for `key`.
+    pub fn try_lock_for(
+        &self,
+        key: K,
+        timeout: Duration,
+    ) -> Result<KeyGuard<'_, K, S>, LockTimeout> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.try_lock_until(key, deadline),
+            None => Ok(self.lock(key)),
+        }
+    }
+
+    /// Blocks until `deadline` at most waiting for `key`.
+    pub fn try_lock_until(
+        &self,
+        key: K,
+        deadline: Instant,
+    ) -> Result<KeyGuard<'_, K, S>, LockTimeout> {
+        block_on_until(self.lock_async(key), deadline).ok_or(LockTimeout)
+    }
+}
+
+impl<K: Hash + Eq, S: BuildHasher> LockMap<K, S> {
+    fn shard(&self, key: &K) -> sync::MutexGuard<'_, Shard<K>> {
+        let n = self.hasher.hash_one(key) as usize % self.shards.len();
+        self.shards[n].lock().unwrap()
+    }
+
+    pub fn is_locked(&self, key: &K) -> bool {
+        self.shard(key).locked.contains_key(key)
+    }
+
+    /// How many keys are locked right now, counted one shard at a time.
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|s| s.lock().unwrap().locked.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn unlock(&self, key: &K) {
+        let mut shard = self.shard(key);
+        let waiters = shard
+            .locked
+            .get_mut(key)
+            .expect("unlocked key isn't locked");
+        match waiters.queue.pop_front() {
+            Some((id, waker)) => {
+                // Leave the entry: the key now belongs to this waiter.
+                waiters.handed_to = Some(id);
+                drop(shard);
+                waker.wake();
+            }
+            None => {
+                shard.locked.remove(key);
+            }
+        }
+    }
+}
+
+impl<'a, K: Hash + Eq + Clone, S: BuildHasher> Future for LockKey<'a, K, S> {
+    type Output = KeyGuard<'a, K, S>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<KeyGuard<'a, K, S>> {
+        let map = self.map;
+        let key = self.key.as_ref().expect("LockKey polled after completion");
+        let mut shard = map.shard(key);
+        let Shard { locked, next_id } = &mut *shard;
+        match (self.id, locked.get_mut(key)) {
+            (None, None) => {
+                locked.insert(key.clone(), Waiters::default());
+                drop(shard);
+                let key = self.key.take().unwrap();
+                Poll::Ready(KeyGuard { map, key })
+            }
+            (None, Some(waiters)) => {
+                let id = *next_id;
+                *next_id += 1;
+                waiters.queue.push_back((id, cx.waker().clone()));
+                drop(shard);
+                self.id = Some(id);
+                Poll::Pending
+            }
+            (Some(id), Some(waiters)) if waiters.handed_to == Some(id) => {
+                waiters.handed_to = None;
+                drop(shard);
+                self.id = None;
+                let key = self.key.take().unwrap();
+                Poll::Ready(KeyGuard { map, key })
+            }
+            (Some(id), Some(waiters)) => {
+                if let Some((_, waker)) = waiters.queue.iter_mut().find(|(i, _)| *i == id) {
+                    waker.clone_from(cx.waker());
+                }
+                Poll::Pending
+            }
+            // The entry stays while anyone is queued for it.
+            (Some(_), None) => unreachable!("queued for a key that isn't locked"),
+        }
+    }
+}
+
+impl<K: Hash + Eq, S: BuildHasher> Drop for LockKey<'_, K, S> {
+    fn drop(&mut self) {
+        let (Some(id), Some(key)) = (self.id, &self.key) else {
+            return;
+        };
+        let mut shard = self.map.shard(key);
+        let waiters = shard.locked.get_mut(key).unwrap();
+        if waiters.handed_to == Some(id) {
+            // We were given the key, but won't use it: pass it on.
+            waiters.handed_to = None;
+            drop(shard);
+            self.map.unlock(key);
+        } else {
+            waiters.queue.retain(|(i, _)| *i != id);
+        }
+    }
+}
+
+impl<K: Hash + Eq, S: BuildHasher> KeyGuard<'_, K, S> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+}
+
+impl<K: Hash + Eq, S: BuildHasher> Drop for KeyGuard<'_, K, S> {
+    fn drop(&mut self) {
+        self.map.unlock(&self.key);
+    }
+}
+
+impl<K: Hash + Eq + Clone> Default for LockMap<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, S> fmt::Debug for LockMap<K, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LockMap")
+            .field("shards", &self.shards.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K: Hash + Eq + fmt::Debug, S: BuildHasher> fmt::Debug for KeyGuard<'_, K, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyGuard").field("key", &self.key).finish()
+    }
+}
+
+impl<K: Hash + Eq, S: BuildHasher> fmt::Debug for LockKey<'_, K, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LockKey")
+            .field("queued", &self.id.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::task::{Context, Poll, Waker};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::async_sync::lock_map::{KeyGuard, LockKey, LockMap};
+    use crate::errors::LockTimeout;
+
+    #[test]
+    fn test_same_key_serializes() {
+        const N: usize = if cfg!(miri) { 20 } else { 1000 };
+        // Few shards, so the keys share them.
+        let map = LockMap::with_shards(2);
+        let inside = [const { AtomicBool::new(false) }; 4];
+        thread::scope(|s| {
+            for t in 0..8 {
+                let (map, inside) = (&map, &inside);
+                s.spawn(move || {
+                    for i in 0..N {
+                        let key = (t + i) % 4;
+                        let guard = map.lock(key);
+                        assert_eq!(*guard.key(), key);
+                        assert!(!inside[key].swap(true, Relaxed));
+                        thread::yield_now();
+                        inside[key].store(false, Relaxed);
+                    }
+                });
+            }
+        });
+        // Unlocked keys leave nothing behind.
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn test_different_keys_dont_wait() {
+        let map = LockMap::with_shards(1);
+        let a = map.lock("a");
+        assert!(map.is_locked(&"a") && !map.is_locked(&"b"));
+        thread::scope(|s| {
+            s.spawn(|| drop(map.lock("b"))).join().unwrap();
+            let waiter = s.spawn(|| drop(map.lock("a")));
+            thread::sleep(Duration::from_millis(10));
+            assert!(!waiter.is_finished());
+            assert!(map.try_lock("a").is_none());
+            drop(a);
+        });
+        assert_eq!(map.len(), 0);
+    }
+
+    fn poll<'a>(lock: &mut LockKey<'a, i32>) -> Poll<KeyGuard<'a, i32>> {
+        Pin::new(lock).poll(&mut Context::from_waker(Waker::noop()))
+    }
+
+    #[test]
+    fn test_async_waiters_are_served_in_order() {
+        let map = LockMap::new();
+        let guard = map.try_lock(1).unwrap();
+        let mut first = map.lock_async(1);
+        let mut second = map.lock_async(1);
+        let mut third = map.lock_async(1);
+        assert!(poll(&mut first).is_pending());
+        assert!(poll(&mut second).is_pending());
+        assert!(poll(&mut third).is_pending());
+        // Another key is free all along.
+        assert!(map.try_lock(2).is_some());
+        drop(guard);
+        // Handed to `first`, which gives it up, so `second` gets it next.
+        assert!(map.try_lock(1).is_none());
+        drop(first);
+        let Poll::Ready(guard) = poll(&mut second) else {
+            panic!("second waiter wasn't handed the key");
+        };
+        assert!(poll(&mut third).is_pending());
+        drop(guard);
+        assert!(poll(&mut third).is_ready());
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn test_timeout() {
+        let map = LockMap::new();
+        let guard = map.lock("entity");
+        thread::scope(|s| {
+            s.spawn(|| {
+                let err = map.try_lock_for("entity", Duration::from_millis(10));
+                assert_eq!(err.unwrap_err(), LockTimeout);
+            })
+            .join()
+            .unwrap();
+            let waiter = s.spawn(|| map.try_lock_for("entity", Duration::from_secs(10)).is_ok());
+            thread::sleep(Duration::from_millis(10));
+            drop(guard);
+            assert!(waiter.join().unwrap());
+        });
+        assert!(map.try_lock_for("entity", Duration::MAX).is_ok());
+        assert!(map.is_empty());
+    }
+}
@@ -0,0 +1,7 @@
+pub mod barrier;
+pub mod event;
+pub mod lock_map;
+pub mod mutex;
+pub mod notify;
+pub mod rwlock;
+pub mod semaphore;
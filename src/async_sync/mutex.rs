@@ -0,0 +1,440 @@
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::future::Future;
+use std::marker::{PhantomData, PhantomPinned};
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::ptr::NonNull;
+use std::sync;
+use std::task::{Context, Poll, Waker};
+
+use crate::exclusive::Exclusive;
+
+/// A mutex whose `lock()` is a future instead of blocking the thread.
+///
+/// Waiters are served in FIFO order: unlocking hands the lock directly to
+/// the longest waiting `lock()` future, so a task that keeps relocking
+/// can't barge ahead of the queue.
+///
+/// The queue is an intrusive list through the waiting `Lock` futures
+/// themselves, so waiting doesn't allocate. That's why `Lock` isn't
+/// `Unpin`: once polled, the list points into it.
+pub struct Mutex<T> {
+    state: sync::Mutex<State>,
+    value: UnsafeCell<T>,
+}
+
+struct State {
+    locked: bool,
+    /// The longest waiting `Lock`.
+    head: Option<NonNull<Waiter>>,
+    tail: Option<NonNull<Waiter>>,
+}
+
+// Safety: The waiters are only used while holding the lock around the
+// `State`, and every `Lock` takes itself off the list before it's gone.
+unsafe impl Send for State {}
+
+/// A `Lock`'s node in the list. Only used while holding the lock around
+/// the `State`.
+struct Waiter {
+    waker: Option<Waker>,
+    prev: Option<NonNull<Waiter>>,
+    next: Option<NonNull<Waiter>>,
+    /// Set by `unlock` when it takes this waiter off the list and hands
+    /// it the lock.
+    handed: bool,
+    /// The list points here, so it must stay put, and a `&mut` to it must
+    /// not claim to be the only way to reach it.
+    _pinned: PhantomPinned,
+}
+
+// Safety: Like `State`'s, its pointers are only followed while holding the
+// lock around the `State`.
+unsafe impl Send for Waiter {}
+
+unsafe impl<T> Sync for Mutex<T> where T: Send {}
+
+/// `Send` if `T` is, to hold it across an `.await`, but only `Sync` if `T`
+/// is too:
+///
+/// ```compile_fail,E0277
+/// use std::cell::Cell;
+///
+/// use atomics_and_locks::async_sync::mutex::MutexGuard;
+///
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<MutexGuard<'_, Cell<i32>>>();
+/// ```
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+    /// Shared, it would be `Sync` whenever the mutex is, which only takes
+    /// `T: Send`: see the impls below.
+    not_sync: PhantomData<*const ()>,
+}
+
+// Safety: Unlocking from another thread is fine, so it's `Send` like a
+// `&mut T`.
+unsafe impl<T: Send> Send for MutexGuard<'_, T> {}
+
+// Safety: A `&MutexGuard` only gives a `&T`.
+unsafe impl<T: Sync> Sync for MutexGuard<'_, T> {}
+
+pub struct Lock<'a, T> {
+    mutex: &'a Mutex<T>,
+    /// Other threads only reach it through the list, and this future only
+    /// through `&mut self`, so it doesn't keep a `&Lock` from being shared.
+    waiter: Exclusive<UnsafeCell<Waiter>>,
+    /// Whether `waiter` is on the list, or was handed the lock and
+    /// hasn't been polled since. Only this future changes it.
+    waiting: bool,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: sync::Mutex::new(State {
+                locked: false,
+                head: None,
+                tail: None,
+            }),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> Lock<'_, T> {
+        Lock {
+            mutex: self,
+            waiter: Exclusive::new(UnsafeCell::new(Waiter {
+                waker: None,
+                prev: None,
+                next: None,
+                handed: false,
+                _pinned: PhantomPinned,
+            })),
+            waiting: false,
+        }
+    }
+
+    /// Fails if the lock is held, or if others are already waiting for it.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let mut state = self.state.lock().unwrap();
+        if state.locked {
+            return None;
+        }
+        state.locked = true;
+        Some(self.guard())
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    fn guard(&self) -> MutexGuard<'_, T> {
+        MutexGuard {
+            mutex: self,
+            not_sync: PhantomData,
+        }
+    }
+
+    fn unlock(&self) {
+        let mut state = self.state.lock().unwrap();
+        match state.pop_front() {
+            Some(waiter) => {
+                // Leave `locked` set: the lock now belongs to this waiter.
+                // Safety: We hold the lock, and it was on the list, so its
+                // `Lock` is still there. It may be gone once we let go.
+                let waker = unsafe {
+                    let waiter = &mut *waiter.as_ptr();
+                    waiter.handed = true;
+                    waiter.waker.take()
+                };
+                drop(state);
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+            }
+            None => state.locked = false,
+        }
+    }
+}
+
+impl State {
+    /// Safety: The lock around `self` must be held, `waiter` must not be on
+    /// the list, and must stay in place until it's off it again.
+    unsafe fn push_back(&mut self, waiter: NonNull<Waiter>) {
+        (*waiter.as_ptr()).prev = self.tail;
+        (*waiter.as_ptr()).next = None;
+        match self.tail {
+            Some(tail) => (*tail.as_ptr()).next = Some(waiter),
+            None => self.head = Some(waiter),
+        }
+        self.tail = Some(waiter);
+    }
+
+    /// Safety: The lock around `self` must be held, and `waiter` must be on
+    /// the list.
+    unsafe fn remove(&mut self, waiter: NonNull<Waiter>) {
+        let (prev, next) = {
+            let waiter = &mut *waiter.as_ptr();
+            (waiter.prev.take(), waiter.next.take())
+        };
+        match prev {
+            Some(prev) => (*prev.as_ptr()).next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(next) => (*next.as_ptr()).prev = prev,
+            None => self.tail = prev,
+        }
+    }
+
+    /// Must be called with the lock around `self` held.
+    fn pop_front(&mut self) -> Option<NonNull<Waiter>> {
+        let head = self.head?;
+        // Safety: It's the head of the list.
+        unsafe { self.remove(head) };
+        Some(head)
+    }
+}
+
+impl<'a, T> Future for Lock<'a, T> {
+    type Output = MutexGuard<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<MutexGuard<'a, T>> {
+        // Safety: Nothing is moved out of it.
+        let this = unsafe { self.get_unchecked_mut() };
+        let mutex = this.mutex;
+        let waiter = this.waiter.get_mut().get();
+        let mut state = mutex.state.lock().unwrap();
+        if !this.waiting {
+            // `unlock` hands the lock over instead of releasing it while
+            // anyone is queued, so an unlocked mutex has no waiters to overtake.
+            if !state.locked {
+                state.locked = true;
+                return Poll::Ready(mutex.guard());
+            }
+            // Safety: We hold the lock, it isn't on the list yet, and it's
+            // pinned, and takes itself off the list when it's dropped.
+            unsafe {
+                (*waiter).waker = Some(cx.waker().clone());
+                state.push_back(NonNull::new_unchecked(waiter));
+            }
+            this.waiting = true;
+            return Poll::Pending;
+        }
+        // Safety: We hold the lock.
+        let waiter = unsafe { &mut *waiter };
+        if waiter.handed {
+            waiter.handed = false;
+            this.waiting = false;
+            return Poll::Ready(mutex.guard());
+        }
+        match &mut waiter.waker {
+            Some(waker) => waker.clone_from(cx.waker()),
+            None => waiter.waker = Some(cx.waker().clone()),
+        }
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Lock<'_, T> {
+    fn drop(&mut self) {
+        if !self.waiting {
+            return;
+        }
+        let waiter = self.waiter.get_mut().get();
+        let mut state = self.mutex.state.lock().unwrap();
+        // Safety: We hold the lock, and if it wasn't handed the lock, it's
+        // still on the list.
+        unsafe {
+            if !(*waiter).handed {
+                state.remove(NonNull::new_unchecked(waiter));
+                return;
+            }
+        }
+        // We were given the lock, but won't use it: pass it on.
+        drop(state);
+        self.mutex.unlock();
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safety: The very existence of this Guard
+        // guarantees we've exclusively locked the lock.
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: The very existence of this Guard
+        // guarantees we've exclusively locked the lock.
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// Doesn't wait: shows `<locked>` if the lock is taken.
+impl<T: fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("Mutex");
+        match self.try_lock() {
+            Some(guard) => d.field("data", &&*guard),
+            None => d.field("data", &format_args!("<locked>")),
+        };
+        d.finish()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> fmt::Debug for Lock<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Lock")
+            .field("queued", &self.waiting)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+    use std::thread;
+
+    use crate::async_sync::mutex::{Lock, Mutex, MutexGuard};
+    use crate::exec::executor::block_on;
+
+    #[test]
+    fn test_lock_from_many_threads() {
+        const N: usize = if cfg!(miri) { 100 } else { 1000 };
+        let mutex = Mutex::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    block_on(async {
+                        for _ in 0..N {
+                            *mutex.lock().await += 1;
+                        }
+                    })
+                });
+            }
+        });
+        assert_eq!(mutex.into_inner(), 4 * N);
+    }
+
+    #[test]
+    fn test_waiters_are_served_in_fifo_order() {
+        let mutex = Mutex::new(());
+        let mut cx = Context::from_waker(Waker::noop());
+        let guard = mutex.try_lock().unwrap();
+        let mut first = pin!(mutex.lock());
+        let mut second = pin!(mutex.lock());
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        drop(guard);
+        // The lock was handed to `first`, so neither `second` nor a new
+        // locker can take it.
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        assert!(mutex.try_lock().is_none());
+        let Poll::Ready(guard) = first.as_mut().poll(&mut cx) else {
+            panic!("first waiter should have the lock");
+        };
+        drop(guard);
+        assert!(second.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn test_cancelled_waiter_passes_the_lock_on() {
+        let mutex = Mutex::new(());
+        let mut cx = Context::from_waker(Waker::noop());
+        let guard = mutex.try_lock().unwrap();
+        let mut first = Box::pin(mutex.lock());
+        let mut second = pin!(mutex.lock());
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        drop(guard);
+        drop(first);
+        assert!(second.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn test_cancelled_waiters_leave_the_queue() {
+        let mutex = Mutex::new(());
+        let mut cx = Context::from_waker(Waker::noop());
+        let guard = mutex.try_lock().unwrap();
+        let mut first = Box::pin(mutex.lock());
+        let mut second = Box::pin(mutex.lock());
+        let mut third = Box::pin(mutex.lock());
+        let mut fourth = pin!(mutex.lock());
+        for lock in [&mut first, &mut second, &mut third] {
+            assert!(lock.as_mut().poll(&mut cx).is_pending());
+        }
+        assert!(fourth.as_mut().poll(&mut cx).is_pending());
+        // From the middle, the front, then the back.
+        drop(second);
+        drop(first);
+        drop(guard);
+        let Poll::Ready(guard) = third.as_mut().poll(&mut cx) else {
+            panic!("third waiter should have the lock");
+        };
+        assert!(fourth.as_mut().poll(&mut cx).is_pending());
+        drop(guard);
+        drop(third);
+        assert!(fourth.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn test_guard_is_sync_only_if_the_value_is() {
+        fn assert_sync<T: Sync>() {}
+        assert_sync::<MutexGuard<'_, Vec<u8>>>();
+    }
+
+    #[test]
+    fn test_lock_future_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        // Like the mutex itself, it only takes the value to be `Send`.
+        assert_send_sync::<Lock<'_, Cell<i32>>>();
+    }
+
+    #[test]
+    fn test_guard_is_send() {
+        fn assert_send<T: Send>(_: &T) {}
+        let mutex = Mutex::new(String::new());
+        let guard: MutexGuard<'_, String> = block_on(mutex.lock());
+        assert_send(&guard);
+        // Hold the guard across an await point in a future that's Send.
+        let future = async {
+            let mut guard = guard;
+            async {}.await;
+            guard.push('!');
+        };
+        assert_send(&future);
+        block_on(future);
+        assert_eq!(*mutex.try_lock().unwrap(), "!");
+    }
+}
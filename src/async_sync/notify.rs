@@ -0,0 +1,431 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync;
+use std::task::{Context, Poll, Waker};
+
+use crate::platform::backoff::Backoff;
+use crate::sync_shim::atomic::AtomicUsize;
+use crate::sync_shim::atomic::Ordering::{Acquire, Relaxed, Release};
+use crate::sync_shim::const_fn;
+
+/// Nobody is waiting, and there's no stored permit.
+const EMPTY: usize = 0;
+/// At least one `Notified` future is in the waiter list.
+const WAITING: usize = 1;
+/// A `notify_one` call left a permit for the next `Notified`.
+const NOTIFIED: usize = 2;
+const STATE_MASK: usize = 3;
+/// The bits above the state count `notify_waiters` calls.
+const GENERATION_ONE: usize = 4;
+
+/// Wakes up async tasks, without carrying any data.
+///
+/// `notify_one` wakes a single waiter, or, if nobody is waiting, leaves a
+/// permit so the next `notified().await` completes right away. At most one
+/// permit is stored. `notify_waiters` wakes everyone who is waiting right
+/// now (including `Notified` futures that were created but not polled
+/// yet), without leaving a permit.
+///
+/// Everything a task did before `notify_one` or `notify_waiters` is
+/// visible to the task whose `notified().await` completes because of it.
+/// The state is a single atomic, so its operations only need Release where
+/// a permit or a new generation is stored, and Acquire where one is taken.
+/// The rest is ordered by the modification order of that one atomic, or by
+/// the waiter list's mutex.
+pub struct Notify {
+    state: AtomicUsize,
+    waiters: sync::Mutex<Waiters>,
+}
+
+struct Waiters {
+    queue: VecDeque<(u64, Waker)>,
+    /// Waiters that were notified but haven't been polled since, and whether
+    /// they were picked by `notify_one` (and must pass that on if dropped).
+    notified: Vec<(u64, bool)>,
+    next_id: u64,
+}
+
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    /// The `notify_waiters` generation when this future was created.
+    generation: usize,
+    /// Our place in the waiter queue, once we have one.
+    id: Option<u64>,
+}
+
+impl Notify {
+    const_fn! {
+        pub fn new() -> Self {
+            Self {
+                state: AtomicUsize::new(EMPTY),
+                waiters: sync::Mutex::new(Waiters {
+                    queue: VecDeque::new(),
+                    notified: Vec::new(),
+                    next_id: 0,
+                }),
+            }
+        }
+    }
+
+    pub fn notified(&self) -> Notified<'_> {
+        Notified {
+            notify: self,
+            // Only compared: a `notify_waiters` that happened before this
+            // is seen anyway, the atomic's coherence guarantees that.
+            generation: self.state.load(Relaxed) & !STATE_MASK,
+            id: None,
+        }
+    }
+
+    pub fn notify_one(&self) {
+        // Fast path: nobody is waiting, so just leave a permit.
+        let mut s = self.state.load(Relaxed);
+        let mut backoff = Backoff::new();
+        while s & STATE_MASK != WAITING {
+            // Release, for `try_complete` to acquire with the permit.
+            match self
+                .state
+                .compare_exchange(s, s & !STATE_MASK | NOTIFIED, Release, Relaxed)
+            {
+                Ok(_) => return,
+                Err(e) => s = e,
+            }
+            backoff.spin();
+        }
+        // Transitions into and out of WAITING only happen with the lock held.
+        let mut waiters = self.waiters.lock().unwrap();
+        if let Some(waker) = self.notify_one_locked(&mut waiters) {
+            drop(waiters);
+            waker.wake();
+        }
+    }
+
+    /// Picks the first waiter, or stores a permit if there is none.
+    fn notify_one_locked(&self, waiters: &mut Waiters) -> Option<Waker> {
+        let s = self.state.load(Relaxed);
+        match waiters.queue.pop_front() {
+            Some((id, waker)) => {
+                // The waiter gets the notification through the mutex.
+                waiters.notified.push((id, true));
+                if waiters.queue.is_empty() {
+                    self.state.store(s & !STATE_MASK | EMPTY, Relaxed);
+                }
+                Some(waker)
+            }
+            None => {
+                self.state.store(s & !STATE_MASK | NOTIFIED, Release);
+                None
+            }
+        }
+    }
+
+    pub fn notify_waiters(&self) {
+        let mut waiters = self.waiters.lock().unwrap();
+        // A stored permit stays stored, but nobody is waiting anymore. This
+        // has to be a CAS loop, as a lock-free `notify_one` might store a
+        // permit in the meantime.
+        // Release, for `try_complete` to acquire with the new generation.
+        let _ = self.state.fetch_update(Release, Relaxed, |s| {
+            let state = if s & STATE_MASK == NOTIFIED {
+                NOTIFIED
+            } else {
+                EMPTY
+            };
+            Some(((s & !STATE_MASK) + GENERATION_ONE) | state)
+        });
+        let woken: Vec<_> = waiters.queue.drain(..).collect();
+        waiters
+            .notified
+            .extend(woken.iter().map(|(id, _)| (*id, false)));
+        drop(waiters);
+        woken.into_iter().for_each(|(_, waker)| waker.wake());
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Notified<'_> {
+    /// Takes a `notify_waiters` call since our creation, or a stored permit.
+    fn try_complete(&self) -> bool {
+        // Acquire, in case it's the generation that completes us.
+        let mut s = self.notify.state.load(Acquire);
+        let mut backoff = Backoff::new();
+        loop {
+            if s & !STATE_MASK != self.generation {
+                return true;
+            }
+            if s & STATE_MASK != NOTIFIED {
+                return false;
+            }
+            match self
+                .notify
+                .state
+                .compare_exchange(s, s & !STATE_MASK | EMPTY, Acquire, Acquire)
+            {
+                Ok(_) => return true,
+                Err(e) => s = e,
+            }
+            backoff.spin();
+        }
+    }
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let notify = self.notify;
+        if let Some(id) = self.id {
+            let mut waiters = notify.waiters.lock().unwrap();
+            if let Some(i) = waiters.notified.iter().position(|(n, _)| *n == id) {
+                waiters.notified.swap_remove(i);
+                self.id = None;
+                return Poll::Ready(());
+            }
+            if let Some((_, waker)) = waiters.queue.iter_mut().find(|(n, _)| *n == id) {
+                waker.clone_from(cx.waker());
+            }
+            return Poll::Pending;
+        }
+        if self.try_complete() {
+            return Poll::Ready(());
+        }
+        let mut waiters = notify.waiters.lock().unwrap();
+        // Check again now that notifiers can't touch the waiter list.
+        if self.try_complete() {
+            return Poll::Ready(());
+        }
+        // A lock-free `notify_one` can still store a permit between the check
+        // above and here. Queueing next to it would lose the wakeup, since
+        // later notifiers see NOTIFIED and don't look at the queue. Start
+        // over to take it instead, with Acquire there.
+        let s = notify.state.load(Relaxed);
+        if s & STATE_MASK != WAITING
+            && (s & STATE_MASK == NOTIFIED
+                || notify
+                    .state
+                    .compare_exchange(s, s | WAITING, Relaxed, Relaxed)
+                    .is_err())
+        {
+            drop(waiters);
+            return self.poll(cx);
+        }
+        let id = waiters.next_id;
+        waiters.next_id += 1;
+        waiters.queue.push_back((id, cx.waker().clone()));
+        self.id = Some(id);
+        Poll::Pending
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        let Some(id) = self.id else { return };
+        let notify = self.notify;
+        let mut waiters = notify.waiters.lock().unwrap();
+        if let Some(i) = waiters.notified.iter().position(|(n, _)| *n == id) {
+            let (_, from_notify_one) = waiters.notified.swap_remove(i);
+            // Don't let the notification get lost with us.
+            if from_notify_one {
+                if let Some(waker) = notify.notify_one_locked(&mut waiters) {
+                    drop(waiters);
+                    waker.wake();
+                }
+            }
+            return;
+        }
+        waiters.queue.retain(|(n, _)| *n != id);
+        if waiters.queue.is_empty() {
+            let s = notify.state.load(Relaxed);
+            if s & STATE_MASK == WAITING {
+                notify.state.store(s & !STATE_MASK | EMPTY, Relaxed);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Notify {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.load(Relaxed);
+        let mut d = f.debug_struct("Notify");
+        d.field("notified", &(state & STATE_MASK == NOTIFIED));
+        match self.waiters.try_lock() {
+            Ok(waiters) => d.field("waiters", &waiters.queue.len()),
+            Err(_) => d.field("waiters", &format_args!("<locked>")),
+        };
+        d.finish()
+    }
+}
+
+impl fmt::Debug for Notified<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Notified")
+            .field("queued", &self.id.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(all(test, not(any(loom, feature = "shuttle"))))]
+mod tests {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::task::{Context, Waker};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::async_sync::notify::Notify;
+    use crate::exec::executor::{block_on, block_on_until};
+
+    #[test]
+    fn test_permit_is_stored_once() {
+        let notify = Notify::new();
+        let mut cx = Context::from_waker(Waker::noop());
+        notify.notify_one();
+        notify.notify_one();
+        assert!(pin!(notify.notified()).poll(&mut cx).is_ready());
+        assert!(pin!(notify.notified()).poll(&mut cx).is_pending());
+    }
+
+    #[test]
+    fn test_notify_one_while_registering() {
+        // A `notify_one` right as the waiter queued itself left a permit
+        // next to it, instead of waking it.
+        let n = if cfg!(miri) { 20 } else { 10_000 };
+        for _ in 0..n {
+            let notify = Notify::new();
+            thread::scope(|s| {
+                s.spawn(|| notify.notify_one());
+                let deadline = Instant::now() + Duration::from_secs(10);
+                assert_eq!(block_on_until(notify.notified(), deadline), Some(()));
+            });
+        }
+    }
+
+    #[test]
+    fn test_notify_one_wakes_one_waiter() {
+        let notify = Notify::new();
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut first = pin!(notify.notified());
+        let mut second = pin!(notify.notified());
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        notify.notify_one();
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        assert!(first.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn test_notify_waiters_wakes_all_without_permit() {
+        let notify = Notify::new();
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut polled = pin!(notify.notified());
+        assert!(polled.as_mut().poll(&mut cx).is_pending());
+        // Created before the call, but not polled yet.
+        let mut created = pin!(notify.notified());
+        notify.notify_waiters();
+        assert!(polled.as_mut().poll(&mut cx).is_ready());
+        assert!(created.as_mut().poll(&mut cx).is_ready());
+        assert!(pin!(notify.notified()).poll(&mut cx).is_pending());
+    }
+
+    #[test]
+    fn test_dropped_waiter_passes_notification_on() {
+        let notify = Notify::new();
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut first = Box::pin(notify.notified());
+        let mut second = pin!(notify.notified());
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        notify.notify_one();
+        drop(first);
+        assert!(second.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn test_no_lost_notifications_across_threads() {
+        let notify = Notify::new();
+        let received = AtomicUsize::new(0);
+        let n = if cfg!(miri) { 10 } else { 100 };
+        thread::scope(|s| {
+            // Permits don't accumulate, so we can't count notifications.
+            // Keep notifying until all of them arrived, and make sure
+            // waiting for them never hangs.
+            s.spawn(|| {
+                while received.load(Relaxed) < n {
+                    notify.notify_one();
+                    thread::sleep(Duration::from_micros(10));
+                }
+            });
+            block_on(async {
+                for _ in 0..n {
+                    notify.notified().await;
+                    received.fetch_add(1, Relaxed);
+                }
+            });
+        });
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::sync::Arc;
+
+    use crate::async_sync::notify::Notify;
+    use crate::sync_shim::atomic::AtomicUsize;
+    use crate::sync_shim::atomic::Ordering::Relaxed;
+    use crate::sync_shim::thread;
+
+    /// Whatever wrote before `notify_one` is visible once the permit is
+    /// taken. Loom doesn't show a stale read here even with the orderings
+    /// weakened to Relaxed, so this checks the interleavings, not those.
+    #[test]
+    fn loom_permit_publishes_writes_before_notify_one() {
+        crate::sync_shim::model(|| {
+            let shared = Arc::new((Notify::new(), AtomicUsize::new(0)));
+            let t = thread::spawn({
+                let shared = shared.clone();
+                move || {
+                    shared.1.store(1, Relaxed);
+                    shared.0.notify_one();
+                }
+            });
+            // Only loads here, which loom doesn't reorder with the other
+            // thread's writes by itself, so this lets it run first.
+            thread::yield_now();
+            if shared.0.notified().try_complete() {
+                assert_eq!(shared.1.load(Relaxed), 1);
+            }
+            t.join().unwrap();
+        });
+    }
+
+    /// The same for `notify_waiters` and the generation it bumps.
+    #[test]
+    fn loom_generation_publishes_writes_before_notify_waiters() {
+        crate::sync_shim::model(|| {
+            let shared = Arc::new((Notify::new(), AtomicUsize::new(0)));
+            let notified = shared.0.notified();
+            let t = thread::spawn({
+                let shared = shared.clone();
+                move || {
+                    shared.1.store(1, Relaxed);
+                    shared.0.notify_waiters();
+                }
+            });
+            thread::yield_now();
+            if notified.try_complete() {
+                assert_eq!(shared.1.load(Relaxed), 1);
+            }
+            drop(notified);
+            t.join().unwrap();
+        });
+    }
+}
@@ -0,0 +1,430 @@
+use std::cell::UnsafeCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::sync;
+use std::task::{Context, Poll, Waker};
+
+/// An async reader-writer lock.
+///
+/// It's write-preferring: once a writer is waiting, new readers queue up
+/// behind it, and an unlock wakes a waiting writer before any readers.
+/// Like the async `Mutex`, the lock is handed over to the woken waiters
+/// directly, so nobody can sneak in between the wake-up and the poll.
+pub struct RwLock<T> {
+    state: sync::Mutex<State>,
+    value: UnsafeCell<T>,
+}
+
+struct State {
+    readers: usize,
+    writer: bool,
+    read_waiters: VecDeque<(u64, Waker)>,
+    write_waiters: VecDeque<(u64, Waker)>,
+    /// Waiters that were handed the lock but haven't been polled since.
+    granted: Vec<u64>,
+    next_id: u64,
+}
+
+unsafe impl<T> Sync for RwLock<T> where T: Send + Sync {}
+
+pub struct RwLockReadGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+}
+
+pub struct RwLockWriteGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Read,
+    Write,
+}
+
+struct Acquire<'a, T> {
+    rwlock: &'a RwLock<T>,
+    access: Access,
+    /// Our place in the waiter queue, once we have one.
+    id: Option<u64>,
+}
+
+pub struct Read<'a, T>(Acquire<'a, T>);
+
+pub struct Write<'a, T>(Acquire<'a, T>);
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: sync::Mutex::new(State {
+                readers: 0,
+                writer: false,
+                read_waiters: VecDeque::new(),
+                write_waiters: VecDeque::new(),
+                granted: Vec::new(),
+                next_id: 0,
+            }),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn read(&self) -> Read<'_, T> {
+        Read(Acquire {
+            rwlock: self,
+            access: Access::Read,
+            id: None,
+        })
+    }
+
+    pub fn write(&self) -> Write<'_, T> {
+        Write(Acquire {
+            rwlock: self,
+            access: Access::Write,
+            id: None,
+        })
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let mut state = self.state.lock().unwrap();
+        if state.writer || !state.write_waiters.is_empty() {
+            return None;
+        }
+        state.readers += 1;
+        Some(RwLockReadGuard { rwlock: self })
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        let mut state = self.state.lock().unwrap();
+        if state.writer || state.readers > 0 {
+            return None;
+        }
+        state.writer = true;
+        Some(RwLockWriteGuard { rwlock: self })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    fn read_unlock(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.readers -= 1;
+        let wakers = state.hand_over();
+        drop(state);
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    fn write_unlock(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.writer = false;
+        let wakers = state.hand_over();
+        drop(state);
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+impl State {
+    /// Hands the lock to the next waiter(s) if it has become free, and
+    /// returns the wakers to call once the state lock is released.
+    fn hand_over(&mut self) -> Vec<Waker> {
+        if self.writer || self.readers > 0 {
+            return Vec::new();
+        }
+        if let Some((id, waker)) = self.write_waiters.pop_front() {
+            self.writer = true;
+            self.granted.push(id);
+            return vec![waker];
+        }
+        self.hand_over_to_readers()
+    }
+
+    /// Lets all queued readers in, unless a writer has (or wants) the lock.
+    fn hand_over_to_readers(&mut self) -> Vec<Waker> {
+        if self.writer || !self.write_waiters.is_empty() {
+            return Vec::new();
+        }
+        self.readers += self.read_waiters.len();
+        self.read_waiters
+            .drain(..)
+            .map(|(id, waker)| {
+                self.granted.push(id);
+                waker
+            })
+            .collect()
+    }
+}
+
+impl<T> Acquire<'_, T> {
+    /// Returns true once we hold the lock.
+    fn poll_acquire(&mut self, cx: &mut Context<'_>) -> bool {
+        let mut state = self.rwlock.state.lock().unwrap();
+        let Some(id) = self.id else {
+            let free = match self.access {
+                Access::Read => !state.writer && state.write_waiters.is_empty(),
+                Access::Write => !state.writer && state.readers == 0,
+            };
+            if free {
+                match self.access {
+                    Access::Read => state.readers += 1,
+                    Access::Write => state.writer = true,
+                }
+                return true;
+            }
+            let id = state.next_id;
+            state.next_id += 1;
+            let waker = cx.waker().clone();
+            match self.access {
+                Access::Read => state.read_waiters.push_back((id, waker)),
+                Access::Write => state.write_waiters.push_back((id, waker)),
+            }
+            self.id = Some(id);
+            return false;
+        };
+        if let Some(i) = state.granted.iter().position(|&g| g == id) {
+            state.granted.swap_remove(i);
+            self.id = None;
+            return true;
+        }
+        let waiters = match self.access {
+            Access::Read => &mut state.read_waiters,
+            Access::Write => &mut state.write_waiters,
+        };
+        if let Some((_, waker)) = waiters.iter_mut().find(|(i, _)| *i == id) {
+            waker.clone_from(cx.waker());
+        }
+        false
+    }
+}
+
+impl<T> Drop for Acquire<'_, T> {
+    fn drop(&mut self) {
+        let Some(id) = self.id else { return };
+        let mut state = self.rwlock.state.lock().unwrap();
+        if let Some(i) = state.granted.iter().position(|&g| g == id) {
+            // We were given the lock, but won't use it: release it again.
+            state.granted.swap_remove(i);
+            drop(state);
+            match self.access {
+                Access::Read => self.rwlock.read_unlock(),
+                Access::Write => self.rwlock.write_unlock(),
+            }
+            return;
+        }
+        match self.access {
+            Access::Read => state.read_waiters.retain(|(i, _)| *i != id),
+            Access::Write => {
+                state.write_waiters.retain(|(i, _)| *i != id);
+                // Readers may have been queued only because we were waiting.
+                let wakers = state.hand_over_to_readers();
+                drop(state);
+                wakers.into_iter().for_each(Waker::wake);
+            }
+        }
+    }
+}
+
+impl<'a, T> Future for Read<'a, T> {
+    type Output = RwLockReadGuard<'a, T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<RwLockReadGuard<'a, T>> {
+        if self.0.poll_acquire(cx) {
+            Poll::Ready(RwLockReadGuard {
+                rwlock: self.0.rwlock,
+            })
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl<'a, T> Future for Write<'a, T> {
+    type Output = RwLockWriteGuard<'a, T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<RwLockWriteGuard<'a, T>> {
+        if self.0.poll_acquire(cx) {
+            Poll::Ready(RwLockWriteGuard {
+                rwlock: self.0.rwlock,
+            })
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safety: Readers only coexist with other readers.
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.rwlock.read_unlock();
+    }
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safety: The very existence of this Guard
+        // guarantees we've exclusively locked the lock.
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: The very existence of this Guard
+        // guarantees we've exclusively locked the lock.
+        unsafe { &mut *self.rwlock.value.get() }
+    }
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.rwlock.write_unlock();
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// Doesn't wait: shows `<locked>` if the lock can't be read right now.
+impl<T: fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("RwLock");
+        match self.try_read() {
+            Some(guard) => d.field("data", &&*guard),
+            None => d.field("data", &format_args!("<locked>")),
+        };
+        d.finish()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RwLockReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RwLockWriteGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> fmt::Debug for Read<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Read")
+            .field("queued", &self.0.id.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Debug for Write<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Write")
+            .field("queued", &self.0.id.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+    use std::thread;
+
+    use crate::async_sync::rwlock::RwLock;
+    use crate::exec::executor::block_on;
+
+    #[test]
+    fn test_readers_share_writers_exclude() {
+        let rwlock = RwLock::new(0);
+        let r1 = rwlock.try_read().unwrap();
+        let r2 = block_on(rwlock.read());
+        assert_eq!(*r1 + *r2, 0);
+        assert!(rwlock.try_write().is_none());
+        drop((r1, r2));
+        let mut w = block_on(rwlock.write());
+        *w += 1;
+        assert!(rwlock.try_read().is_none());
+        drop(w);
+        assert_eq!(*rwlock.try_read().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_waiting_writer_blocks_new_readers() {
+        let rwlock = RwLock::new(());
+        let mut cx = Context::from_waker(Waker::noop());
+        let reader = rwlock.try_read().unwrap();
+        let mut writer = pin!(rwlock.write());
+        assert!(writer.as_mut().poll(&mut cx).is_pending());
+        let mut late_reader = pin!(rwlock.read());
+        assert!(late_reader.as_mut().poll(&mut cx).is_pending());
+        assert!(rwlock.try_read().is_none());
+        drop(reader);
+        let Poll::Ready(guard) = writer.as_mut().poll(&mut cx) else {
+            panic!("writer should have the lock");
+        };
+        assert!(late_reader.as_mut().poll(&mut cx).is_pending());
+        drop(guard);
+        assert!(late_reader.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn test_cancelled_writer_lets_queued_readers_in() {
+        let rwlock = RwLock::new(());
+        let mut cx = Context::from_waker(Waker::noop());
+        let reader = rwlock.try_read().unwrap();
+        let mut writer = Box::pin(rwlock.write());
+        assert!(writer.as_mut().poll(&mut cx).is_pending());
+        let mut late_reader = pin!(rwlock.read());
+        assert!(late_reader.as_mut().poll(&mut cx).is_pending());
+        drop(writer);
+        assert!(late_reader.as_mut().poll(&mut cx).is_ready());
+        drop(reader);
+    }
+
+    #[test]
+    fn test_concurrent_readers_and_writers() {
+        const N: u64 = if cfg!(miri) { 100 } else { 1000 };
+        let rwlock = RwLock::new((0u64, 0u64));
+        thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    block_on(async {
+                        for _ in 0..N {
+                            let mut w = rwlock.write().await;
+                            w.0 += 1;
+                            w.1 += 1;
+                        }
+                    })
+                });
+            }
+            for _ in 0..2 {
+                s.spawn(|| {
+                    block_on(async {
+                        for _ in 0..N {
+                            let r = rwlock.read().await;
+                            assert_eq!(r.0, r.1);
+                        }
+                    })
+                });
+            }
+        });
+        assert_eq!(rwlock.into_inner(), (2 * N, 2 * N));
+    }
+}
@@ -0,0 +1,498 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{self, Arc};
+use std::task::{Context, Poll, Waker};
+use std::time::Instant;
+
+use crate::cancel::{CancellationToken, RunUntilCancelled};
+use crate::exec::timer::{timeout_at, Timeout};
+
+/// An async counting semaphore.
+///
+/// Acquire futures complete in FIFO order. While the waiter at the head of
+/// the queue doesn't fit, released permits are kept for it rather than
+/// handed to anyone behind it, so an `acquire_many(n)` can't be starved by
+/// a stream of single-permit acquirers. Asking for more permits than will
+/// ever be available waits forever.
+pub struct Semaphore {
+    state: sync::Mutex<State>,
+}
+
+struct State {
+    permits: usize,
+    waiters: VecDeque<Waiter>,
+    /// Waiters whose permits were reserved, but that haven't been polled since.
+    granted: Vec<u64>,
+    next_id: u64,
+}
+
+struct Waiter {
+    id: u64,
+    permits: usize,
+    waker: Waker,
+}
+
+/// Bookkeeping shared by the borrowed and owned acquire futures.
+struct Acquisition {
+    permits: usize,
+    /// Our place in the waiter queue, once we have one.
+    id: Option<u64>,
+}
+
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    acquisition: Acquisition,
+}
+
+pub struct AcquireOwned {
+    semaphore: Arc<Semaphore>,
+    acquisition: Acquisition,
+}
+
+#[must_use = "the permits are released right away if the permit is dropped"]
+pub struct Permit<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+}
+
+/// A permit that keeps its semaphore alive, so it can be moved into a
+/// spawned task that outlives the scope the semaphore was borrowed in.
+#[must_use = "the permits are released right away if the permit is dropped"]
+pub struct OwnedPermit {
+    semaphore: Arc<Semaphore>,
+    permits: usize,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            state: sync::Mutex::new(State {
+                permits,
+                waiters: VecDeque::new(),
+                granted: Vec::new(),
+                next_id: 0,
+            }),
+        }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().unwrap().permits
+    }
+
+    pub fn acquire(&self) -> Acquire<'_> {
+        self.acquire_many(1)
+    }
+
+    pub fn acquire_many(&self, permits: usize) -> Acquire<'_> {
+        Acquire {
+            semaphore: self,
+            acquisition: Acquisition { permits, id: None },
+        }
+    }
+
+    /// Like `acquire`, but fails with `Cancelled` once `token` is
+    /// cancelled, giving up its place in the queue.
+    pub fn acquire_cancellable<'a>(
+        &'a self,
+        token: &'a CancellationToken,
+    ) -> RunUntilCancelled<'a, Acquire<'a>> {
+        self.acquire_many_cancellable(1, token)
+    }
+
+    pub fn acquire_many_cancellable<'a>(
+        &'a self,
+        permits: usize,
+        token: &'a CancellationToken,
+    ) -> RunUntilCancelled<'a, Acquire<'a>> {
+        token.run_until_cancelled(self.acquire_many(permits))
+    }
+
+    /// Like `acquire`, but fails with `Elapsed` at `deadline`, giving up
+    /// its place in the queue.
+    pub fn acquire_until(&self, deadline: Instant) -> Timeout<Acquire<'_>> {
+        self.acquire_many_until(1, deadline)
+    }
+
+    pub fn acquire_many_until(&self, permits: usize, deadline: Instant) -> Timeout<Acquire<'_>> {
+        timeout_at(self.acquire_many(permits), deadline)
+    }
+
+    pub fn acquire_owned(self: &Arc<Self>) -> AcquireOwned {
+        self.acquire_many_owned(1)
+    }
+
+    pub fn acquire_many_owned(self: &Arc<Self>, permits: usize) -> AcquireOwned {
+        AcquireOwned {
+            semaphore: self.clone(),
+            acquisition: Acquisition { permits, id: None },
+        }
+    }
+
+    pub fn try_acquire(&self) -> Option<Permit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    /// Fails if there aren't enough permits, or if others are already waiting.
+    pub fn try_acquire_many(&self, permits: usize) -> Option<Permit<'_>> {
+        let mut state = self.state.lock().unwrap();
+        if !state.waiters.is_empty() || state.permits < permits {
+            return None;
+        }
+        state.permits -= permits;
+        Some(Permit {
+            semaphore: self,
+            permits,
+        })
+    }
+
+    pub fn add_permits(&self, permits: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.permits += permits;
+        let wakers = state.hand_out();
+        drop(state);
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+impl State {
+    /// Reserves permits for waiters from the front of the queue for as
+    /// long as they fit, and returns the wakers to call once the state
+    /// lock is released. Whatever is left stays put until the new front
+    /// fits, even if a waiter further back would fit now.
+    fn hand_out(&mut self) -> Vec<Waker> {
+        let mut wakers = Vec::new();
+        while let Some(front) = self.waiters.front() {
+            if front.permits > self.permits {
+                break;
+            }
+            let waiter = self.waiters.pop_front().unwrap();
+            self.permits -= waiter.permits;
+            self.granted.push(waiter.id);
+            wakers.push(waiter.waker);
+        }
+        wakers
+    }
+}
+
+impl Acquisition {
+    /// Returns true once the permits are ours.
+    fn poll(&mut self, semaphore: &Semaphore, cx: &mut Context<'_>) -> bool {
+        let mut state = semaphore.state.lock().unwrap();
+        let Some(id) = self.id else {
+            if state.waiters.is_empty() && state.permits >= self.permits {
+                state.permits -= self.permits;
+                return true;
+            }
+            let id = state.next_id;
+            state.next_id += 1;
+            state.waiters.push_back(Waiter {
+                id,
+                permits: self.permits,
+                waker: cx.waker().clone(),
+            });
+            self.id = Some(id);
+            return false;
+        };
+        if let Some(i) = state.granted.iter().position(|&g| g == id) {
+            state.granted.swap_remove(i);
+            self.id = None;
+            return true;
+        }
+        if let Some(waiter) = state.waiters.iter_mut().find(|w| w.id == id) {
+            waiter.waker.clone_from(cx.waker());
+        }
+        false
+    }
+
+    fn cancel(&mut self, semaphore: &Semaphore) {
+        let Some(id) = self.id.take() else { return };
+        let mut state = semaphore.state.lock().unwrap();
+        if let Some(i) = state.granted.iter().position(|&g| g == id) {
+            // The permits were reserved for us: give them back.
+            state.granted.swap_remove(i);
+            state.permits += self.permits;
+        } else {
+            state.waiters.retain(|w| w.id != id);
+        }
+        // Either way, the waiters behind us may fit now.
+        let wakers = state.hand_out();
+        drop(state);
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = Permit<'a>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Permit<'a>> {
+        let semaphore = self.semaphore;
+        if self.acquisition.poll(semaphore, cx) {
+            Poll::Ready(Permit {
+                semaphore,
+                permits: self.acquisition.permits,
+            })
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        self.acquisition.cancel(self.semaphore);
+    }
+}
+
+impl Future for AcquireOwned {
+    type Output = OwnedPermit;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<OwnedPermit> {
+        let this = &mut *self;
+        if this.acquisition.poll(&this.semaphore, cx) {
+            Poll::Ready(OwnedPermit {
+                semaphore: this.semaphore.clone(),
+                permits: this.acquisition.permits,
+            })
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for AcquireOwned {
+    fn drop(&mut self) {
+        self.acquisition.cancel(&self.semaphore);
+    }
+}
+
+impl Permit<'_> {
+    pub fn permits(&self) -> usize {
+        self.permits
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.add_permits(self.permits);
+    }
+}
+
+impl OwnedPermit {
+    pub fn permits(&self) -> usize {
+        self.permits
+    }
+
+    pub fn semaphore(&self) -> &Arc<Semaphore> {
+        &self.semaphore
+    }
+}
+
+impl Drop for OwnedPermit {
+    fn drop(&mut self) {
+        self.semaphore.add_permits(self.permits);
+    }
+}
+
+impl fmt::Debug for Semaphore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("Semaphore");
+        match self.state.try_lock() {
+            Ok(state) => d
+                .field("permits", &state.permits)
+                .field("waiters", &state.waiters.len()),
+            Err(_) => d.field("permits", &format_args!("<locked>")),
+        };
+        d.finish()
+    }
+}
+
+impl fmt::Debug for Acquire<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Acquire")
+            .field("permits", &self.acquisition.permits)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Debug for AcquireOwned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AcquireOwned")
+            .field("permits", &self.acquisition.permits)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Debug for Permit<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Permit")
+            .field("permits", &self.permits)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Debug for OwnedPermit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OwnedPermit")
+            .field("permits", &self.permits)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::sync::atomic::{AtomicBool, AtomicUsize};
+    use std::sync::Arc;
+    use std::task::{Context, Waker};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::async_sync::semaphore::Semaphore;
+    use crate::cancel::CancellationToken;
+    use crate::errors::Cancelled;
+    use crate::exec::executor::block_on;
+    use crate::exec::timer::Elapsed;
+
+    #[test]
+    fn test_permits_are_returned_on_drop() {
+        let semaphore = Semaphore::new(3);
+        let a = block_on(semaphore.acquire_many(2));
+        assert_eq!(a.permits(), 2);
+        assert_eq!(semaphore.available_permits(), 1);
+        assert!(semaphore.try_acquire_many(2).is_none());
+        drop(a);
+        assert_eq!(semaphore.available_permits(), 3);
+    }
+
+    #[test]
+    fn test_big_waiter_is_not_overtaken() {
+        let semaphore = Semaphore::new(2);
+        let mut cx = Context::from_waker(Waker::noop());
+        let one = semaphore.try_acquire().unwrap();
+        let mut big = pin!(semaphore.acquire_many(2));
+        assert!(big.as_mut().poll(&mut cx).is_pending());
+        // A permit is free, but the big waiter is first in line.
+        let mut small = pin!(semaphore.acquire());
+        assert!(small.as_mut().poll(&mut cx).is_pending());
+        assert!(semaphore.try_acquire().is_none());
+        drop(one);
+        assert!(small.as_mut().poll(&mut cx).is_pending());
+        assert!(big.as_mut().poll(&mut cx).is_ready());
+        assert!(small.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn test_cancelled_waiter_unblocks_the_queue() {
+        let semaphore = Semaphore::new(1);
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut big = Box::pin(semaphore.acquire_many(5));
+        assert!(big.as_mut().poll(&mut cx).is_pending());
+        let mut small = pin!(semaphore.acquire());
+        assert!(small.as_mut().poll(&mut cx).is_pending());
+        drop(big);
+        assert!(small.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn test_cancelled_acquire() {
+        let semaphore = Semaphore::new(1);
+        let token = CancellationToken::new();
+        let one = block_on(semaphore.acquire_cancellable(&token)).unwrap();
+        thread::scope(|s| {
+            let big = s.spawn(|| block_on(semaphore.acquire_many_cancellable(2, &token)).err());
+            thread::sleep(Duration::from_millis(10));
+            token.cancel();
+            assert_eq!(big.join().unwrap(), Some(Cancelled));
+        });
+        // The cancelled waiter left the queue.
+        drop(one);
+        assert!(semaphore.try_acquire().is_some());
+        assert!(block_on(semaphore.acquire_cancellable(&token)).is_err());
+    }
+
+    #[test]
+    fn test_acquire_until() {
+        let semaphore = Semaphore::new(1);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let one = block_on(semaphore.acquire_until(deadline)).unwrap();
+        thread::scope(|s| {
+            let waiter = s.spawn(|| {
+                let deadline = Instant::now() + Duration::from_millis(10);
+                block_on(semaphore.acquire_until(deadline)).err()
+            });
+            assert_eq!(waiter.join().unwrap(), Some(Elapsed));
+        });
+        // The waiter that timed out left the queue.
+        drop(one);
+        assert!(semaphore.try_acquire().is_some());
+        assert!(block_on(semaphore.acquire_many_until(1, deadline)).is_ok());
+    }
+
+    #[test]
+    fn test_big_acquirer_is_not_starved() {
+        // Without the queue, some small permit would always be held while
+        // the big acquirer looks, and it would never see all of them free.
+        const PERMITS: usize = 4;
+        const LIMIT: usize = if cfg!(miri) { 200 } else { 100_000 };
+        let semaphore = Semaphore::new(PERMITS);
+        let done = AtomicBool::new(false);
+        let starved = AtomicBool::new(false);
+        thread::scope(|s| {
+            for _ in 0..PERMITS * 2 {
+                s.spawn(|| {
+                    for _ in 0..LIMIT {
+                        if done.load(Relaxed) {
+                            return;
+                        }
+                        let _permit = block_on(semaphore.acquire());
+                        thread::yield_now();
+                    }
+                    starved.store(true, Relaxed);
+                });
+            }
+            s.spawn(|| {
+                thread::yield_now();
+                let permit = block_on(semaphore.acquire_many(PERMITS));
+                assert_eq!(permit.permits(), PERMITS);
+                done.store(true, Relaxed);
+            });
+        });
+        assert!(!starved.load(Relaxed));
+        assert_eq!(semaphore.available_permits(), PERMITS);
+    }
+
+    #[test]
+    fn test_owned_permit_bounds_concurrency() {
+        let semaphore = Arc::new(Semaphore::new(2));
+        let running = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let permit = semaphore.acquire_owned();
+                let running = running.clone();
+                thread::spawn(move || {
+                    let _permit = block_on(permit);
+                    assert!(running.fetch_add(1, Relaxed) < 2);
+                    thread::yield_now();
+                    running.fetch_sub(1, Relaxed);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(semaphore.available_permits(), 2);
+    }
+
+    #[test]
+    fn test_static() {
+        static SEMAPHORE: Semaphore = Semaphore::new(2);
+        let permit = block_on(SEMAPHORE.acquire_many(2));
+        assert!(SEMAPHORE.try_acquire().is_none());
+        drop(permit);
+        assert_eq!(SEMAPHORE.available_permits(), 2);
+    }
+}
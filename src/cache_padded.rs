@@ -0,0 +1,91 @@
+//! Keeping values that different threads write to on different cache
+//! lines.
+//!
+//! When two threads write to two atomics on the same cache line, the line
+//! bounces between their cores on every write, as if they were writing to
+//! the same atomic: false sharing. [`CachePadded`] aligns (and so pads) a
+//! value to a cache line of its own. On x86-64 that's two 64-byte lines,
+//! since the prefetcher pulls in pairs of them, and on AArch64 it's 128
+//! bytes too, which is the line size of Apple's cores. Elsewhere, 64.
+//! `perf::false_sharing` measures what that's worth.
+
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+
+/// A value on a cache line of its own, like crossbeam's.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "powerpc64"
+    ),
+    repr(align(128))
+)]
+#[cfg_attr(
+    not(any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "powerpc64"
+    )),
+    repr(align(64))
+)]
+pub struct CachePadded<T> {
+    value: T,
+}
+
+impl<T> CachePadded<T> {
+    pub const fn new(value: T) -> Self {
+        Self { value }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for CachePadded<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T> From<T> for CachePadded<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for CachePadded<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachePadded")
+            .field("value", &self.value)
+            .finish()
+    }
+}
+
+#[cfg(all(test, not(any(loom, feature = "shuttle"))))]
+mod tests {
+    use std::mem::{align_of, size_of};
+
+    use crate::cache_padded::CachePadded;
+
+    #[test]
+    fn test_padded_values_are_a_line_apart() {
+        let pair = [CachePadded::new(1u8), CachePadded::new(2u8)];
+        let distance = &*pair[1] as *const u8 as usize - &*pair[0] as *const u8 as usize;
+        assert!(distance >= 64);
+        assert_eq!(size_of::<CachePadded<u8>>(), align_of::<CachePadded<u8>>());
+        let mut padded = CachePadded::from(vec![1]);
+        padded.push(2);
+        assert_eq!(padded.into_inner(), [1, 2]);
+    }
+}
@@ -0,0 +1,290 @@
+//! Cancelling blocking waits from another thread.
+//!
+//! A [`CancellationToken`] is handed to the waits that should stop on
+//! shutdown: receiving from a `simple_channel`, the timed waits of `Lock`
+//! and `RwLock`, and acquiring a `Semaphore`. Cancelling it makes them all
+//! return `Err(Cancelled)` right away, instead of waiting out their
+//! timeout or forever.
+//!
+//! The token wraps an [`Interrupt`] from `platform::wait`, which knows how
+//! to wake every thread waiting with it, whatever each one is waiting on.
+//! Tasks awaiting [`CancellationToken::cancelled`] are woken too.
+//!
+//! Tokens form a tree: cancelling one cancels the tokens made with its
+//! `child_token`, and theirs, but not its parent. Clones are the same
+//! token.
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+use crate::arc::{Arc, Weak};
+use crate::errors::Cancelled;
+use crate::locks::Mutex;
+use crate::platform::wait::Interrupt;
+use crate::sync_shim::plain_atomic::AtomicU64;
+use crate::sync_shim::plain_atomic::Ordering::Relaxed;
+
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    node: Arc<Node>,
+}
+
+#[derive(Default)]
+struct Node {
+    interrupt: Interrupt,
+    children: Mutex<Vec<Weak<Node>>>,
+    /// Tasks waiting for it to be cancelled, by the id of their future.
+    wakers: Mutex<Vec<(u64, Waker)>>,
+    next_id: AtomicU64,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A token that's cancelled along with this one, but can also be
+    /// cancelled by itself.
+    pub fn child_token(&self) -> Self {
+        let child = Self::new();
+        let mut children = self.node.children.lock();
+        // `cancel` raises the interrupt before taking the children, so
+        // either it sees this one, or we see it raised.
+        if self.is_cancelled() {
+            drop(children);
+            child.cancel();
+            return child;
+        }
+        // Forget about the children that are gone.
+        children.retain(|c| c.upgrade().is_some());
+        children.push(Arc::downgrade(&child.node));
+        child
+    }
+
+    /// Cancels this token and all its children, waking everything that's
+    /// waiting with them. Returns once the blocked threads have noticed.
+    pub fn cancel(&self) {
+        self.node.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.node.interrupt.is_raised()
+    }
+
+    /// Fails with `Cancelled` if it's cancelled, for checking between
+    /// steps of a longer job.
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// What blocking waits register with, to be woken when it's cancelled.
+    /// See `platform::wait::wait_interruptible`.
+    pub fn interrupt(&self) -> &Interrupt {
+        &self.node.interrupt
+    }
+
+    /// A future that completes once it's cancelled.
+    pub fn cancelled(&self) -> WaitForCancellation<'_> {
+        WaitForCancellation {
+            token: self,
+            id: None,
+        }
+    }
+
+    /// Runs `future` until it completes, or fails with `Cancelled` as soon
+    /// as the token is cancelled, dropping it.
+    pub fn run_until_cancelled<F: Future>(&self, future: F) -> RunUntilCancelled<'_, F> {
+        RunUntilCancelled {
+            future,
+            cancelled: self.cancelled(),
+        }
+    }
+}
+
+impl Node {
+    fn cancel(&self) {
+        if self.interrupt.is_raised() {
+            return;
+        }
+        self.interrupt.raise();
+        let wakers = std::mem::take(&mut *self.wakers.lock());
+        wakers.into_iter().for_each(|(_, w)| w.wake());
+        let children = std::mem::take(&mut *self.children.lock());
+        for child in children {
+            if let Some(child) = child.upgrade() {
+                child.cancel();
+            }
+        }
+    }
+}
+
+/// Completes once the token is cancelled. See
+/// [`CancellationToken::cancelled`].
+#[must_use = "futures do nothing unless polled"]
+pub struct WaitForCancellation<'a> {
+    token: &'a CancellationToken,
+    /// Where our waker is in the list, once it's there.
+    id: Option<u64>,
+}
+
+impl Future for WaitForCancellation<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let node = &self.token.node;
+        if node.interrupt.is_raised() {
+            return Poll::Ready(());
+        }
+        let mut wakers = node.wakers.lock();
+        // `cancel` raises the interrupt before taking the wakers.
+        if node.interrupt.is_raised() {
+            return Poll::Ready(());
+        }
+        match self.id {
+            Some(id) => match wakers.iter_mut().find(|(i, _)| *i == id) {
+                Some((_, waker)) => waker.clone_from(cx.waker()),
+                None => wakers.push((id, cx.waker().clone())),
+            },
+            None => {
+                let id = node.next_id.fetch_add(1, Relaxed);
+                wakers.push((id, cx.waker().clone()));
+                drop(wakers);
+                self.id = Some(id);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for WaitForCancellation<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            self.token.node.wakers.lock().retain(|(i, _)| *i != id);
+        }
+    }
+}
+
+/// See [`CancellationToken::run_until_cancelled`].
+#[must_use = "futures do nothing unless polled"]
+pub struct RunUntilCancelled<'a, F> {
+    future: F,
+    cancelled: WaitForCancellation<'a>,
+}
+
+impl<F: Future> Future for RunUntilCancelled<'_, F> {
+    type Output = Result<F::Output, Cancelled>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: `future` is pinned along with us, and never moved out.
+        let this = unsafe { self.get_unchecked_mut() };
+        if Pin::new(&mut this.cancelled).poll(cx).is_ready() {
+            return Poll::Ready(Err(Cancelled));
+        }
+        // Safety: See above.
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        future.poll(cx).map(Ok)
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+impl fmt::Debug for WaitForCancellation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("WaitForCancellation")
+            .field(self.token)
+            .finish()
+    }
+}
+
+impl<F> fmt::Debug for RunUntilCancelled<'_, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("RunUntilCancelled")
+            .field(self.cancelled.token)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll, Waker};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::cancel::CancellationToken;
+    use crate::errors::Cancelled;
+    use crate::exec::executor::block_on;
+
+    #[test]
+    fn test_children_are_cancelled_with_their_parent() {
+        let root = CancellationToken::new();
+        let child = root.child_token();
+        let grandchild = child.child_token();
+        let other = root.child_token();
+        other.cancel();
+        assert!(!root.is_cancelled());
+        assert!(!child.is_cancelled());
+        child.clone().cancel();
+        assert!(grandchild.is_cancelled());
+        assert!(!root.is_cancelled());
+        assert_eq!(root.check(), Ok(()));
+
+        let child = root.child_token();
+        root.cancel();
+        assert!(child.is_cancelled());
+        // Made after the parent was cancelled.
+        assert_eq!(root.child_token().check(), Err(Cancelled));
+        assert_eq!(
+            format!("{child:?}"),
+            "CancellationToken { cancelled: true }"
+        );
+    }
+
+    #[test]
+    fn test_cancelled_future() {
+        let token = CancellationToken::new();
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut first = token.cancelled();
+        assert_eq!(Pin::new(&mut first).poll(&mut cx), Poll::Pending);
+        assert_eq!(Pin::new(&mut first).poll(&mut cx), Poll::Pending);
+        assert_eq!(token.node.wakers.lock().len(), 1);
+        drop(first);
+        assert!(token.node.wakers.lock().is_empty());
+
+        thread::scope(|s| {
+            let child = token.child_token();
+            let waiter = s.spawn(move || block_on(child.cancelled()));
+            thread::sleep(Duration::from_millis(10));
+            token.cancel();
+            waiter.join().unwrap();
+        });
+        block_on(token.cancelled());
+    }
+
+    #[test]
+    fn test_run_until_cancelled() {
+        let token = CancellationToken::new();
+        assert_eq!(block_on(token.run_until_cancelled(async { 1 })), Ok(1));
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                token.cancel();
+            });
+            let never = std::future::pending::<()>();
+            assert_eq!(block_on(token.run_until_cancelled(never)), Err(Cancelled));
+        });
+    }
+}
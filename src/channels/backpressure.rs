@@ -0,0 +1,119 @@
+//! Hooks a bounded channel calls as it fills up and drains, so a program
+//! can shed load or log when a queue saturates, without polling its length
+//! from another thread.
+//!
+//! The hooks are called on the thread that sent or received, right after
+//! the change, and only on the transition: `on_full` when a send fills the
+//! channel, not on every send that finds it full. They run on the hot path,
+//! so they should be quick, and they mustn't use the channel they're
+//! watching, which could deadlock or recurse.
+//!
+//! Implement [`Backpressure`] on a type, or build one out of closures with
+//! [`Hooks`].
+
+use std::fmt;
+use std::sync::Arc;
+
+pub trait Backpressure: Send + Sync {
+    /// A send filled the channel, so the next one would have to wait.
+    fn on_full(&self) {}
+
+    /// A receive took the last message.
+    fn on_empty(&self) {}
+
+    /// A send filled the channel to half its capacity or more, from below
+    /// that.
+    fn on_half_capacity(&self) {}
+}
+
+/// So the hooks' state can be looked at from outside the channel.
+impl<B: Backpressure + ?Sized> Backpressure for Arc<B> {
+    fn on_full(&self) {
+        (**self).on_full();
+    }
+
+    fn on_empty(&self) {
+        (**self).on_empty();
+    }
+
+    fn on_half_capacity(&self) {
+        (**self).on_half_capacity();
+    }
+}
+
+type Hook = Box<dyn Fn() + Send + Sync>;
+
+/// `Backpressure` out of closures, for the hooks that are set.
+#[derive(Default)]
+pub struct Hooks {
+    full: Option<Hook>,
+    empty: Option<Hook>,
+    half: Option<Hook>,
+}
+
+impl Hooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn on_full(mut self, f: impl Fn() + Send + Sync + 'static) -> Self {
+        self.full = Some(Box::new(f));
+        self
+    }
+
+    pub fn on_empty(mut self, f: impl Fn() + Send + Sync + 'static) -> Self {
+        self.empty = Some(Box::new(f));
+        self
+    }
+
+    pub fn on_half_capacity(mut self, f: impl Fn() + Send + Sync + 'static) -> Self {
+        self.half = Some(Box::new(f));
+        self
+    }
+}
+
+impl Backpressure for Hooks {
+    fn on_full(&self) {
+        if let Some(f) = &self.full {
+            f();
+        }
+    }
+
+    fn on_empty(&self) {
+        if let Some(f) = &self.empty {
+            f();
+        }
+    }
+
+    fn on_half_capacity(&self) {
+        if let Some(f) = &self.half {
+            f();
+        }
+    }
+}
+
+/// Calls the hooks for a channel of `capacity` that went from `before` to
+/// `after` messages (or bytes).
+pub(crate) fn changed(hooks: &dyn Backpressure, capacity: usize, before: usize, after: usize) {
+    if after > before {
+        let half = capacity.div_ceil(2);
+        if before < half && after >= half {
+            hooks.on_half_capacity();
+        }
+        if after >= capacity {
+            hooks.on_full();
+        }
+    } else if after == 0 && before > 0 {
+        hooks.on_empty();
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("on_full", &self.full.is_some())
+            .field("on_empty", &self.empty.is_some())
+            .field("on_half_capacity", &self.half.is_some())
+            .finish()
+    }
+}
@@ -1,140 +1,821 @@
-mod simple_channel {
+#[cfg(feature = "std")]
+pub mod simple_channel {
     use std::collections::VecDeque;
+    use std::fmt;
     use std::sync::{Condvar, Mutex};
+    #[cfg(test)]
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::cancel::CancellationToken;
+    use crate::errors::{Cancelled, RecvTimeoutError, TryRecvError, WaitError};
+    use crate::platform::backoff::Backoff;
+    use crate::sync_shim::plain_atomic::AtomicUsize;
+    use crate::sync_shim::plain_atomic::Ordering::Relaxed;
+    use crate::sync_shim::trace_event;
 
+    /// How many rounds of `Backoff::spin` `receive` spends checking the
+    /// queue before it waits on the condition variable. Together that's a
+    /// few hundred spin hints, about as long as waking a thread takes.
+    pub const DEFAULT_SPINS: u32 = 8;
+
+    /// A `Mutex<VecDeque>` with a `Condvar`, plus two counters that are
+    /// only written while holding the lock, but can be read without it.
+    /// `len` lets `try_receive` and `is_empty` skip the lock when there's
+    /// nothing to receive, and `waiters` lets `send` skip notifying when
+    /// nobody is waiting, which is a system call with std's `Condvar`.
     pub struct Channel<T> {
         queue: Mutex<VecDeque<T>>,
         item_ready: Condvar,
+        len: AtomicUsize,
+        /// Receivers waiting on `item_ready`.
+        waiters: AtomicUsize,
+        spins: u32,
     }
 
     impl<T> Channel<T> {
-        pub fn new() -> Self {
+        pub const fn new() -> Self {
+            Self::with_spins(DEFAULT_SPINS)
+        }
+
+        /// A channel whose receivers spin for `spins` rounds of backoff
+        /// before they go to sleep, or not at all with 0. Spinning is
+        /// worth it when messages come in faster than a thread wakes up.
+        pub const fn with_spins(spins: u32) -> Self {
             Self {
                 queue: Mutex::new(VecDeque::new()),
                 item_ready: Condvar::new(),
+                len: AtomicUsize::new(0),
+                waiters: AtomicUsize::new(0),
+                spins,
             }
         }
+
         pub fn send(&self, message: T) {
-            self.queue.lock().unwrap().push_back(message);
-            self.item_ready.notify_one();
+            let mut queue = self.queue.lock().unwrap();
+            queue.push_back(message);
+            self.len.store(queue.len(), Relaxed);
+            // Unlocking the queue releases it.
+            trace_event!(Send, core::ptr::from_ref(self).addr(), Release);
+            // Waiters register while holding the lock, so this sees them all.
+            let waiting = self.waiters.load(Relaxed) > 0;
+            drop(queue);
+            if waiting {
+                self.item_ready.notify_one();
+            }
         }
+
         pub fn receive(&self) -> T {
+            if let Some(message) = self.spin_receive() {
+                return message;
+            }
             let mut b = self.queue.lock().unwrap();
             loop {
-                if let Some(message) = b.pop_front() {
+                if let Some(message) = self.pop(&mut b) {
                     return message;
                 }
+                self.waiters.fetch_add(1, Relaxed);
                 b = self.item_ready.wait(b).unwrap();
+                self.waiters.fetch_sub(1, Relaxed);
+            }
+        }
+
+        /// Doesn't touch the lock if the channel is empty.
+        pub fn try_receive(&self) -> Result<T, TryRecvError> {
+            if self.is_empty() {
+                return Err(TryRecvError::Empty);
+            }
+            self.pop(&mut self.queue.lock().unwrap())
+                .ok_or(TryRecvError::Empty)
+        }
+
+        /// The number of messages waiting, without taking the lock. It may
+        /// change right after.
+        pub fn len(&self) -> usize {
+            self.len.load(Relaxed)
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.len() == 0
+        }
+
+        /// Gives up once the timeout has passed without a message.
+        pub fn receive_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+            match Instant::now().checked_add(timeout) {
+                Some(deadline) => self.receive_until(deadline),
+                None => Ok(self.receive()),
+            }
+        }
+
+        /// Gives up at `deadline` if there's no message by then.
+        pub fn receive_until(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+            if let Some(message) = self.spin_receive() {
+                return Ok(message);
+            }
+            let mut b = self.queue.lock().unwrap();
+            loop {
+                if let Some(message) = self.pop(&mut b) {
+                    return Ok(message);
+                }
+                let left = deadline
+                    .checked_duration_since(Instant::now())
+                    .ok_or(RecvTimeoutError::Timeout)?;
+                self.waiters.fetch_add(1, Relaxed);
+                b = self.item_ready.wait_timeout(b, left).unwrap().0;
+                self.waiters.fetch_sub(1, Relaxed);
+            }
+        }
+
+        /// Like `receive`, but gives up once `token` is cancelled.
+        pub fn receive_cancellable(&self, token: &CancellationToken) -> Result<T, Cancelled> {
+            self.receive_interruptible(None, token)
+                .map_err(|_| Cancelled)
+        }
+
+        /// Like `receive_until`, but also gives up once `token` is
+        /// cancelled, without waiting for the deadline.
+        pub fn receive_until_cancellable(
+            &self,
+            deadline: Instant,
+            token: &CancellationToken,
+        ) -> Result<T, WaitError> {
+            self.receive_interruptible(Some(deadline), token)
+        }
+
+        fn receive_interruptible(
+            &self,
+            deadline: Option<Instant>,
+            token: &CancellationToken,
+        ) -> Result<T, WaitError> {
+            let item_ready = &self.item_ready;
+            token
+                .interrupt()
+                .while_registered(&|| item_ready.notify_all(), || {
+                    let mut b = self.queue.lock().unwrap();
+                    loop {
+                        if let Some(message) = self.pop(&mut b) {
+                            return Ok(message);
+                        }
+                        token.check()?;
+                        let left = match deadline {
+                            Some(deadline) => Some(
+                                deadline
+                                    .checked_duration_since(Instant::now())
+                                    .ok_or(WaitError::Timeout)?,
+                            ),
+                            None => None,
+                        };
+                        self.waiters.fetch_add(1, Relaxed);
+                        b = match left {
+                            Some(left) => self.item_ready.wait_timeout(b, left).unwrap().0,
+                            None => self.item_ready.wait(b).unwrap(),
+                        };
+                        self.waiters.fetch_sub(1, Relaxed);
+                    }
+                })
+        }
+
+        /// Only call this while holding the lock, with its guard.
+        fn pop(&self, queue: &mut VecDeque<T>) -> Option<T> {
+            let message = queue.pop_front()?;
+            self.len.store(queue.len(), Relaxed);
+            trace_event!(Receive, core::ptr::from_ref(self).addr(), Acquire);
+            Some(message)
+        }
+
+        /// Checks the queue between spins, without waiting for the lock,
+        /// or taking it while it's empty, so a spinning receiver doesn't
+        /// hold up the senders.
+        fn spin_receive(&self) -> Option<T> {
+            let mut backoff = Backoff::new();
+            for _ in 0..self.spins {
+                if !self.is_empty() {
+                    if let Ok(mut queue) = self.queue.try_lock() {
+                        if let Some(message) = self.pop(&mut queue) {
+                            return Some(message);
+                        }
+                    }
+                }
+                backoff.spin();
+            }
+            None
+        }
+    }
+
+    impl<T> Default for Channel<T> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    /// Doesn't take the lock.
+    impl<T> fmt::Debug for Channel<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Channel")
+                .field("len", &self.len())
+                .field("waiters", &self.waiters.load(Relaxed))
+                .field("spins", &self.spins)
+                .finish()
+        }
+    }
+
+    #[test]
+    fn test_empty_channel_does_not_take_the_lock() {
+        let channel = Channel::new();
+        let guard = channel.queue.lock().unwrap();
+        assert!(channel.is_empty());
+        assert_eq!(channel.try_receive(), Err(TryRecvError::Empty));
+        drop(guard);
+        channel.send(1);
+        channel.send(2);
+        assert_eq!(channel.len(), 2);
+        assert_eq!(channel.try_receive(), Ok(1));
+        assert_eq!(channel.len(), 1);
+    }
+
+    #[test]
+    fn test_send_wakes_a_registered_waiter() {
+        let channel = Channel::with_spins(0);
+        thread::scope(|s| {
+            let receiver = s.spawn(|| channel.receive());
+            while channel.waiters.load(Relaxed) == 0 {
+                thread::yield_now();
             }
+            channel.send(42);
+            assert_eq!(receiver.join().unwrap(), 42);
+        });
+        assert_eq!(channel.waiters.load(Relaxed), 0);
+    }
+
+    #[test]
+    #[cfg(not(loom))]
+    fn test_receive_with_and_without_spinning() {
+        let leaks = crate::leak_check::LeakCheck::new();
+        for channel in [Channel::new(), Channel::with_spins(0)] {
+            thread::scope(|s| {
+                s.spawn(|| {
+                    leaks.track(|| {
+                        for i in 0..100 {
+                            channel.send(i);
+                            if i % 10 == 0 {
+                                thread::sleep(Duration::from_millis(1));
+                            }
+                        }
+                    });
+                });
+                for i in 0..100 {
+                    assert_eq!(channel.receive(), i);
+                }
+            });
         }
     }
+
+    #[test]
+    fn test_cancelled_receive() {
+        let channel = Channel::new();
+        let token = CancellationToken::new();
+        channel.send(1);
+        assert_eq!(channel.receive_cancellable(&token), Ok(1));
+        assert_eq!(
+            channel.receive_until_cancellable(Instant::now(), &token),
+            Err(WaitError::Timeout)
+        );
+        thread::scope(|s| {
+            let waiters: Vec<_> = (0..2)
+                .map(|_| s.spawn(|| channel.receive_cancellable(&token)))
+                .collect();
+            let timed = s.spawn(|| {
+                let deadline = Instant::now() + Duration::from_secs(60);
+                channel.receive_until_cancellable(deadline, &token.child_token())
+            });
+            thread::sleep(Duration::from_millis(10));
+            token.cancel();
+            for w in waiters {
+                assert_eq!(w.join().unwrap(), Err(Cancelled));
+            }
+            assert_eq!(timed.join().unwrap(), Err(WaitError::Cancelled));
+        });
+        // A message that's there is still received.
+        channel.send(2);
+        assert_eq!(channel.receive_cancellable(&token), Ok(2));
+    }
+
+    /// The time from sending to receiving, for messages sent a few
+    /// microseconds apart.
+    #[cfg(test)]
+    fn latency(channel: &Channel<Instant>) -> Duration {
+        const MESSAGES: u32 = 10_000;
+        thread::scope(|s| {
+            s.spawn(|| {
+                for _ in 0..MESSAGES {
+                    let start = Instant::now();
+                    while start.elapsed() < Duration::from_micros(5) {
+                        std::hint::spin_loop();
+                    }
+                    channel.send(Instant::now());
+                }
+            });
+            (0..MESSAGES)
+                .map(|_| channel.receive().elapsed())
+                .sum::<Duration>()
+                / MESSAGES
+        })
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_recv_busy`,
+    /// on a machine with two cores to spare.
+    #[test]
+    #[ignore]
+    fn bench_recv_busy() {
+        println!("   parking only: {:?}", latency(&Channel::with_spins(0)));
+        println!(
+            "spin, then park: {:?}",
+            latency(&Channel::with_spins(DEFAULT_SPINS))
+        );
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_producer_heavy`,
+    /// and compare with the commit before `send` skipped notifying.
+    #[test]
+    #[ignore]
+    fn bench_producer_heavy() {
+        const MESSAGES: u32 = 1_000_000;
+        let channel = Channel::new();
+        let start = Instant::now();
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for i in 0..MESSAGES / 4 {
+                        channel.send(i);
+                    }
+                });
+            }
+            for _ in 0..MESSAGES {
+                channel.receive();
+            }
+        });
+        println!("{:?} per message", start.elapsed() / MESSAGES);
+    }
 }
 
-mod one_shot_channel {
-    use std::cell::UnsafeCell;
-    use std::mem::MaybeUninit;
-    use std::sync::atomic::AtomicBool;
-    use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+#[cfg(feature = "std")]
+pub mod priority_channel {
+    use std::collections::VecDeque;
+    use std::fmt;
+    use std::sync::{Condvar, Mutex};
+    #[cfg(test)]
     use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::errors::{RecvTimeoutError, TryRecvError};
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum Priority {
+        High = 0,
+        Normal = 1,
+        Low = 2,
+    }
 
+    const LEVELS: usize = 3;
+
+    pub const DEFAULT_AGING: Duration = Duration::from_millis(100);
+
+    /// Like `simple_channel`, but messages with a higher priority overtake
+    /// lower ones. To avoid starvation, a message that has been waiting for
+    /// longer than the aging threshold is delivered first regardless of its
+    /// priority (the longest waiting one first).
+    pub struct Channel<T> {
+        queues: Mutex<[VecDeque<(Instant, T)>; LEVELS]>,
+        item_ready: Condvar,
+        aging: Duration,
+    }
+
+    impl<T> Channel<T> {
+        pub const fn new() -> Self {
+            Self::with_aging(DEFAULT_AGING)
+        }
+
+        pub const fn with_aging(aging: Duration) -> Self {
+            Self {
+                queues: Mutex::new([VecDeque::new(), VecDeque::new(), VecDeque::new()]),
+                item_ready: Condvar::new(),
+                aging,
+            }
+        }
+
+        pub fn send(&self, priority: Priority, message: T) {
+            self.queues.lock().unwrap()[priority as usize].push_back((Instant::now(), message));
+            self.item_ready.notify_one();
+        }
+
+        pub fn receive(&self) -> T {
+            let mut b = self.queues.lock().unwrap();
+            loop {
+                if let Some(level) = Self::next_level(&b, self.aging) {
+                    return b[level].pop_front().unwrap().1;
+                }
+                b = self.item_ready.wait(b).unwrap();
+            }
+        }
+
+        pub fn try_receive(&self) -> Result<T, TryRecvError> {
+            let mut b = self.queues.lock().unwrap();
+            let level = Self::next_level(&b, self.aging).ok_or(TryRecvError::Empty)?;
+            Ok(b[level].pop_front().unwrap().1)
+        }
+
+        /// Gives up once the timeout has passed without a message.
+        pub fn receive_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+            match Instant::now().checked_add(timeout) {
+                Some(deadline) => self.receive_until(deadline),
+                None => Ok(self.receive()),
+            }
+        }
+
+        /// Gives up at `deadline` if there's no message by then.
+        pub fn receive_until(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+            let mut b = self.queues.lock().unwrap();
+            loop {
+                if let Some(level) = Self::next_level(&b, self.aging) {
+                    return Ok(b[level].pop_front().unwrap().1);
+                }
+                let left = deadline
+                    .checked_duration_since(Instant::now())
+                    .ok_or(RecvTimeoutError::Timeout)?;
+                b = self.item_ready.wait_timeout(b, left).unwrap().0;
+            }
+        }
+
+        fn next_level(queues: &[VecDeque<(Instant, T)>; LEVELS], aging: Duration) -> Option<usize> {
+            let now = Instant::now();
+            let overdue = (0..LEVELS)
+                .filter_map(|level| Some((queues[level].front()?.0, level)))
+                .filter(|(sent, _)| now.duration_since(*sent) >= aging)
+                .min();
+            match overdue {
+                Some((_, level)) => Some(level),
+                None => (0..LEVELS).find(|&level| !queues[level].is_empty()),
+            }
+        }
+    }
+
+    impl<T> Default for Channel<T> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    /// Doesn't wait for the lock: shows `<locked>` if it's taken.
+    impl<T> fmt::Debug for Channel<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let mut d = f.debug_struct("PriorityChannel");
+            match self.queues.try_lock() {
+                Ok(queues) => d.field("lens", &queues.each_ref().map(VecDeque::len)),
+                Err(_) => d.field("lens", &format_args!("<locked>")),
+            };
+            d.field("aging", &self.aging).finish()
+        }
+    }
+
+    #[test]
+    fn test_higher_priority_first() {
+        let channel = Channel::new();
+        channel.send(Priority::Low, "low");
+        channel.send(Priority::Normal, "normal");
+        channel.send(Priority::High, "high");
+        channel.send(Priority::High, "high 2");
+        assert_eq!(channel.receive(), "high");
+        assert_eq!(channel.receive(), "high 2");
+        assert_eq!(channel.receive(), "normal");
+        assert_eq!(channel.receive(), "low");
+    }
+
+    #[test]
+    fn test_aged_message_overtakes_higher_priority() {
+        let channel = Channel::with_aging(Duration::from_millis(20));
+        channel.send(Priority::Low, "low");
+        thread::sleep(Duration::from_millis(30));
+        channel.send(Priority::High, "high");
+        assert_eq!(channel.receive(), "low");
+        assert_eq!(channel.receive(), "high");
+    }
+
+    #[test]
+    fn test_receive_blocks_until_send() {
+        let channel = Channel::new();
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                channel.send(Priority::Normal, 42);
+            });
+            assert_eq!(channel.receive(), 42);
+        });
+    }
+
+    #[test]
+    fn test_static() {
+        static CHANNEL: Channel<u32> = Channel::new();
+        thread::spawn(|| CHANNEL.send(Priority::Low, 1));
+        assert_eq!(CHANNEL.receive(), 1);
+    }
+}
+
+pub mod one_shot_channel {
+    use core::cell::UnsafeCell;
+    use core::fmt;
+    use core::mem::MaybeUninit;
+    #[cfg(all(test, not(loom)))]
+    use std::thread;
+
+    use crate::errors::{SendError, TryRecvError};
+    use crate::sync_shim::atomic::AtomicU8;
+    use crate::sync_shim::atomic::Ordering::{Acquire, Relaxed, Release};
+    use crate::sync_shim::{chaos_point, const_fn, trace_event, unsync_load_u8};
+
+    /// Nothing sent yet.
+    const EMPTY: u8 = 0;
+    /// A sender is writing the message.
+    const WRITING: u8 = 1;
+    /// The message is there, for the first receiver to take.
+    const READY: u8 = 2;
+    /// A receiver is moving the message out.
+    const READING: u8 = 3;
+    /// Sent and received. Nothing can be sent or received anymore.
+    const CONSUMED: u8 = 4;
+
+    /// A channel for one message, with its whole state in one atomic,
+    /// which only ever moves forward: empty, writing, ready, reading,
+    /// consumed. So there's no combination of flags that doesn't make
+    /// sense, and the message is only touched by whoever moved it to
+    /// `WRITING` or `READING`.
     pub struct Channel<T> {
         message: UnsafeCell<MaybeUninit<T>>,
-        ready: AtomicBool,
-        in_use: AtomicBool,
+        state: AtomicU8,
     }
 
     unsafe impl<T> Sync for Channel<T> where T: Send {}
 
     impl<T> Channel<T> {
-        pub const fn new() -> Self {
-            Self {
-                message: UnsafeCell::new(MaybeUninit::uninit()),
-                ready: AtomicBool::new(false),
-                in_use: AtomicBool::new(false),
+        const_fn! {
+            pub fn new() -> Self {
+                Self {
+                    message: UnsafeCell::new(MaybeUninit::uninit()),
+                    state: AtomicU8::new(EMPTY),
+                }
             }
         }
 
-        /// Panics when trying to send more than one message.
-        pub fn send(&self, message: T) {
-            if self.in_use.swap(true, Relaxed) {
-                panic!("can't send more than one message!");
+        /// Gives the message back when trying to send more than one message.
+        pub fn send(&self, message: T) -> Result<(), SendError<T>> {
+            chaos_point!("one_shot::before_send");
+            if self
+                .state
+                .compare_exchange(EMPTY, WRITING, Relaxed, Relaxed)
+                .is_err()
+            {
+                return Err(SendError(message));
             }
+            chaos_point!("one_shot::writing");
+            // Safety: Only we got to move the state to WRITING.
             unsafe {
                 (*self.message.get()).write(message);
             }
-            self.ready.store(true, Release);
+            chaos_point!("one_shot::send");
+            trace_event!(Send, core::ptr::from_ref(self).addr(), Release);
+            self.state.store(READY, Release);
+            Ok(())
+        }
+
+        /// Panics when trying to send more than one message.
+        pub fn send_or_panic(&self, message: T) {
+            if self.send(message).is_err() {
+                panic!("can't send more than one message!");
+            }
         }
 
         pub fn is_ready(&self) -> bool {
-            self.ready.load(Relaxed)
+            self.state.load(Relaxed) == READY
         }
 
-        /// Panics if no message is available yet.
+        /// Fails if no message is available (anymore).
         ///
         /// Tip: Use `is_ready` to check first.
-        ///
-        /// Safety: Only call this once!
-        pub fn receive(&self) -> T {
-            if !self.ready.swap(false, Acquire) {
-                panic!("no message available!");
+        pub fn receive(&self) -> Result<T, TryRecvError> {
+            chaos_point!("one_shot::before_receive");
+            if self
+                .state
+                .compare_exchange(READY, READING, Acquire, Relaxed)
+                .is_err()
+            {
+                return Err(TryRecvError::Empty);
             }
+            trace_event!(Receive, core::ptr::from_ref(self).addr(), Acquire);
+            chaos_point!("one_shot::reading");
+            // Safety: Only we got to move the state from READY to READING.
+            let message = unsafe { (*self.message.get()).assume_init_read() };
+            chaos_point!("one_shot::read");
+            self.state.store(CONSUMED, Relaxed);
+            Ok(message)
+        }
+
+        /// Panics if no message is available yet.
+        pub fn receive_or_panic(&self) -> T {
+            self.receive().expect("no message available!")
+        }
+    }
+
+    impl<T> Default for Channel<T> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
 
-            // Safety: We've just checked (and reset) the ready flag.
-            unsafe { (*self.message.get()).assume_init_read() }
+    impl<T> fmt::Debug for Channel<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let state = match self.state.load(Relaxed) {
+                EMPTY => "empty",
+                WRITING => "writing",
+                READY => "ready",
+                READING => "reading",
+                _ => "consumed",
+            };
+            f.debug_struct("OneShotChannel")
+                .field("state", &format_args!("{state}"))
+                .finish()
         }
     }
 
     impl<T> Drop for Channel<T> {
         fn drop(&mut self) {
-            if *self.ready.get_mut() {
+            if unsync_load_u8(&mut self.state) == READY {
                 unsafe { self.message.get_mut().assume_init_drop() }
             }
         }
     }
 
     #[test]
+    #[cfg(not(loom))]
     fn test_one_shot_channel_with_parking() {
         let channel = Channel::new();
         let t = thread::current();
         thread::scope(|s| {
             s.spawn(|| {
-                channel.send("hello world!");
+                channel.send("hello world!").unwrap();
                 t.unpark();
             });
             while !channel.is_ready() {
                 thread::park();
             }
-            assert_eq!(channel.receive(), "hello world!");
+            assert_eq!(channel.receive(), Ok("hello world!"));
+            assert_eq!(channel.receive(), Err(TryRecvError::Empty));
         });
     }
 
     #[test]
+    #[cfg(not(loom))]
     #[should_panic]
     fn test_one_shot_channel_calling_send_twice_should_panic() {
         let channel = Channel::new();
         let t = thread::current();
         thread::scope(|s| {
             s.spawn(|| {
-                channel.send("hello world!");
-                channel.send("");
+                channel.send_or_panic("hello world!");
+                channel.send_or_panic("");
                 t.unpark();
             });
             while !channel.is_ready() {
                 thread::park();
             }
-            assert_eq!(channel.receive(), "hello world!");
+            assert_eq!(channel.receive_or_panic(), "hello world!");
+        });
+    }
+
+    #[test]
+    #[cfg(not(loom))]
+    fn test_static() {
+        static CHANNEL: Channel<&str> = Channel::new();
+        thread::spawn(|| CHANNEL.send("hello"));
+        while !CHANNEL.is_ready() {
+            thread::yield_now();
+        }
+        assert_eq!(CHANNEL.receive(), Ok("hello"));
+    }
+
+    #[test]
+    #[cfg(not(loom))]
+    fn test_state_is_one_byte() {
+        assert_eq!(core::mem::size_of::<Channel<u8>>(), 2);
+        let channel = Channel::new();
+        assert_eq!(format!("{channel:?}"), "OneShotChannel { state: empty }");
+        channel.send(1).unwrap();
+        assert_eq!(format!("{channel:?}"), "OneShotChannel { state: ready }");
+        channel.receive().unwrap();
+        assert_eq!(channel.send(2), Err(SendError(2)));
+        assert_eq!(format!("{channel:?}"), "OneShotChannel { state: consumed }");
+    }
+
+    #[test]
+    #[cfg(loom)]
+    fn loom_receive_sees_what_was_written_before_send() {
+        use loom::sync::Arc;
+
+        use crate::sync_shim::atomic::AtomicUsize;
+        use crate::sync_shim::thread;
+
+        crate::sync_shim::model(|| {
+            let channel = Arc::new(Channel::new());
+            let written = Arc::new(AtomicUsize::new(0));
+            let t = thread::spawn({
+                let (channel, written) = (channel.clone(), written.clone());
+                move || {
+                    written.store(1, Relaxed);
+                    channel.send(1).unwrap();
+                }
+            });
+            // No retrying: loom never finishes exploring a spinning receiver.
+            if let Ok(message) = channel.receive() {
+                assert_eq!(message, 1);
+                assert_eq!(written.load(Relaxed), 1);
+            }
+            t.join().unwrap();
+        });
+    }
+
+    #[test]
+    #[cfg(loom)]
+    fn loom_only_one_sender_wins() {
+        use loom::sync::Arc;
+
+        use crate::sync_shim::thread;
+
+        crate::sync_shim::model(|| {
+            let channel = Arc::new(Channel::new());
+            let senders: Vec<_> = (1..=2)
+                .map(|i| {
+                    let channel = channel.clone();
+                    thread::spawn(move || channel.send(i).is_ok())
+                })
+                .collect();
+            let sent: Vec<bool> = senders.into_iter().map(|t| t.join().unwrap()).collect();
+            assert_eq!(sent.iter().filter(|&&ok| ok).count(), 1);
+            let winner = if sent[0] { 1 } else { 2 };
+            assert_eq!(channel.receive(), Ok(winner));
+            assert_eq!(channel.receive(), Err(TryRecvError::Empty));
+        });
+    }
+
+    #[test]
+    #[cfg(loom)]
+    fn loom_message_is_received_or_dropped_exactly_once() {
+        use loom::sync::Arc;
+
+        use crate::sync_shim::atomic::AtomicUsize;
+        use crate::sync_shim::thread;
+
+        struct CountDrops(Arc<AtomicUsize>);
+        impl Drop for CountDrops {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Relaxed);
+            }
+        }
+
+        crate::sync_shim::model(|| {
+            let drops = Arc::new(AtomicUsize::new(0));
+            let channel = Arc::new(Channel::new());
+            let sender = thread::spawn({
+                let (channel, drops) = (channel.clone(), drops.clone());
+                move || channel.send(CountDrops(drops)).is_ok()
+            });
+            let receiver = thread::spawn({
+                let channel = channel.clone();
+                move || channel.receive().is_ok()
+            });
+            // Both threads may have lost to each other, or not.
+            let received = channel.receive().is_ok();
+            assert!(sender.join().unwrap());
+            let received = received as usize + receiver.join().unwrap() as usize;
+            assert!(received <= 1);
+            drop(channel);
+            assert_eq!(drops.load(Relaxed), 1);
         });
     }
 }
 
-mod sender_receiver_channel_with_arc {
-    use std::cell::UnsafeCell;
-    use std::mem::MaybeUninit;
-    use std::sync::atomic::AtomicBool;
-    use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
-    use std::sync::Arc;
+pub mod sender_receiver_channel_with_arc {
+    use core::cell::UnsafeCell;
+    use core::fmt;
+    use core::mem::MaybeUninit;
+    #[cfg(all(test, not(loom)))]
     use std::thread;
 
+    // Our own `Arc`, rather than std's, so loom sees the ordering of its
+    // last drop, before `Channel::drop` reads `ready` without an atomic.
+    use crate::arc::Arc;
+    use crate::errors::TryRecvError;
+    use crate::sync_shim::atomic::AtomicBool;
+    use crate::sync_shim::atomic::Ordering::{Acquire, Relaxed, Release};
+    use crate::sync_shim::{chaos_point, trace_event, unsync_load};
+
     pub struct Sender<T> {
         channel: Arc<Channel<T>>,
     }
@@ -151,6 +832,20 @@ mod sender_receiver_channel_with_arc {
 
     unsafe impl<T> Sync for Channel<T> where T: Send {}
 
+    impl<T> fmt::Debug for Sender<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Sender").finish_non_exhaustive()
+        }
+    }
+
+    impl<T> fmt::Debug for Receiver<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Receiver")
+                .field("ready", &self.is_ready())
+                .finish()
+        }
+    }
+
     pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
         let a = Arc::new(Channel {
             message: UnsafeCell::new(MaybeUninit::uninit()),
@@ -163,6 +858,8 @@ mod sender_receiver_channel_with_arc {
         /// This never panics. :)
         pub fn send(self, message: T) {
             unsafe { (*self.channel.message.get()).write(message) };
+            chaos_point!("one_shot::send");
+            trace_event!(Send, core::ptr::from_ref(&*self.channel).addr(), Release);
             self.channel.ready.store(true, Release);
         }
     }
@@ -171,27 +868,32 @@ mod sender_receiver_channel_with_arc {
         pub fn is_ready(&self) -> bool {
             self.channel.ready.load(Relaxed)
         }
-        pub fn receive(self) -> T {
+        pub fn receive(self) -> Result<T, TryRecvError> {
             if !self.channel.ready.swap(false, Acquire) {
-                panic!("no message available!");
+                return Err(TryRecvError::Empty);
             }
-            unsafe { (*self.channel.message.get()).assume_init_read() }
+            trace_event!(Receive, core::ptr::from_ref(&*self.channel).addr(), Acquire);
+            Ok(unsafe { (*self.channel.message.get()).assume_init_read() })
+        }
+        pub fn receive_or_panic(self) -> T {
+            self.receive().expect("no message available!")
         }
     }
 
     impl<T> Drop for Channel<T> {
         fn drop(&mut self) {
-            if *self.ready.get_mut() {
+            if unsync_load(&mut self.ready) {
                 unsafe { self.message.get_mut().assume_init_drop() }
             }
         }
     }
 
     #[test]
-    #[should_panic]
+    #[cfg(not(loom))]
     fn test_sender_receiver() {
+        let leaks = crate::leak_check::LeakCheck::new();
         thread::scope(|s| {
-            let (sender, receiver) = channel();
+            let (sender, receiver) = leaks.track(channel);
             let t = thread::current();
             s.spawn(move || {
                 sender.send("hello world!");
@@ -201,18 +903,49 @@ mod sender_receiver_channel_with_arc {
             while !receiver.is_ready() {
                 thread::park();
             }
-            assert_eq!(receiver.receive(), "hello world!");
+            assert_eq!(receiver.receive(), Ok("hello world!"));
+        });
+    }
+
+    /// Fails if `send` stores `ready` with less than Release, or `receive`
+    /// swaps it with less than Acquire.
+    #[test]
+    #[cfg(loom)]
+    fn loom_receive_sees_what_was_written_before_send() {
+        use crate::sync_shim::atomic::AtomicUsize;
+        use crate::sync_shim::thread;
+
+        crate::sync_shim::model(|| {
+            let (sender, receiver) = channel();
+            let written = loom::sync::Arc::new(AtomicUsize::new(0));
+            let t = thread::spawn({
+                let written = written.clone();
+                move || {
+                    written.store(1, Relaxed);
+                    sender.send(1);
+                }
+            });
+            if let Ok(message) = receiver.receive() {
+                assert_eq!(message, 1);
+                assert_eq!(written.load(Relaxed), 1);
+            }
+            t.join().unwrap();
         });
     }
 }
 
-mod sender_receiver_channel_with_borrowing {
-    use std::cell::UnsafeCell;
-    use std::mem::MaybeUninit;
-    use std::sync::atomic::AtomicBool;
-    use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+pub mod sender_receiver_channel_with_borrowing {
+    use core::cell::UnsafeCell;
+    use core::fmt;
+    use core::mem::MaybeUninit;
+    #[cfg(all(test, not(loom)))]
     use std::thread;
 
+    use crate::errors::TryRecvError;
+    use crate::sync_shim::atomic::AtomicBool;
+    use crate::sync_shim::atomic::Ordering::{Acquire, Relaxed, Release};
+    use crate::sync_shim::{chaos_point, const_fn, trace_event, unsync_load};
+
     pub struct Channel<T> {
         message: UnsafeCell<MaybeUninit<T>>,
         ready: AtomicBool,
@@ -229,21 +962,53 @@ mod sender_receiver_channel_with_borrowing {
     }
 
     impl<T> Channel<T> {
-        pub const fn new() -> Self {
-            Self {
-                message: UnsafeCell::new(MaybeUninit::uninit()),
-                ready: AtomicBool::new(false),
+        const_fn! {
+            pub fn new() -> Self {
+                Self {
+                    message: UnsafeCell::new(MaybeUninit::uninit()),
+                    ready: AtomicBool::new(false),
+                }
             }
         }
-        pub fn split(&mut self) -> (Sender<T>, Receiver<T>) {
+        pub fn split(&mut self) -> (Sender<'_, T>, Receiver<'_, T>) {
             *self = Self::new();
             (Sender { channel: self }, Receiver { channel: self })
         }
     }
 
+    impl<T> Default for Channel<T> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl<T> fmt::Debug for Channel<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Channel")
+                .field("ready", &self.ready.load(Relaxed))
+                .finish()
+        }
+    }
+
+    impl<T> fmt::Debug for Sender<'_, T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Sender").finish_non_exhaustive()
+        }
+    }
+
+    impl<T> fmt::Debug for Receiver<'_, T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Receiver")
+                .field("ready", &self.is_ready())
+                .finish()
+        }
+    }
+
     impl<T> Sender<'_, T> {
         pub fn send(self, message: T) {
             unsafe { (*self.channel.message.get()).write(message) };
+            chaos_point!("one_shot::send");
+            trace_event!(Send, core::ptr::from_ref(self.channel).addr(), Release);
             self.channel.ready.store(true, Release);
         }
     }
@@ -253,23 +1018,28 @@ mod sender_receiver_channel_with_borrowing {
             self.channel.ready.load(Relaxed)
         }
 
-        pub fn receive(self) -> T {
+        pub fn receive(self) -> Result<T, TryRecvError> {
             if !self.channel.ready.swap(false, Acquire) {
-                panic!("no message available!");
+                return Err(TryRecvError::Empty);
             }
-            unsafe { (*self.channel.message.get()).assume_init_read() }
+            trace_event!(Receive, core::ptr::from_ref(self.channel).addr(), Acquire);
+            Ok(unsafe { (*self.channel.message.get()).assume_init_read() })
+        }
+        pub fn receive_or_panic(self) -> T {
+            self.receive().expect("no message available!")
         }
     }
 
     impl<T> Drop for Channel<T> {
         fn drop(&mut self) {
-            if *self.ready.get_mut() {
+            if unsync_load(&mut self.ready) {
                 unsafe { self.message.get_mut().assume_init_drop() }
             }
         }
     }
 
     #[test]
+    #[cfg(not(loom))]
     fn test_sender_receiver() {
         let mut channel = Channel::new();
         thread::scope(|s| {
@@ -282,7 +1052,7 @@ mod sender_receiver_channel_with_borrowing {
             while !receiver.is_ready() {
                 thread::park();
             }
-            assert_eq!(receiver.receive(), "hello world!");
+            assert_eq!(receiver.receive(), Ok("hello world!"));
         });
     }
 }
@@ -0,0 +1,275 @@
+//! Merging several channels into one stream, in order.
+//!
+//! [`merge_ordered`] takes channels that each receive items in order of
+//! some key, like per-shard event streams ordered by timestamp, and yields
+//! the items of all of them in that order. It holds on to the next item of
+//! every channel, its head, and yields the smallest one. So before it can
+//! yield anything, it has to wait until every channel has a head or is
+//! disconnected: an empty channel might still receive an item that comes
+//! before all the heads it has. A channel that never disconnects and goes
+//! quiet therefore stalls the merge, which is what the timed and
+//! non-blocking variants are for.
+//!
+//! Ties go to the channel that comes first, so the merge is stable.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::channels::traits::Receiver;
+use crate::errors::{RecvError, RecvTimeoutError, TryRecvError};
+
+/// Yields the items of several channels in key order. See the module
+/// docs.
+pub struct MergeOrdered<T, R, F> {
+    receivers: Vec<R>,
+    /// The next item of each channel, once received.
+    heads: Vec<Option<T>>,
+    /// Whether each channel can still receive something.
+    open: Vec<bool>,
+    key: F,
+}
+
+/// Merges `receivers`, which each receive items in the order of `key`,
+/// into one stream in that order.
+pub fn merge_ordered<T, R, K, F>(
+    receivers: impl IntoIterator<Item = R>,
+    key: F,
+) -> MergeOrdered<T, R, F>
+where
+    R: Receiver<T>,
+    F: FnMut(&T) -> K,
+    K: Ord,
+{
+    let receivers: Vec<R> = receivers.into_iter().collect();
+    MergeOrdered {
+        heads: receivers.iter().map(|_| None).collect(),
+        open: receivers.iter().map(|_| true).collect(),
+        receivers,
+        key,
+    }
+}
+
+impl<T, R, K, F> MergeOrdered<T, R, F>
+where
+    R: Receiver<T>,
+    F: FnMut(&T) -> K,
+    K: Ord,
+{
+    /// Blocks until every channel has a head or is disconnected, and takes
+    /// the smallest head. Fails once every channel is disconnected and
+    /// empty.
+    pub fn recv(&mut self) -> Result<T, RecvError> {
+        self.fill(|r| r.recv().map_err(|RecvError| TryRecvError::Disconnected))
+            .map_err(|_| RecvError)?;
+        self.take_smallest().ok_or(RecvError)
+    }
+
+    /// Fails with `Empty` if a channel that isn't disconnected has no head
+    /// yet, since its next item might be the smallest.
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        self.fill(Receiver::try_recv)?;
+        self.take_smallest().ok_or(TryRecvError::Disconnected)
+    }
+
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.recv_until(deadline),
+            None => self
+                .recv()
+                .map_err(|RecvError| RecvTimeoutError::Disconnected),
+        }
+    }
+
+    /// The heads received before it timed out are kept for the next call.
+    pub fn recv_until(&mut self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.fill(|r| match r.recv_until(deadline) {
+            Ok(item) => Ok(item),
+            Err(RecvTimeoutError::Timeout) => Err(TryRecvError::Empty),
+            Err(RecvTimeoutError::Disconnected) => Err(TryRecvError::Disconnected),
+        })
+        .map_err(|_| RecvTimeoutError::Timeout)?;
+        self.take_smallest().ok_or(RecvTimeoutError::Disconnected)
+    }
+
+    /// Receives a head for every open channel that doesn't have one, with
+    /// `recv`, and stops at the first one that's `Empty`. Disconnected
+    /// channels are closed for good.
+    fn fill(
+        &mut self,
+        mut recv: impl FnMut(&R) -> Result<T, TryRecvError>,
+    ) -> Result<(), TryRecvError> {
+        for ((receiver, head), open) in self
+            .receivers
+            .iter()
+            .zip(&mut self.heads)
+            .zip(&mut self.open)
+        {
+            if head.is_some() || !*open {
+                continue;
+            }
+            match recv(receiver) {
+                Ok(item) => *head = Some(item),
+                Err(TryRecvError::Disconnected) => *open = false,
+                Err(TryRecvError::Empty) => return Err(TryRecvError::Empty),
+            }
+        }
+        Ok(())
+    }
+
+    fn take_smallest(&mut self) -> Option<T> {
+        let key = &mut self.key;
+        let (i, _) = self
+            .heads
+            .iter()
+            .enumerate()
+            .filter_map(|(i, head)| Some((i, key(head.as_ref()?))))
+            // `min_by` keeps the first of equal ones.
+            .min_by(|(_, a), (_, b)| a.cmp(b))?;
+        self.heads[i].take()
+    }
+
+    /// How many of the channels aren't disconnected yet, or still have a
+    /// head to yield.
+    pub fn active(&self) -> usize {
+        self.open
+            .iter()
+            .zip(&self.heads)
+            .filter(|(open, head)| **open || head.is_some())
+            .count()
+    }
+
+    /// Gives the channels back, with the heads that were received from
+    /// them but not yielded yet.
+    pub fn into_inner(self) -> Vec<(R, Option<T>)> {
+        self.receivers.into_iter().zip(self.heads).collect()
+    }
+}
+
+/// Blocks for every item. Ends once every channel is disconnected.
+impl<T, R, K, F> Iterator for MergeOrdered<T, R, F>
+where
+    R: Receiver<T>,
+    F: FnMut(&T) -> K,
+    K: Ord,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.recv().ok()
+    }
+}
+
+impl<T, R, F> fmt::Debug for MergeOrdered<T, R, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MergeOrdered")
+            .field("channels", &self.receivers.len())
+            .field("heads", &self.heads.iter().filter(|h| h.is_some()).count())
+            .field("open", &self.open.iter().filter(|o| **o).count())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::channels::channel::simple_channel;
+    use crate::channels::merge::merge_ordered;
+    use crate::channels::traits::Receiver;
+    use crate::errors::{RecvError, RecvTimeoutError, TryRecvError};
+
+    /// The crate's channels never disconnect, std's do.
+    struct Closable<T>(mpsc::Receiver<T>);
+
+    impl<T> Receiver<T> for Closable<T> {
+        fn try_recv(&self) -> Result<T, TryRecvError> {
+            self.0.try_recv().map_err(|e| match e {
+                mpsc::TryRecvError::Empty => TryRecvError::Empty,
+                mpsc::TryRecvError::Disconnected => TryRecvError::Disconnected,
+            })
+        }
+
+        fn recv(&self) -> Result<T, RecvError> {
+            self.0.recv().map_err(|_| RecvError)
+        }
+    }
+
+    #[test]
+    fn test_merges_shards_by_timestamp() {
+        const N: u64 = if cfg!(miri) { 20 } else { 1000 };
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..4).map(|_| mpsc::channel()).unzip();
+        thread::scope(|s| {
+            for (shard, tx) in senders.into_iter().enumerate() {
+                s.spawn(move || {
+                    // Every shard gets its own timestamps, in order.
+                    for i in 0..N {
+                        tx.send((i * 4 + shard as u64, shard)).unwrap();
+                    }
+                });
+            }
+            let merged: Vec<_> =
+                merge_ordered(receivers.into_iter().map(Closable), |e| e.0).collect();
+            assert_eq!(merged.len() as u64, 4 * N);
+            assert!(merged.iter().enumerate().all(|(i, e)| e.0 == i as u64));
+        });
+    }
+
+    #[test]
+    fn test_waits_for_every_head() {
+        let (a_tx, a) = mpsc::channel();
+        let (b_tx, b) = mpsc::channel();
+        let mut merged = merge_ordered([Closable(a), Closable(b)], |&t: &u32| t);
+        a_tx.send(5).unwrap();
+        // `b` could still get something before 5.
+        assert_eq!(merged.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(
+            merged.recv_timeout(Duration::from_millis(5)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        b_tx.send(3).unwrap();
+        b_tx.send(5).unwrap();
+        assert_eq!(merged.try_recv(), Ok(3));
+        // A tie goes to the first channel.
+        assert_eq!(merged.into_inner()[0].1, Some(5));
+
+        let (a_tx, a) = mpsc::channel();
+        let (b_tx, b) = mpsc::channel::<u32>();
+        let mut merged = merge_ordered([Closable(a), Closable(b)], |&t| t);
+        a_tx.send(1).unwrap();
+        a_tx.send(2).unwrap();
+        drop(b_tx);
+        assert_eq!(merged.recv(), Ok(1));
+        assert_eq!(merged.active(), 1);
+        drop(a_tx);
+        assert_eq!(merged.try_recv(), Ok(2));
+        assert_eq!(merged.try_recv(), Err(TryRecvError::Disconnected));
+        assert_eq!(merged.recv(), Err(RecvError));
+        assert_eq!(merged.active(), 0);
+    }
+
+    #[test]
+    fn test_borrowed_channels() {
+        let shards = [
+            simple_channel::Channel::new(),
+            simple_channel::Channel::new(),
+        ];
+        for (i, word) in ["b", "d", "a", "c", "e"].into_iter().enumerate() {
+            shards[i % 2].send(word);
+        }
+        // The channels hold "b", "a", "e" and "d", "c", out of order, so
+        // this only shows that the smallest head goes first.
+        let mut merged = merge_ordered(&shards, |w: &&str| *w);
+        assert_eq!(merged.recv_until(Instant::now()), Ok("b"));
+        assert_eq!(merged.try_recv(), Ok("a"));
+        assert_eq!(merged.try_recv(), Ok("d"));
+        assert_eq!(merged.try_recv(), Ok("c"));
+        // The other channel is empty, and never disconnects.
+        assert_eq!(merged.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(
+            format!("{merged:?}"),
+            "MergeOrdered { channels: 2, heads: 1, open: 2 }"
+        );
+    }
+}
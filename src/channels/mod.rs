@@ -1 +1,36 @@
+#[cfg(feature = "std")]
+pub mod backpressure;
 pub mod channel;
+#[cfg(feature = "std")]
+pub mod merge;
+#[cfg(feature = "std")]
+pub mod mpsc;
+pub mod oneshot_async;
+#[cfg(feature = "std")]
+pub mod pipe;
+#[cfg(feature = "std")]
+pub mod pubsub;
+#[cfg(feature = "std")]
+pub mod rpc;
+#[cfg(feature = "std")]
+pub mod sharded;
+#[cfg(feature = "std")]
+mod signal;
+#[cfg(feature = "std")]
+pub mod tick;
+#[cfg(feature = "std")]
+pub mod traits;
+
+pub use channel::one_shot_channel::Channel as OneShotChannel;
+#[cfg(feature = "std")]
+pub use channel::priority_channel::{Channel as PriorityChannel, Priority};
+#[cfg(feature = "std")]
+pub use channel::simple_channel::Channel;
+#[cfg(feature = "std")]
+pub use pubsub::{PubSub, Subscriber};
+#[cfg(feature = "std")]
+pub use sharded::ShardedChannel;
+#[cfg(feature = "std")]
+pub use tick::{tick, tick_with, MissedTicks, Ticker};
+#[cfg(feature = "std")]
+pub use traits::{Receiver, Sender};
@@ -0,0 +1,514 @@
+//! A drop-in for `std::sync::mpsc`, on this crate's locks.
+//!
+//! `channel`, `sync_channel` and the types they return have the same
+//! methods as std's, and the errors are the ones from
+//! [`errors`](crate::errors), which have the same names and variants, so
+//! switching is a matter of changing the import:
+//!
+//! ```
+//! use atomics_and_locks::channels::mpsc::{channel, RecvError};
+//!
+//! let (tx, rx) = channel();
+//! std::thread::spawn(move || tx.send(42).unwrap());
+//! assert_eq!(rx.recv(), Ok(42));
+//! assert_eq!(rx.recv(), Err(RecvError));
+//! ```
+//!
+//! The queue is a `VecDeque` behind the crate's [`Mutex`], and waiting is
+//! done on two counters with `platform::wait`: one for the receiver, which
+//! senders bump, and one for blocked senders, which the receiver bumps.
+//!
+//! Like std's, `sync_channel(0)` is a rendezvous channel: `send` returns
+//! once the message is received, and `try_send` only succeeds while the
+//! receiver is waiting.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::arc::Arc;
+use crate::channels::signal::Signal;
+pub use crate::errors::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError};
+use crate::locks::Mutex;
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    /// Signaled when a message is sent, or the last sender leaves.
+    sent: Signal,
+    /// Signaled when a message is received, or the receiver leaves.
+    received: Signal,
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    /// `None` for `channel`, unbounded.
+    bound: Option<usize>,
+    senders: usize,
+    receiver: bool,
+    /// How many messages were ever sent and received, for a rendezvous
+    /// sender to know when its message was taken.
+    pushed: u64,
+    taken: u64,
+}
+
+/// The sending half of a [`channel`].
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// The sending half of a [`sync_channel`].
+pub struct SyncSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// An unbounded channel: sending never blocks.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let shared = new_shared(None);
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+/// A channel that holds at most `bound` messages, after which sending
+/// blocks. With 0, every send waits for its message to be received.
+pub fn sync_channel<T>(bound: usize) -> (SyncSender<T>, Receiver<T>) {
+    let shared = new_shared(Some(bound));
+    (
+        SyncSender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+fn new_shared<T>(bound: Option<usize>) -> Arc<Shared<T>> {
+    Arc::new(Shared {
+        state: Mutex::new(State {
+            queue: VecDeque::new(),
+            bound,
+            senders: 1,
+            receiver: true,
+            pushed: 0,
+            taken: 0,
+        }),
+        sent: Signal::new(),
+        received: Signal::new(),
+    })
+}
+
+impl<T> State<T> {
+    /// Whether there's room for one more message. A rendezvous channel
+    /// holds one while its sender waits for it to be taken.
+    fn has_room(&self) -> bool {
+        self.bound
+            .is_none_or(|bound| self.queue.len() < bound.max(1))
+    }
+
+    /// Returns the message's ticket.
+    fn push(&mut self, message: T) -> u64 {
+        self.queue.push_back(message);
+        self.pushed += 1;
+        self.pushed
+    }
+}
+
+impl<T> Shared<T> {
+    /// Blocks while there's no room, then sends.
+    fn send(&self, message: T) -> Result<(), SendError<T>> {
+        let ticket = loop {
+            let mut state = self.state.lock();
+            if !state.receiver {
+                return Err(SendError(message));
+            }
+            if state.has_room() {
+                break state.push(message);
+            }
+            drop(state);
+            self.received.wait_for(
+                || {
+                    let state = self.state.lock();
+                    !state.receiver || state.has_room()
+                },
+                None,
+            );
+        };
+        self.sent.notify();
+        if self.state.lock().bound == Some(0) {
+            return self.rendezvous(ticket);
+        }
+        Ok(())
+    }
+
+    fn try_send(&self, message: T) -> Result<(), TrySendError<T>> {
+        let mut state = self.state.lock();
+        if !state.receiver {
+            return Err(TrySendError::Disconnected(message));
+        }
+        let room = match state.bound {
+            // Only straight into the hands of a waiting receiver.
+            Some(0) => state.queue.is_empty() && self.sent.is_waiting(),
+            _ => state.has_room(),
+        };
+        if !room {
+            return Err(TrySendError::Full(message));
+        }
+        state.push(message);
+        drop(state);
+        self.sent.notify();
+        Ok(())
+    }
+
+    /// Waits for the message with `ticket` to be received. If the receiver
+    /// leaves first, the message is taken back out.
+    fn rendezvous(&self, ticket: u64) -> Result<(), SendError<T>> {
+        self.received.wait_for(
+            || {
+                let state = self.state.lock();
+                state.taken >= ticket || !state.receiver
+            },
+            None,
+        );
+        let mut state = self.state.lock();
+        if state.taken >= ticket {
+            return Ok(());
+        }
+        // It's the only message, since there's room for one.
+        Err(SendError(state.queue.pop_back().unwrap()))
+    }
+
+    fn add_sender(&self) {
+        self.state.lock().senders += 1;
+    }
+
+    fn drop_sender(&self) {
+        let mut state = self.state.lock();
+        state.senders -= 1;
+        let last = state.senders == 0;
+        drop(state);
+        if last {
+            self.sent.notify();
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    /// Fails, giving the message back, if the receiver is gone.
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        self.shared.send(t)
+    }
+}
+
+impl<T> SyncSender<T> {
+    /// Blocks while the channel is full. Fails, giving the message back,
+    /// if the receiver is gone.
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        self.shared.send(t)
+    }
+
+    pub fn try_send(&self, t: T) -> Result<(), TrySendError<T>> {
+        self.shared.try_send(t)
+    }
+}
+
+impl<T> Receiver<T> {
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.shared.state.lock();
+        match state.queue.pop_front() {
+            Some(message) => {
+                state.taken += 1;
+                drop(state);
+                self.shared.received.notify();
+                Ok(message)
+            }
+            None if state.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Blocks until a message arrives. Fails once every sender is gone and
+    /// the queue is empty.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.recv_deadline_opt(None).map_err(|_| RecvError)
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.recv_deadline_opt(Instant::now().checked_add(timeout))
+    }
+
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.recv_deadline_opt(Some(deadline))
+    }
+
+    fn recv_deadline_opt(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        loop {
+            match self.try_recv() {
+                Ok(message) => return Ok(message),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            let ready = self.shared.sent.wait_for(
+                || {
+                    let state = self.shared.state.lock();
+                    !state.queue.is_empty() || state.senders == 0
+                },
+                deadline,
+            );
+            if !ready {
+                return Err(RecvTimeoutError::Timeout);
+            }
+        }
+    }
+
+    /// Blocks for every message, until every sender is gone.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { rx: self }
+    }
+
+    /// The messages that are there already, without blocking.
+    pub fn try_iter(&self) -> TryIter<'_, T> {
+        TryIter { rx: self }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.add_sender();
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Clone for SyncSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.add_sender();
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.drop_sender();
+    }
+}
+
+impl<T> Drop for SyncSender<T> {
+    fn drop(&mut self) {
+        self.shared.drop_sender();
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().receiver = false;
+        self.shared.received.notify();
+    }
+}
+
+pub struct Iter<'a, T> {
+    rx: &'a Receiver<T>,
+}
+
+pub struct TryIter<'a, T> {
+    rx: &'a Receiver<T>,
+}
+
+pub struct IntoIter<T> {
+    rx: Receiver<T>,
+}
+
+impl<T> Iterator for Iter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+impl<T> Iterator for TryIter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.try_recv().ok()
+    }
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { rx: self }
+    }
+}
+
+// Like std's, they don't show anything.
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Debug for SyncSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyncSender").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Debug for Iter<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Iter").field("rx", self.rx).finish()
+    }
+}
+
+impl<T> fmt::Debug for TryIter<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TryIter").field("rx", self.rx).finish()
+    }
+}
+
+impl<T> fmt::Debug for IntoIter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IntoIter").field("rx", &self.rx).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    /// The same code, once against std and once against ours, with only
+    /// the import changed.
+    macro_rules! same_as_std {
+        ($name:ident, $mpsc:path) => {
+            fn $name() -> Vec<String> {
+                use $mpsc as mpsc;
+                let mut log = Vec::new();
+
+                let (tx, rx) = mpsc::channel();
+                thread::scope(|s| {
+                    for t in 0..3 {
+                        let tx = tx.clone();
+                        s.spawn(move || (0..10).for_each(|i| tx.send(t * 10 + i).unwrap()));
+                    }
+                });
+                drop(tx);
+                let mut all: Vec<u32> = rx.iter().collect();
+                all.sort_unstable();
+                log.push(format!("{:?}", all == (0..30).collect::<Vec<_>>()));
+                log.push(format!("{:?}", rx.try_recv()));
+                log.push(format!("{:?}", rx.recv_timeout(Duration::ZERO)));
+
+                let (tx, rx) = mpsc::sync_channel(2);
+                log.push(format!("{:?}", tx.try_send(1)));
+                log.push(format!("{:?}", tx.try_send(2)));
+                log.push(format!("{:?}", tx.try_send(3).unwrap_err().to_string()));
+                log.push(format!("{:?}", rx.try_iter().collect::<Vec<_>>()));
+                log.push(format!("{:?}", rx.recv_timeout(Duration::from_millis(5))));
+                drop(rx);
+                log.push(format!("{:?}", tx.send(4).unwrap_err().0));
+
+                // A rendezvous: `send` returns once the message is taken.
+                let (tx, rx) = mpsc::sync_channel(0);
+                log.push(format!("{:?}", tx.try_send(5).is_err()));
+                let receiver = thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(10));
+                    assert_eq!(rx.recv(), Ok(6));
+                    rx
+                });
+                log.push(format!("{:?}", tx.send(6)));
+                let rx = receiver.join().unwrap();
+                drop(tx);
+                log.push(format!("{:?}", rx.recv()));
+                log
+            }
+        };
+    }
+
+    same_as_std!(with_std, std::sync::mpsc);
+    same_as_std!(with_ours, crate::channels::mpsc);
+
+    #[test]
+    fn test_same_as_std() {
+        assert_eq!(with_std(), with_ours());
+    }
+
+    #[test]
+    fn test_rendezvous_sender_gets_its_message_back() {
+        use crate::channels::mpsc::{sync_channel, SendError};
+
+        let (tx, rx) = sync_channel(0);
+        thread::scope(|s| {
+            let sender = s.spawn(move || tx.send("hello"));
+            thread::sleep(Duration::from_millis(10));
+            // Never received.
+            drop(rx);
+            assert_eq!(sender.join().unwrap(), Err(SendError("hello")));
+        });
+    }
+
+    #[test]
+    fn test_bounded_senders_block() {
+        use crate::channels::mpsc::sync_channel;
+
+        const N: u32 = if cfg!(miri) { 50 } else { 1_000 };
+        let (tx, rx) = sync_channel(1);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                let tx = tx.clone();
+                s.spawn(move || (0..N).for_each(|i| tx.send(i).unwrap()));
+            }
+            drop(tx);
+            assert_eq!(rx.into_iter().count(), 4 * N as usize);
+        });
+    }
+
+    #[test]
+    fn test_recv_timeout_too_long_for_an_instant() {
+        use crate::channels::mpsc::channel;
+
+        let (tx, rx) = channel();
+        thread::scope(|s| {
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                tx.send("late").unwrap();
+            });
+            // Waits without a deadline, like std does.
+            assert_eq!(rx.recv_timeout(Duration::MAX), Ok("late"));
+        });
+    }
+}
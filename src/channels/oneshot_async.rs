@@ -0,0 +1,297 @@
+// Needs native compare-and-swap. The crate's own `Arc` doesn't, with
+// `portable-atomic`.
+#[cfg(target_has_atomic = "ptr")]
+use alloc::sync::Arc;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::future::{poll_fn, Future};
+use core::mem::MaybeUninit;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+#[cfg(not(target_has_atomic = "ptr"))]
+use crate::arc::Arc;
+use crate::errors::{RecvError, SendError, TryRecvError};
+use crate::locks::spin_lock::SpinLock;
+use crate::sync_shim::plain_atomic::AtomicU8;
+use crate::sync_shim::plain_atomic::Ordering::{Acquire, Relaxed, Release};
+
+/// A message has been written and may be read.
+const SENT: u8 = 1;
+/// The receiver already took the message out.
+const TAKEN: u8 = 2;
+/// The receiver was closed or dropped.
+const RX_CLOSED: u8 = 4;
+/// The sender was dropped (with or without sending).
+const TX_DROPPED: u8 = 8;
+
+struct Channel<T> {
+    state: AtomicU8,
+    message: UnsafeCell<MaybeUninit<T>>,
+    rx_waker: SpinLock<Option<Waker>>,
+    tx_waker: SpinLock<Option<Waker>>,
+}
+
+unsafe impl<T> Sync for Channel<T> where T: Send {}
+
+pub struct Sender<T> {
+    channel: Arc<Channel<T>>,
+}
+
+/// Resolves to the message, or to `RecvError` if the sender was dropped
+/// without sending. Polling it again after that panics in debug builds.
+pub struct Receiver<T> {
+    channel: Arc<Channel<T>>,
+    #[cfg(debug_assertions)]
+    completed: bool,
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let a = Arc::new(Channel {
+        state: AtomicU8::new(0),
+        message: UnsafeCell::new(MaybeUninit::uninit()),
+        rx_waker: SpinLock::new(None),
+        tx_waker: SpinLock::new(None),
+    });
+    (
+        Sender { channel: a.clone() },
+        Receiver {
+            channel: a,
+            #[cfg(debug_assertions)]
+            completed: false,
+        },
+    )
+}
+
+fn wake(slot: &SpinLock<Option<Waker>>) {
+    let waker = slot.lock().take();
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
+
+impl<T> Sender<T> {
+    /// Gives the message back if the receiver is already closed. A receiver
+    /// closing concurrently with this call may or may not get the message.
+    pub fn send(self, message: T) -> Result<(), SendError<T>> {
+        if self.is_closed() {
+            return Err(SendError(message));
+        }
+        // Safety: Only the (single, consumed) sender writes the message,
+        // and the receiver doesn't read it before it sees SENT.
+        unsafe { (*self.channel.message.get()).write(message) };
+        self.channel.state.fetch_or(SENT, Release);
+        wake(&self.channel.rx_waker);
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.channel.state.load(Relaxed) & RX_CLOSED != 0
+    }
+
+    /// Ready once the receiver is closed or dropped, so a producer can stop
+    /// working on a message nobody will receive.
+    pub fn poll_closed(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.is_closed() {
+            return Poll::Ready(());
+        }
+        *self.channel.tx_waker.lock() = Some(cx.waker().clone());
+        // Check again, in case the receiver closed before our waker was in place.
+        if self.is_closed() {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+
+    pub async fn closed(&mut self) {
+        poll_fn(|cx| self.poll_closed(cx)).await
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.channel.state.fetch_or(TX_DROPPED, Release);
+        wake(&self.channel.rx_waker);
+    }
+}
+
+impl<T> Receiver<T> {
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let state = self.channel.state.load(Acquire);
+        if state & TAKEN != 0 {
+            return Err(TryRecvError::Disconnected);
+        }
+        if state & SENT != 0 {
+            // Only we set TAKEN, so Relaxed is enough.
+            self.channel.state.fetch_or(TAKEN, Relaxed);
+            // Safety: SENT was set with Release after the message was written,
+            // and TAKEN makes sure we only read it once.
+            return Ok(unsafe { (*self.channel.message.get()).assume_init_read() });
+        }
+        if state & TX_DROPPED != 0 {
+            return Err(TryRecvError::Disconnected);
+        }
+        Err(TryRecvError::Empty)
+    }
+
+    /// Tells the sender nobody is interested anymore. A message sent
+    /// before this can still be received.
+    pub fn close(&mut self) {
+        self.channel.state.fetch_or(RX_CLOSED, Relaxed);
+        wake(&self.channel.tx_waker);
+    }
+
+    fn poll_message(&mut self, cx: &mut Context<'_>) -> Poll<Result<T, RecvError>> {
+        match self.try_recv() {
+            Ok(message) => return Poll::Ready(Ok(message)),
+            Err(TryRecvError::Disconnected) => return Poll::Ready(Err(RecvError)),
+            Err(TryRecvError::Empty) => {}
+        }
+        *self.channel.rx_waker.lock() = Some(cx.waker().clone());
+        // Check again, in case the sender finished before our waker was in place.
+        match self.try_recv() {
+            Ok(message) => Poll::Ready(Ok(message)),
+            Err(TryRecvError::Disconnected) => Poll::Ready(Err(RecvError)),
+            Err(TryRecvError::Empty) => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<T, RecvError>> {
+        // Otherwise, a second poll would resolve to `RecvError`, as if the
+        // sender never sent anything.
+        #[cfg(debug_assertions)]
+        assert!(!self.completed, "one-shot Receiver polled after completion");
+        let poll = self.poll_message(cx);
+        #[cfg(debug_assertions)]
+        {
+            self.completed = poll.is_ready();
+        }
+        poll
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender")
+            .field("closed", &self.is_closed())
+            .finish()
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.channel.state.load(Relaxed);
+        f.debug_struct("Receiver")
+            .field("sent", &(state & SENT != 0))
+            .field("taken", &(state & TAKEN != 0))
+            .field("sender_dropped", &(state & TX_DROPPED != 0))
+            .finish()
+    }
+}
+
+impl<T> Drop for Channel<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() & (SENT | TAKEN) == SENT {
+            unsafe { self.message.get_mut().assume_init_drop() }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::task::{Context, Poll, Waker};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::channels::oneshot_async::channel;
+    use crate::errors::{RecvError, SendError, TryRecvError};
+    use crate::exec::executor::block_on;
+
+    #[test]
+    fn test_receive_from_another_thread() {
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            sender.send("hello world!").unwrap();
+        });
+        assert_eq!(block_on(receiver), Ok("hello world!"));
+    }
+
+    #[test]
+    fn test_dropped_sender_resolves_receiver() {
+        let (sender, receiver) = channel::<()>();
+        let mut receiver = pin!(receiver);
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(receiver.as_mut().poll(&mut cx).is_pending());
+        drop(sender);
+        assert_eq!(receiver.as_mut().poll(&mut cx), Poll::Ready(Err(RecvError)));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "one-shot Receiver polled after completion")]
+    fn test_polling_after_completion_panics() {
+        let (sender, receiver) = channel();
+        sender.send(1).unwrap();
+        let mut receiver = pin!(receiver);
+        let mut cx = Context::from_waker(Waker::noop());
+        assert_eq!(receiver.as_mut().poll(&mut cx), Poll::Ready(Ok(1)));
+        let _ = receiver.as_mut().poll(&mut cx);
+    }
+
+    #[test]
+    fn test_send_to_closed_receiver_fails() {
+        let (sender, mut receiver) = channel();
+        assert!(!sender.is_closed());
+        receiver.close();
+        assert_eq!(sender.send(1), Err(SendError(1)));
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn test_poll_closed_wakes_producer() {
+        let (mut sender, receiver) = channel::<()>();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            drop(receiver);
+        });
+        block_on(sender.closed());
+        assert!(sender.is_closed());
+    }
+
+    #[test]
+    fn test_unreceived_message_is_dropped() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+        struct DetectDrop;
+        impl Drop for DetectDrop {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+        let (sender, receiver) = channel();
+        assert!(sender.send(DetectDrop).is_ok());
+        assert_eq!(NUM_DROPS.load(Relaxed), 0);
+        drop(receiver);
+        assert_eq!(NUM_DROPS.load(Relaxed), 1);
+
+        let (sender, mut receiver) = channel();
+        assert!(sender.send(DetectDrop).is_ok());
+        drop(receiver.try_recv());
+        drop(receiver);
+        assert_eq!(NUM_DROPS.load(Relaxed), 2);
+    }
+}
@@ -0,0 +1,493 @@
+//! A byte pipe between two threads, with `std::io::Read` and `Write` on
+//! either end.
+//!
+//! The bytes go through a single-producer single-consumer ring buffer. The
+//! writer only ever moves `tail`, and the reader only `head`, so neither
+//! takes a lock: the writer copies bytes in behind `tail` and publishes
+//! them by storing it with Release, and the reader copies them out and
+//! hands the space back by storing `head`. Positions are wrapping `u32`s
+//! and the capacity is a power of two, so `tail - head` is always how many
+//! bytes are in the ring.
+//!
+//! A side that finds the ring empty, or full, waits on a counter the other
+//! side bumps whenever it moves its position or goes away, so closing one
+//! end wakes the other too. Reading returns `Ok(0)`, the end of the stream,
+//! once the writer is dropped and the rest is read. Writing fails with
+//! `BrokenPipe` once the reader is dropped. Either end can be given a
+//! timeout, after which waiting fails with `TimedOut`.
+//!
+//! A pipe made with `pipe_with_backpressure` calls the hooks of a
+//! [`Backpressure`] as the ring fills up and drains, counting bytes.
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::arc::Arc;
+use crate::cache_padded::CachePadded;
+use crate::channels::backpressure::{changed, Backpressure};
+use crate::channels::signal::Signal;
+use crate::errors::AllocError;
+use crate::oom;
+use crate::sync_shim::plain_atomic::Ordering::{Acquire, Relaxed, Release};
+use crate::sync_shim::plain_atomic::{AtomicBool, AtomicU32};
+
+struct Ring {
+    buf: Box<[UnsafeCell<u8>]>,
+    /// Where the reader reads next. Only the reader stores it.
+    head: CachePadded<AtomicU32>,
+    /// Where the writer writes next. Only the writer stores it.
+    tail: CachePadded<AtomicU32>,
+    /// Signaled when bytes are written, or the writer is dropped.
+    data: Signal,
+    /// Signaled when bytes are read, or the reader is dropped.
+    space: Signal,
+    reader_closed: AtomicBool,
+    writer_closed: AtomicBool,
+    hooks: Option<Box<dyn Backpressure>>,
+}
+
+unsafe impl Sync for Ring {}
+
+/// The reading end of a [`pipe`].
+pub struct ChannelReader {
+    ring: Arc<Ring>,
+    timeout: Option<Duration>,
+}
+
+/// The writing end of a [`pipe`].
+pub struct ChannelWriter {
+    ring: Arc<Ring>,
+    timeout: Option<Duration>,
+}
+
+/// A pipe that holds `capacity` bytes, rounded up to a power of two. Does
+/// what the `OomPolicy` says if it can't allocate them.
+///
+/// Panics if `capacity` is zero or more than 2³¹.
+pub fn pipe(capacity: usize) -> (ChannelWriter, ChannelReader) {
+    try_pipe(capacity).unwrap_or_else(|e| oom::handle(e))
+}
+
+/// A pipe like `pipe(capacity)`, or an error instead of failing to
+/// allocate it.
+pub fn try_pipe(capacity: usize) -> Result<(ChannelWriter, ChannelReader), AllocError> {
+    new_pipe(capacity, None)
+}
+
+/// A pipe like `pipe(capacity)`, that calls `hooks` when the writer fills
+/// it up, or the reader empties it.
+pub fn pipe_with_backpressure(
+    capacity: usize,
+    hooks: impl Backpressure + 'static,
+) -> (ChannelWriter, ChannelReader) {
+    new_pipe(capacity, Some(Box::new(hooks))).unwrap_or_else(|e| oom::handle(e))
+}
+
+fn new_pipe(
+    capacity: usize,
+    hooks: Option<Box<dyn Backpressure>>,
+) -> Result<(ChannelWriter, ChannelReader), AllocError> {
+    assert!(
+        capacity > 0 && capacity <= 1 << 31,
+        "pipe capacity must be between 1 and 2^31"
+    );
+    let ring = Arc::try_new(Ring {
+        buf: oom::try_boxed_slice(capacity.next_power_of_two(), || UnsafeCell::new(0))?,
+        head: CachePadded::new(AtomicU32::new(0)),
+        tail: CachePadded::new(AtomicU32::new(0)),
+        data: Signal::new(),
+        space: Signal::new(),
+        reader_closed: AtomicBool::new(false),
+        writer_closed: AtomicBool::new(false),
+        hooks,
+    })?;
+    Ok((
+        ChannelWriter {
+            ring: ring.clone(),
+            timeout: None,
+        },
+        ChannelReader {
+            ring,
+            timeout: None,
+        },
+    ))
+}
+
+impl Ring {
+    fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    fn ptr(&self, pos: u32) -> *mut u8 {
+        let i = pos as usize & (self.buf.len() - 1);
+        UnsafeCell::raw_get(self.buf[i..].as_ptr())
+    }
+
+    /// How many bytes are in the ring.
+    fn len(&self) -> usize {
+        self.tail
+            .load(Acquire)
+            .wrapping_sub(self.head.load(Acquire)) as usize
+    }
+
+    /// Copies as much of `data` in as fits. Only the writer calls this.
+    fn write_some(&self, data: &[u8]) -> usize {
+        let tail = self.tail.load(Relaxed);
+        // Acquire, so the reader is done with the space it handed back.
+        let head = self.head.load(Acquire);
+        let len = tail.wrapping_sub(head) as usize;
+        let n = (self.capacity() - len).min(data.len());
+        if n == 0 {
+            return 0;
+        }
+        self.copy(tail, n, |dst, src_offset, len| {
+            // Safety: These bytes are between `tail` and `head` plus the
+            // capacity, which the reader doesn't touch.
+            unsafe { dst.copy_from_nonoverlapping(data[src_offset..].as_ptr(), len) }
+        });
+        self.tail.store(tail.wrapping_add(n as u32), Release);
+        self.data.notify();
+        if let Some(hooks) = &self.hooks {
+            changed(&**hooks, self.capacity(), len, len + n);
+        }
+        n
+    }
+
+    /// Copies as many bytes out as there are, up to `out.len()`. Only the
+    /// reader calls this.
+    fn read_some(&self, out: &mut [u8]) -> usize {
+        let head = self.head.load(Relaxed);
+        // Acquire, so we see the bytes written before it.
+        let tail = self.tail.load(Acquire);
+        let len = tail.wrapping_sub(head) as usize;
+        let n = len.min(out.len());
+        if n == 0 {
+            return 0;
+        }
+        self.copy(head, n, |src, dst_offset, len| {
+            // Safety: These bytes are between `head` and `tail`, which the
+            // writer doesn't touch.
+            unsafe { src.copy_to_nonoverlapping(out[dst_offset..].as_mut_ptr(), len) }
+        });
+        self.head.store(head.wrapping_add(n as u32), Release);
+        self.space.notify();
+        if let Some(hooks) = &self.hooks {
+            changed(&**hooks, self.capacity(), len, len - n);
+        }
+        n
+    }
+
+    /// Calls `f` with the ring pointer, the offset into the caller's slice,
+    /// and the length, for the one or two pieces `n` bytes from `pos` on
+    /// are split into by the end of the buffer.
+    fn copy(&self, pos: u32, n: usize, mut f: impl FnMut(*mut u8, usize, usize)) {
+        let start = pos as usize & (self.capacity() - 1);
+        let first = n.min(self.capacity() - start);
+        f(self.ptr(pos), 0, first);
+        if first < n {
+            f(self.ptr(0), first, n - first);
+        }
+    }
+}
+
+impl ChannelReader {
+    /// How long a read waits for bytes before it fails with `TimedOut`,
+    /// or `None` to wait as long as it takes.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// How many bytes can be read without waiting.
+    pub fn available(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.ring.capacity()
+    }
+}
+
+impl io::Read for ChannelReader {
+    /// Waits until there's at least one byte, or the writer is gone.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let ring = &*self.ring;
+        let deadline = self.timeout.and_then(|t| Instant::now().checked_add(t));
+        loop {
+            let n = ring.read_some(buf);
+            if n > 0 {
+                return Ok(n);
+            }
+            if ring.writer_closed.load(Acquire) {
+                // It might have written more right before it left.
+                return Ok(ring.read_some(buf));
+            }
+            if !ring.data.wait_for(
+                || ring.len() > 0 || ring.writer_closed.load(Acquire),
+                deadline,
+            ) {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+        }
+    }
+}
+
+impl ChannelWriter {
+    /// How long a write waits for space before it fails with `TimedOut`,
+    /// or `None` to wait as long as it takes.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    pub fn write_timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// How many bytes can be written without waiting.
+    pub fn free(&self) -> usize {
+        self.ring.capacity() - self.ring.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.ring.capacity()
+    }
+}
+
+impl io::Write for ChannelWriter {
+    /// Waits until there's room for at least one byte, or the reader is
+    /// gone.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let ring = &*self.ring;
+        let deadline = self.timeout.and_then(|t| Instant::now().checked_add(t));
+        loop {
+            if ring.reader_closed.load(Acquire) {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            let n = ring.write_some(buf);
+            if n > 0 {
+                return Ok(n);
+            }
+            if !ring.space.wait_for(
+                || ring.len() < ring.capacity() || ring.reader_closed.load(Acquire),
+                deadline,
+            ) {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+        }
+    }
+
+    /// Bytes are visible to the reader as soon as they're written, so
+    /// there's nothing to flush.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for ChannelReader {
+    fn drop(&mut self) {
+        self.ring.reader_closed.store(true, Release);
+        self.ring.space.notify();
+    }
+}
+
+impl Drop for ChannelWriter {
+    fn drop(&mut self) {
+        self.ring.writer_closed.store(true, Release);
+        self.ring.data.notify();
+    }
+}
+
+impl fmt::Debug for ChannelReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChannelReader")
+            .field("available", &self.available())
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+impl fmt::Debug for ChannelWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChannelWriter")
+            .field("free", &self.free())
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, BufRead, BufReader, Read, Write};
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::channels::backpressure::{Backpressure, Hooks};
+    use crate::channels::pipe::{pipe, pipe_with_backpressure};
+
+    #[test]
+    fn test_copy_through_a_small_pipe() {
+        const LEN: usize = if cfg!(miri) { 1000 } else { 100_000 };
+        let data: Vec<u8> = (0..LEN).map(|i| (i * 7 % 251) as u8).collect();
+        // Not a power of two, and much smaller than the data, so it wraps
+        // around a lot.
+        let (mut writer, mut reader) = pipe(13);
+        assert_eq!(writer.capacity(), 16);
+        thread::scope(|s| {
+            s.spawn(|| {
+                io::copy(&mut &data[..], &mut writer).unwrap();
+                drop(writer);
+            });
+            let mut received = Vec::new();
+            reader.read_to_end(&mut received).unwrap();
+            assert_eq!(received, data);
+        });
+    }
+
+    #[test]
+    fn test_lines() {
+        let (mut writer, reader) = pipe(64);
+        thread::scope(|s| {
+            s.spawn(move || {
+                for i in 0..100 {
+                    writeln!(writer, "line {i}").unwrap();
+                }
+            });
+            let lines: Vec<String> = BufReader::new(reader).lines().map(Result::unwrap).collect();
+            assert_eq!(lines.len(), 100);
+            assert_eq!(lines[42], "line 42");
+        });
+    }
+
+    #[test]
+    fn test_closing() {
+        let (mut writer, mut reader) = pipe(4);
+        writer.write_all(b"abc").unwrap();
+        assert_eq!(reader.available(), 3);
+        drop(writer);
+        // The rest is still read before the end of the stream.
+        let mut buf = [0; 8];
+        assert_eq!(reader.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"abc");
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+
+        let (mut writer, reader) = pipe(4);
+        thread::scope(|s| {
+            // Blocked on a full pipe when the reader goes away.
+            let w = s.spawn(move || writer.write_all(b"too long for it"));
+            thread::sleep(Duration::from_millis(10));
+            drop(reader);
+            let err = w.join().unwrap().unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        });
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let (mut writer, mut reader) = pipe(2);
+        reader.set_read_timeout(Some(Duration::from_millis(5)));
+        writer.set_write_timeout(Some(Duration::from_millis(5)));
+        let err = reader.read(&mut [0; 4]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(writer.write(b"abc").unwrap(), 2);
+        assert_eq!(writer.free(), 0);
+        let err = writer.write(b"c").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                let mut buf = [0; 2];
+                reader.set_read_timeout(Some(Duration::MAX));
+                reader.read_exact(&mut buf).unwrap();
+                assert_eq!(&buf, b"ab");
+            });
+            writer.set_write_timeout(Some(Duration::from_secs(10)));
+            assert_eq!(writer.write(b"c").unwrap(), 1);
+        });
+        assert_eq!(
+            format!("{writer:?}"),
+            "ChannelWriter { free: 1, capacity: 2 }"
+        );
+    }
+
+    #[derive(Default)]
+    struct Counts([AtomicUsize; 3]);
+
+    impl Backpressure for Counts {
+        fn on_full(&self) {
+            self.0[0].fetch_add(1, Relaxed);
+        }
+
+        fn on_empty(&self) {
+            self.0[1].fetch_add(1, Relaxed);
+        }
+
+        fn on_half_capacity(&self) {
+            self.0[2].fetch_add(1, Relaxed);
+        }
+    }
+
+    impl Counts {
+        /// Full, empty, half.
+        fn get(&self) -> [usize; 3] {
+            self.0.each_ref().map(|c| c.load(Relaxed))
+        }
+    }
+
+    #[test]
+    fn test_backpressure_hooks() {
+        let counts = Arc::new(Counts::default());
+        let (mut w, mut r) = pipe_with_backpressure(16, counts.clone());
+        let mut buf = [0; 16];
+        w.write_all(&[1; 7]).unwrap();
+        assert_eq!(counts.get(), [0, 0, 0]);
+        w.write_all(&[2; 2]).unwrap();
+        assert_eq!(counts.get(), [0, 0, 1]);
+        // Only called when crossing.
+        w.write_all(&[3; 2]).unwrap();
+        assert_eq!(counts.get(), [0, 0, 1]);
+        assert_eq!(w.write(&[4; 10]).unwrap(), 5);
+        assert_eq!(counts.get(), [1, 0, 1]);
+        r.read_exact(&mut buf[..10]).unwrap();
+        assert_eq!(counts.get(), [1, 0, 1]);
+        r.read_exact(&mut buf[..6]).unwrap();
+        assert_eq!(counts.get(), [1, 1, 1]);
+        // Filling it in one go crosses half and fills it.
+        w.write_all(&[5; 16]).unwrap();
+        assert_eq!(counts.get(), [2, 1, 2]);
+
+        // The hooks run on the side that made the change.
+        let full = Arc::new(AtomicUsize::new(0));
+        let hooks = Hooks::new().on_full({
+            let full = full.clone();
+            move || {
+                full.fetch_add(1, Relaxed);
+            }
+        });
+        assert_eq!(
+            format!("{hooks:?}"),
+            "Hooks { on_full: true, on_empty: false, on_half_capacity: false }"
+        );
+        let (mut w, mut r) = pipe_with_backpressure(4, hooks);
+        thread::scope(|s| {
+            s.spawn(move || w.write_all(&[0; 64]).unwrap());
+            let mut out = Vec::new();
+            r.read_to_end(&mut out).unwrap();
+            assert_eq!(out.len(), 64);
+        });
+        assert!(full.load(Relaxed) >= 1);
+    }
+}
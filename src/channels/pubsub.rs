@@ -0,0 +1,341 @@
+//! Publish/subscribe by topic.
+//!
+//! Every subscriber has its own queue, a `simple_channel`, and a message
+//! published to a topic is cloned into the queue of each subscriber of the
+//! topic, and moved into the last one. Which subscribers that are is looked
+//! up in a subscription table, split into shards by the hash of the topic,
+//! each behind an `RwLock`: publishing only read-locks the shard of its
+//! topic, so publishers don't wait for each other, and only subscribing
+//! and unsubscribing write-lock it.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::time::{Duration, Instant};
+
+use crate::arc::Arc;
+use crate::channels::channel::simple_channel;
+use crate::errors::{AllocError, RecvTimeoutError, TryRecvError};
+use crate::locks::{RawFutexRwLock, RwLock};
+use crate::oom;
+use crate::sync_shim::plain_atomic::AtomicU64;
+use crate::sync_shim::plain_atomic::Ordering::Relaxed;
+
+/// How many shards `PubSub::new` splits the subscription table into.
+pub const DEFAULT_SHARDS: usize = 16;
+
+/// The subscribers of each topic, split into shards.
+type Shard<K, T> = RwLock<HashMap<K, Vec<Arc<Mailbox<K, T>>>>, RawFutexRwLock>;
+
+struct Table<K, T> {
+    shards: Box<[Shard<K, T>]>,
+    hasher: RandomState,
+    next_id: AtomicU64,
+}
+
+struct Mailbox<K, T> {
+    /// Tells the subscribers of a topic apart, to unsubscribe one.
+    id: u64,
+    queue: simple_channel::Channel<(K, T)>,
+}
+
+/// A handle to publish with, and to make subscribers. Clones share the
+/// subscription table.
+pub struct PubSub<K, T> {
+    table: Arc<Table<K, T>>,
+}
+
+/// Receives the messages published to the topics it subscribed to, in the
+/// order they were published to each topic. Dropping it unsubscribes it
+/// from all of them.
+pub struct Subscriber<K: Hash + Eq, T> {
+    table: Arc<Table<K, T>>,
+    mailbox: Arc<Mailbox<K, T>>,
+    topics: Vec<K>,
+}
+
+impl<K: Hash + Eq, T> Table<K, T> {
+    fn shard(&self, topic: &K) -> &Shard<K, T> {
+        &self.shards[self.hasher.hash_one(topic) as usize % self.shards.len()]
+    }
+
+    fn unsubscribe(&self, topic: &K, id: u64) {
+        let mut shard = self.shard(topic).write();
+        if let Some(subscribers) = shard.get_mut(topic) {
+            subscribers.retain(|m| m.id != id);
+            if subscribers.is_empty() {
+                shard.remove(topic);
+            }
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, T: Clone> PubSub<K, T> {
+    pub fn new() -> Self {
+        Self::with_shards(DEFAULT_SHARDS)
+    }
+
+    /// Does what the `OomPolicy` says if it can't allocate the table.
+    pub fn with_shards(shards: usize) -> Self {
+        Self::try_with_shards(shards).unwrap_or_else(|e| oom::handle(e))
+    }
+
+    /// Returns an error instead of failing to allocate the table. The
+    /// shards are empty, so that's all `PubSub` allocates up front.
+    pub fn try_with_shards(shards: usize) -> Result<Self, AllocError> {
+        assert!(shards > 0, "a PubSub needs at least one shard");
+        Ok(Self {
+            table: Arc::try_new(Table {
+                shards: oom::try_boxed_slice(shards, || RwLock::new(HashMap::new()))?,
+                hasher: RandomState::new(),
+                next_id: AtomicU64::new(0),
+            })?,
+        })
+    }
+
+    /// A subscriber to no topics yet.
+    pub fn subscriber(&self) -> Subscriber<K, T> {
+        Subscriber {
+            table: self.table.clone(),
+            mailbox: Arc::new(Mailbox {
+                id: self.table.next_id.fetch_add(1, Relaxed),
+                queue: simple_channel::Channel::new(),
+            }),
+            topics: Vec::new(),
+        }
+    }
+
+    /// A subscriber to `topics`.
+    pub fn subscribe(&self, topics: impl IntoIterator<Item = K>) -> Subscriber<K, T> {
+        let mut subscriber = self.subscriber();
+        topics.into_iter().for_each(|t| subscriber.subscribe(t));
+        subscriber
+    }
+
+    /// Sends `message` to every subscriber of `topic`, and returns how
+    /// many there were. It's dropped if there were none.
+    pub fn publish(&self, topic: K, message: T) -> usize {
+        let shard = self.table.shard(&topic).read();
+        let Some((last, rest)) = shard.get(&topic).and_then(|s| s.split_last()) else {
+            return 0;
+        };
+        for mailbox in rest {
+            mailbox.queue.send((topic.clone(), message.clone()));
+        }
+        last.queue.send((topic, message));
+        rest.len() + 1
+    }
+
+    pub fn subscriber_count(&self, topic: &K) -> usize {
+        self.table
+            .shard(topic)
+            .read()
+            .get(topic)
+            .map_or(0, Vec::len)
+    }
+}
+
+impl<K: Hash + Eq + Clone, T> Subscriber<K, T> {
+    /// Also receives the messages published to `topic` from now on. Does
+    /// nothing if it's subscribed already.
+    pub fn subscribe(&mut self, topic: K) {
+        if self.topics.contains(&topic) {
+            return;
+        }
+        let mut shard = self.table.shard(&topic).write();
+        shard
+            .entry(topic.clone())
+            .or_default()
+            .push(self.mailbox.clone());
+        drop(shard);
+        self.topics.push(topic);
+    }
+
+    /// Stops receiving messages published to `topic` from now on. Those
+    /// already queued are still received. Returns whether it was
+    /// subscribed.
+    pub fn unsubscribe(&mut self, topic: &K) -> bool {
+        let Some(i) = self.topics.iter().position(|t| t == topic) else {
+            return false;
+        };
+        self.table.unsubscribe(topic, self.mailbox.id);
+        self.topics.swap_remove(i);
+        true
+    }
+
+    pub fn topics(&self) -> &[K] {
+        &self.topics
+    }
+
+    /// Blocks until a message arrives, and returns it with its topic.
+    pub fn recv(&self) -> (K, T) {
+        self.mailbox.queue.receive()
+    }
+
+    pub fn try_recv(&self) -> Result<(K, T), TryRecvError> {
+        self.mailbox.queue.try_receive()
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<(K, T), RecvTimeoutError> {
+        self.mailbox.queue.receive_timeout(timeout)
+    }
+
+    pub fn recv_until(&self, deadline: Instant) -> Result<(K, T), RecvTimeoutError> {
+        self.mailbox.queue.receive_until(deadline)
+    }
+
+    /// How many messages are waiting to be received.
+    pub fn len(&self) -> usize {
+        self.mailbox.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K: Hash + Eq, T> Drop for Subscriber<K, T> {
+    fn drop(&mut self) {
+        for topic in &self.topics {
+            self.table.unsubscribe(topic, self.mailbox.id);
+        }
+    }
+}
+
+impl<K, T> Clone for PubSub<K, T> {
+    fn clone(&self) -> Self {
+        Self {
+            table: self.table.clone(),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, T: Clone> Default for PubSub<K, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, T> fmt::Debug for PubSub<K, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PubSub")
+            .field("shards", &self.table.shards.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K: Hash + Eq + fmt::Debug, T> fmt::Debug for Subscriber<K, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscriber")
+            .field("topics", &self.topics)
+            .field("queued", &self.mailbox.queue.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::channels::pubsub::PubSub;
+    use crate::errors::{RecvTimeoutError, TryRecvError};
+
+    #[test]
+    fn test_routing() {
+        let bus = PubSub::with_shards(2);
+        let mut prices = bus.subscribe(["AAPL", "MSFT"]);
+        let all = bus.subscribe(["AAPL", "MSFT", "GOOG"]);
+        assert_eq!(bus.publish("AAPL", 1), 2);
+        assert_eq!(bus.publish("GOOG", 2), 1);
+        assert_eq!(bus.publish("TSLA", 3), 0);
+        assert_eq!(prices.try_recv(), Ok(("AAPL", 1)));
+        assert_eq!(prices.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(all.len(), 2);
+        assert_eq!(all.recv(), ("AAPL", 1));
+        assert_eq!(all.recv(), ("GOOG", 2));
+
+        assert!(prices.unsubscribe(&"AAPL"));
+        assert!(!prices.unsubscribe(&"AAPL"));
+        assert_eq!(prices.topics(), ["MSFT"]);
+        assert_eq!(bus.subscriber_count(&"AAPL"), 1);
+        drop(all);
+        assert_eq!(bus.subscriber_count(&"AAPL"), 0);
+        assert_eq!(bus.publish("AAPL", 4), 0);
+        assert_eq!(
+            format!("{prices:?}"),
+            r#"Subscriber { topics: ["MSFT"], queued: 0 }"#
+        );
+    }
+
+    #[test]
+    fn test_publishers_and_subscribers_on_threads() {
+        const N: u32 = if cfg!(miri) { 20 } else { 1000 };
+        const TOPICS: [&str; 4] = ["a", "b", "c", "d"];
+        let bus = PubSub::new();
+        thread::scope(|s| {
+            let subscribers: Vec<_> = TOPICS
+                .iter()
+                .map(|&topic| {
+                    // Subscribed before anything is published.
+                    let sub = bus.subscribe([topic, "all"]);
+                    s.spawn(move || {
+                        let mut next = [0; 2];
+                        for _ in 0..2 * N {
+                            let (t, i) = sub.recv();
+                            // In order per topic.
+                            let n = &mut next[usize::from(t == "all")];
+                            assert!(t == topic || t == "all");
+                            assert_eq!(i, *n);
+                            *n += 1;
+                        }
+                        assert_eq!(sub.try_recv(), Err(TryRecvError::Empty));
+                    })
+                })
+                .collect();
+            for topic in TOPICS {
+                let bus = bus.clone();
+                s.spawn(move || {
+                    for i in 0..N {
+                        assert_eq!(bus.publish(topic, i), 1);
+                    }
+                });
+            }
+            for i in 0..N {
+                assert_eq!(bus.publish("all", i), TOPICS.len());
+            }
+            subscribers.into_iter().for_each(|t| t.join().unwrap());
+        });
+        assert_eq!(bus.subscriber_count(&"all"), 0);
+    }
+
+    #[test]
+    fn test_try_with_shards() {
+        let bus = PubSub::try_with_shards(1).unwrap();
+        let sub = bus.subscribe(["a"]);
+        assert_eq!(bus.publish("a", 1), 1);
+        assert_eq!(sub.try_recv(), Ok(("a", 1)));
+        assert!(PubSub::<&str, u8>::try_with_shards(usize::MAX / 2).is_err());
+    }
+
+    #[test]
+    fn test_recv_timeout() {
+        let bus = PubSub::<u8, String>::new();
+        let sub = bus.subscribe([7]);
+        assert_eq!(
+            sub.recv_timeout(Duration::from_millis(5)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                bus.publish(7, "late".to_string());
+            });
+            assert_eq!(
+                sub.recv_timeout(Duration::from_secs(10)),
+                Ok((7, "late".to_string()))
+            );
+        });
+    }
+}
@@ -0,0 +1,448 @@
+//! Requests that get a reply.
+//!
+//! Clients send requests over a `simple_channel`, each paired with the
+//! sending half of an `oneshot_async` channel for its reply, and get the
+//! receiving half back. The server receives `(request, ReplySender)` pairs,
+//! and replies to each one whenever it's done, in any order.
+//!
+//! A reply can be waited for by blocking the thread, with or without a
+//! timeout, or by awaiting it. If the server drops the `ReplySender`
+//! without replying, or is dropped itself with requests still queued, the
+//! client gets a `RecvError` instead of waiting forever. Once every client
+//! is dropped, the server's `recv` returns `RecvError` after the last
+//! queued request.
+//!
+//! Like an `Arc`, a `Client` can be downgraded to a `WeakClient`, which
+//! doesn't count as a client, for background tasks that should only send
+//! requests while someone else keeps the channel open. Upgrading it fails
+//! once the last client is gone.
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::arc::{Arc, Weak};
+use crate::channels::channel::simple_channel;
+use crate::channels::oneshot_async;
+use crate::errors::{RecvError, RecvTimeoutError, SendError, TryRecvError};
+use crate::exec::executor::{block_on, block_on_until};
+use crate::sync_shim::plain_atomic::Ordering::SeqCst;
+use crate::sync_shim::plain_atomic::{AtomicBool, AtomicUsize};
+
+struct Shared<Req, Resp> {
+    /// `None` is sent by the last client to leave, to wake the server.
+    queue: simple_channel::Channel<Option<(Req, ReplySender<Resp>)>>,
+    clients: AtomicUsize,
+    server_dropped: AtomicBool,
+}
+
+pub struct Client<Req, Resp> {
+    shared: Arc<Shared<Req, Resp>>,
+}
+
+/// A handle that can become a `Client` again, as long as there still is
+/// one. See [`Client::downgrade`].
+pub struct WeakClient<Req, Resp> {
+    shared: Weak<Shared<Req, Resp>>,
+}
+
+pub struct Server<Req, Resp> {
+    shared: Arc<Shared<Req, Resp>>,
+}
+
+/// Where the server sends the reply to one request.
+pub struct ReplySender<Resp> {
+    tx: oneshot_async::Sender<Resp>,
+}
+
+/// Where the client receives the reply to one request. It's also a future
+/// that resolves to the reply.
+pub struct ReplyReceiver<Resp> {
+    rx: oneshot_async::Receiver<Resp>,
+}
+
+pub fn channel<Req, Resp>() -> (Client<Req, Resp>, Server<Req, Resp>) {
+    let shared = Arc::new(Shared {
+        queue: simple_channel::Channel::new(),
+        clients: AtomicUsize::new(1),
+        server_dropped: AtomicBool::new(false),
+    });
+    (
+        Client {
+            shared: shared.clone(),
+        },
+        Server { shared },
+    )
+}
+
+impl<Req, Resp> Shared<Req, Resp> {
+    /// Drops the queued requests, so their clients get a `RecvError`.
+    fn drain(&self) {
+        while self.queue.try_receive().is_ok() {}
+    }
+}
+
+impl<Req, Resp> Client<Req, Resp> {
+    /// Queues `request`, and returns where its reply will arrive. Gives
+    /// the request back if the server is gone.
+    pub fn send(&self, request: Req) -> Result<ReplyReceiver<Resp>, SendError<Req>> {
+        if self.shared.server_dropped.load(SeqCst) {
+            return Err(SendError(request));
+        }
+        let (tx, rx) = oneshot_async::channel();
+        self.shared.queue.send(Some((request, ReplySender { tx })));
+        // The server drains the queue after setting the flag, and we check
+        // it after queueing, so one of us drops the request if it's gone.
+        if self.shared.server_dropped.load(SeqCst) {
+            self.shared.drain();
+        }
+        Ok(ReplyReceiver { rx })
+    }
+
+    /// Sends `request` and blocks until the reply arrives.
+    pub fn call(&self, request: Req) -> Result<Resp, RecvError> {
+        self.send(request).map_err(|_| RecvError)?.recv()
+    }
+
+    /// A handle that doesn't keep the server's `recv` waiting for it.
+    pub fn downgrade(&self) -> WeakClient<Req, Resp> {
+        WeakClient {
+            shared: Arc::downgrade(&self.shared),
+        }
+    }
+
+    /// Sends `request` and blocks for at most `timeout` for the reply.
+    pub fn call_timeout(&self, request: Req, timeout: Duration) -> Result<Resp, RecvTimeoutError> {
+        let mut reply = self
+            .send(request)
+            .map_err(|_| RecvTimeoutError::Disconnected)?;
+        reply.recv_timeout(timeout)
+    }
+}
+
+impl<Req, Resp> WeakClient<Req, Resp> {
+    /// Fails once every `Client` has been dropped, even though the server
+    /// might not have noticed yet, so it never sees one come back.
+    pub fn upgrade(&self) -> Option<Client<Req, Resp>> {
+        let shared = self.shared.upgrade()?;
+        let mut n = shared.clients.load(SeqCst);
+        loop {
+            if n == 0 {
+                return None;
+            }
+            match shared
+                .clients
+                .compare_exchange_weak(n, n + 1, SeqCst, SeqCst)
+            {
+                Ok(_) => return Some(Client { shared }),
+                Err(e) => n = e,
+            }
+        }
+    }
+}
+
+impl<Req, Resp> Server<Req, Resp> {
+    /// Blocks until a request arrives. Fails once every client is gone and
+    /// all their requests have been received.
+    pub fn recv(&self) -> Result<(Req, ReplySender<Resp>), RecvError> {
+        if self.shared.clients.load(SeqCst) == 0 && self.shared.queue.is_empty() {
+            return Err(RecvError);
+        }
+        self.shared.queue.receive().ok_or(RecvError)
+    }
+
+    pub fn try_recv(&self) -> Result<(Req, ReplySender<Resp>), TryRecvError> {
+        match self.shared.queue.try_receive() {
+            Ok(Some(request)) => Ok(request),
+            Ok(None) => Err(TryRecvError::Disconnected),
+            Err(TryRecvError::Empty) if self.shared.clients.load(SeqCst) == 0 => {
+                Err(TryRecvError::Disconnected)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn recv_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<(Req, ReplySender<Resp>), RecvTimeoutError> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.recv_until(deadline),
+            None => self
+                .recv()
+                .map_err(|RecvError| RecvTimeoutError::Disconnected),
+        }
+    }
+
+    pub fn recv_until(
+        &self,
+        deadline: Instant,
+    ) -> Result<(Req, ReplySender<Resp>), RecvTimeoutError> {
+        if self.shared.clients.load(SeqCst) == 0 && self.shared.queue.is_empty() {
+            return Err(RecvTimeoutError::Disconnected);
+        }
+        match self.shared.queue.receive_until(deadline) {
+            Ok(Some(request)) => Ok(request),
+            Ok(None) => Err(RecvTimeoutError::Disconnected),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Receives requests until every client is gone.
+    pub fn iter(&self) -> impl Iterator<Item = (Req, ReplySender<Resp>)> + '_ {
+        std::iter::from_fn(|| self.recv().ok())
+    }
+}
+
+impl<Resp> ReplySender<Resp> {
+    /// Gives the reply back if the client isn't waiting for it anymore.
+    pub fn reply(self, reply: Resp) -> Result<(), SendError<Resp>> {
+        self.tx.send(reply)
+    }
+
+    /// Whether the client dropped its `ReplyReceiver`, so the reply isn't
+    /// needed anymore.
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+}
+
+impl<Resp> ReplyReceiver<Resp> {
+    /// Blocks until the reply arrives.
+    pub fn recv(self) -> Result<Resp, RecvError> {
+        block_on(self.rx)
+    }
+
+    pub fn try_recv(&mut self) -> Result<Resp, TryRecvError> {
+        self.rx.try_recv()
+    }
+
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Resp, RecvTimeoutError> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.recv_until(deadline),
+            None => block_on(&mut self.rx).map_err(|RecvError| RecvTimeoutError::Disconnected),
+        }
+    }
+
+    /// Can be called again after it timed out.
+    pub fn recv_until(&mut self, deadline: Instant) -> Result<Resp, RecvTimeoutError> {
+        match block_on_until(&mut self.rx, deadline) {
+            Some(result) => result.map_err(|_| RecvTimeoutError::Disconnected),
+            None => Err(RecvTimeoutError::Timeout),
+        }
+    }
+}
+
+impl<Resp> Future for ReplyReceiver<Resp> {
+    type Output = Result<Resp, RecvError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Resp, RecvError>> {
+        Pin::new(&mut self.rx).poll(cx)
+    }
+}
+
+impl<Req, Resp> Clone for Client<Req, Resp> {
+    fn clone(&self) -> Self {
+        self.shared.clients.fetch_add(1, SeqCst);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<Req, Resp> Clone for WeakClient<Req, Resp> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<Req, Resp> Drop for Client<Req, Resp> {
+    fn drop(&mut self) {
+        if self.shared.clients.fetch_sub(1, SeqCst) == 1 {
+            self.shared.queue.send(None);
+        }
+    }
+}
+
+impl<Req, Resp> Drop for Server<Req, Resp> {
+    fn drop(&mut self) {
+        self.shared.server_dropped.store(true, SeqCst);
+        self.shared.drain();
+    }
+}
+
+impl<Req, Resp> fmt::Debug for Client<Req, Resp> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("clients", &self.shared.clients.load(SeqCst))
+            .field("server_dropped", &self.shared.server_dropped.load(SeqCst))
+            .finish()
+    }
+}
+
+impl<Req, Resp> fmt::Debug for WeakClient<Req, Resp> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("WeakClient")
+    }
+}
+
+impl<Req, Resp> fmt::Debug for Server<Req, Resp> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Server")
+            .field("clients", &self.shared.clients.load(SeqCst))
+            .field("queued", &self.shared.queue.len())
+            .finish()
+    }
+}
+
+impl<Resp> fmt::Debug for ReplySender<Resp> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplySender")
+            .field("closed", &self.is_closed())
+            .finish()
+    }
+}
+
+impl<Resp> fmt::Debug for ReplyReceiver<Resp> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ReplyReceiver").field(&self.rx).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::channels::rpc::channel;
+    use crate::errors::{RecvError, RecvTimeoutError, SendError, TryRecvError};
+    use crate::exec::executor::block_on;
+
+    #[test]
+    fn test_calls_from_many_clients() {
+        const N: u64 = if cfg!(miri) { 10 } else { 1000 };
+        let (client, server) = channel::<u64, u64>();
+        thread::scope(|s| {
+            s.spawn(move || {
+                // Until every client below is dropped.
+                for (request, reply) in server.iter() {
+                    reply.reply(request * 2).unwrap();
+                }
+            });
+            for t in 0..4 {
+                let client = client.clone();
+                s.spawn(move || {
+                    for i in 0..N {
+                        assert_eq!(client.call(t * N + i), Ok(2 * (t * N + i)));
+                    }
+                });
+            }
+            drop(client);
+        });
+    }
+
+    #[test]
+    fn test_replies_out_of_order() {
+        let (client, server) = channel();
+        let mut first = client.send("first").unwrap();
+        let second = client.send("second").unwrap();
+        let (a, reply_a) = server.try_recv().unwrap();
+        let (b, reply_b) = server.recv().unwrap();
+        assert_eq!((a, b), ("first", "second"));
+        assert_eq!(first.try_recv(), Err(TryRecvError::Empty));
+        reply_b.reply(2).unwrap();
+        assert_eq!(block_on(second), Ok(2));
+        // Not replying at all.
+        drop(reply_a);
+        assert_eq!(first.try_recv(), Err(TryRecvError::Disconnected));
+        // Nobody waits for this reply anymore.
+        drop(client.send("third").unwrap());
+        let (_, reply) = server.recv().unwrap();
+        assert!(reply.is_closed());
+        assert_eq!(reply.reply(3), Err(SendError(3)));
+        drop(client);
+        assert_eq!(
+            server.try_recv().map(|r| r.0),
+            Err(TryRecvError::Disconnected)
+        );
+        assert!(server.recv().is_err());
+    }
+
+    #[test]
+    fn test_reply_timeout() {
+        let (client, server) = channel::<(), &str>();
+        assert_eq!(
+            client.call_timeout((), Duration::from_millis(5)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        let mut reply = client.send(()).unwrap();
+        assert_eq!(
+            reply.recv_timeout(Duration::from_millis(5)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        thread::scope(|s| {
+            s.spawn(|| {
+                // The first request timed out, but is still queued.
+                server.recv().unwrap();
+                let (_, r) = server.recv().unwrap();
+                thread::sleep(Duration::from_millis(10));
+                r.reply("late").unwrap();
+            });
+            // Waiting again after a timeout.
+            assert_eq!(reply.recv_timeout(Duration::from_secs(10)), Ok("late"));
+        });
+        assert_eq!(
+            server.recv_timeout(Duration::from_millis(5)).map(|r| r.0),
+            Err(RecvTimeoutError::Timeout)
+        );
+    }
+
+    #[test]
+    fn test_server_dropped() {
+        let (client, server) = channel::<u8, u8>();
+        let reply = client.send(1).unwrap();
+        drop(server);
+        assert_eq!(reply.recv(), Err(RecvError));
+        assert!(matches!(client.send(2), Err(SendError(2))));
+        assert_eq!(client.call(3), Err(RecvError));
+    }
+
+    #[test]
+    fn test_weak_client() {
+        let (client, server) = channel::<u32, u32>();
+        let weak = client.downgrade();
+        thread::scope(|s| {
+            let background = s.spawn(move || {
+                let mut sent = 0;
+                // Sends for as long as someone else keeps the channel open.
+                while let Some(client) = weak.upgrade() {
+                    if sent < 100 && client.send(sent).is_ok() {
+                        sent += 1;
+                    }
+                    drop(client);
+                    thread::yield_now();
+                }
+                sent
+            });
+            thread::sleep(Duration::from_millis(10));
+            drop(client);
+            let sent = background.join().unwrap();
+            // The weak handle didn't keep the server waiting.
+            assert_eq!(server.iter().count(), sent as usize);
+        });
+
+        let (client, server) = channel::<(), ()>();
+        let weak = client.downgrade();
+        let again = weak.clone().upgrade().unwrap();
+        drop(client);
+        assert_eq!(server.try_recv().map(|r| r.0), Err(TryRecvError::Empty));
+        drop(again);
+        assert!(weak.upgrade().is_none());
+        assert!(server.recv().is_err());
+        assert_eq!(format!("{weak:?}"), "WeakClient");
+    }
+}
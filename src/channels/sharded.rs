@@ -0,0 +1,310 @@
+//! A channel split into shards, so producers don't all fight over one
+//! lock.
+//!
+//! Every shard is a `simple_channel`. A producer always sends to the same
+//! shard, picked by hashing its thread id, so producers on different
+//! threads mostly take different locks. Receivers sweep the shards,
+//! starting one further along each time, so none is favored.
+//!
+//! That gives up FIFO across producers: messages from the same thread
+//! arrive in the order they were sent, but a message can overtake one
+//! that was sent earlier from another thread. Compared to
+//! `simple_channel`, sends scale with the number of producers, and
+//! receives get slower with the number of shards, since an empty channel
+//! has to look at all of them.
+//!
+//! A receiver with nothing to receive sleeps on a counter that senders
+//! bump, with `platform::wait`. Senders only touch it when someone is
+//! sleeping.
+
+use std::cell::Cell;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cache_padded::CachePadded;
+use crate::channels::channel::simple_channel;
+use crate::errors::{AllocError, RecvTimeoutError, TryRecvError};
+use crate::oom;
+use crate::platform::wait::{wait, wait_until, wake_one};
+use crate::sync_shim::plain_atomic::Ordering::{Relaxed, SeqCst};
+use crate::sync_shim::plain_atomic::{fence, AtomicU32, AtomicUsize};
+
+pub struct ShardedChannel<T> {
+    shards: Box<[CachePadded<simple_channel::Channel<T>>]>,
+    /// Where the next sweep starts.
+    next: CachePadded<AtomicUsize>,
+    /// Bumped by a send while receivers are sleeping on it.
+    sent: CachePadded<AtomicU32>,
+    sleeping: CachePadded<AtomicUsize>,
+}
+
+thread_local! {
+    /// The hash of this thread's id, to pick its shard.
+    static THREAD_HASH: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+fn thread_hash() -> usize {
+    THREAD_HASH.with(|hash| {
+        *hash.get().get_or_insert_with(|| {
+            let mut hasher = DefaultHasher::new();
+            thread::current().id().hash(&mut hasher);
+            hasher.finish() as usize
+        })
+    })
+}
+
+impl<T> ShardedChannel<T> {
+    /// A shard per core.
+    pub fn new() -> Self {
+        Self::with_shards(thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+
+    /// Does what the `OomPolicy` says if it can't allocate the shards.
+    pub fn with_shards(shards: usize) -> Self {
+        Self::try_with_shards(shards).unwrap_or_else(|e| oom::handle(e))
+    }
+
+    /// Returns an error instead of failing to allocate the shards.
+    pub fn try_with_shards(shards: usize) -> Result<Self, AllocError> {
+        assert!(shards > 0, "a ShardedChannel needs at least one shard");
+        Ok(Self {
+            // The receivers below wait on `sent` instead, so the shards
+            // shouldn't spin before looking at it.
+            shards: oom::try_boxed_slice(shards, || {
+                CachePadded::new(simple_channel::Channel::with_spins(0))
+            })?,
+            next: CachePadded::new(AtomicUsize::new(0)),
+            sent: CachePadded::new(AtomicU32::new(0)),
+            sleeping: CachePadded::new(AtomicUsize::new(0)),
+        })
+    }
+
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Sends to this thread's shard.
+    pub fn send(&self, message: T) {
+        self.shards[thread_hash() % self.shards.len()].send(message);
+        // Pairs with the fence in `sleep`: either the receiver sees the
+        // message, or we see it sleeping.
+        fence(SeqCst);
+        if self.sleeping.load(Relaxed) > 0 {
+            self.sent.fetch_add(1, Relaxed);
+            wake_one(&self.sent);
+        }
+    }
+
+    /// Takes a message from the first shard that has one, starting after
+    /// where the last sweep started.
+    pub fn try_receive(&self) -> Result<T, TryRecvError> {
+        let n = self.shards.len();
+        let start = self.next.fetch_add(1, Relaxed);
+        (0..n)
+            .find_map(|i| self.shards[(start + i) % n].try_receive().ok())
+            .ok_or(TryRecvError::Empty)
+    }
+
+    pub fn receive(&self) -> T {
+        loop {
+            if let Ok(message) = self.try_receive() {
+                return message;
+            }
+            if let Some(message) = self.sleep(None) {
+                return message;
+            }
+        }
+    }
+
+    pub fn receive_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.receive_until(deadline),
+            None => Ok(self.receive()),
+        }
+    }
+
+    pub fn receive_until(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        loop {
+            if let Ok(message) = self.try_receive() {
+                return Ok(message);
+            }
+            if Instant::now() >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            if let Some(message) = self.sleep(Some(deadline)) {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Sleeps until a send, unless one came in since the last sweep, in
+    /// which case that message is returned.
+    fn sleep(&self, deadline: Option<Instant>) -> Option<T> {
+        let sent = self.sent.load(Relaxed);
+        self.sleeping.fetch_add(1, Relaxed);
+        fence(SeqCst);
+        let message = self.try_receive().ok();
+        if message.is_none() {
+            match deadline {
+                Some(deadline) => wait_until(&self.sent, sent, deadline),
+                None => wait(&self.sent, sent),
+            }
+        }
+        self.sleeping.fetch_sub(1, Relaxed);
+        message
+    }
+
+    /// The number of messages in all shards. It may change right after.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|s| s.is_empty())
+    }
+}
+
+impl<T> Default for ShardedChannel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> fmt::Debug for ShardedChannel<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShardedChannel")
+            .field("shards", &self.shards.len())
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Barrier;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::channels::channel::simple_channel;
+    use crate::channels::sharded::ShardedChannel;
+    use crate::channels::traits::{Receiver, Sender};
+    use crate::errors::{AllocError, RecvTimeoutError, TryRecvError};
+
+    #[test]
+    fn test_fifo_per_producer() {
+        const N: u32 = if cfg!(miri) { 20 } else { 10_000 };
+        let channel = ShardedChannel::with_shards(3);
+        thread::scope(|s| {
+            for t in 0..4 {
+                let channel = &channel;
+                s.spawn(move || {
+                    for i in 0..N {
+                        channel.send((t, i));
+                    }
+                });
+            }
+            let mut next = [0; 4];
+            for _ in 0..4 * N {
+                let (t, i) = channel.receive();
+                assert_eq!(i, next[t]);
+                next[t] += 1;
+            }
+        });
+        assert_eq!(channel.try_receive(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn test_many_consumers() {
+        const N: u64 = if cfg!(miri) { 20 } else { 10_000 };
+        let channel = ShardedChannel::with_shards(4);
+        let total: u64 = thread::scope(|s| {
+            let consumers: Vec<_> = (0..3)
+                .map(|_| s.spawn(|| (0..N).map(|_| channel.receive()).sum::<u64>()))
+                .collect();
+            for t in 0..3 {
+                let channel = &channel;
+                s.spawn(move || (0..N).for_each(|i| channel.send(t * N + i)));
+            }
+            consumers.into_iter().map(|c| c.join().unwrap()).sum()
+        });
+        assert_eq!(total, (0..3 * N).sum());
+    }
+
+    #[test]
+    fn test_try_with_shards() {
+        assert_eq!(
+            ShardedChannel::<u8>::try_with_shards(3).unwrap().shards(),
+            3
+        );
+        let err = ShardedChannel::<u8>::try_with_shards(usize::MAX / 2).err();
+        assert_eq!(err, Some(AllocError::overflow()));
+    }
+
+    #[test]
+    fn test_receive_timeout() {
+        let channel = ShardedChannel::with_shards(2);
+        assert_eq!(
+            channel.receive_timeout(Duration::from_millis(5)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                Sender::send(&channel, "late").unwrap();
+            });
+            assert_eq!(
+                channel.recv_until(Instant::now() + Duration::from_secs(10)),
+                Ok("late")
+            );
+        });
+        Sender::send(&channel, "now").unwrap();
+        assert_eq!(channel.receive_timeout(Duration::MAX), Ok("now"));
+        assert!(channel.is_empty());
+        assert_eq!(
+            format!("{channel:?}"),
+            "ShardedChannel { shards: 2, len: 0 }"
+        );
+    }
+
+    /// Sends from `producers` threads at once, with one thread receiving,
+    /// and returns the time per message.
+    fn throughput<C: Sender<usize> + Receiver<usize> + Sync>(
+        channel: &C,
+        producers: usize,
+    ) -> Duration {
+        const MESSAGES: usize = 1 << 20;
+        let per_producer = MESSAGES / producers;
+        let barrier = Barrier::new(producers + 1);
+        thread::scope(|s| {
+            for _ in 0..producers {
+                s.spawn(|| {
+                    barrier.wait();
+                    for i in 0..per_producer {
+                        channel.send(i).unwrap();
+                    }
+                });
+            }
+            barrier.wait();
+            let start = Instant::now();
+            for _ in 0..per_producer * producers {
+                channel.recv().unwrap();
+            }
+            start.elapsed() / (per_producer * producers) as u32
+        })
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_sharded_producers`,
+    /// on a machine with plenty of cores.
+    #[test]
+    #[ignore]
+    fn bench_sharded_producers() {
+        for producers in [16, 32, 64] {
+            let single = throughput(&simple_channel::Channel::new(), producers);
+            let sharded = throughput(&ShardedChannel::new(), producers);
+            println!("{producers} producers: single queue {single:?}, sharded {sharded:?}");
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! Something threads wait for, and other threads signal, for the channels
+//! that keep their own state and only need to be told when to look at it
+//! again.
+
+use std::time::Instant;
+
+use crate::platform::wait::{wait, wait_until, wake_all};
+use crate::sync_shim::plain_atomic::Ordering::{Relaxed, SeqCst};
+use crate::sync_shim::plain_atomic::{AtomicU32, AtomicUsize};
+
+pub(crate) struct Signal {
+    /// Bumped on every signal, and waited on.
+    generation: AtomicU32,
+    /// How many threads are in `wait_for`, so `notify` can skip waking
+    /// when there are none.
+    waiting: AtomicUsize,
+}
+
+impl Signal {
+    pub(crate) const fn new() -> Self {
+        Self {
+            generation: AtomicU32::new(0),
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    /// Pairs with `wait_for`: the bump and the load of `waiting` are
+    /// SeqCst, like the increment of `waiting` and the load of the
+    /// generation there, so either we see the waiter, or it sees the bump.
+    pub(crate) fn notify(&self) {
+        self.generation.fetch_add(1, SeqCst);
+        if self.waiting.load(SeqCst) > 0 {
+            wake_all(&self.generation);
+        }
+    }
+
+    /// Whether a thread is waiting, or about to.
+    pub(crate) fn is_waiting(&self) -> bool {
+        self.waiting.load(SeqCst) > 0
+    }
+
+    /// Waits until `ready` returns true, or until `deadline`, which it
+    /// returns false for.
+    pub(crate) fn wait_for(
+        &self,
+        mut ready: impl FnMut() -> bool,
+        deadline: Option<Instant>,
+    ) -> bool {
+        self.waiting.fetch_add(1, SeqCst);
+        let ready = loop {
+            let generation = self.generation.load(SeqCst);
+            if ready() {
+                break true;
+            }
+            match deadline {
+                None => wait(&self.generation, generation),
+                Some(deadline) if Instant::now() >= deadline => break false,
+                Some(deadline) => wait_until(&self.generation, generation, deadline),
+            }
+        };
+        self.waiting.fetch_sub(1, Relaxed);
+        ready
+    }
+}
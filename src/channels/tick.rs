@@ -0,0 +1,261 @@
+//! A channel that receives the time, periodically.
+//!
+//! [`tick`] returns a `Ticker`, which receives the `Instant` each tick was
+//! due at, every `period`. The messages are sent by the timer thread of
+//! [`exec::timer`](crate::exec::timer): every tick schedules the next one
+//! on the timer wheel, with a waker that sends the message from there, so
+//! there's no thread per ticker.
+//!
+//! A tick is missed when it's sent late, because the timer thread fell
+//! behind, or while the previous one still hasn't been received. What
+//! happens then is picked with [`MissedTicks`].
+
+use std::fmt;
+use std::sync::Mutex;
+use std::task::Waker;
+use std::time::{Duration, Instant};
+
+use crate::arc::{Arc, Wake};
+use crate::channels::channel::simple_channel;
+use crate::errors::{RecvTimeoutError, TryRecvError};
+use crate::exec::timer::{schedule, unschedule};
+
+/// What a `Ticker` does about ticks it missed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MissedTicks {
+    /// Sends every tick, however late, and queues them up while the
+    /// receiver is behind, so none is lost.
+    Burst,
+    /// Sends only the latest of the ticks that are due, and none while the
+    /// previous one hasn't been received, keeping to the schedule.
+    #[default]
+    Skip,
+    /// Like `Skip`, but a late tick moves the schedule: the next one is a
+    /// full period after it was sent.
+    Delay,
+}
+
+/// Receives a tick every period, until it's dropped.
+pub struct Ticker {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    queue: simple_channel::Channel<Instant>,
+    period: Duration,
+    missed: MissedTicks,
+    /// The next tick, or None once the ticker is dropped.
+    next: Mutex<Option<Next>>,
+}
+
+struct Next {
+    deadline: Instant,
+    /// Its timer on the wheel.
+    id: Option<u64>,
+}
+
+/// Ticks every `period`, skipping the ticks it misses.
+pub fn tick(period: Duration) -> Ticker {
+    tick_with(period, MissedTicks::Skip)
+}
+
+pub fn tick_with(period: Duration, missed: MissedTicks) -> Ticker {
+    assert!(!period.is_zero(), "a Ticker needs a period");
+    let shared = Arc::new(Shared {
+        queue: simple_channel::Channel::new(),
+        period,
+        missed,
+        next: Mutex::new(None),
+    });
+    let mut next = shared.next.lock().unwrap();
+    Shared::arm(
+        &shared,
+        next.insert(Next {
+            deadline: Instant::now() + period,
+            id: None,
+        }),
+    );
+    drop(next);
+    Ticker { shared }
+}
+
+impl Shared {
+    /// Puts the next tick on the timer wheel, sending the ones that are
+    /// due already.
+    fn arm(this: &Arc<Self>, next: &mut Next) {
+        loop {
+            next.id = schedule(next.deadline, Waker::from(this.clone()));
+            if next.id.is_some() {
+                return;
+            }
+            this.fire(next);
+        }
+    }
+
+    /// Sends the tick that's due, and moves `next` on to the one after.
+    fn fire(&self, next: &mut Next) {
+        let now = Instant::now();
+        let behind = !self.queue.is_empty();
+        match self.missed {
+            MissedTicks::Burst => {
+                while next.deadline <= now {
+                    self.queue.send(next.deadline);
+                    next.deadline += self.period;
+                }
+            }
+            MissedTicks::Skip => {
+                let missed = now.saturating_duration_since(next.deadline).as_nanos()
+                    / self.period.as_nanos();
+                let latest = next.deadline + self.period * u32::try_from(missed).unwrap();
+                if !behind {
+                    self.queue.send(latest);
+                }
+                next.deadline = latest + self.period;
+            }
+            MissedTicks::Delay => {
+                if !behind {
+                    self.queue.send(next.deadline);
+                }
+                next.deadline = now.max(next.deadline) + self.period;
+            }
+        }
+    }
+}
+
+/// Woken by the timer thread when a tick is due.
+impl Wake for Shared {
+    fn wake(this: Arc<Self>) {
+        let mut next = this.next.lock().unwrap();
+        // Dropped while the timer was firing.
+        let Some(next) = next.as_mut() else {
+            return;
+        };
+        this.fire(next);
+        Self::arm(&this, next);
+    }
+}
+
+impl Ticker {
+    /// Blocks until the next tick, and returns when it was due.
+    pub fn recv(&self) -> Instant {
+        self.shared.queue.receive()
+    }
+
+    pub fn try_recv(&self) -> Result<Instant, TryRecvError> {
+        self.shared.queue.try_receive()
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Instant, RecvTimeoutError> {
+        self.shared.queue.receive_timeout(timeout)
+    }
+
+    pub fn recv_until(&self, deadline: Instant) -> Result<Instant, RecvTimeoutError> {
+        self.shared.queue.receive_until(deadline)
+    }
+
+    pub fn period(&self) -> Duration {
+        self.shared.period
+    }
+
+    /// How many ticks have been sent but not received.
+    pub fn len(&self) -> usize {
+        self.shared.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Drop for Ticker {
+    fn drop(&mut self) {
+        // The timer's waker holds on to the rest, so it has to go.
+        if let Some(Next {
+            deadline,
+            id: Some(id),
+        }) = self.shared.next.lock().unwrap().take()
+        {
+            unschedule(deadline, id);
+        }
+    }
+}
+
+impl fmt::Debug for Ticker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ticker")
+            .field("period", &self.shared.period)
+            .field("missed", &self.shared.missed)
+            .field("queued", &self.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::channels::tick::{tick, tick_with, MissedTicks};
+    use crate::channels::traits::Receiver;
+    use crate::errors::TryRecvError;
+
+    const PERIOD: Duration = Duration::from_millis(5);
+
+    #[test]
+    fn test_ticks_on_schedule() {
+        let start = Instant::now();
+        let ticker = tick(PERIOD);
+        let mut last = start;
+        for i in 1..=5 {
+            let due = ticker.recv();
+            assert!(due > last);
+            assert!(Instant::now() >= start + PERIOD * i);
+            last = due;
+        }
+        assert_eq!(ticker.period(), PERIOD);
+        // Through the trait too.
+        assert!(Receiver::recv(&ticker).unwrap() > last);
+    }
+
+    #[test]
+    fn test_missed_ticks() {
+        let burst = tick_with(PERIOD, MissedTicks::Burst);
+        let skip = tick(PERIOD);
+        let delay = tick_with(PERIOD, MissedTicks::Delay);
+        while burst.len() < 5 {
+            thread::sleep(PERIOD);
+        }
+        // Nothing was received, so at most the first tick is queued,
+        // unless it's a burst.
+        assert!(skip.len() <= 1);
+        assert!(delay.len() <= 1);
+        let due: Vec<_> = std::iter::from_fn(|| burst.try_recv().ok()).collect();
+        assert!(due.windows(2).all(|w| w[1] - w[0] == PERIOD));
+
+        // The ones after that were skipped, but the schedule wasn't moved.
+        let first = skip.recv();
+        let next = skip.recv();
+        assert!(next > first);
+        assert_eq!((next - first).as_nanos() % PERIOD.as_nanos(), 0);
+
+        let first = delay.recv();
+        let next = delay.recv();
+        assert!(next - first >= PERIOD);
+    }
+
+    #[test]
+    fn test_dropped_ticker_stops() {
+        let ticker = tick(PERIOD);
+        ticker.recv();
+        drop(ticker);
+        // The timer wheel is shared, so others still tick.
+        tick_with(PERIOD, MissedTicks::Delay).recv();
+
+        let hourly = tick(Duration::from_secs(3600));
+        assert_eq!(hourly.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(
+            format!("{hourly:?}"),
+            "Ticker { period: 3600s, missed: Skip, queued: 0 }"
+        );
+    }
+}
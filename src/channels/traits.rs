@@ -0,0 +1,319 @@
+//! Traits shared by the channels, so code can be written once and then
+//! run on top of any of them.
+//!
+//! Only the non-blocking methods are required. The blocking and timed
+//! ones default to retrying those with `thread::yield_now()` in between,
+//! which channels that can actually wait for a message override. The
+//! `_timeout` methods call the `_until` ones with a deadline, so those are
+//! the ones to override.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::channels::channel::{one_shot_channel, priority_channel, simple_channel};
+use crate::channels::mpsc;
+use crate::channels::sharded::ShardedChannel;
+use crate::channels::tick::Ticker;
+use crate::errors::{
+    RecvError, RecvTimeoutError, SendError, SendTimeoutError, TryRecvError, TrySendError,
+};
+
+pub trait Sender<T> {
+    fn try_send(&self, message: T) -> Result<(), TrySendError<T>>;
+
+    fn send(&self, mut message: T) -> Result<(), SendError<T>> {
+        loop {
+            match self.try_send(message) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Full(m)) => message = m,
+                Err(TrySendError::Disconnected(m)) => return Err(SendError(m)),
+            }
+            thread::yield_now();
+        }
+    }
+
+    fn send_timeout(&self, message: T, timeout: Duration) -> Result<(), SendTimeoutError<T>> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.send_until(message, deadline),
+            None => self
+                .send(message)
+                .map_err(|SendError(m)| SendTimeoutError::Disconnected(m)),
+        }
+    }
+
+    fn send_until(&self, mut message: T, deadline: Instant) -> Result<(), SendTimeoutError<T>> {
+        loop {
+            match self.try_send(message) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Full(m)) => message = m,
+                Err(TrySendError::Disconnected(m)) => {
+                    return Err(SendTimeoutError::Disconnected(m))
+                }
+            }
+            if Instant::now() >= deadline {
+                return Err(SendTimeoutError::Timeout(message));
+            }
+            thread::yield_now();
+        }
+    }
+}
+
+pub trait Receiver<T> {
+    fn try_recv(&self) -> Result<T, TryRecvError>;
+
+    fn recv(&self) -> Result<T, RecvError> {
+        loop {
+            match self.try_recv() {
+                Ok(message) => return Ok(message),
+                Err(TryRecvError::Empty) => thread::yield_now(),
+                Err(TryRecvError::Disconnected) => return Err(RecvError),
+            }
+        }
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.recv_until(deadline),
+            None => self
+                .recv()
+                .map_err(|RecvError| RecvTimeoutError::Disconnected),
+        }
+    }
+
+    fn recv_until(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        loop {
+            match self.try_recv() {
+                Ok(message) => return Ok(message),
+                Err(TryRecvError::Empty) if Instant::now() >= deadline => {
+                    return Err(RecvTimeoutError::Timeout)
+                }
+                Err(TryRecvError::Empty) => thread::yield_now(),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+            }
+        }
+    }
+}
+
+impl<T, S: Sender<T> + ?Sized> Sender<T> for &S {
+    fn try_send(&self, message: T) -> Result<(), TrySendError<T>> {
+        (**self).try_send(message)
+    }
+
+    fn send(&self, message: T) -> Result<(), SendError<T>> {
+        (**self).send(message)
+    }
+
+    fn send_until(&self, message: T, deadline: Instant) -> Result<(), SendTimeoutError<T>> {
+        (**self).send_until(message, deadline)
+    }
+}
+
+impl<T, R: Receiver<T> + ?Sized> Receiver<T> for &R {
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        (**self).try_recv()
+    }
+
+    fn recv(&self) -> Result<T, RecvError> {
+        (**self).recv()
+    }
+
+    fn recv_until(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        (**self).recv_until(deadline)
+    }
+}
+
+/// Unbounded, and never disconnected.
+impl<T> Sender<T> for simple_channel::Channel<T> {
+    fn try_send(&self, message: T) -> Result<(), TrySendError<T>> {
+        self.send(message);
+        Ok(())
+    }
+}
+
+impl<T> Receiver<T> for simple_channel::Channel<T> {
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.try_receive()
+    }
+
+    fn recv(&self) -> Result<T, RecvError> {
+        Ok(self.receive())
+    }
+
+    fn recv_until(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.receive_until(deadline)
+    }
+}
+
+/// Sends with `Priority::Normal`.
+impl<T> Sender<T> for priority_channel::Channel<T> {
+    fn try_send(&self, message: T) -> Result<(), TrySendError<T>> {
+        self.send(priority_channel::Priority::Normal, message);
+        Ok(())
+    }
+}
+
+impl<T> Receiver<T> for priority_channel::Channel<T> {
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.try_receive()
+    }
+
+    fn recv(&self) -> Result<T, RecvError> {
+        Ok(self.receive())
+    }
+
+    fn recv_until(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.receive_until(deadline)
+    }
+}
+
+/// Full after the first message. The channel doesn't track whether its
+/// message has been received, so receiving again waits forever (or until
+/// the timeout) rather than reporting a disconnect.
+impl<T> Sender<T> for one_shot_channel::Channel<T> {
+    fn try_send(&self, message: T) -> Result<(), TrySendError<T>> {
+        one_shot_channel::Channel::send(self, message)
+            .map_err(|SendError(message)| TrySendError::Full(message))
+    }
+}
+
+impl<T> Receiver<T> for one_shot_channel::Channel<T> {
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.receive()
+    }
+}
+
+impl<T> Sender<T> for ShardedChannel<T> {
+    fn try_send(&self, message: T) -> Result<(), TrySendError<T>> {
+        self.send(message);
+        Ok(())
+    }
+}
+
+impl<T> Receiver<T> for ShardedChannel<T> {
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.try_receive()
+    }
+
+    fn recv(&self) -> Result<T, RecvError> {
+        Ok(self.receive())
+    }
+
+    fn recv_until(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.receive_until(deadline)
+    }
+}
+
+/// The ticks are sent for as long as it's around.
+impl Receiver<Instant> for Ticker {
+    fn try_recv(&self) -> Result<Instant, TryRecvError> {
+        self.try_recv()
+    }
+
+    fn recv(&self) -> Result<Instant, RecvError> {
+        Ok(self.recv())
+    }
+
+    fn recv_until(&self, deadline: Instant) -> Result<Instant, RecvTimeoutError> {
+        self.recv_until(deadline)
+    }
+}
+
+impl<T> Sender<T> for mpsc::Sender<T> {
+    fn try_send(&self, message: T) -> Result<(), TrySendError<T>> {
+        self.send(message)
+            .map_err(|SendError(message)| TrySendError::Disconnected(message))
+    }
+}
+
+impl<T> Sender<T> for mpsc::SyncSender<T> {
+    fn try_send(&self, message: T) -> Result<(), TrySendError<T>> {
+        self.try_send(message)
+    }
+
+    fn send(&self, message: T) -> Result<(), SendError<T>> {
+        self.send(message)
+    }
+}
+
+impl<T> Receiver<T> for mpsc::Receiver<T> {
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.try_recv()
+    }
+
+    fn recv(&self) -> Result<T, RecvError> {
+        self.recv()
+    }
+
+    fn recv_until(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.recv_deadline(deadline)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::channels::channel::{one_shot_channel, priority_channel, simple_channel};
+    use crate::channels::mpsc;
+    use crate::channels::sharded::ShardedChannel;
+    use crate::channels::traits::{Receiver, Sender};
+    use crate::errors::{RecvTimeoutError, TryRecvError, TrySendError};
+
+    /// Written once, run on every channel.
+    fn ping<C: Sender<u32> + Receiver<u32> + Sync>(channel: &C) {
+        assert_eq!(channel.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(
+            channel.recv_timeout(Duration::from_millis(5)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        let deadline = Instant::now() + Duration::from_millis(5);
+        assert_eq!(channel.recv_until(deadline), Err(RecvTimeoutError::Timeout));
+        assert!(Instant::now() >= deadline);
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                channel.send(7).unwrap();
+            });
+            assert_eq!(channel.recv(), Ok(7));
+        });
+    }
+
+    #[test]
+    fn test_all_channels() {
+        ping(&simple_channel::Channel::new());
+        ping(&priority_channel::Channel::new());
+        ping(&one_shot_channel::Channel::new());
+        ping(&ShardedChannel::with_shards(2));
+        ping(&Split(mpsc::channel()));
+        ping(&Split(mpsc::sync_channel(1)));
+    }
+
+    /// The two halves of a channel, as one.
+    struct Split<S, R>((S, R));
+
+    impl<S: Sender<u32>, R> Sender<u32> for Split<S, R> {
+        fn try_send(&self, message: u32) -> Result<(), TrySendError<u32>> {
+            self.0 .0.try_send(message)
+        }
+    }
+
+    impl<S, R: Receiver<u32>> Receiver<u32> for Split<S, R> {
+        fn try_recv(&self) -> Result<u32, TryRecvError> {
+            self.0 .1.try_recv()
+        }
+
+        fn recv_until(&self, deadline: Instant) -> Result<u32, RecvTimeoutError> {
+            self.0 .1.recv_until(deadline)
+        }
+    }
+
+    #[test]
+    fn test_one_shot_is_full_after_one_message() {
+        let channel = one_shot_channel::Channel::new();
+        assert!(Sender::try_send(&channel, 1).is_ok());
+        assert_eq!(Sender::try_send(&channel, 2), Err(TrySendError::Full(2)));
+        assert_eq!(channel.try_recv(), Ok(1));
+        assert_eq!(channel.try_recv(), Err(TryRecvError::Empty));
+    }
+}
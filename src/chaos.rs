@@ -0,0 +1,300 @@
+//! Pause points inside the primitives, to make rare interleavings happen
+//! on purpose.
+//!
+//! With the `chaos` feature, the primitives pass a few labeled points, and
+//! a test can make every thread that gets there yield or sleep first. That
+//! turns a race that needs luck to show up into one that shows up every
+//! time, which is what you want when demonstrating why an ordering matters.
+//! Without the feature, the points compile to nothing.
+//!
+//! The points:
+//!
+//! - `"arc::upgrade"`: in `Weak::upgrade`, right after the compare-exchange
+//!   that bumped the reference count.
+//! - `"one_shot::send"`: in the one-shot channels' `send`, between writing
+//!   the message and setting the ready flag.
+//! - `"one_shot::before_send"`, `"one_shot::writing"`,
+//!   `"one_shot::before_receive"`, `"one_shot::reading"` and
+//!   `"one_shot::read"`: around every other step of `OneShotChannel`'s
+//!   `send` and `receive`, so together with `"one_shot::send"`, a thread
+//!   can be stopped between any two of them.
+//! - `"spin_lock::lock"`: in `SpinLock::lock`, right after taking the lock.
+//!
+//! The pauses are global, so they also slow down any other test running at
+//! the same time. They don't change what those tests see.
+//!
+//! A [`RaceConfig`] pauses at random instead, yielding or sleeping only
+//! some of the time, so every run tries a different interleaving. It can
+//! be built in code, or read from the `CHAOS_RACE` environment variable,
+//! like `CHAOS_RACE=yield=30,sleep=20,max_sleep_us=500` (the chances in
+//! percent). `examples/race_demo.rs` uses it on deliberately broken
+//! channels, which then fail on every run instead of passing by accident.
+//!
+//! For more control than a pause, [`set_hook`] installs a function that
+//! every thread calls at every point, which can block the thread for as
+//! long as it wants. `examples/channel_stepper.rs` uses it to step through
+//! a send and a receive by hand.
+//!
+//! With the `replay` feature, [`crate::replay`] can record the order in
+//! which threads pass the points, and make them pass in that order again.
+
+use std::env;
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use rand::Rng;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pause {
+    Yield,
+    Sleep(Duration),
+    Random(RaceConfig),
+}
+
+/// How often to yield or sleep at a point, for [`Pause::Random`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RaceConfig {
+    yield_percent: u32,
+    sleep_percent: u32,
+    max_sleep: Duration,
+}
+
+impl RaceConfig {
+    /// Never pauses, until configured to.
+    pub const fn new() -> Self {
+        Self {
+            yield_percent: 0,
+            sleep_percent: 0,
+            max_sleep: Duration::from_micros(100),
+        }
+    }
+
+    /// Panics if the chances add up to more than 100.
+    pub const fn yield_percent(mut self, percent: u32) -> Self {
+        assert!(
+            percent.saturating_add(self.sleep_percent) <= 100,
+            "more than 100%"
+        );
+        self.yield_percent = percent;
+        self
+    }
+
+    /// Panics if the chances add up to more than 100.
+    pub const fn sleep_percent(mut self, percent: u32) -> Self {
+        assert!(
+            self.yield_percent.saturating_add(percent) <= 100,
+            "more than 100%"
+        );
+        self.sleep_percent = percent;
+        self
+    }
+
+    /// Sleeps are anywhere from zero to this long.
+    pub const fn max_sleep(mut self, max: Duration) -> Self {
+        self.max_sleep = max;
+        self
+    }
+
+    /// The configuration in `CHAOS_RACE`, if it's set. Keys that aren't
+    /// there keep their defaults. Panics if it can't be parsed.
+    pub fn from_env() -> Option<Self> {
+        let var = env::var("CHAOS_RACE").ok()?;
+        Some(Self::parse(&var).unwrap_or_else(|e| panic!("bad CHAOS_RACE {var:?}: {e}")))
+    }
+
+    fn parse(s: &str) -> Result<Self, String> {
+        let mut config = Self::new();
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("{pair:?} isn't key=value"))?;
+            let value: u32 = value
+                .parse()
+                .map_err(|_| format!("{value:?} isn't a number"))?;
+            match key {
+                "yield" => config.yield_percent = value,
+                "sleep" => config.sleep_percent = value,
+                "max_sleep_us" => config.max_sleep = Duration::from_micros(value.into()),
+                _ => return Err(format!("unknown key {key:?}")),
+            }
+        }
+        if config.yield_percent.saturating_add(config.sleep_percent) > 100 {
+            return Err("the chances add up to more than 100%".into());
+        }
+        Ok(config)
+    }
+
+    fn pause(self) {
+        let roll = rand::thread_rng().gen_range(0..100);
+        if roll < self.yield_percent {
+            thread::yield_now();
+        } else if roll < self.yield_percent + self.sleep_percent {
+            thread::sleep(rand::thread_rng().gen_range(Duration::ZERO..=self.max_sleep));
+        }
+    }
+}
+
+impl Default for RaceConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Point {
+    name: &'static str,
+    pause: Option<Pause>,
+    hits: usize,
+}
+
+static POINTS: Mutex<Vec<Point>> = Mutex::new(Vec::new());
+static HOOK: Mutex<Option<fn(&'static str)>> = Mutex::new(None);
+
+fn with_point<R>(name: &'static str, f: impl FnOnce(&mut Point) -> R) -> R {
+    // Nothing panics while holding this, so poisoning doesn't matter.
+    let mut points = POINTS.lock().unwrap_or_else(PoisonError::into_inner);
+    let i = match points.iter().position(|p| p.name == name) {
+        Some(i) => i,
+        None => {
+            points.push(Point {
+                name,
+                pause: None,
+                hits: 0,
+            });
+            points.len() - 1
+        }
+    };
+    f(&mut points[i])
+}
+
+/// Makes threads pause at the point from now on. Replaces an earlier pause
+/// at the same point.
+pub fn pause_at(name: &'static str, pause: Pause) {
+    with_point(name, |p| p.pause = Some(pause));
+}
+
+/// Stops pausing at the point.
+pub fn resume_at(name: &'static str) {
+    with_point(name, |p| p.pause = None);
+}
+
+/// How many times a thread passed the point, paused or not.
+pub fn hits(name: &'static str) -> usize {
+    with_point(name, |p| p.hits)
+}
+
+/// Calls `hook` with the name of the point, whenever a thread passes one,
+/// before its pause. `None` removes it.
+pub fn set_hook(hook: Option<fn(&'static str)>) {
+    *HOOK.lock().unwrap_or_else(PoisonError::into_inner) = hook;
+}
+
+/// Passes a point of your own, for code outside this crate.
+pub fn point(name: &'static str) {
+    #[cfg(feature = "replay")]
+    crate::replay::arrive();
+    let hook = *HOOK.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(hook) = hook {
+        hook(name);
+    }
+    // Not paused with the table locked, or one paused thread would hold up
+    // every other point.
+    match with_point(name, |p| {
+        p.hits += 1;
+        p.pause
+    }) {
+        None => {}
+        Some(Pause::Yield) => thread::yield_now(),
+        Some(Pause::Sleep(d)) => thread::sleep(d),
+        Some(Pause::Random(config)) => config.pause(),
+    }
+    #[cfg(feature = "replay")]
+    crate::replay::point(name, None);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::channels::OneShotChannel;
+    use crate::chaos::{hits, pause_at, resume_at, set_hook, Pause, RaceConfig};
+
+    #[test]
+    fn test_pause_between_write_and_ready_flag() {
+        let channel = OneShotChannel::new();
+        let before = hits("one_shot::send");
+        pause_at("one_shot::send", Pause::Sleep(Duration::from_millis(200)));
+        thread::scope(|s| {
+            s.spawn(|| channel.send(1).unwrap());
+            while hits("one_shot::send") == before {
+                thread::yield_now();
+            }
+            // The message is written, but the sender is asleep before
+            // setting the flag, every time.
+            assert!(!channel.is_ready());
+        });
+        resume_at("one_shot::send");
+        assert_eq!(channel.receive(), Ok(1));
+    }
+
+    #[test]
+    fn test_hook_sees_every_step() {
+        static SEEN: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+        fn hook(name: &'static str) {
+            // Other tests pass points too.
+            if thread::current().name() == Some("hooked") {
+                SEEN.lock().unwrap().push(name);
+            }
+        }
+        set_hook(Some(hook));
+        let channel = OneShotChannel::new();
+        thread::scope(|s| {
+            thread::Builder::new()
+                .name("hooked".into())
+                .spawn_scoped(s, || {
+                    channel.send(1).unwrap();
+                    channel.receive().unwrap()
+                })
+                .unwrap();
+        });
+        set_hook(None);
+        assert_eq!(
+            *SEEN.lock().unwrap(),
+            [
+                "one_shot::before_send",
+                "one_shot::writing",
+                "one_shot::send",
+                "one_shot::before_receive",
+                "one_shot::reading",
+                "one_shot::read",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_race_config_from_env_format() {
+        assert_eq!(
+            RaceConfig::parse("yield=30, sleep=20,max_sleep_us=500"),
+            Ok(RaceConfig::new()
+                .yield_percent(30)
+                .sleep_percent(20)
+                .max_sleep(Duration::from_micros(500)))
+        );
+        assert_eq!(RaceConfig::parse(""), Ok(RaceConfig::new()));
+        assert!(RaceConfig::parse("yield").is_err());
+        assert!(RaceConfig::parse("sleep=often").is_err());
+        assert!(RaceConfig::parse("nap=1").is_err());
+        assert!(RaceConfig::parse("yield=60,sleep=60").is_err());
+
+        let always = RaceConfig::new().sleep_percent(100);
+        let before = hits("chaos::test_race");
+        pause_at("chaos::test_race", Pause::Random(always));
+        for _ in 0..10 {
+            crate::chaos::point("chaos::test_race");
+        }
+        resume_at("chaos::test_race");
+        assert_eq!(hits("chaos::test_race"), before + 10);
+    }
+}
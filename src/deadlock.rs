@@ -0,0 +1,241 @@
+//! Deadlock detection for `Lock` and `RwLock`, like the one in the
+//! `parking_lot` crate.
+//!
+//! With the `deadlock_detection` feature, every lock records who holds it
+//! and who's waiting for it, and [`check_deadlock`] looks for cycles in that
+//! graph. Call it from a background thread every now and then:
+//!
+//! ```no_run
+//! use std::thread;
+//! use std::time::Duration;
+//!
+//! use atomics_and_locks::deadlock;
+//!
+//! thread::spawn(|| loop {
+//!     thread::sleep(Duration::from_secs(1));
+//!     for cycle in deadlock::check_deadlock() {
+//!         eprintln!("deadlock between {} threads:", cycle.len());
+//!         for t in cycle {
+//!             eprintln!("{:?} waits for {:#x} at", t.thread_id(), t.lock_addr());
+//!             eprintln!("{}", t.backtrace());
+//!         }
+//!     }
+//! });
+//! ```
+//!
+//! The locks are created in a `const fn`, so there's no backtrace of where
+//! a lock was created, but there's one of where each deadlocked thread
+//! started waiting (if `RUST_BACKTRACE` enables them). All of this makes
+//! locking a lot slower, so it's meant for debug builds.
+
+use std::backtrace::Backtrace;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, ThreadId};
+
+#[derive(Default)]
+struct Graph {
+    /// Lock address to the threads holding it. More than one for a
+    /// read-locked `RwLock`.
+    holders: HashMap<usize, Vec<ThreadId>>,
+    /// The locks each thread is waiting for. Usually just one, but locking
+    /// a `Mutex` can involve waiting for one of the locks of
+    /// `platform::parking` too. The last one is the one it's waiting for
+    /// right now.
+    waiting: HashMap<ThreadId, Vec<(usize, Arc<Backtrace>)>>,
+}
+
+impl Graph {
+    fn stop_waiting(&mut self, id: ThreadId, addr: usize) {
+        if let Some(waits) = self.waiting.get_mut(&id) {
+            if let Some(i) = waits.iter().rposition(|(a, _)| *a == addr) {
+                waits.remove(i);
+            }
+            if waits.is_empty() {
+                self.waiting.remove(&id);
+            }
+        }
+    }
+}
+
+static GRAPH: Mutex<Option<Graph>> = Mutex::new(None);
+
+fn with_graph<R>(f: impl FnOnce(&mut Graph) -> R) -> R {
+    // Nothing panics while holding this, so poisoning doesn't matter.
+    let mut graph = GRAPH.lock().unwrap_or_else(PoisonError::into_inner);
+    f(graph.get_or_insert_with(Graph::default))
+}
+
+/// The current thread is about to block on the lock.
+pub(crate) fn waiting(addr: usize) {
+    let backtrace = Arc::new(Backtrace::capture());
+    let id = thread::current().id();
+    with_graph(|g| g.waiting.entry(id).or_default().push((addr, backtrace)));
+}
+
+/// The current thread got the lock, whether it had to wait or not.
+pub(crate) fn acquired(addr: usize) {
+    let id = thread::current().id();
+    with_graph(|g| {
+        g.stop_waiting(id, addr);
+        g.holders.entry(addr).or_default().push(id);
+    });
+}
+
+/// The current thread gave up waiting for the lock, for a timeout or a
+/// cancellation.
+pub(crate) fn gave_up(addr: usize) {
+    let id = thread::current().id();
+    with_graph(|g| g.stop_waiting(id, addr));
+}
+
+/// A guard of the lock is being dropped. Guards can be sent to other
+/// threads, so this isn't necessarily the thread that locked it.
+pub(crate) fn released(addr: usize) {
+    let id = thread::current().id();
+    with_graph(|g| {
+        let Some(holders) = g.holders.get_mut(&addr) else {
+            return;
+        };
+        let i = holders.iter().position(|h| *h == id).unwrap_or(0);
+        holders.swap_remove(i);
+        if holders.is_empty() {
+            g.holders.remove(&addr);
+        }
+    });
+}
+
+/// A thread that's part of a deadlock.
+#[derive(Debug, Clone)]
+pub struct DeadlockedThread {
+    thread_id: ThreadId,
+    lock_addr: usize,
+    backtrace: Arc<Backtrace>,
+}
+
+impl DeadlockedThread {
+    pub fn thread_id(&self) -> ThreadId {
+        self.thread_id
+    }
+
+    /// The address of the lock the thread is waiting for.
+    pub fn lock_addr(&self) -> usize {
+        self.lock_addr
+    }
+
+    /// Where the thread started waiting.
+    pub fn backtrace(&self) -> &Backtrace {
+        &self.backtrace
+    }
+}
+
+/// Returns every cycle of threads that are each waiting for a lock held by
+/// the next one, or nothing if there's no deadlock.
+pub fn check_deadlock() -> Vec<Vec<DeadlockedThread>> {
+    with_graph(|g| {
+        let mut cycles = Vec::new();
+        let mut done: Vec<ThreadId> = Vec::new();
+        for &start in g.waiting.keys() {
+            if done.contains(&start) {
+                continue;
+            }
+            // Follow the waits-for edges from here. Only the first holder of
+            // a read-locked `RwLock` is followed, so a cycle through one of
+            // its other readers goes unnoticed.
+            let mut path = vec![start];
+            let mut t = start;
+            while let Some(&next) = g
+                .waiting
+                .get(&t)
+                .and_then(|waits| waits.last())
+                .and_then(|(addr, _)| g.holders.get(addr))
+                .and_then(|h| h.first())
+            {
+                if let Some(i) = path.iter().position(|p| *p == next) {
+                    cycles.push(
+                        path[i..]
+                            .iter()
+                            .map(|id| {
+                                let (addr, backtrace) = g.waiting[id].last().unwrap();
+                                DeadlockedThread {
+                                    thread_id: *id,
+                                    lock_addr: *addr,
+                                    backtrace: backtrace.clone(),
+                                }
+                            })
+                            .collect(),
+                    );
+                    break;
+                }
+                if done.contains(&next) {
+                    // Already followed from there.
+                    break;
+                }
+                path.push(next);
+                t = next;
+            }
+            done.extend(path);
+        }
+        cycles
+    })
+}
+
+// With `lock_order`, these panic before they get to deadlock.
+#[cfg(all(test, not(feature = "lock_order")))]
+mod tests {
+    use std::ptr;
+    use std::sync::Barrier;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::deadlock::{check_deadlock, with_graph};
+    use crate::locks::Mutex;
+
+    #[test]
+    fn test_finds_lock_order_inversion() {
+        static A: Mutex<()> = Mutex::new(());
+        static B: Mutex<()> = Mutex::new(());
+        static BARRIER: Barrier = Barrier::new(2);
+        // These never finish, so they're not scoped.
+        let t1 = thread::spawn(|| {
+            let _a = A.lock();
+            BARRIER.wait();
+            let _b = B.lock();
+        });
+        let t2 = thread::spawn(|| {
+            let _b = B.lock();
+            BARRIER.wait();
+            let _a = A.lock();
+        });
+        let cycle = loop {
+            if let Some(cycle) = check_deadlock().pop() {
+                break cycle;
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        let mut threads: Vec<_> = cycle.iter().map(|t| t.thread_id()).collect();
+        let mut expected = vec![t1.thread().id(), t2.thread().id()];
+        threads.sort_by_key(|id| format!("{id:?}"));
+        expected.sort_by_key(|id| format!("{id:?}"));
+        assert_eq!(threads, expected);
+        let mut locks: Vec<_> = cycle.iter().map(|t| t.lock_addr()).collect();
+        locks.sort();
+        let mut expected = vec![ptr::from_ref(&A).addr(), ptr::from_ref(&B).addr()];
+        expected.sort();
+        assert_eq!(locks, expected);
+    }
+
+    #[test]
+    fn test_forgets_waits_that_gave_up() {
+        let mutex = Mutex::new(());
+        let _guard = mutex.lock();
+        thread::scope(|s| {
+            let waiter = s.spawn(|| {
+                assert!(mutex.try_lock_for(Duration::from_millis(5)).is_err());
+                thread::current().id()
+            });
+            let id = waiter.join().unwrap();
+            with_graph(|g| assert!(!g.waiting.contains_key(&id)));
+        });
+    }
+}
@@ -0,0 +1,136 @@
+//! Making a value `Sync` by never sharing it.
+//!
+//! A `&Exclusive<T>` gives no access to the `T` at all: only
+//! `&mut Exclusive<T>` does, and a `&mut` is unique. So it's `Sync` for any
+//! `T`, even one that isn't, like a `Box<dyn FnMut() + Send>` or a future
+//! holding a `Cell`. That helps a struct that is shared as a whole, but
+//! only ever uses such a field through `&mut self`, or after taking it
+//! apart: wrap the field instead of writing `unsafe impl Sync` for the
+//! whole struct. Like the unstable `std::sync::Exclusive`, or the
+//! `sync_wrapper` crate.
+//!
+//! The `lock()` future of `async_sync::mutex::Mutex` wraps its node of the
+//! waiter list in one, for example: other threads only reach the node
+//! through the list, so the future can be `Sync` without vouching for the
+//! node's raw pointers.
+
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+#[derive(Default)]
+#[repr(transparent)]
+pub struct Exclusive<T: ?Sized> {
+    value: T,
+}
+
+// Safety: A shared reference can't get at the value.
+unsafe impl<T: ?Sized> Sync for Exclusive<T> {}
+
+impl<T> Exclusive<T> {
+    pub const fn new(value: T) -> Self {
+        Self { value }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T: ?Sized> Exclusive<T> {
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut T> {
+        // Safety: The value is structurally pinned: it's never moved out
+        // of a pinned `Exclusive`.
+        unsafe { self.map_unchecked_mut(|e| &mut e.value) }
+    }
+
+    /// Shared access, for values that are `Sync` anyway.
+    pub fn get(&self) -> &T
+    where
+        T: Sync,
+    {
+        &self.value
+    }
+
+    pub fn from_mut(value: &mut T) -> &mut Self {
+        // Safety: It's `repr(transparent)`.
+        unsafe { &mut *(core::ptr::from_mut(value) as *mut Self) }
+    }
+
+    pub fn from_pin_mut(value: Pin<&mut T>) -> Pin<&mut Self> {
+        // Safety: The value stays pinned in place, see `get_pin_mut`.
+        unsafe { value.map_unchecked_mut(Self::from_mut) }
+    }
+}
+
+impl<T> From<T> for Exclusive<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+/// Polls the future it wraps, which makes a future `Sync` that wasn't.
+impl<F: Future + ?Sized> Future for Exclusive<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        self.get_pin_mut().poll(cx)
+    }
+}
+
+/// Can't show the value: that would take a `&T` from a shared reference.
+impl<T: ?Sized> fmt::Debug for Exclusive<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Exclusive").finish_non_exhaustive()
+    }
+}
+
+#[cfg(all(test, not(any(loom, feature = "shuttle"))))]
+mod tests {
+    use std::cell::Cell;
+    use std::thread;
+
+    use crate::exclusive::Exclusive;
+    #[cfg(feature = "std")]
+    use crate::exec::executor::block_on;
+
+    fn assert_sync<T: Sync>(_: &T) {}
+
+    #[test]
+    fn test_shares_non_sync_values() {
+        let mut counter = Exclusive::new(Cell::new(0));
+        let shared = &counter;
+        assert_sync(shared);
+        thread::scope(|s| {
+            s.spawn(|| assert_eq!(format!("{shared:?}"), "Exclusive { .. }"));
+        });
+        counter.get_mut().set(1);
+        assert_eq!(counter.into_inner().into_inner(), 1);
+
+        let mut cell = Cell::new(2);
+        Exclusive::from_mut(&mut cell).get_mut().set(3);
+        assert_eq!(cell.get(), 3);
+        assert_eq!(*Exclusive::new(5).get(), 5);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_future() {
+        let not_sync = Cell::new(1);
+        let future = Exclusive::new(async move {
+            let value = not_sync.get();
+            std::future::ready(()).await;
+            value + 1
+        });
+        assert_sync(&future);
+        assert_eq!(block_on(future), 2);
+
+        let ready = std::pin::pin!(std::future::ready(7));
+        assert_eq!(block_on(Exclusive::from_pin_mut(ready)), 7);
+    }
+}
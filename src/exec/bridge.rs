@@ -0,0 +1,247 @@
+//! Adapters between the blocking and the async parts of the crate.
+//!
+//! Blocking calls are moved onto a helper thread that wakes the waiting
+//! task once it's done. The other way around, async primitives get
+//! `blocking_*` methods that run their futures with `block_on`.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use crate::async_sync::mutex::{self, MutexGuard};
+use crate::async_sync::rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use crate::channels::channel::simple_channel;
+use crate::channels::oneshot_async;
+use crate::errors::RecvError;
+use crate::exec::executor::block_on;
+
+/// Resolves to the result of a blocking call made on a helper thread.
+pub struct Unblock<T> {
+    receiver: oneshot_async::Receiver<thread::Result<T>>,
+}
+
+/// Runs a blocking closure on its own thread, so it can be awaited without
+/// blocking the executor. A panic in the closure is resumed in the task.
+pub fn unblock<F, T>(f: F) -> Unblock<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (sender, receiver) = oneshot_async::channel();
+    thread::spawn(move || {
+        // Nothing to do if the task isn't interested anymore.
+        let _ = sender.send(panic::catch_unwind(AssertUnwindSafe(f)));
+    });
+    Unblock { receiver }
+}
+
+impl<T> Future for Unblock<T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        match Pin::new(&mut self.receiver).poll(cx) {
+            Poll::Ready(Ok(Ok(value))) => Poll::Ready(value),
+            Poll::Ready(Ok(Err(payload))) => panic::resume_unwind(payload),
+            // The thread always sends, unless it got killed.
+            Poll::Ready(Err(RecvError)) => unreachable!(),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Receives from a blocking `simple_channel` asynchronously.
+///
+/// A forwarding thread does the blocking `receive()` calls and hands the
+/// messages over. Messages that were forwarded but never received go back
+/// into the channel when the `AsyncReceiver` is dropped. The forwarding
+/// thread only notices at the next message, which it sends back too, so
+/// that one may end up behind messages sent after it.
+pub struct AsyncReceiver<T> {
+    channel: Arc<simple_channel::Channel<T>>,
+    inbox: Arc<Mutex<Inbox<T>>>,
+}
+
+struct Inbox<T> {
+    messages: VecDeque<T>,
+    waker: Option<Waker>,
+    receiver_dropped: bool,
+}
+
+pub struct Recv<'a, T> {
+    receiver: &'a mut AsyncReceiver<T>,
+}
+
+impl<T: Send + 'static> AsyncReceiver<T> {
+    pub fn new(channel: Arc<simple_channel::Channel<T>>) -> Self {
+        let inbox = Arc::new(Mutex::new(Inbox {
+            messages: VecDeque::new(),
+            waker: None,
+            receiver_dropped: false,
+        }));
+        let forward_to = inbox.clone();
+        let forward_from = channel.clone();
+        thread::spawn(move || loop {
+            let message = forward_from.receive();
+            let mut inbox = forward_to.lock().unwrap();
+            if inbox.receiver_dropped {
+                forward_from.send(message);
+                return;
+            }
+            inbox.messages.push_back(message);
+            let waker = inbox.waker.take();
+            drop(inbox);
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        });
+        Self { channel, inbox }
+    }
+}
+
+impl<T> AsyncReceiver<T> {
+    pub fn recv(&mut self) -> Recv<'_, T> {
+        Recv { receiver: self }
+    }
+
+    pub fn try_recv(&mut self) -> Option<T> {
+        self.inbox.lock().unwrap().messages.pop_front()
+    }
+}
+
+impl<T> Drop for AsyncReceiver<T> {
+    fn drop(&mut self) {
+        let mut inbox = self.inbox.lock().unwrap();
+        inbox.receiver_dropped = true;
+        for message in inbox.messages.drain(..) {
+            self.channel.send(message);
+        }
+    }
+}
+
+impl<T> Future for Recv<'_, T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut inbox = self.receiver.inbox.lock().unwrap();
+        match inbox.messages.pop_front() {
+            Some(message) => Poll::Ready(message),
+            None => {
+                inbox.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for Unblock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Unblock").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Debug for AsyncReceiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("AsyncReceiver");
+        match self.inbox.try_lock() {
+            Ok(inbox) => d.field("forwarded", &inbox.messages.len()),
+            Err(_) => d.field("forwarded", &format_args!("<locked>")),
+        };
+        d.finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Debug for Recv<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recv").finish_non_exhaustive()
+    }
+}
+
+impl<T> oneshot_async::Receiver<T> {
+    /// Blocks the current thread until the message arrives.
+    pub fn blocking_recv(self) -> Result<T, RecvError> {
+        block_on(self)
+    }
+}
+
+impl<T> mutex::Mutex<T> {
+    /// Locks from outside of any task, waiting in line with the tasks.
+    pub fn blocking_lock(&self) -> MutexGuard<'_, T> {
+        block_on(self.lock())
+    }
+}
+
+impl<T> RwLock<T> {
+    pub fn blocking_read(&self) -> RwLockReadGuard<'_, T> {
+        block_on(self.read())
+    }
+
+    pub fn blocking_write(&self) -> RwLockWriteGuard<'_, T> {
+        block_on(self.write())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::async_sync::mutex::Mutex;
+    use crate::channels::channel::simple_channel::Channel;
+    use crate::channels::oneshot_async;
+    use crate::exec::bridge::{unblock, AsyncReceiver};
+    use crate::exec::executor::block_on;
+
+    #[test]
+    fn test_unblock() {
+        let value = block_on(unblock(|| {
+            thread::sleep(Duration::from_millis(10));
+            42
+        }));
+        assert_eq!(value, 42);
+    }
+
+    #[test]
+    #[should_panic(expected = "boom")]
+    fn test_unblock_resumes_panic() {
+        block_on(unblock(|| panic!("boom")));
+    }
+
+    #[test]
+    fn test_async_receiver() {
+        let channel = Arc::new(Channel::new());
+        let mut receiver = AsyncReceiver::new(channel.clone());
+        thread::spawn(move || {
+            for i in 0..100 {
+                channel.send(i);
+            }
+        });
+        block_on(async {
+            for i in 0..100 {
+                assert_eq!(receiver.recv().await, i);
+            }
+        });
+    }
+
+    #[test]
+    fn test_blocking_calls_on_async_primitives() {
+        let (sender, receiver) = oneshot_async::channel();
+        let mutex = Mutex::new(0);
+        thread::scope(|s| {
+            s.spawn(|| {
+                block_on(async {
+                    *mutex.lock().await += 1;
+                    sender.send(()).unwrap();
+                })
+            });
+            receiver.blocking_recv().unwrap();
+            *mutex.blocking_lock() += 1;
+        });
+        assert_eq!(mutex.into_inner(), 2);
+    }
+}
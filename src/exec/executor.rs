@@ -0,0 +1,181 @@
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::Instant;
+
+use crate::arc::reference_counting::better_weak::{Arc, Wake};
+use crate::platform::wait::{wait, wait_until, wake_one, BACKEND};
+use crate::sync_shim::plain_atomic::AtomicU32;
+use crate::sync_shim::plain_atomic::Ordering::{Acquire, Release};
+
+const EMPTY: u32 = 0;
+const NOTIFIED: u32 = 1;
+
+/// Wakes `block_on` through `platform::wait`, like the tokens threads park
+/// on in `platform::parking`, rather than through std's `Thread::unpark`.
+/// That way it sleeps on whichever backend the locks use.
+struct Unparker {
+    state: AtomicU32,
+}
+
+impl Unparker {
+    fn new() -> Self {
+        Self {
+            state: AtomicU32::new(EMPTY),
+        }
+    }
+
+    /// Takes the notification, if there is one. Acquire matches the
+    /// Release in `wake_by_ref`, so whatever happened before the wake-up is
+    /// visible to the next poll.
+    fn take(&self) -> bool {
+        self.state.swap(EMPTY, Acquire) == NOTIFIED
+    }
+}
+
+impl Wake for Unparker {
+    fn wake(this: Arc<Self>) {
+        Self::wake_by_ref(&this);
+    }
+
+    fn wake_by_ref(this: &Arc<Self>) {
+        // If it was already notified, whoever did that has woken it.
+        if this.state.swap(NOTIFIED, Release) == EMPTY {
+            wake_one(&this.state);
+        }
+    }
+}
+
+/// Runs a future to completion on the current thread, sleeping whenever
+/// the future is pending.
+///
+/// A wake-up that arrives before we sleep leaves a notification behind, so
+/// it can't be lost, and a spurious one only costs an extra poll.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let unparker = Arc::new(Unparker::new());
+    let waker = Waker::from(unparker.clone());
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        while !unparker.take() {
+            // Spinning would only keep whoever is to wake us from running.
+            if BACKEND == "spin-only" {
+                thread::yield_now();
+            } else {
+                wait(&unparker.state, EMPTY);
+            }
+        }
+    }
+}
+
+/// Like [`block_on`], but gives up at `deadline`: the future is dropped,
+/// and `None` returned, if it isn't ready by then.
+pub fn block_on_until<F: Future>(future: F, deadline: Instant) -> Option<F::Output> {
+    let mut future = pin!(future);
+    let unparker = Arc::new(Unparker::new());
+    let waker = Waker::from(unparker.clone());
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return Some(output);
+        }
+        while !unparker.take() {
+            if Instant::now() >= deadline {
+                return None;
+            }
+            if BACKEND == "spin-only" {
+                thread::yield_now();
+            } else {
+                wait_until(&unparker.state, EMPTY, deadline);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll, Waker};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::exec::executor::{block_on, block_on_until};
+
+    #[test]
+    fn test_ready_future() {
+        assert_eq!(block_on(async { 1 + 2 }), 3);
+    }
+
+    struct YieldOnce(bool);
+
+    impl Future for YieldOnce {
+        type Output = ();
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                return Poll::Ready(());
+            }
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn test_self_waking_future() {
+        block_on(async {
+            for _ in 0..100 {
+                YieldOnce(false).await;
+            }
+        });
+    }
+
+    #[derive(Default)]
+    struct Signal {
+        done: bool,
+        waker: Option<Waker>,
+    }
+
+    struct WaitForSignal(Arc<Mutex<Signal>>);
+
+    impl Future for WaitForSignal {
+        type Output = ();
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            let mut signal = self.0.lock().unwrap();
+            if signal.done {
+                return Poll::Ready(());
+            }
+            signal.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn test_woken_from_another_thread() {
+        let signal = Arc::new(Mutex::new(Signal::default()));
+        let s = signal.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            let mut signal = s.lock().unwrap();
+            signal.done = true;
+            if let Some(waker) = signal.waker.take() {
+                waker.wake();
+            }
+        });
+        block_on(WaitForSignal(signal));
+    }
+
+    #[test]
+    fn test_block_on_until() {
+        let deadline = Instant::now() + Duration::from_millis(20);
+        assert_eq!(block_on_until(async { 1 }, deadline), Some(1));
+        let signal = Arc::new(Mutex::new(Signal::default()));
+        assert_eq!(block_on_until(WaitForSignal(signal), deadline), None);
+        assert!(Instant::now() >= deadline);
+    }
+}
@@ -0,0 +1,3 @@
+pub mod bridge;
+pub mod executor;
+pub mod timer;
@@ -0,0 +1,334 @@
+//! `sleep` and `timeout` futures, driven by a hashed timer wheel.
+//!
+//! Timers are hashed into a fixed number of slots by the tick they expire
+//! in, so registering and cancelling a timer is cheap no matter how many
+//! there are. A dedicated thread advances the wheel once per tick and wakes
+//! the tasks whose timers have expired. It parks indefinitely while there
+//! are no timers at all.
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Mutex, OnceLock};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+const TICK: Duration = Duration::from_millis(1);
+const SLOTS: usize = 256;
+
+struct Timer {
+    wheel: Mutex<Wheel>,
+    driver: Thread,
+    start: Instant,
+}
+
+struct Wheel {
+    slots: [Vec<Entry>; SLOTS],
+    len: usize,
+    /// All ticks up to and including this one have been processed.
+    processed: u64,
+    next_id: u64,
+}
+
+struct Entry {
+    id: u64,
+    tick: u64,
+    waker: Waker,
+}
+
+fn timer() -> &'static Timer {
+    static TIMER: OnceLock<Timer> = OnceLock::new();
+    TIMER.get_or_init(|| {
+        let driver = thread::Builder::new()
+            .name("timer-wheel".into())
+            .spawn(|| drive(timer()))
+            .expect("failed to spawn the timer thread");
+        Timer {
+            wheel: Mutex::new(Wheel {
+                slots: std::array::from_fn(|_| Vec::new()),
+                len: 0,
+                processed: 0,
+                next_id: 0,
+            }),
+            driver: driver.thread().clone(),
+            start: Instant::now(),
+        }
+    })
+}
+
+fn drive(timer: &Timer) -> ! {
+    loop {
+        let now = timer.tick_of(Instant::now());
+        let mut wheel = timer.wheel.lock().unwrap();
+        let mut wakers = Vec::new();
+        // Going around more than once would only revisit the same slots.
+        let first = wheel.processed + 1;
+        for tick in first..=now.min(first + SLOTS as u64 - 1) {
+            let slot = &mut wheel.slots[tick as usize % SLOTS];
+            let mut i = 0;
+            while i < slot.len() {
+                if slot[i].tick <= now {
+                    wakers.push(slot.swap_remove(i).waker);
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        wheel.len -= wakers.len();
+        wheel.processed = wheel.processed.max(now);
+        let idle = wheel.len == 0;
+        drop(wheel);
+        wakers.into_iter().for_each(Waker::wake);
+        // A new timer unparks us, so there's no need to tick while idle.
+        if idle {
+            thread::park();
+        } else {
+            thread::park_timeout(TICK);
+        }
+    }
+}
+
+impl Timer {
+    /// The tick an instant falls in, rounded down.
+    fn tick_of(&self, instant: Instant) -> u64 {
+        (instant.saturating_duration_since(self.start).as_nanos() / TICK.as_nanos()) as u64
+    }
+
+    /// The first tick that starts at or after the deadline.
+    fn expiry_tick(&self, deadline: Instant) -> u64 {
+        let nanos = deadline.saturating_duration_since(self.start).as_nanos();
+        nanos.div_ceil(TICK.as_nanos()) as u64
+    }
+
+    /// Returns None if the deadline has already passed as far as the
+    /// driver is concerned.
+    fn register(&self, deadline: Instant, waker: Waker) -> Option<u64> {
+        let tick = self.expiry_tick(deadline);
+        let mut wheel = self.wheel.lock().unwrap();
+        if tick <= wheel.processed {
+            return None;
+        }
+        let id = wheel.next_id;
+        wheel.next_id += 1;
+        wheel.slots[tick as usize % SLOTS].push(Entry { id, tick, waker });
+        wheel.len += 1;
+        let was_idle = wheel.len == 1;
+        drop(wheel);
+        if was_idle {
+            self.driver.unpark();
+        }
+        Some(id)
+    }
+
+    /// Returns false if the timer has already fired.
+    fn update(&self, deadline: Instant, id: u64, waker: &Waker) -> bool {
+        let tick = self.expiry_tick(deadline);
+        let mut wheel = self.wheel.lock().unwrap();
+        let slot = &mut wheel.slots[tick as usize % SLOTS];
+        match slot.iter_mut().find(|e| e.id == id) {
+            Some(entry) => {
+                entry.waker.clone_from(waker);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn cancel(&self, deadline: Instant, id: u64) {
+        let tick = self.expiry_tick(deadline);
+        let mut wheel = self.wheel.lock().unwrap();
+        let slot = &mut wheel.slots[tick as usize % SLOTS];
+        if let Some(i) = slot.iter().position(|e| e.id == id) {
+            slot.swap_remove(i);
+            wheel.len -= 1;
+        }
+    }
+}
+
+/// Wakes `waker` on the timer thread once `deadline` has passed, for
+/// timers that aren't futures, like `channels::tick`. Returns None if it
+/// has passed already, and then it's never woken.
+pub(crate) fn schedule(deadline: Instant, waker: Waker) -> Option<u64> {
+    timer().register(deadline, waker)
+}
+
+/// Cancels a timer from `schedule`, unless it has fired already.
+pub(crate) fn unschedule(deadline: Instant, id: u64) {
+    timer().cancel(deadline, id);
+}
+
+/// Completes once its deadline has passed.
+#[derive(Debug)]
+pub struct Sleep {
+    /// None if it never completes.
+    deadline: Option<Instant>,
+    /// Our timer in the wheel, once we have one.
+    id: Option<u64>,
+}
+
+/// Never completes if the duration is too long for an `Instant`.
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep {
+        deadline: Instant::now().checked_add(duration),
+        id: None,
+    }
+}
+
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep {
+        deadline: Some(deadline),
+        id: None,
+    }
+}
+
+impl Sleep {
+    /// None if it never completes.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let Some(deadline) = self.deadline else {
+            return Poll::Pending;
+        };
+        if Instant::now() >= deadline {
+            if let Some(id) = self.id.take() {
+                timer().cancel(deadline, id);
+            }
+            return Poll::Ready(());
+        }
+        // The driver may have seen the deadline pass since our check above,
+        // in which case the timer is gone (or won't be added).
+        let timer = timer();
+        let registered = match self.id {
+            Some(id) => timer.update(deadline, id, cx.waker()),
+            None => {
+                self.id = timer.register(deadline, cx.waker().clone());
+                self.id.is_some()
+            }
+        };
+        if registered {
+            Poll::Pending
+        } else {
+            self.id = None;
+            Poll::Ready(())
+        }
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let (Some(deadline), Some(id)) = (self.deadline, self.id) {
+            timer().cancel(deadline, id);
+        }
+    }
+}
+
+/// The future given to `timeout` didn't complete in time.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Elapsed;
+
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+}
+
+/// Runs the future, but gives up on it once the duration has passed.
+/// Never gives up if the duration is too long for an `Instant`.
+pub fn timeout<F: Future>(future: F, duration: Duration) -> Timeout<F> {
+    Timeout {
+        future,
+        sleep: sleep(duration),
+    }
+}
+
+/// Runs the future, but gives up on it at `deadline`.
+pub fn timeout_at<F: Future>(future: F, deadline: Instant) -> Timeout<F> {
+    Timeout {
+        future,
+        sleep: sleep_until(deadline),
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<F::Output, Elapsed>> {
+        // Safety: `future` is structurally pinned: we never move it out,
+        // and don't hand out unpinned references to it.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        match Pin::new(&mut this.sleep).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<F> fmt::Debug for Timeout<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Timeout")
+            .field("deadline", &self.sleep.deadline)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::pending;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::exec::executor::block_on;
+    use crate::exec::timer::{sleep, timeout, timeout_at, Elapsed};
+
+    #[test]
+    fn test_sleep() {
+        let start = Instant::now();
+        block_on(sleep(Duration::from_millis(20)));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let result = block_on(timeout(pending::<()>(), Duration::from_millis(10)));
+        assert_eq!(result, Err(Elapsed));
+        let result = block_on(timeout(async { 5 }, Duration::from_secs(10)));
+        assert_eq!(result, Ok(5));
+        let result = block_on(timeout(async { 5 }, Duration::MAX));
+        assert_eq!(result, Ok(5));
+        let deadline = Instant::now() + Duration::from_millis(10);
+        assert_eq!(
+            block_on(timeout_at(pending::<()>(), deadline)),
+            Err(Elapsed)
+        );
+        assert!(Instant::now() >= deadline);
+    }
+
+    #[test]
+    fn test_many_sleepers() {
+        // More timers than slots, and deadlines spanning several rounds.
+        thread::scope(|s| {
+            for i in 0..8 {
+                s.spawn(move || {
+                    block_on(async {
+                        for j in 0..50u64 {
+                            let duration = Duration::from_micros((i * 50 + j) * 37 % 700);
+                            let start = Instant::now();
+                            sleep(duration).await;
+                            assert!(start.elapsed() >= duration);
+                        }
+                    })
+                });
+            }
+        });
+    }
+}
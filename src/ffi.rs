@@ -0,0 +1,351 @@
+//! `extern "C"` functions for a few of the primitives, so C and C++ test
+//! programs can run them next to pthreads.
+//!
+//! Every primitive is an opaque handle that `_new` allocates and `_free`
+//! frees. The declarations are in `include/atomics_and_locks.h`. To link
+//! them into a C program, build the library as a static one:
+//!
+//! ```text
+//! cargo rustc --release --lib --features ffi --crate-type staticlib
+//! cc -O2 bench.c -Iinclude target/release/libatomics_and_locks.a -lpthread -ldl -lm
+//! ```
+//!
+//! - [`AalMutex`] is the futex [`RawFutexMutex`], without data to guard.
+//! - [`AalChannel`] is a bounded channel of `void *`, a
+//!   [`sync_channel`] holding both ends, so it's never disconnected. It
+//!   only passes the pointers on: what they point to is the program's
+//!   business, and messages still in it when it's freed are forgotten.
+//! - [`AalEvent`] is a manual-reset [`AsyncEvent`]: it stays set until
+//!   it's reset, and setting it releases everyone waiting.
+//!
+//! The functions returning an `int` return one of the `AAL_` codes.
+
+use std::ffi::{c_int, c_void};
+use std::time::Duration;
+
+use crate::async_sync::event::AsyncEvent;
+use crate::channels::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender, TryRecvError};
+use crate::locks::{RawFutexMutex, RawLock};
+
+pub const AAL_OK: c_int = 0;
+/// A `try_send` found the channel full.
+pub const AAL_FULL: c_int = 1;
+/// A `try_recv` found the channel empty.
+pub const AAL_EMPTY: c_int = 2;
+pub const AAL_TIMEOUT: c_int = 3;
+
+pub struct AalMutex(RawFutexMutex);
+
+pub struct AalChannel {
+    tx: SyncSender<Message>,
+    rx: Receiver<Message>,
+}
+
+pub struct AalEvent(AsyncEvent);
+
+/// A pointer from C, which the channel hands over without looking at it.
+struct Message(*mut c_void);
+
+// Safety: It's only passed on, never dereferenced.
+unsafe impl Send for Message {}
+
+#[no_mangle]
+pub extern "C" fn aal_mutex_new() -> *mut AalMutex {
+    Box::into_raw(Box::new(AalMutex(RawFutexMutex::INIT)))
+}
+
+/// # Safety
+///
+/// `mutex` must come from `aal_mutex_new`, be unlocked, and not be used
+/// anymore.
+#[no_mangle]
+pub unsafe extern "C" fn aal_mutex_free(mutex: *mut AalMutex) {
+    drop(Box::from_raw(mutex));
+}
+
+/// # Safety
+///
+/// `mutex` must come from `aal_mutex_new`, and not be freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn aal_mutex_lock(mutex: *const AalMutex) {
+    (*mutex).0.lock();
+}
+
+/// Returns whether it locked it.
+///
+/// # Safety
+///
+/// See `aal_mutex_lock`.
+#[no_mangle]
+pub unsafe extern "C" fn aal_mutex_trylock(mutex: *const AalMutex) -> bool {
+    (*mutex).0.try_lock()
+}
+
+/// # Safety
+///
+/// See `aal_mutex_lock`. It must be locked, but unlike a pthread mutex, it
+/// can be unlocked by another thread than the one that locked it.
+#[no_mangle]
+pub unsafe extern "C" fn aal_mutex_unlock(mutex: *const AalMutex) {
+    (*mutex).0.unlock();
+}
+
+/// A channel that holds at most `capacity` messages. With 0, every send
+/// waits for its message to be received.
+#[no_mangle]
+pub extern "C" fn aal_channel_new(capacity: usize) -> *mut AalChannel {
+    let (tx, rx) = sync_channel(capacity);
+    Box::into_raw(Box::new(AalChannel { tx, rx }))
+}
+
+/// # Safety
+///
+/// `channel` must come from `aal_channel_new`, and not be used anymore.
+#[no_mangle]
+pub unsafe extern "C" fn aal_channel_free(channel: *mut AalChannel) {
+    drop(Box::from_raw(channel));
+}
+
+/// Blocks while the channel is full. Always returns `AAL_OK`.
+///
+/// # Safety
+///
+/// `channel` must come from `aal_channel_new`, and not be freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn aal_channel_send(
+    channel: *const AalChannel,
+    message: *mut c_void,
+) -> c_int {
+    let _ = (*channel).tx.send(Message(message));
+    AAL_OK
+}
+
+/// Returns `AAL_FULL` instead of blocking.
+///
+/// # Safety
+///
+/// See `aal_channel_send`.
+#[no_mangle]
+pub unsafe extern "C" fn aal_channel_try_send(
+    channel: *const AalChannel,
+    message: *mut c_void,
+) -> c_int {
+    match (*channel).tx.try_send(Message(message)) {
+        Ok(()) => AAL_OK,
+        Err(_) => AAL_FULL,
+    }
+}
+
+/// Blocks until there's a message, and stores it in `*message`. Always
+/// returns `AAL_OK`.
+///
+/// # Safety
+///
+/// See `aal_channel_send`. `message` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn aal_channel_recv(
+    channel: *const AalChannel,
+    message: *mut *mut c_void,
+) -> c_int {
+    // The channel holds a sender, so this can't fail.
+    if let Ok(Message(m)) = (*channel).rx.recv() {
+        *message = m;
+    }
+    AAL_OK
+}
+
+/// Returns `AAL_EMPTY` instead of blocking, leaving `*message` alone.
+///
+/// # Safety
+///
+/// See `aal_channel_recv`.
+#[no_mangle]
+pub unsafe extern "C" fn aal_channel_try_recv(
+    channel: *const AalChannel,
+    message: *mut *mut c_void,
+) -> c_int {
+    match (*channel).rx.try_recv() {
+        Ok(Message(m)) => {
+            *message = m;
+            AAL_OK
+        }
+        Err(TryRecvError::Empty | TryRecvError::Disconnected) => AAL_EMPTY,
+    }
+}
+
+/// Returns `AAL_TIMEOUT` if no message came within `timeout_ms`
+/// milliseconds, leaving `*message` alone.
+///
+/// # Safety
+///
+/// See `aal_channel_recv`.
+#[no_mangle]
+pub unsafe extern "C" fn aal_channel_recv_timeout(
+    channel: *const AalChannel,
+    message: *mut *mut c_void,
+    timeout_ms: u64,
+) -> c_int {
+    match (*channel)
+        .rx
+        .recv_timeout(Duration::from_millis(timeout_ms))
+    {
+        Ok(Message(m)) => {
+            *message = m;
+            AAL_OK
+        }
+        Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => AAL_TIMEOUT,
+    }
+}
+
+/// An event that isn't set.
+#[no_mangle]
+pub extern "C" fn aal_event_new() -> *mut AalEvent {
+    Box::into_raw(Box::new(AalEvent(AsyncEvent::new())))
+}
+
+/// # Safety
+///
+/// `event` must come from `aal_event_new`, and not be used anymore.
+#[no_mangle]
+pub unsafe extern "C" fn aal_event_free(event: *mut AalEvent) {
+    drop(Box::from_raw(event));
+}
+
+/// # Safety
+///
+/// `event` must come from `aal_event_new`, and not be freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn aal_event_set(event: *const AalEvent) {
+    (*event).0.set();
+}
+
+/// # Safety
+///
+/// See `aal_event_set`.
+#[no_mangle]
+pub unsafe extern "C" fn aal_event_reset(event: *const AalEvent) {
+    (*event).0.reset();
+}
+
+/// # Safety
+///
+/// See `aal_event_set`.
+#[no_mangle]
+pub unsafe extern "C" fn aal_event_is_set(event: *const AalEvent) -> bool {
+    (*event).0.is_set()
+}
+
+/// Blocks until it's set.
+///
+/// # Safety
+///
+/// See `aal_event_set`.
+#[no_mangle]
+pub unsafe extern "C" fn aal_event_wait(event: *const AalEvent) {
+    (*event).0.wait_sync();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::c_void;
+    use std::ptr;
+    use std::thread;
+
+    use crate::ffi::*;
+
+    /// Handles are raw pointers, which aren't `Send`. It's only ever moved
+    /// into the threads, so it needn't be `Sync`.
+    struct Shared<T>(*mut T);
+
+    impl<T> Clone for Shared<T> {
+        fn clone(&self) -> Self {
+            *self
+        }
+    }
+
+    impl<T> Copy for Shared<T> {}
+
+    unsafe impl<T> Send for Shared<T> {}
+
+    impl<T> Shared<T> {
+        /// Closures capture only the fields they use, so `.0` wouldn't
+        /// move the whole, `Send`, wrapper into them.
+        fn ptr(self) -> *mut T {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_mutex() {
+        const N: usize = if cfg!(miri) { 50 } else { 10_000 };
+        let mutex = Shared(aal_mutex_new());
+        let mut counter = 0usize;
+        let counter = Shared(&mut counter as *mut usize);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(move || {
+                    for _ in 0..N {
+                        unsafe {
+                            aal_mutex_lock(mutex.ptr());
+                            *counter.ptr() += 1;
+                            aal_mutex_unlock(mutex.ptr());
+                        }
+                    }
+                });
+            }
+        });
+        unsafe {
+            assert_eq!(*counter.ptr(), 4 * N);
+            assert!(aal_mutex_trylock(mutex.ptr()));
+            assert!(!aal_mutex_trylock(mutex.ptr()));
+            aal_mutex_unlock(mutex.ptr());
+            aal_mutex_free(mutex.ptr());
+        }
+    }
+
+    #[test]
+    fn test_channel() {
+        let channel = Shared(aal_channel_new(2));
+        let mut values = [1u32, 2, 3];
+        let first = values.as_mut_ptr().cast::<c_void>();
+        let mut out = ptr::null_mut();
+        unsafe {
+            assert_eq!(aal_channel_try_recv(channel.ptr(), &mut out), AAL_EMPTY);
+            assert_eq!(
+                aal_channel_recv_timeout(channel.ptr(), &mut out, 1),
+                AAL_TIMEOUT
+            );
+            assert_eq!(aal_channel_try_send(channel.ptr(), first), AAL_OK);
+            assert_eq!(aal_channel_send(channel.ptr(), first.add(4)), AAL_OK);
+            assert_eq!(aal_channel_try_send(channel.ptr(), first), AAL_FULL);
+        }
+        let received = Shared(first.wrapping_add(8));
+        thread::scope(|s| {
+            s.spawn(move || unsafe { aal_channel_send(channel.ptr(), received.ptr()) });
+            for offset in [0, 4, 8] {
+                unsafe { assert_eq!(aal_channel_recv(channel.ptr(), &mut out), AAL_OK) };
+                assert_eq!(out, first.wrapping_add(offset));
+            }
+        });
+        unsafe { aal_channel_free(channel.ptr()) };
+    }
+
+    #[test]
+    fn test_event() {
+        let event = Shared(aal_event_new());
+        thread::scope(|s| {
+            let waiters: Vec<_> = (0..3)
+                .map(|_| s.spawn(move || unsafe { aal_event_wait(event.ptr()) }))
+                .collect();
+            unsafe { aal_event_set(event.ptr()) };
+            waiters.into_iter().for_each(|w| w.join().unwrap());
+        });
+        unsafe {
+            assert!(aal_event_is_set(event.ptr()));
+            aal_event_wait(event.ptr());
+            aal_event_reset(event.ptr());
+            assert!(!aal_event_is_set(event.ptr()));
+            aal_event_free(event.ptr());
+        }
+    }
+}
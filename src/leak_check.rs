@@ -0,0 +1,297 @@
+//! A global allocator for the tests, which counts what each test leaves
+//! allocated.
+//!
+//! Leaking an `ArcData` or a node that a structure should have reclaimed
+//! doesn't crash anything, so a test can't tell, unless it's run under
+//! Miri or a leak sanitizer. With this allocator, it fails the test.
+//!
+//! Tests run in parallel, in one process, so a global count would mix
+//! them up. Instead, a table holds the address of every live allocation
+//! that a [`LeakCheck`] is counting, and its count goes down when it's
+//! freed, whichever thread frees it. Only allocations made inside
+//! [`LeakCheck::track`] count, on the thread calling it, so a test tracks
+//! the code under test, and not the threads or the lazily initialized
+//! statics around it, which outlive it. Those include the books that the
+//! `lock_order` and `deadlock_detection` features keep on `Lock`s.
+//!
+//! The table is on the side, rather than a header in front of every
+//! allocation, since reading a header through the pointer of a `Box`
+//! reads outside the `Box`, which Miri rejects.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::{Cell, UnsafeCell};
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::thread;
+
+use crate::locks::raw::{RawLock, RawSpinLock};
+
+/// How many checks can run at once. A check that found a leak keeps its
+/// slot, since the leaked allocations may still be freed later.
+const SLOTS: usize = 64;
+
+/// How many tracked allocations can be live at once, over all checks.
+/// More aren't tracked.
+const CAPACITY: usize = 1 << 12;
+
+static LIVE: [AtomicUsize; SLOTS] = [const { AtomicUsize::new(0) }; SLOTS];
+static IN_USE: [AtomicBool; SLOTS] = [const { AtomicBool::new(false) }; SLOTS];
+
+std::thread_local! {
+    /// The slot of the check tracking this thread's allocations, plus one,
+    /// or 0.
+    static TRACKING: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Open addressing with linear probing, keyed by address, 0 being empty.
+/// A spin lock, since std's locks may allocate on some platforms.
+struct Table {
+    lock: RawSpinLock,
+    addrs: UnsafeCell<[usize; CAPACITY]>,
+    slots: UnsafeCell<[u8; CAPACITY]>,
+    /// Lets frees skip the lock while nothing is tracked.
+    len: AtomicUsize,
+}
+
+// Safety: `addrs` and `slots` are only used while holding `lock`.
+unsafe impl Sync for Table {}
+
+static TABLE: Table = Table {
+    lock: RawSpinLock::INIT,
+    addrs: UnsafeCell::new([0; CAPACITY]),
+    slots: UnsafeCell::new([0; CAPACITY]),
+    len: AtomicUsize::new(0),
+};
+
+fn home(addr: usize) -> usize {
+    // Fibonacci hashing: the top bits of the product are well mixed.
+    addr.wrapping_mul(0x9e37_79b9_7f4a_7c15_u64 as usize) >> (usize::BITS - CAPACITY.ilog2())
+}
+
+impl Table {
+    fn with<R>(&self, f: impl FnOnce(&mut [usize; CAPACITY], &mut [u8; CAPACITY]) -> R) -> R {
+        self.lock.lock();
+        // Safety: We hold the lock.
+        let result = f(unsafe { &mut *self.addrs.get() }, unsafe {
+            &mut *self.slots.get()
+        });
+        // Safety: We locked it above.
+        unsafe { self.lock.unlock() };
+        result
+    }
+
+    fn insert(&self, addr: usize, slot: usize) {
+        self.with(|addrs, slots| {
+            if self.len.load(Relaxed) == CAPACITY - 1 {
+                return;
+            }
+            let mut i = home(addr);
+            while addrs[i] != 0 {
+                i = (i + 1) % CAPACITY;
+            }
+            addrs[i] = addr;
+            slots[i] = slot as u8;
+            self.len.fetch_add(1, Relaxed);
+            LIVE[slot].fetch_add(1, Relaxed);
+        });
+    }
+
+    /// The slot of the check that tracks `addr`, if any.
+    fn remove(&self, addr: usize) -> Option<usize> {
+        // Freeing an allocation comes after making it, so this sees it
+        // counted if it's tracked.
+        if self.len.load(Relaxed) == 0 {
+            return None;
+        }
+        self.with(|addrs, slots| {
+            let mut i = home(addr);
+            while addrs[i] != addr {
+                if addrs[i] == 0 {
+                    return None;
+                }
+                i = (i + 1) % CAPACITY;
+            }
+            let slot = usize::from(slots[i]);
+            // Move later entries of the same run back into the hole, so
+            // lookups don't stop at it before reaching them.
+            let mut j = i;
+            loop {
+                j = (j + 1) % CAPACITY;
+                if addrs[j] == 0 {
+                    break;
+                }
+                let k = home(addrs[j]);
+                // Whether `k` lies cyclically in (i, j], where the entry at
+                // `j` can stay.
+                let stays = if i <= j {
+                    i < k && k <= j
+                } else {
+                    i < k || k <= j
+                };
+                if !stays {
+                    addrs[i] = addrs[j];
+                    slots[i] = slots[j];
+                    i = j;
+                }
+            }
+            addrs[i] = 0;
+            self.len.fetch_sub(1, Relaxed);
+            LIVE[slot].fetch_sub(1, Relaxed);
+            Some(slot)
+        })
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Tracking = Tracking;
+
+struct Tracking;
+
+/// The slot of the check tracking the calling thread, if any.
+fn tracking() -> Option<usize> {
+    // Doesn't allocate: it has no destructor to register.
+    TRACKING.try_with(Cell::get).unwrap_or(0).checked_sub(1)
+}
+
+unsafe impl GlobalAlloc for Tracking {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if let (false, Some(slot)) = (ptr.is_null(), tracking()) {
+            TABLE.insert(ptr.addr(), slot);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if let (false, Some(slot)) = (ptr.is_null(), tracking()) {
+            TABLE.insert(ptr.addr(), slot);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // Before freeing, so the address can't be handed out again and
+        // tracked in the meantime.
+        TABLE.remove(ptr.addr());
+        System.dealloc(ptr, layout);
+    }
+
+    /// Keeps counting it for the same check.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let slot = TABLE.remove(ptr.addr());
+        let new = System.realloc(ptr, layout, new_size);
+        if let Some(slot) = slot {
+            // If it failed, the old one is still there.
+            let addr = if new.is_null() {
+                ptr.addr()
+            } else {
+                new.addr()
+            };
+            TABLE.insert(addr, slot);
+        }
+        new
+    }
+}
+
+/// Panics when it's dropped while allocations made in [`track`] are still
+/// live.
+///
+/// [`track`]: LeakCheck::track
+pub(crate) struct LeakCheck {
+    slot: usize,
+}
+
+impl LeakCheck {
+    pub(crate) fn new() -> Self {
+        let slot = (0..SLOTS)
+            .find(|&i| {
+                IN_USE[i]
+                    .compare_exchange(false, true, Acquire, Relaxed)
+                    .is_ok()
+            })
+            .expect("too many leak checks at once");
+        Self { slot }
+    }
+
+    /// Runs `f`, counting what it allocates on this thread. Call it on
+    /// every thread that allocates for the code under test.
+    pub(crate) fn track<R>(&self, f: impl FnOnce() -> R) -> R {
+        struct Restore(usize);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                TRACKING.set(self.0);
+            }
+        }
+        let _restore = Restore(TRACKING.replace(self.slot + 1));
+        f()
+    }
+
+    /// How many of the allocations made in `track` haven't been freed yet.
+    pub(crate) fn live(&self) -> usize {
+        LIVE[self.slot].load(Relaxed)
+    }
+}
+
+impl Drop for LeakCheck {
+    fn drop(&mut self) {
+        let live = self.live();
+        if live == 0 {
+            IN_USE[self.slot].store(false, Release);
+        } else if !thread::panicking() {
+            panic!("{live} allocations leaked");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+    use std::thread;
+
+    use crate::leak_check::{home, LeakCheck, TABLE};
+
+    #[test]
+    fn test_frees_on_other_threads_count() {
+        let leaks = LeakCheck::new();
+        let mut v = leaks.track(|| vec![Box::new(1u8), Box::new(2)]);
+        assert_eq!(leaks.live(), 3);
+        // Reallocating keeps counting it once.
+        leaks.track(|| v.reserve(100));
+        v.shrink_to_fit();
+        assert_eq!(leaks.live(), 3);
+        // Untracked allocations don't count.
+        v.push(Box::new(3));
+        assert_eq!(leaks.live(), 3);
+        thread::spawn(move || drop(v)).join().unwrap();
+        assert_eq!(leaks.live(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "1 allocations leaked")]
+    fn test_leak_panics() {
+        let leaks = LeakCheck::new();
+        mem::forget(leaks.track(|| Box::new([0u64; 4])));
+    }
+
+    #[test]
+    fn test_removal_keeps_runs_reachable() {
+        // Four addresses that want the same place in the table, so they
+        // form a run. Other tests use it too, so they're in the last
+        // megabyte of the address space, which no allocation can be in.
+        let same: Vec<usize> = (usize::MAX - (1 << 20)..)
+            .filter(|&a| home(a) == home(usize::MAX))
+            .take(4)
+            .collect();
+        let leaks = LeakCheck::new();
+        for &addr in &same {
+            TABLE.insert(addr, leaks.slot);
+        }
+        assert_eq!(leaks.live(), 4);
+        for &addr in [same[1], same[0], same[3], same[2]].iter() {
+            assert_eq!(TABLE.remove(addr), Some(leaks.slot));
+            assert_eq!(TABLE.remove(addr), None);
+        }
+        assert_eq!(leaks.live(), 0);
+    }
+}
@@ -0,0 +1,100 @@
+//! Concurrency primitives built from scratch, following Mara Bos'
+//! [Rust Atomics and Locks](https://marabos.nl/atomics/).
+//!
+//! The most commonly used types are re-exported at the crate root. The
+//! modules also contain the step-by-step versions from the book, which are
+//! kept around for reference.
+//!
+//! Without the default `std` feature, the crate is `no_std` (but needs
+//! `alloc`), leaving only the primitives that are built from atomics
+//! alone: `Arc`, the locks, the one-shot channels, the work-stealing
+//! deque and the RCU cells. How the `Mutex` sleeps is picked with the
+//! backend features, see [`platform::wait`]. On targets without
+//! compare-and-swap, like `thumbv6m`, the `portable-atomic` feature fills
+//! in the atomics.
+//!
+//! Debug builds panic on misuse the types can't rule out by themselves:
+//! unlocking a raw lock that isn't locked, calling `SpinLock::unlock` on
+//! another thread than the one that locked it, or polling a one-shot
+//! `Receiver` again after it completed. Release builds don't check.
+//!
+//! Tests and fuzzers can also check the invariants of an `Arc`, a `Mutex`
+//! or a work-stealing deque between operations, with their
+//! `debug_validate` methods, to find where a count or an index went wrong
+//! instead of only the crash it leads to later. Those check in every build.
+//!
+//! The tests also run under Miri, which checks the unsafe code for
+//! undefined behavior, with smaller iteration counts. No pointer is ever
+//! turned into an integer and back, so it passes with strict provenance.
+//! The timer thread of `exec` runs for as long as the process does, so
+//! Miri has to be told that's fine:
+//!
+//! ```text
+//! MIRIFLAGS="-Zmiri-strict-provenance -Zmiri-ignore-leaks" cargo +nightly miri test
+//! ```
+//!
+//! CI runs exactly that, in `.github/workflows/miri.yml`. A few tests that
+//! depend on timing, or on system calls Miri doesn't have, are ignored
+//! there.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+// `memory.atomic.wait32` and `notify` aren't stable yet, but threaded
+// WebAssembly takes a nightly compiler anyway. See `platform::wait`.
+#![cfg_attr(
+    all(target_arch = "wasm32", target_feature = "atomics"),
+    feature(stdarch_wasm_atomic_wait)
+)]
+#![cfg_attr(feature = "nightly", feature(dropck_eyepatch))]
+
+extern crate alloc;
+
+pub mod arc;
+#[cfg(feature = "std")]
+pub mod async_sync;
+pub mod cache_padded;
+#[cfg(feature = "std")]
+pub mod cancel;
+pub mod channels;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+#[cfg(feature = "deadlock_detection")]
+pub mod deadlock;
+pub mod errors;
+pub mod exclusive;
+#[cfg(feature = "std")]
+pub mod exec;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(all(test, not(loom)))]
+mod leak_check;
+#[cfg(feature = "lock_order")]
+mod lock_order;
+pub mod lockfree;
+pub mod locks;
+#[cfg(feature = "std")]
+pub mod memory_ordering;
+pub mod oom;
+#[cfg(feature = "std")]
+pub mod perf;
+pub mod platform;
+#[cfg(feature = "std")]
+pub mod pool;
+pub mod prelude;
+pub mod rcu;
+#[cfg(feature = "replay")]
+pub mod replay;
+#[cfg(feature = "stats")]
+pub mod stats;
+mod sync_shim;
+pub mod testing;
+#[cfg(feature = "trace")]
+pub mod trace;
+
+pub use arc::{Arc, Weak};
+pub use cache_padded::CachePadded;
+#[cfg(feature = "std")]
+pub use channels::{Channel, PriorityChannel};
+pub use exclusive::Exclusive;
+pub use locks::{SpinLock, SpinLockGuard};
+#[cfg(feature = "std")]
+pub use pool::{ThreadPool, WaitGroup, WorkStealingPool};
@@ -0,0 +1,157 @@
+//! Lock order validation for `Lock` and `RwLock`, like the kernel's lockdep.
+//!
+//! With the `lock_order` feature, taking a lock while holding another
+//! records that the second comes after the first. Taking two locks in the
+//! opposite order of what was recorded before panics, even if it didn't
+//! deadlock this time, with the backtraces of both places. That finds
+//! potential deadlocks that'd otherwise only show up under just the right
+//! timing, which usually means in production.
+//!
+//! Only blocking locks count: `try_lock` can't deadlock. A guard that's
+//! dropped on another thread than the one that locked it still counts as
+//! held by the first thread. This makes locking a lot slower, so it's meant
+//! for debug builds.
+
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+
+/// For every lock, the locks that were taken while holding it, with where
+/// that first happened.
+static AFTER: Mutex<Option<HashMap<usize, HashMap<usize, Backtrace>>>> = Mutex::new(None);
+
+std::thread_local! {
+    static HELD: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+fn with_after<R>(f: impl FnOnce(&mut HashMap<usize, HashMap<usize, Backtrace>>) -> R) -> R {
+    // Nothing panics while holding this, so poisoning doesn't matter.
+    let mut after = AFTER.lock().unwrap_or_else(PoisonError::into_inner);
+    f(after.get_or_insert_with(HashMap::default))
+}
+
+/// Whether `to` was ever taken after `from`, directly or through other locks.
+fn comes_after(after: &HashMap<usize, HashMap<usize, Backtrace>>, from: usize, to: usize) -> bool {
+    let mut seen = vec![from];
+    let mut todo = vec![from];
+    while let Some(l) = todo.pop() {
+        for &next in after.get(&l).into_iter().flat_map(|a| a.keys()) {
+            if next == to {
+                return true;
+            }
+            if !seen.contains(&next) {
+                seen.push(next);
+                todo.push(next);
+            }
+        }
+    }
+    false
+}
+
+/// The current thread is about to block on the lock. Panics if it holds a
+/// lock that was taken after this one before.
+pub(crate) fn before_lock(addr: usize) {
+    let held = HELD.with(|h| h.borrow().clone());
+    let violation = with_after(|after| {
+        for &h in &held {
+            if h == addr {
+                continue;
+            }
+            if comes_after(after, addr, h) {
+                // Point to where the reverse order started, if it's direct.
+                let earlier = after
+                    .get(&addr)
+                    .and_then(|a| a.get(&h))
+                    .map(|bt| bt.to_string());
+                return Some((h, earlier));
+            }
+            after
+                .entry(h)
+                .or_default()
+                .entry(addr)
+                .or_insert_with(Backtrace::force_capture);
+        }
+        None
+    });
+    if let Some((h, earlier)) = violation {
+        panic!(
+            "lock order violation: locking {addr:#x} while holding {h:#x} at\n{}\n\
+             but {h:#x} was locked while holding {addr:#x} before{}",
+            Backtrace::force_capture(),
+            match earlier {
+                Some(bt) => format!(" at\n{bt}"),
+                None => " (through other locks)".to_string(),
+            }
+        );
+    }
+}
+
+/// The current thread got the lock.
+pub(crate) fn acquired(addr: usize) {
+    HELD.with(|h| h.borrow_mut().push(addr));
+}
+
+pub(crate) fn released(addr: usize) {
+    HELD.with(|h| {
+        let mut h = h.borrow_mut();
+        if let Some(i) = h.iter().rposition(|l| *l == addr) {
+            h.remove(i);
+        }
+    });
+}
+
+/// The lock is gone, so a new one at the same address doesn't inherit its
+/// order.
+pub(crate) fn forget(addr: usize) {
+    with_after(|after| {
+        after.remove(&addr);
+        for a in after.values_mut() {
+            a.remove(&addr);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::locks::Mutex;
+
+    #[test]
+    fn test_consistent_order_is_fine() {
+        let a = Mutex::new(());
+        let b = Mutex::new(());
+        thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    let _a = a.lock();
+                    let _b = b.lock();
+                });
+            }
+        });
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "Miri can't capture backtraces in isolation")]
+    fn test_inconsistent_order_panics() {
+        let a = Mutex::new(());
+        let b = Mutex::new(());
+        thread::scope(|s| {
+            s.spawn(|| {
+                let _a = a.lock();
+                let _b = b.lock();
+            })
+            .join()
+            .unwrap();
+            let result = s
+                .spawn(|| {
+                    let _b = b.lock();
+                    let _a = a.lock();
+                })
+                .join();
+            let message = *result.unwrap_err().downcast::<String>().unwrap();
+            assert!(message.starts_with("lock order violation"));
+        });
+    }
+}
@@ -0,0 +1,550 @@
+use alloc::boxed::Box;
+// Needs native compare-and-swap. The crate's own `Arc` doesn't, with
+// `portable-atomic`.
+#[cfg(target_has_atomic = "ptr")]
+use alloc::sync::Arc;
+use core::cell::Cell;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ptr;
+
+#[cfg(not(target_has_atomic = "ptr"))]
+use crate::arc::Arc;
+use crate::cache_padded::CachePadded;
+use crate::errors::AllocError;
+use crate::oom;
+use crate::sync_shim::atomic::Ordering::{Acquire, Relaxed, Release, SeqCst};
+use crate::sync_shim::atomic::{fence, AtomicIsize, AtomicPtr};
+
+/// A fixed-capacity Chase–Lev work-stealing deque.
+///
+/// The owning `Worker` pushes and pops at the bottom, any number of
+/// `Stealer`s take from the top. Items are boxed, so every slot is a single
+/// `AtomicPtr` and a stealer racing with the owner never reads a slot
+/// non-atomically. The stealers' `top` and the owner's `bottom` are on
+/// cache lines of their own, so pushing doesn't slow down stealing.
+struct Inner<T> {
+    top: CachePadded<AtomicIsize>,
+    bottom: CachePadded<AtomicIsize>,
+    slots: Box<[AtomicPtr<T>]>,
+}
+
+impl<T> Inner<T> {
+    fn slot(&self, index: isize) -> &AtomicPtr<T> {
+        // The capacity is a power of two, so masking is the same as `%`,
+        // also for the (never negative in practice) indices.
+        &self.slots[index as usize & (self.slots.len() - 1)]
+    }
+}
+
+unsafe impl<T: Send> Send for Inner<T> {}
+unsafe impl<T: Send> Sync for Inner<T> {}
+
+impl<T> Drop for Inner<T> {
+    fn drop(&mut self) {
+        let top = self.top.load(Relaxed);
+        let bottom = self.bottom.load(Relaxed);
+        for i in top..bottom {
+            // Safety: Every slot between top and bottom holds a pointer from
+            // Box::into_raw that nobody took out.
+            drop(unsafe { Box::from_raw(self.slot(i).load(Relaxed)) });
+        }
+    }
+}
+
+pub struct Worker<T> {
+    inner: Arc<Inner<T>>,
+    // Only one thread may push and pop, so `Worker` must not be `Sync`.
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+pub struct Stealer<T> {
+    inner: Arc<Inner<T>>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Steal<T> {
+    Empty,
+    Success(T),
+    /// Lost a race with another thief or the owner; trying again may work.
+    Retry,
+}
+
+/// Panics if `capacity` is not a power of two. Does what the `OomPolicy`
+/// says if it can't allocate the slots.
+pub fn deque<T>(capacity: usize) -> (Worker<T>, Stealer<T>) {
+    try_deque(capacity).unwrap_or_else(|e| oom::handle(e))
+}
+
+/// Returns an error instead of failing to allocate the slots. The few
+/// words around them are still allocated the usual way, see `oom`.
+///
+/// Panics if `capacity` is not a power of two.
+pub fn try_deque<T>(capacity: usize) -> Result<(Worker<T>, Stealer<T>), AllocError> {
+    assert!(
+        capacity.is_power_of_two(),
+        "capacity must be a power of two"
+    );
+    let inner = Arc::new(Inner {
+        top: CachePadded::new(AtomicIsize::new(0)),
+        bottom: CachePadded::new(AtomicIsize::new(0)),
+        slots: oom::try_boxed_slice(capacity, || AtomicPtr::new(ptr::null_mut()))?,
+    });
+    Ok((
+        Worker {
+            inner: inner.clone(),
+            _not_sync: PhantomData,
+        },
+        Stealer { inner },
+    ))
+}
+
+impl<T> Worker<T> {
+    /// Gives the value back when the deque is full.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let inner = &*self.inner;
+        let b = inner.bottom.load(Relaxed);
+        // A stale top only makes the deque look fuller than it is. Acquire,
+        // so a stealer that moved top past a slot has read the pointer in
+        // it before we overwrite it. Loom can't check this one: it never
+        // lets a load read a store that comes later, which is what Relaxed
+        // would allow here.
+        let t = inner.top.load(Acquire);
+        if b - t >= inner.slots.len() as isize {
+            return Err(value);
+        }
+        inner.slot(b).store(Box::into_raw(Box::new(value)), Relaxed);
+        // Release publishes the slot (and the boxed value) to the
+        // stealers' Acquire load of `bottom`.
+        inner.bottom.store(b + 1, Release);
+        Ok(())
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let inner = &*self.inner;
+        let b = inner.bottom.load(Relaxed) - 1;
+        inner.bottom.store(b, Relaxed);
+        // SeqCst makes the reservation of slot b and the stealers'
+        // reservation of slot t through `top` agree on a single order.
+        fence(SeqCst);
+        let t = inner.top.load(Relaxed);
+        if t > b {
+            // Empty.
+            inner.bottom.store(b + 1, Relaxed);
+            return None;
+        }
+        let p = inner.slot(b).load(Relaxed);
+        if t == b {
+            // Last item: race the stealers for it through `top`.
+            let won = inner
+                .top
+                .compare_exchange(t, t + 1, SeqCst, Relaxed)
+                .is_ok();
+            inner.bottom.store(b + 1, Relaxed);
+            if !won {
+                return None;
+            }
+        }
+        // Safety: Slot b was reserved for us above, and came from Box::into_raw.
+        Some(*unsafe { Box::from_raw(p) })
+    }
+
+    pub fn stealer(&self) -> Stealer<T> {
+        Stealer {
+            inner: self.inner.clone(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        let b = self.inner.bottom.load(Relaxed);
+        let t = self.inner.top.load(Relaxed);
+        (b - t).max(0) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.inner.slots.len()
+    }
+
+    /// Panics if `top` and `bottom` are out of bounds: `top` past `bottom`,
+    /// more items between them than there are slots, or a slot between them
+    /// that was never filled. Only the owner moves `bottom`, and stealers
+    /// only move `top` up to it, so this holds between the owner's
+    /// operations even while others steal.
+    pub fn debug_validate(&self) {
+        let inner = &*self.inner;
+        let b = inner.bottom.load(Relaxed);
+        let t = inner.top.load(Acquire);
+        assert!(0 <= t && t <= b, "top {t} is out of bounds, bottom is {b}");
+        assert!(
+            b - t <= inner.slots.len() as isize,
+            "{} items in {} slots",
+            b - t,
+            inner.slots.len()
+        );
+        for i in t..b {
+            assert!(!inner.slot(i).load(Relaxed).is_null(), "slot {i} is empty");
+        }
+    }
+}
+
+impl<T> Stealer<T> {
+    pub fn steal(&self) -> Steal<T> {
+        let inner = &*self.inner;
+        let t = inner.top.load(Acquire);
+        fence(SeqCst);
+        let b = inner.bottom.load(Acquire);
+        if t >= b {
+            return Steal::Empty;
+        }
+        // If the owner wrapped around and overwrote this slot in the meantime,
+        // `top` has moved on too and the compare-exchange below fails, so we
+        // never use a pointer we aren't entitled to.
+        let p = inner.slot(t).load(Relaxed);
+        if inner
+            .top
+            .compare_exchange(t, t + 1, SeqCst, Relaxed)
+            .is_err()
+        {
+            return Steal::Retry;
+        }
+        // Safety: Winning the compare-exchange on top reserved slot t for us.
+        Steal::Success(*unsafe { Box::from_raw(p) })
+    }
+
+    /// Moves about half of the items, the oldest ones, to the bottom of
+    /// `into`, as many as fit, and returns how many that were.
+    ///
+    /// The owner pops without touching `top` unless it's down to the last
+    /// item, so reserving the whole batch with one compare-exchange could
+    /// hand out items the owner has popped in the meantime. Instead, every
+    /// item is reserved like `steal` does, checking `bottom` again before
+    /// each one, and the batch ends early when the owner gets close or
+    /// another thief gets in between. The items are moved without being
+    /// reboxed, and only published to `into`'s thieves once all of them are
+    /// in.
+    ///
+    /// Panics if `into` is this deque's own worker.
+    pub fn steal_batch(&self, into: &Worker<T>) -> Steal<usize> {
+        let src = &*self.inner;
+        let dst = &*into.inner;
+        assert!(!ptr::eq(src, dst), "can't steal from a deque into itself");
+        let mut t = src.top.load(Acquire);
+        fence(SeqCst);
+        let b = src.bottom.load(Acquire);
+        if t >= b {
+            return Steal::Empty;
+        }
+        // Only we push to `into`. Acquire on its `top`, like in `push`.
+        let db = dst.bottom.load(Relaxed);
+        let room = dst.slots.len() as isize - (db - dst.top.load(Acquire));
+        let batch = ((b - t + 1) / 2).min(room);
+        let mut taken = 0;
+        while taken < batch {
+            if taken > 0 {
+                fence(SeqCst);
+                if t >= src.bottom.load(Acquire) {
+                    break;
+                }
+            }
+            let p = src.slot(t).load(Relaxed);
+            if src.top.compare_exchange(t, t + 1, SeqCst, Relaxed).is_err() {
+                break;
+            }
+            // Free, since the batch fits, and only seen by thieves of `into`
+            // once `bottom` is stored below.
+            dst.slot(db + taken).store(p, Relaxed);
+            t += 1;
+            taken += 1;
+        }
+        if taken == 0 && batch > 0 {
+            return Steal::Retry;
+        }
+        dst.bottom.store(db + taken, Release);
+        Steal::Success(taken as usize)
+    }
+
+    /// Like `steal_batch`, and then pops the newest of the stolen items
+    /// from `into`, so a thief gets something to work on right away.
+    pub fn steal_batch_and_pop(&self, into: &Worker<T>) -> Steal<T> {
+        match self.steal_batch(into) {
+            // Only our thieves could have taken it in the meantime.
+            Steal::Success(n) if n > 0 => into.pop().map_or(Steal::Retry, Steal::Success),
+            // `into` is full, so no batch, but one item can still be taken.
+            Steal::Success(_) => self.steal(),
+            Steal::Empty => Steal::Empty,
+            Steal::Retry => Steal::Retry,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let t = self.inner.top.load(Acquire);
+        let b = self.inner.bottom.load(Acquire);
+        t >= b
+    }
+}
+
+impl<T> Clone for Stealer<T> {
+    fn clone(&self) -> Self {
+        Stealer {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Worker<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Worker")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+impl<T> fmt::Debug for Stealer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Stealer")
+            .field("is_empty", &self.is_empty())
+            .finish()
+    }
+}
+
+#[cfg(all(test, not(any(loom, feature = "shuttle"))))]
+mod tests {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::thread;
+    use std::time::Instant;
+
+    use crate::errors::AllocError;
+    use crate::leak_check::LeakCheck;
+    use crate::lockfree::deque::{deque, try_deque, Steal};
+
+    #[test]
+    fn test_owner_is_lifo_and_stealer_is_fifo() {
+        let (worker, stealer) = deque(8);
+        for i in 0..4 {
+            worker.push(i).unwrap();
+        }
+        assert_eq!(worker.pop(), Some(3));
+        assert_eq!(stealer.steal(), Steal::Success(0));
+        assert_eq!(worker.len(), 2);
+        assert_eq!(worker.pop(), Some(2));
+        assert_eq!(worker.pop(), Some(1));
+        assert_eq!(worker.pop(), None);
+        assert_eq!(stealer.steal(), Steal::Empty);
+    }
+
+    #[test]
+    fn test_debug_validate() {
+        let (worker, stealer) = deque(2);
+        worker.debug_validate();
+        worker.push(1).unwrap();
+        worker.push(2).unwrap();
+        worker.debug_validate();
+        assert_eq!(stealer.steal(), Steal::Success(1));
+        assert_eq!(worker.pop(), Some(2));
+        assert_eq!(worker.pop(), None);
+        worker.debug_validate();
+        // A steal that moved `top` without checking `bottom`.
+        worker.inner.top.fetch_add(1, Relaxed);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| worker.debug_validate()));
+        assert!(result.is_err());
+        worker.inner.top.fetch_sub(1, Relaxed);
+    }
+
+    #[test]
+    fn test_push_fails_when_full() {
+        let (worker, stealer) = deque(2);
+        worker.push(1).unwrap();
+        worker.push(2).unwrap();
+        assert_eq!(worker.push(3), Err(3));
+        assert_eq!(stealer.steal(), Steal::Success(1));
+        worker.push(3).unwrap();
+    }
+
+    #[test]
+    fn test_try_deque() {
+        let (worker, _) = try_deque::<u8>(4).unwrap();
+        assert_eq!(worker.capacity(), 4);
+        // More slots than fit in the address space.
+        let err = try_deque::<u8>(1 << (usize::BITS - 2)).err();
+        assert_eq!(err, Some(AllocError::overflow()));
+    }
+
+    #[test]
+    fn test_remaining_items_are_dropped() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+        struct DetectDrop;
+        impl Drop for DetectDrop {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+        let leaks = LeakCheck::new();
+        let (worker, stealer) = leaks.track(|| deque(4));
+        for _ in 0..3 {
+            assert!(leaks.track(|| worker.push(DetectDrop)).is_ok());
+        }
+        drop(worker.pop());
+        assert_eq!(NUM_DROPS.load(Relaxed), 1);
+        drop(worker);
+        drop(stealer);
+        assert_eq!(NUM_DROPS.load(Relaxed), 3);
+        // The boxes they were in, and the slots.
+        assert_eq!(leaks.live(), 0);
+    }
+
+    #[test]
+    fn test_every_item_is_taken_exactly_once() {
+        const ITEMS: usize = if cfg!(miri) { 1_000 } else { 100_000 };
+        let leaks = LeakCheck::new();
+        let (worker, stealer) = leaks.track(|| deque(64));
+        let stolen = AtomicUsize::new(0);
+        let sum = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for _ in 0..3 {
+                let stealer = stealer.clone();
+                let (stolen, sum) = (&stolen, &sum);
+                s.spawn(move || {
+                    while stolen.load(Relaxed) < ITEMS {
+                        if let Steal::Success(i) = stealer.steal() {
+                            sum.fetch_add(i, Relaxed);
+                            stolen.fetch_add(1, Relaxed);
+                        }
+                    }
+                });
+            }
+            let mut next = 0;
+            while next < ITEMS {
+                match leaks.track(|| worker.push(next)) {
+                    Ok(()) => next += 1,
+                    Err(_) => {
+                        if let Some(i) = worker.pop() {
+                            sum.fetch_add(i, Relaxed);
+                            stolen.fetch_add(1, Relaxed);
+                        }
+                    }
+                }
+            }
+            while let Some(i) = worker.pop() {
+                sum.fetch_add(i, Relaxed);
+                stolen.fetch_add(1, Relaxed);
+            }
+        });
+        assert_eq!(sum.into_inner(), ITEMS * (ITEMS - 1) / 2);
+        // Every box was freed by whoever took it out.
+        drop((worker, stealer));
+        assert_eq!(leaks.live(), 0);
+    }
+
+    #[test]
+    fn test_steal_batch() {
+        let (worker, stealer) = deque(8);
+        let (thief, thief_stealer) = deque(4);
+        for i in 0..5 {
+            worker.push(i).unwrap();
+        }
+        // Half, rounded up, oldest first.
+        assert_eq!(stealer.steal_batch(&thief), Steal::Success(3));
+        assert_eq!(thief_stealer.steal(), Steal::Success(0));
+        assert_eq!(worker.len(), 2);
+        assert_eq!(stealer.steal_batch_and_pop(&thief), Steal::Success(3));
+        assert_eq!(thief.len(), 2);
+        worker.push(5).unwrap();
+        thief.push(6).unwrap();
+        // Half is one, and it just fits.
+        assert_eq!(stealer.steal_batch(&thief), Steal::Success(1));
+        thief.debug_validate();
+        assert_eq!(stealer.steal_batch(&thief), Steal::Success(0));
+        // Full, so only the one that's popped right away.
+        assert_eq!(stealer.steal_batch_and_pop(&thief), Steal::Success(5));
+        assert_eq!(stealer.steal_batch(&thief), Steal::Empty);
+        let mut left = Vec::new();
+        while let Some(i) = thief.pop() {
+            left.push(i);
+        }
+        assert_eq!(left, [4, 6, 2, 1]);
+    }
+
+    #[test]
+    #[should_panic = "can't steal from a deque into itself"]
+    fn test_steal_batch_into_itself() {
+        let (worker, stealer) = deque::<()>(2);
+        stealer.steal_batch(&worker);
+    }
+
+    #[test]
+    fn test_batches_take_every_item_once() {
+        const ITEMS: usize = if cfg!(miri) { 500 } else { 100_000 };
+        let (worker, stealer) = deque(64);
+        let taken = AtomicUsize::new(0);
+        let sum = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for _ in 0..2 {
+                let stealer = stealer.clone();
+                let (taken, sum) = (&taken, &sum);
+                s.spawn(move || {
+                    let (own, _) = deque(16);
+                    while taken.load(Relaxed) < ITEMS {
+                        if let Steal::Success(i) = stealer.steal_batch_and_pop(&own) {
+                            sum.fetch_add(i, Relaxed);
+                            taken.fetch_add(1, Relaxed);
+                        }
+                        while let Some(i) = own.pop() {
+                            sum.fetch_add(i, Relaxed);
+                            taken.fetch_add(1, Relaxed);
+                        }
+                    }
+                });
+            }
+            for i in 0..ITEMS {
+                if let Err(i) = worker.push(i) {
+                    sum.fetch_add(i, Relaxed);
+                    taken.fetch_add(1, Relaxed);
+                    // Keep the owner popping too, so it races the batches.
+                    if let Some(i) = worker.pop() {
+                        sum.fetch_add(i, Relaxed);
+                        taken.fetch_add(1, Relaxed);
+                    }
+                }
+            }
+            while let Some(i) = worker.pop() {
+                sum.fetch_add(i, Relaxed);
+                taken.fetch_add(1, Relaxed);
+            }
+        });
+        assert_eq!(sum.into_inner(), ITEMS * (ITEMS - 1) / 2);
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_push_steal`,
+    /// on a machine with two cores to spare. Compare with the commit before
+    /// `top` and `bottom` were padded to see what that did.
+    #[test]
+    #[ignore]
+    fn bench_push_steal() {
+        const ITEMS: usize = 10_000_000;
+        let (worker, stealer) = deque(1024);
+        let start = Instant::now();
+        thread::scope(|s| {
+            s.spawn(|| {
+                let mut stolen = 0;
+                while stolen < ITEMS {
+                    if let Steal::Success(_) = stealer.steal() {
+                        stolen += 1;
+                    }
+                }
+            });
+            for i in 0..ITEMS {
+                while worker.push(i).is_err() {
+                    std::hint::spin_loop();
+                }
+            }
+        });
+        println!("{:?} per item", start.elapsed() / ITEMS as u32);
+    }
+}
@@ -0,0 +1,3 @@
+pub mod deque;
+
+pub use deque::{deque, try_deque, Steal, Stealer, Worker};
@@ -0,0 +1,201 @@
+//! A fixed-size set of flags that threads can wait on one at a time, built
+//! on `platform::wait`.
+//!
+//! The flags are packed 32 to an `AtomicU32`, so a table of them, like the
+//! `READY` array of the fences example in chapter 3, takes a bit per entry
+//! instead of a byte, and a thread waiting for one entry doesn't need to be
+//! parked and unparked by hand. A waiter sleeps on the word that holds its
+//! bit, and checks the bit again whenever that word changes, so changes to
+//! the other bits of the word wake it for nothing. Setting or clearing a
+//! bit only makes the wake call when some thread is waiting on the set.
+
+use core::fmt;
+
+use crate::platform::wait::{wait, wake_all};
+use crate::sync_shim::plain_atomic::AtomicU32;
+use crate::sync_shim::plain_atomic::Ordering::{Acquire, Relaxed, SeqCst};
+
+/// `32 * WORDS` flags, all clear to start with.
+pub struct AtomicBitset<const WORDS: usize> {
+    words: [AtomicU32; WORDS],
+    /// How many threads are in `wait_for`.
+    waiters: AtomicU32,
+}
+
+impl<const WORDS: usize> AtomicBitset<WORDS> {
+    pub const LEN: usize = WORDS * 32;
+
+    pub const fn new() -> Self {
+        Self {
+            words: [const { AtomicU32::new(0) }; WORDS],
+            waiters: AtomicU32::new(0),
+        }
+    }
+
+    pub const fn len(&self) -> usize {
+        Self::LEN
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        WORDS == 0
+    }
+
+    /// The word and the mask of bit `i`. Panics if it's out of range.
+    fn locate(&self, i: usize) -> (&AtomicU32, u32) {
+        assert!(i < Self::LEN, "bit {i} out of range for {} bits", Self::LEN);
+        (&self.words[i / 32], 1 << (i % 32))
+    }
+
+    /// Everything done before the bit was last set or cleared is visible
+    /// after this.
+    pub fn get(&self, i: usize) -> bool {
+        let (word, mask) = self.locate(i);
+        word.load(Acquire) & mask != 0
+    }
+
+    /// Sets bit `i`, waking the threads waiting for it, and returns whether
+    /// it was set already.
+    pub fn set(&self, i: usize) -> bool {
+        let (word, mask) = self.locate(i);
+        let old = word.fetch_or(mask, SeqCst);
+        if old & mask == 0 {
+            self.wake(word);
+        }
+        old & mask != 0
+    }
+
+    /// Clears bit `i`, waking the threads waiting for it, and returns
+    /// whether it was set.
+    pub fn clear(&self, i: usize) -> bool {
+        let (word, mask) = self.locate(i);
+        let old = word.fetch_and(!mask, SeqCst);
+        if old & mask != 0 {
+            self.wake(word);
+        }
+        old & mask != 0
+    }
+
+    /// The change to the word and the load of `waiters` are both SeqCst,
+    /// like the increment of `waiters` and the load of the word in
+    /// `wait_for`, so either we see the waiter, or it sees the change.
+    fn wake(&self, word: &AtomicU32) {
+        if self.waiters.load(SeqCst) != 0 {
+            wake_all(word);
+        }
+    }
+
+    /// Blocks until bit `i` is set.
+    pub fn wait_for_set(&self, i: usize) {
+        self.wait_for(i, true);
+    }
+
+    /// Blocks until bit `i` is clear.
+    pub fn wait_for_clear(&self, i: usize) {
+        self.wait_for(i, false);
+    }
+
+    fn wait_for(&self, i: usize, set: bool) {
+        let (word, mask) = self.locate(i);
+        let done = |w: u32| (w & mask != 0) == set;
+        if done(word.load(Acquire)) {
+            return;
+        }
+        self.waiters.fetch_add(1, SeqCst);
+        loop {
+            let w = word.load(SeqCst);
+            if done(w) {
+                break;
+            }
+            wait(word, w);
+        }
+        self.waiters.fetch_sub(1, Relaxed);
+        // Synchronizes with the change we waited for.
+        word.load(Acquire);
+    }
+
+    /// The indices of the bits that are set, one word at a time, so bits
+    /// of different words might be from different moments.
+    pub fn iter_set(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(n, word)| {
+            let w = word.load(Acquire);
+            (0..32)
+                .filter(move |b| w & (1 << b) != 0)
+                .map(move |b| n * 32 + b)
+        })
+    }
+}
+
+impl<const WORDS: usize> Default for AtomicBitset<WORDS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const WORDS: usize> fmt::Debug for AtomicBitset<WORDS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter_set()).finish()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::locks::bitset::AtomicBitset;
+
+    /// The fences example, with the data handed over through the bitset.
+    #[test]
+    fn test_readiness_table() {
+        static READY: AtomicBitset<1> = AtomicBitset::new();
+        static mut DATA: [u64; 10] = [0; 10];
+        thread::scope(|s| {
+            (0..10).for_each(|i| {
+                s.spawn(move || {
+                    // Safety: Only this thread writes this entry, and only
+                    // before setting its bit.
+                    unsafe { DATA[i] = i as u64 * 10 };
+                    assert!(!READY.set(i));
+                });
+            });
+            for i in (0..10).rev() {
+                READY.wait_for_set(i);
+                // Safety: Its bit is set, so it's written.
+                assert_eq!(unsafe { DATA[i] }, i as u64 * 10);
+            }
+        });
+        assert_eq!(
+            READY.iter_set().collect::<Vec<_>>(),
+            (0..10).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_waiters_on_the_same_word() {
+        let bits = AtomicBitset::<2>::new();
+        assert_eq!(bits.len(), 64);
+        bits.set(33);
+        thread::scope(|s| {
+            // Woken by every change to the first word, but only done with
+            // its own bit.
+            let a = s.spawn(|| bits.wait_for_set(5));
+            let b = s.spawn(|| bits.wait_for_clear(33));
+            thread::sleep(Duration::from_millis(10));
+            bits.set(6);
+            bits.set(32);
+            thread::sleep(Duration::from_millis(10));
+            assert!(!a.is_finished() && !b.is_finished());
+            assert!(bits.clear(33));
+            assert!(!bits.clear(33));
+            bits.set(5);
+        });
+        assert!(bits.get(5) && bits.get(6) && !bits.get(33));
+        assert_eq!(format!("{bits:?}"), "{5, 6, 32}");
+    }
+
+    #[test]
+    #[should_panic = "bit 64 out of range for 64 bits"]
+    fn test_out_of_range() {
+        AtomicBitset::<2>::new().set(64);
+    }
+}
@@ -0,0 +1,355 @@
+//! A reader-biased wrapper around another `RawRwLock`, after Dice and
+//! Kogan's "BRAVO: Biased Locking for Reader-Writer Locks" (2019).
+//!
+//! Every reader of a plain reader-writer lock writes to its state, so on a
+//! read-mostly lock, the readers fight over that one cache line even
+//! though they never wait for each other. With BRAVO, while the lock is
+//! read-biased, a reader instead claims a slot in a global table of
+//! visible readers, picked by hashing its thread and the lock's address,
+//! by writing the lock's address into it. Different readers mostly land
+//! on different slots, and every slot has a cache line of its own, so
+//! they don't share anything but the bias flag, which they only read.
+//!
+//! A writer takes the underlying lock, which stops new readers that go
+//! through it, then revokes the bias and waits until no slot holds the
+//! lock's address anymore. That scan of the whole table is what makes
+//! writing expensive, so after a revocation, the bias stays off for
+//! [`INHIBIT_FACTOR`] times as long as the revocation took, and only a
+//! reader that goes through the underlying lock after that turns it back
+//! on. A reader whose slot is taken, by another lock or thread, also goes
+//! through the underlying lock.
+//!
+//! The two sides are a Dekker-style handshake: a reader writes its slot
+//! and then reads the flag, a writer writes the flag and then reads the
+//! slots, all SeqCst, so at least one of them sees the other.
+
+use core::cell::Cell;
+use std::thread;
+use std::time::Instant;
+
+use crate::cache_padded::CachePadded;
+use crate::locks::once::OnceLock;
+use crate::locks::raw::{RawFutexRwLock, RawRwLock};
+use crate::platform::backoff::Backoff;
+use crate::sync_shim::plain_atomic::Ordering::{Acquire, Relaxed, Release, SeqCst};
+use crate::sync_shim::plain_atomic::{AtomicBool, AtomicU64, AtomicUsize};
+
+/// How many visible reader slots there are, shared by all the locks.
+pub const VISIBLE_READERS: usize = 256;
+
+/// How many times as long as a revocation took the bias stays off after it.
+pub const INHIBIT_FACTOR: u32 = 9;
+
+/// How many locks a thread can read through the table at once. Any more go
+/// through the underlying lock.
+const HELD: usize = 4;
+
+/// Each slot holds the address of the lock that a reader holds through it,
+/// or 0.
+static SLOTS: [CachePadded<AtomicUsize>; VISIBLE_READERS] =
+    [const { CachePadded::new(AtomicUsize::new(0)) }; VISIBLE_READERS];
+
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+
+thread_local! {
+    /// The addresses of the locks this thread holds through the table, or
+    /// 0. Its own address tells the threads apart, for the hash.
+    static FAST_READS: Cell<[usize; HELD]> = const { Cell::new([0; HELD]) };
+}
+
+fn now() -> u64 {
+    EPOCH.get_or_init(Instant::now).elapsed().as_nanos() as u64
+}
+
+/// A `RawRwLock` whose readers don't touch it while it's read-biased.
+///
+/// Writing is much slower than with `R` alone, so this is only worth it
+/// for locks that are rarely written, like configuration. Read locks can't
+/// be upgraded.
+#[derive(Debug)]
+pub struct RawBravoRwLock<R = RawFutexRwLock> {
+    inner: R,
+    /// Whether readers may use the table.
+    read_bias: AtomicBool,
+    /// Until when, in nanoseconds since `EPOCH`, readers shouldn't turn
+    /// the bias back on.
+    inhibit_until: AtomicU64,
+}
+
+impl<R: RawRwLock> RawBravoRwLock<R> {
+    /// Whether readers use the table right now.
+    pub fn is_read_biased(&self) -> bool {
+        self.read_bias.load(Acquire)
+    }
+
+    fn addr(&self) -> usize {
+        core::ptr::from_ref(self).addr()
+    }
+
+    fn slot(&self) -> &'static AtomicUsize {
+        let thread = FAST_READS.with(|f| core::ptr::from_ref(f).addr());
+        // Fibonacci hashing: the top bits of the product are mixed best.
+        let hash =
+            (thread ^ self.addr().rotate_left(17)).wrapping_mul(0x9e37_79b9_7f4a_7c15_u64 as usize);
+        let bits = VISIBLE_READERS.trailing_zeros();
+        &SLOTS[hash >> (usize::BITS - bits)]
+    }
+
+    /// Tries to read-lock through the table.
+    fn try_read_fast(&self) -> bool {
+        if !self.read_bias.load(SeqCst) {
+            return false;
+        }
+        let mut held = FAST_READS.get();
+        let Some(free) = held.iter().position(|&a| a == 0) else {
+            return false;
+        };
+        let slot = self.slot();
+        if slot
+            .compare_exchange(0, self.addr(), SeqCst, Relaxed)
+            .is_err()
+        {
+            return false;
+        }
+        // A writer that revoked the bias before it could see our slot has
+        // to be seen here.
+        if !self.read_bias.load(SeqCst) {
+            slot.store(0, Relaxed);
+            return false;
+        }
+        held[free] = self.addr();
+        FAST_READS.set(held);
+        true
+    }
+
+    /// After read-locking the underlying lock: turns the bias back on, if
+    /// it's been off for long enough.
+    fn rebias(&self) {
+        if !self.read_bias.load(Relaxed) && now() >= self.inhibit_until.load(Relaxed) {
+            self.read_bias.store(true, SeqCst);
+        }
+    }
+
+    /// With the underlying lock write-locked: turns the bias off, and waits
+    /// for the readers in the table to leave, unless `wait` is false and
+    /// there are some. Returns whether there are none.
+    fn revoke(&self, wait: bool) -> bool {
+        if !self.read_bias.load(Relaxed) {
+            return true;
+        }
+        self.read_bias.store(false, SeqCst);
+        let start = now();
+        let mut backoff = Backoff::new();
+        for slot in &SLOTS {
+            while slot.load(SeqCst) == self.addr() {
+                if !wait {
+                    return false;
+                }
+                if backoff.is_completed() {
+                    thread::yield_now();
+                } else {
+                    backoff.spin();
+                }
+            }
+        }
+        let end = now();
+        let inhibit = (end - start).saturating_mul(INHIBIT_FACTOR.into());
+        self.inhibit_until
+            .store(end.saturating_add(inhibit), Relaxed);
+        true
+    }
+}
+
+unsafe impl<R: RawRwLock> RawRwLock for RawBravoRwLock<R> {
+    const INIT: Self = Self {
+        inner: R::INIT,
+        read_bias: AtomicBool::new(true),
+        inhibit_until: AtomicU64::new(0),
+    };
+
+    fn read(&self) {
+        if !self.try_read_fast() {
+            self.inner.read();
+            self.rebias();
+        }
+    }
+
+    fn try_read(&self) -> bool {
+        if self.try_read_fast() {
+            return true;
+        }
+        let locked = self.inner.try_read();
+        if locked {
+            self.rebias();
+        }
+        locked
+    }
+
+    unsafe fn read_unlock(&self) {
+        let mut held = FAST_READS.get();
+        if let Some(i) = held.iter().position(|&a| a == self.addr()) {
+            held[i] = 0;
+            FAST_READS.set(held);
+            self.slot().store(0, Release);
+        } else {
+            self.inner.read_unlock();
+        }
+    }
+
+    fn write(&self) {
+        self.inner.write();
+        self.revoke(true);
+    }
+
+    /// Fails if there are readers in the table.
+    fn try_write(&self) -> bool {
+        if !self.inner.try_write() {
+            return false;
+        }
+        if !self.revoke(false) {
+            // Back to how it was, so the next writer waits for them. Nobody
+            // else could have changed it while we're write-locked.
+            self.read_bias.store(true, SeqCst);
+            // Safety: We just write-locked it.
+            unsafe { self.inner.write_unlock() };
+            return false;
+        }
+        true
+    }
+
+    unsafe fn write_unlock(&self) {
+        self.inner.write_unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::locks::bravo::{RawBravoRwLock, HELD};
+    use crate::locks::{RawFutexRwLock, RawRwLock, RawSpinRwLock, RwLock};
+    use crate::platform::affinity::available_cores;
+
+    #[test]
+    fn test_readers_see_consistent_pairs() {
+        const N: u32 = if cfg!(miri) { 50 } else { 10_000 };
+        let rwlock = RwLock::<_, RawBravoRwLock>::new((0, 0));
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..N {
+                    let mut w = rwlock.write();
+                    w.0 += 1;
+                    w.1 += 1;
+                    drop(w);
+                    if i % 16 == 0 {
+                        thread::yield_now();
+                    }
+                }
+            });
+            for _ in 0..3 {
+                s.spawn(|| {
+                    for _ in 0..N {
+                        let r = rwlock.read();
+                        assert_eq!(r.0, r.1);
+                    }
+                });
+            }
+        });
+        assert_eq!(rwlock.into_inner(), (N, N));
+    }
+
+    #[test]
+    fn test_bias() {
+        let raw = RawBravoRwLock::<RawSpinRwLock>::INIT;
+        assert!(raw.is_read_biased());
+        // More read locks than a thread can hold through the table.
+        for _ in 0..HELD + 2 {
+            raw.read();
+        }
+        assert!(!raw.try_write());
+        assert!(raw.is_read_biased());
+        for _ in 0..HELD + 2 {
+            unsafe { raw.read_unlock() };
+        }
+
+        // Writing revokes the bias, and while it's inhibited, readers use
+        // the underlying lock.
+        raw.write();
+        unsafe { raw.write_unlock() };
+        assert!(!raw.is_read_biased());
+        assert!(raw.try_read());
+        assert!(!raw.inner.try_write());
+        unsafe { raw.read_unlock() };
+
+        // Long after, a reader turns it back on.
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !raw.is_read_biased() {
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(1));
+            raw.read();
+            unsafe { raw.read_unlock() };
+        }
+        let rwlock = RwLock::<_, RawBravoRwLock>::new(0);
+        thread::scope(|s| {
+            let r = rwlock.read();
+            // Has to wait for the reader in the table.
+            s.spawn(|| *rwlock.write() += 1);
+            thread::sleep(Duration::from_millis(10));
+            assert_eq!(*r, 0);
+            drop(r);
+        });
+        assert_eq!(*rwlock.read(), 1);
+    }
+
+    const READS: u32 = 1_000_000;
+
+    /// Returns the time per read section, with one in `write_every`
+    /// sections being a write instead, by `threads` threads at once.
+    fn read_mostly<R: RawRwLock + Sync>(threads: u32, write_every: u32) -> Duration {
+        let rwlock = RwLock::<[u64; 4], R>::new([0; 4]);
+        let start = Instant::now();
+        thread::scope(|s| {
+            for _ in 0..threads {
+                s.spawn(|| {
+                    for i in 0..READS / threads {
+                        if i % write_every == 0 {
+                            rwlock.write()[0] += 1;
+                        } else {
+                            std::hint::black_box(rwlock.read().iter().sum::<u64>());
+                        }
+                    }
+                });
+            }
+        });
+        start.elapsed() / READS
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_bravo`,
+    /// on a machine with many cores to see the difference.
+    #[test]
+    #[ignore]
+    fn bench_bravo() {
+        let threads = available_cores() as u32;
+        println!("{threads} threads:");
+        for write_every in [u32::MAX, 10_000, 100] {
+            if write_every == u32::MAX {
+                println!("only reads:");
+            } else {
+                println!("one write in {write_every} sections:");
+            }
+            println!(
+                "  RawBravoRwLock: {:?}",
+                read_mostly::<RawBravoRwLock>(threads, write_every)
+            );
+            println!(
+                "  RawFutexRwLock: {:?}",
+                read_mostly::<RawFutexRwLock>(threads, write_every)
+            );
+            println!(
+                "   RawSpinRwLock: {:?}",
+                read_mostly::<RawSpinRwLock>(threads, write_every)
+            );
+        }
+    }
+}
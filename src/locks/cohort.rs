@@ -0,0 +1,300 @@
+//! A cohort lock: a lock per NUMA node, and a global one on top.
+//!
+//! A thread first takes the lock of the node it runs on, and then the
+//! global lock. But when it unlocks while another thread of the same node
+//! waits for that node's lock, it keeps the global lock locked and passes
+//! it along with the node's lock. So the lock, and the data it protects,
+//! stays within a node for a batch of critical sections, instead of its
+//! cache lines crossing the interconnect between sockets on nearly every
+//! handoff, which is what makes flat locks slow on multi-socket machines.
+//! After [`MAX_BATCH`] handoffs, the global lock is released anyway, so
+//! other nodes get a turn.
+//!
+//! This is the C-TKT-TKT-like lock from Dice, Marathe and Shavit's "Lock
+//! Cohorting" (2012), with fair ticket locks per node and a test-and-set
+//! global lock. That one may be unlocked by another thread than the one
+//! that locked it, which cohorting needs. On a machine with one node, it's
+//! a ticket lock with an extra, always uncontended, spin lock.
+
+use core::cell::Cell;
+use std::thread;
+
+use crate::cache_padded::CachePadded;
+use crate::locks::raw::{RawLock, RawSpinLock};
+use crate::platform::affinity::current_numa_node;
+use crate::platform::backoff::Backoff;
+use crate::sync_shim::plain_atomic::Ordering::{Acquire, Relaxed, Release};
+use crate::sync_shim::plain_atomic::{AtomicBool, AtomicU32, AtomicUsize};
+
+/// How many node locks there are. Threads on higher nodes share them.
+pub const MAX_NODES: usize = 8;
+
+/// How many times the global lock is passed within a node before it's
+/// released for the other nodes.
+pub const MAX_BATCH: u32 = 64;
+
+/// How many locks a thread takes before it checks its node again, since
+/// that's a system call and the scheduler rarely moves a thread.
+const NODE_REFRESH: u32 = 256;
+
+thread_local! {
+    /// The node this thread last ran on, and how many more locks to take
+    /// before checking again.
+    static NODE: Cell<(usize, u32)> = const { Cell::new((0, 0)) };
+}
+
+fn cached_node() -> usize {
+    NODE.with(|cell| {
+        let (node, left) = cell.get();
+        if left > 0 {
+            cell.set((node, left - 1));
+            return node;
+        }
+        let node = current_numa_node() % MAX_NODES;
+        cell.set((node, NODE_REFRESH));
+        node
+    })
+}
+
+/// A ticket lock, for the threads of one node.
+#[derive(Debug)]
+struct NodeLock {
+    next_ticket: AtomicU32,
+    now_serving: AtomicU32,
+    /// Whether the thread that locked this last passed the global lock
+    /// along. Only accessed while holding this lock.
+    has_global: AtomicBool,
+    /// How many times in a row the global lock was passed along.
+    batch: AtomicU32,
+}
+
+impl NodeLock {
+    const fn new() -> Self {
+        Self {
+            next_ticket: AtomicU32::new(0),
+            now_serving: AtomicU32::new(0),
+            has_global: AtomicBool::new(false),
+            batch: AtomicU32::new(0),
+        }
+    }
+
+    fn lock(&self) {
+        let ticket = self.next_ticket.fetch_add(1, Relaxed);
+        let mut backoff = Backoff::new();
+        while self.now_serving.load(Acquire) != ticket {
+            // Only the thread with the next ticket can take it, so if it
+            // isn't running, spinning any longer only keeps it from running.
+            if backoff.is_completed() {
+                thread::yield_now();
+            } else {
+                backoff.spin();
+            }
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        // Acquire on `now_serving`, like `lock`: that's what `unlock`
+        // releases. Only taking the ticket with Acquire would leave us
+        // reading a stale `has_global`, and skipping the global lock.
+        let ticket = self.now_serving.load(Acquire);
+        self.next_ticket
+            .compare_exchange(ticket, ticket.wrapping_add(1), Relaxed, Relaxed)
+            .is_ok()
+    }
+
+    /// Whether another thread has taken a ticket. Only call this while
+    /// holding the lock.
+    fn has_waiters(&self) -> bool {
+        let serving = self.now_serving.load(Relaxed);
+        self.next_ticket.load(Relaxed) != serving.wrapping_add(1)
+    }
+
+    fn unlock(&self) {
+        self.now_serving.fetch_add(1, Release);
+    }
+}
+
+/// A NUMA-aware cohort lock, as a `RawLock`.
+///
+/// It takes `MAX_NODES` cache lines, so it's meant for a few hot locks,
+/// not for every object.
+#[derive(Debug)]
+pub struct RawCohortLock {
+    global: RawSpinLock,
+    /// Each on a cache line of its own, so the nodes don't share one.
+    nodes: [CachePadded<NodeLock>; MAX_NODES],
+    /// The node whose lock the owner holds. Only accessed while holding
+    /// the global lock.
+    owner: AtomicUsize,
+}
+
+impl RawCohortLock {
+    fn lock_on(&self, node: usize) {
+        let local = &self.nodes[node];
+        local.lock();
+        if !local.has_global.load(Relaxed) {
+            self.global.lock();
+        }
+        self.owner.store(node, Relaxed);
+    }
+
+    fn try_lock_on(&self, node: usize) -> bool {
+        let local = &self.nodes[node];
+        if !local.try_lock() {
+            return false;
+        }
+        if !local.has_global.load(Relaxed) && !self.global.try_lock() {
+            local.unlock();
+            return false;
+        }
+        self.owner.store(node, Relaxed);
+        true
+    }
+}
+
+unsafe impl RawLock for RawCohortLock {
+    const INIT: Self = Self {
+        global: RawSpinLock::INIT,
+        nodes: [const { CachePadded::new(NodeLock::new()) }; MAX_NODES],
+        owner: AtomicUsize::new(0),
+    };
+
+    fn lock(&self) {
+        self.lock_on(cached_node());
+    }
+
+    fn try_lock(&self) -> bool {
+        self.try_lock_on(cached_node())
+    }
+
+    unsafe fn unlock(&self) {
+        let local = &self.nodes[self.owner.load(Relaxed)];
+        let batch = local.batch.load(Relaxed);
+        if batch < MAX_BATCH && local.has_waiters() {
+            // Keep the global lock, for the next thread on this node. The
+            // node lock's release passes it along.
+            local.batch.store(batch + 1, Relaxed);
+            local.has_global.store(true, Relaxed);
+        } else {
+            local.batch.store(0, Relaxed);
+            local.has_global.store(false, Relaxed);
+            self.global.unlock();
+        }
+        local.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::UnsafeCell;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::locks::cohort::{RawCohortLock, MAX_BATCH};
+    use crate::locks::{Lock, RawFutexMutex, RawLock, RawMutex, RawSpinLock};
+    use crate::platform::affinity::{available_cores, numa_nodes};
+
+    #[test]
+    #[cfg_attr(miri, ignore = "Miri can't call getcpu")]
+    fn test_cohort_lock() {
+        let lock = Lock::<_, RawCohortLock>::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..10_000 {
+                        *lock.lock() += 1;
+                    }
+                });
+            }
+        });
+        assert_eq!(lock.into_inner(), 40_000);
+
+        let lock = Lock::<_, RawCohortLock>::new(());
+        let guard = lock.lock();
+        assert!(lock.try_lock().is_none());
+        drop(guard);
+        assert!(lock.try_lock().is_some());
+    }
+
+    struct Exclusive {
+        inside: AtomicBool,
+        count: UnsafeCell<u32>,
+    }
+
+    unsafe impl Sync for Exclusive {}
+
+    /// Threads pretending to be on different nodes, so both the handoffs
+    /// within a node and those through the global lock happen.
+    #[test]
+    fn test_nodes_exclude_each_other() {
+        const N: u32 = if cfg!(miri) { 50 } else { 10_000 };
+        let lock = RawCohortLock::INIT;
+        let shared = Exclusive {
+            inside: AtomicBool::new(false),
+            count: UnsafeCell::new(0),
+        };
+        thread::scope(|s| {
+            for t in 0..4 {
+                let (lock, shared) = (&lock, &shared);
+                s.spawn(move || {
+                    for i in 0..N {
+                        let node = t % 2;
+                        if i % 8 == 0 {
+                            while !lock.try_lock_on(node) {
+                                std::hint::spin_loop();
+                            }
+                        } else {
+                            lock.lock_on(node);
+                        }
+                        assert!(!shared.inside.swap(true, Relaxed));
+                        // Safety: We hold the lock.
+                        unsafe { *shared.count.get() += 1 };
+                        shared.inside.store(false, Relaxed);
+                        // Safety: We hold the lock.
+                        unsafe { lock.unlock() };
+                    }
+                });
+            }
+        });
+        assert_eq!(shared.count.into_inner(), 4 * N);
+        for node in &lock.nodes {
+            assert!(!node.has_global.load(Relaxed));
+            assert!(node.batch.load(Relaxed) <= MAX_BATCH);
+        }
+        assert!(lock.global.try_lock());
+    }
+
+    const INCREMENTS: u32 = 1_000_000;
+
+    /// Returns the time per increment of a counter, by `threads` threads at
+    /// the same time.
+    fn increment<R: RawLock + Sync>(threads: u32) -> Duration {
+        let counter = Lock::<u32, R>::new(0);
+        let start = Instant::now();
+        thread::scope(|s| {
+            for _ in 0..threads {
+                s.spawn(|| {
+                    for _ in 0..INCREMENTS / threads {
+                        *counter.lock() += 1;
+                    }
+                });
+            }
+        });
+        start.elapsed() / INCREMENTS
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_cohort_lock`,
+    /// on a machine with more than one socket to see the difference.
+    #[test]
+    #[ignore]
+    fn bench_cohort_lock() {
+        let threads = available_cores() as u32;
+        println!("{threads} threads on {} NUMA node(s):", numa_nodes());
+        println!("  RawCohortLock: {:?}", increment::<RawCohortLock>(threads));
+        println!("       RawMutex: {:?}", increment::<RawMutex>(threads));
+        println!("  RawFutexMutex: {:?}", increment::<RawFutexMutex>(threads));
+        println!("    RawSpinLock: {:?}", increment::<RawSpinLock>(threads));
+    }
+}
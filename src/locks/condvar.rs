@@ -0,0 +1,353 @@
+//! A condition variable for [`Mutex`](crate::locks::Mutex), with its waiters
+//! in `platform::parking`, like the mutex's own.
+//!
+//! Like chapter 9's, it has a counter that every notification bumps, so a
+//! thread that was notified between unlocking the mutex and going to sleep
+//! notices it. The counter is checked with the waiter's bucket locked, and
+//! a notification only looks for waiters after bumping it, so it either
+//! finds the waiter parked or the waiter sees the new count.
+//!
+//! `notify_all` wakes only one thread. The others would only wake up to
+//! find the mutex taken and go back to sleep on it, so they're moved over
+//! to the mutex's queue right away, and each unlock wakes the next. That
+//! only works while the mutex is locked, which it usually is when notifying,
+//! otherwise they're all woken.
+
+use core::ptr;
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+use crate::locks::raw::{RawLock, RawMutex};
+use crate::locks::MutexGuard;
+#[cfg(feature = "std")]
+use crate::platform::parking::park_until;
+use crate::platform::parking::{park, requeue, unpark_all, unpark_one};
+#[cfg(feature = "stats")]
+use crate::stats::{CondvarRecorder, CondvarStats};
+use crate::sync_shim::plain_atomic::Ordering::Relaxed;
+use crate::sync_shim::plain_atomic::{AtomicPtr, AtomicU32};
+
+/// Like std's, all threads waiting at the same time must use the same
+/// mutex. Unlike std's, that's the same mutex forever: waiting with
+/// another one panics.
+#[derive(Debug)]
+pub struct Condvar {
+    counter: AtomicU32,
+    /// The mutex it's used with, to move waiters over to. Set by the first
+    /// `wait`.
+    mutex: AtomicPtr<RawMutex>,
+    #[cfg(feature = "stats")]
+    stats: CondvarRecorder,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            counter: AtomicU32::new(0),
+            mutex: AtomicPtr::new(ptr::null_mut()),
+            #[cfg(feature = "stats")]
+            stats: CondvarRecorder::new(),
+        }
+    }
+
+    fn addr(&self) -> usize {
+        ptr::from_ref(self).addr()
+    }
+
+    /// Unlocks the mutex while waiting for a notification, and locks it
+    /// again before returning. Might also return without one, when the
+    /// thread is woken by `notify_all` but another thread got to the data
+    /// first.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.raw();
+        self.start_wait(mutex);
+        let counter = self.counter.load(Relaxed);
+        // Safety: The guard means we hold the mutex, and it's locked again
+        // before the guard is given back.
+        unsafe { mutex.unlock() };
+        park(self.addr(), || self.counter.load(Relaxed) == counter);
+        mutex.lock();
+        guard
+    }
+
+    /// Like `wait`, but gives up waiting for a notification at `deadline`.
+    /// Also returns whether it timed out.
+    ///
+    /// A waiter that `notify_all` moved to the mutex's queue takes itself
+    /// off that queue when it gives up.
+    #[cfg(feature = "std")]
+    pub fn wait_until<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        deadline: Instant,
+    ) -> (MutexGuard<'a, T>, bool) {
+        let mutex = guard.raw();
+        self.start_wait(mutex);
+        let counter = self.counter.load(Relaxed);
+        // Safety: As in `wait`.
+        unsafe { mutex.unlock() };
+        let unparked = park_until(
+            self.addr(),
+            || self.counter.load(Relaxed) == counter,
+            deadline,
+            None,
+        );
+        mutex.lock();
+        (guard, !unparked && Instant::now() >= deadline)
+    }
+
+    /// Like `wait_until`, with a deadline `timeout` from now. Waits without
+    /// one if it doesn't fit in an `Instant`.
+    #[cfg(feature = "std")]
+    pub fn wait_timeout<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Duration,
+    ) -> (MutexGuard<'a, T>, bool) {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.wait_until(guard, deadline),
+            None => (self.wait(guard), false),
+        }
+    }
+
+    /// Checks that it's always the same mutex.
+    fn start_wait(&self, mutex: &RawMutex) {
+        let p = ptr::from_ref(mutex).cast_mut();
+        if let Err(other) = self
+            .mutex
+            .compare_exchange(ptr::null_mut(), p, Relaxed, Relaxed)
+        {
+            assert!(other == p, "a Condvar can only be used with one Mutex");
+        }
+        #[cfg(feature = "stats")]
+        self.stats.waits.fetch_add(1, Relaxed);
+    }
+
+    /// Waits for as long as `condition` returns true.
+    pub fn wait_while<'a, T>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        if !condition(&mut guard) {
+            return guard;
+        }
+        loop {
+            guard = self.wait(guard);
+            if !condition(&mut guard) {
+                return guard;
+            }
+            #[cfg(feature = "stats")]
+            self.stats.spurious_wakeups.fetch_add(1, Relaxed);
+        }
+    }
+
+    /// Waits for as long as `condition` returns true, but not past
+    /// `deadline`. Also returns whether it timed out with `condition` still
+    /// true.
+    #[cfg(feature = "std")]
+    pub fn wait_while_until<'a, T>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        deadline: Instant,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> (MutexGuard<'a, T>, bool) {
+        if !condition(&mut guard) {
+            return (guard, false);
+        }
+        loop {
+            let timed_out;
+            (guard, timed_out) = self.wait_until(guard, deadline);
+            if !condition(&mut guard) {
+                return (guard, false);
+            }
+            if timed_out {
+                return (guard, true);
+            }
+            #[cfg(feature = "stats")]
+            self.stats.spurious_wakeups.fetch_add(1, Relaxed);
+        }
+    }
+
+    /// Like `wait_while_until`, with a deadline `timeout` from now. Waits
+    /// without one if it doesn't fit in an `Instant`.
+    #[cfg(feature = "std")]
+    pub fn wait_timeout_while<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Duration,
+        condition: impl FnMut(&mut T) -> bool,
+    ) -> (MutexGuard<'a, T>, bool) {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.wait_while_until(guard, deadline, condition),
+            None => (self.wait_while(guard, condition), false),
+        }
+    }
+
+    pub fn notify_one(&self) {
+        #[cfg(feature = "stats")]
+        self.stats.notifications.fetch_add(1, Relaxed);
+        self.counter.fetch_add(1, Relaxed);
+        unpark_one(self.addr(), |_| {});
+    }
+
+    pub fn notify_all(&self) {
+        #[cfg(feature = "stats")]
+        self.stats.notifications.fetch_add(1, Relaxed);
+        self.counter.fetch_add(1, Relaxed);
+        if !unpark_one(self.addr(), |_| {}) {
+            return;
+        }
+        let mutex = self.mutex.load(Relaxed);
+        let requeued = requeue(self.addr(), mutex.addr(), |waiting| {
+            // Safety: The threads still waiting have a reference to it.
+            waiting && unsafe { &*mutex }.mark_parked()
+        });
+        #[cfg(feature = "stats")]
+        self.stats.requeued.fetch_add(requeued as u64, Relaxed);
+        if requeued == 0 {
+            unpark_all(self.addr());
+        }
+    }
+
+    /// A snapshot of the statistics since it was created or last reset.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> CondvarStats {
+        self.stats.snapshot()
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&self) {
+        self.stats.reset();
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::ptr;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::locks::condvar::Condvar;
+    use crate::locks::Mutex;
+    use crate::platform::parking::parked;
+
+    #[test]
+    fn test_wait_for_items() {
+        const N: u32 = if cfg!(miri) { 20 } else { 1000 };
+        let queue = Mutex::new(Vec::new());
+        let not_empty = Condvar::new();
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..N {
+                    queue.lock().push(i);
+                    not_empty.notify_one();
+                }
+            });
+            let mut received = 0;
+            while received < N {
+                let mut q = not_empty.wait_while(queue.lock(), |q| q.is_empty());
+                assert_eq!(q.remove(0), received);
+                received += 1;
+            }
+        });
+    }
+
+    #[test]
+    fn test_notify_all_wakes_every_waiter() {
+        let state = Mutex::new((false, 0));
+        let condvar = Condvar::new();
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let mut g = condvar.wait_while(state.lock(), |(go, _)| !*go);
+                    g.1 += 1;
+                });
+            }
+            thread::sleep(Duration::from_millis(10));
+            // While holding the mutex, so the others are moved over to it.
+            let mut g = state.lock();
+            g.0 = true;
+            condvar.notify_all();
+        });
+        assert_eq!(state.into_inner(), (true, 4));
+    }
+
+    #[test]
+    #[should_panic = "only be used with one Mutex"]
+    fn test_two_mutexes_panic() {
+        let (a, b) = (Mutex::new(()), Mutex::new(()));
+        let condvar = Condvar::new();
+        let waited = AtomicBool::new(false);
+        thread::scope(|s| {
+            s.spawn(|| {
+                while !waited.load(Relaxed) {
+                    condvar.notify_one();
+                    thread::yield_now();
+                }
+            });
+            drop(condvar.wait(a.lock()));
+            waited.store(true, Relaxed);
+        });
+        drop(condvar.wait(b.lock()));
+    }
+
+    #[test]
+    fn test_timed_waits() {
+        let state = Mutex::new(false);
+        let condvar = Condvar::new();
+        let (_, timed_out) = condvar.wait_timeout(state.lock(), Duration::from_millis(5));
+        assert!(timed_out);
+        let (g, timed_out) =
+            condvar.wait_timeout_while(state.lock(), Duration::from_millis(5), |go| !*go);
+        assert!(timed_out && !*g);
+        drop(g);
+        thread::scope(|s| {
+            for timeout in [Duration::from_secs(10), Duration::MAX] {
+                s.spawn(|| {
+                    thread::sleep(Duration::from_millis(10));
+                    *state.lock() = true;
+                    condvar.notify_all();
+                });
+                let (g, timed_out) = condvar.wait_timeout_while(state.lock(), timeout, |go| !*go);
+                assert!(!timed_out && *g);
+                drop(g);
+                *state.lock() = false;
+            }
+        });
+    }
+
+    #[test]
+    fn test_timed_waiters_moved_to_the_mutex() {
+        let state = Mutex::new(0);
+        let condvar = Condvar::new();
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let deadline = Instant::now() + Duration::from_millis(20);
+                    let (mut g, _) = condvar.wait_until(state.lock(), deadline);
+                    *g += 1;
+                });
+            }
+            thread::sleep(Duration::from_millis(10));
+            // Holds the mutex past the deadline, so the waiters moved to
+            // its queue give up there.
+            let g = state.lock();
+            condvar.notify_all();
+            thread::sleep(Duration::from_millis(20));
+            drop(g);
+        });
+        // None of them is left in either queue.
+        parked(ptr::from_ref(&condvar).addr(), |n| assert_eq!(n, 0));
+        parked(state.lock().raw().addr(), |n| assert_eq!(n, 0));
+        assert_eq!(state.into_inner(), 4);
+    }
+}
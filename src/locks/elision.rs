@@ -0,0 +1,320 @@
+//! Lock elision for `RwLock` readers, with Intel's transactional memory
+//! (RTM, part of TSX), to experiment with.
+//!
+//! [`RawElidedRwLock`] starts a hardware transaction in `read` instead of
+//! taking the lock, and only checks that no writer holds it. That puts the
+//! lock's state in the transaction's read set, so a writer that takes the
+//! lock later aborts the transaction, and the reader starts over from
+//! `read`, where it takes the lock for real this time. So does any other
+//! abort: a conflict with another thread's write to the data, the data not
+//! fitting in the cache, or a system call in the read section. When the
+//! transaction commits in `read_unlock`, the readers never wrote to the
+//! lock, so they didn't fight over its cache line either.
+//!
+//! Only worth it for short read sections without system calls, and only on
+//! CPUs that have RTM enabled: many have it disabled by microcode. Without
+//! it, or under Miri, this is a plain `RawSpinRwLock`. [`stats`] counts
+//! how the attempts went, for all these locks together.
+
+use core::arch::asm;
+use core::cell::Cell;
+
+use crate::locks::raw::{RawRwLock, RawSpinRwLock};
+use crate::sync_shim::plain_atomic::AtomicU64;
+use crate::sync_shim::plain_atomic::Ordering::Relaxed;
+
+/// What `xbegin` leaves in eax when the transaction started.
+const STARTED: u32 = u32::MAX;
+/// The abort status bits.
+const ABORT_EXPLICIT: u32 = 1 << 0;
+const ABORT_CONFLICT: u32 = 1 << 2;
+const ABORT_CAPACITY: u32 = 1 << 3;
+
+/// How many elided locks a thread can hold at once. Transactions nest
+/// (as one big one), but this needs a slot for each.
+const SLOTS: usize = 4;
+
+thread_local! {
+    /// The addresses of the locks this thread reads inside its current
+    /// transaction without having locked them, or 0. Written inside the
+    /// transaction, so an abort resets them along with everything else.
+    static ELIDED: Cell<[usize; SLOTS]> = const { Cell::new([0; SLOTS]) };
+}
+
+/// A snapshot of the elision counters.
+///
+/// Transactions nest as one big one, so these count only the outermost
+/// ones: an abort always goes back to where that one started.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ElisionStats {
+    /// Transactions started.
+    pub attempts: u64,
+    /// Transactions committed, without taking the lock.
+    pub elided: u64,
+    /// Aborted because a writer held the lock.
+    pub writer_held: u64,
+    /// Aborted because another thread touched the same memory.
+    pub conflict: u64,
+    /// Aborted because the read section touched too much memory.
+    pub capacity: u64,
+    /// Aborted for another reason, like a system call or an interrupt.
+    pub other: u64,
+}
+
+impl ElisionStats {
+    /// The fraction of attempts that succeeded.
+    pub fn success_rate(&self) -> f64 {
+        if self.attempts == 0 {
+            return 0.0;
+        }
+        self.elided as f64 / self.attempts as f64
+    }
+}
+
+/// Only touched outside of transactions, so updating them doesn't abort
+/// anyone else's. That's why only the outermost ones are counted.
+static ATTEMPTS: AtomicU64 = AtomicU64::new(0);
+static ELIDED_COUNT: AtomicU64 = AtomicU64::new(0);
+static WRITER_HELD: AtomicU64 = AtomicU64::new(0);
+static CONFLICT: AtomicU64 = AtomicU64::new(0);
+static CAPACITY: AtomicU64 = AtomicU64::new(0);
+static OTHER: AtomicU64 = AtomicU64::new(0);
+
+pub fn stats() -> ElisionStats {
+    ElisionStats {
+        attempts: ATTEMPTS.load(Relaxed),
+        elided: ELIDED_COUNT.load(Relaxed),
+        writer_held: WRITER_HELD.load(Relaxed),
+        conflict: CONFLICT.load(Relaxed),
+        capacity: CAPACITY.load(Relaxed),
+        other: OTHER.load(Relaxed),
+    }
+}
+
+pub fn reset_stats() {
+    for c in [
+        &ATTEMPTS,
+        &ELIDED_COUNT,
+        &WRITER_HELD,
+        &CONFLICT,
+        &CAPACITY,
+        &OTHER,
+    ] {
+        c.store(0, Relaxed);
+    }
+}
+
+/// Whether this CPU can run transactions.
+pub fn is_supported() -> bool {
+    !cfg!(miri) && std::arch::is_x86_feature_detected!("rtm")
+}
+
+/// Starts a transaction, and returns `STARTED`. If it aborts, execution
+/// continues from here again, with all memory and registers as they were,
+/// except that this returns the abort status instead.
+///
+/// # Safety
+///
+/// The CPU must support RTM.
+#[inline(always)]
+unsafe fn xbegin() -> u32 {
+    let status: u32;
+    asm!("mov eax, -1", "xbegin 2f", "2:", out("eax") status, options(nostack));
+    status
+}
+
+/// Commits the transaction.
+///
+/// # Safety
+///
+/// Only call this inside a transaction.
+#[inline(always)]
+unsafe fn xend() {
+    asm!("xend", options(nostack));
+}
+
+/// Aborts the transaction with `ABORT_EXPLICIT` (and code 0xff).
+///
+/// # Safety
+///
+/// Only call this inside a transaction.
+#[inline(always)]
+unsafe fn xabort() {
+    asm!("xabort 0xff", options(nostack));
+}
+
+/// A `RawSpinRwLock` whose readers elide the lock when they can.
+#[derive(Debug)]
+pub struct RawElidedRwLock {
+    inner: RawSpinRwLock,
+}
+
+impl RawElidedRwLock {
+    fn addr(&self) -> usize {
+        core::ptr::from_ref(self).addr()
+    }
+
+    /// Tries to run the read section as a transaction. Returns false if it
+    /// didn't start, or aborted and came back here.
+    fn try_elide(&self) -> bool {
+        let slots = ELIDED.get();
+        let Some(slot) = slots.iter().position(|&a| a == 0) else {
+            return false;
+        };
+        if self.inner.is_write_locked() {
+            return false;
+        }
+        let outermost = slots == [0; SLOTS];
+        if outermost {
+            ATTEMPTS.fetch_add(1, Relaxed);
+        }
+        // Safety: `read` checked that RTM is supported.
+        let status = unsafe { xbegin() };
+        if status == STARTED {
+            if self.inner.is_write_locked() {
+                // Safety: We're inside the transaction.
+                unsafe { xabort() };
+            }
+            let mut slots = slots;
+            slots[slot] = self.addr();
+            ELIDED.set(slots);
+            return true;
+        }
+        let counter = if status & ABORT_EXPLICIT != 0 {
+            &WRITER_HELD
+        } else if status & ABORT_CONFLICT != 0 {
+            &CONFLICT
+        } else if status & ABORT_CAPACITY != 0 {
+            &CAPACITY
+        } else {
+            &OTHER
+        };
+        counter.fetch_add(1, Relaxed);
+        false
+    }
+}
+
+unsafe impl RawRwLock for RawElidedRwLock {
+    const INIT: Self = Self {
+        inner: RawSpinRwLock::INIT,
+    };
+
+    fn read(&self) {
+        if !(is_supported() && self.try_elide()) {
+            self.inner.read();
+        }
+    }
+
+    fn try_read(&self) -> bool {
+        self.inner.try_read()
+    }
+
+    unsafe fn read_unlock(&self) {
+        let mut slots = ELIDED.get();
+        // Only set inside a transaction, so if it's there, we're in one.
+        if let Some(slot) = slots.iter().position(|&a| a == self.addr()) {
+            slots[slot] = 0;
+            ELIDED.set(slots);
+            xend();
+            if slots == [0; SLOTS] {
+                ELIDED_COUNT.fetch_add(1, Relaxed);
+            }
+        } else {
+            self.inner.read_unlock();
+        }
+    }
+
+    fn write(&self) {
+        self.inner.write();
+    }
+
+    fn try_write(&self) -> bool {
+        self.inner.try_write()
+    }
+
+    unsafe fn write_unlock(&self) {
+        self.inner.write_unlock();
+    }
+
+    /// An elided read lock can't be upgraded: the transaction would have
+    /// to write to the lock, which aborts it.
+    unsafe fn try_upgrade(&self) -> bool {
+        !ELIDED.get().contains(&self.addr()) && self.inner.try_upgrade()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Instant;
+
+    use crate::locks::elision::{is_supported, stats, RawElidedRwLock};
+    use crate::locks::RwLock;
+
+    #[test]
+    fn test_readers_see_consistent_pairs() {
+        const N: u64 = if cfg!(miri) { 100 } else { 10_000 };
+        let rwlock = RwLock::<_, RawElidedRwLock>::new((0, 0));
+        let before = stats();
+        thread::scope(|s| {
+            s.spawn(|| {
+                for _ in 0..N {
+                    let mut w = rwlock.write();
+                    w.0 += 1;
+                    w.1 += 1;
+                }
+            });
+            for _ in 0..2 {
+                s.spawn(|| {
+                    for _ in 0..N {
+                        let r = rwlock.read();
+                        assert_eq!(r.0, r.1);
+                    }
+                });
+            }
+        });
+        assert_eq!(rwlock.into_inner(), (N, N));
+        if !is_supported() {
+            assert_eq!(stats().attempts, before.attempts);
+        }
+    }
+
+    #[test]
+    fn test_nested_and_out_of_order() {
+        let (a, b) = (
+            RwLock::<_, RawElidedRwLock>::new(1),
+            RwLock::<_, RawElidedRwLock>::new(2),
+        );
+        let ra = a.read();
+        let rb = b.read();
+        let ra2 = a.read();
+        assert_eq!(*ra + *rb + *ra2, 4);
+        drop(ra);
+        drop(rb);
+        drop(ra2);
+        assert!(a.try_write().is_some());
+        assert!(b.try_write().is_some());
+    }
+
+    /// Run with `cargo test --release --features lock_elision -- --ignored --nocapture bench_elision`.
+    #[test]
+    #[ignore]
+    fn bench_elision() {
+        const READS: u32 = 1_000_000;
+        println!("RTM supported: {}", is_supported());
+        let rwlock = RwLock::<_, RawElidedRwLock>::new([0u64; 8]);
+        let start = Instant::now();
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..READS / 4 {
+                        std::hint::black_box(rwlock.read().iter().sum::<u64>());
+                    }
+                });
+            }
+        });
+        println!("{:?} per read", start.elapsed() / READS);
+        let stats = stats();
+        println!("{stats:?}, {:.1}% elided", stats.success_rate() * 100.0);
+    }
+}
@@ -0,0 +1,331 @@
+//! A spin lock for data shared with interrupt handlers, on bare metal.
+//!
+//! If an interrupt handler tried to take a plain [`SpinLock`] that the code
+//! it interrupted was holding, it would spin forever: the holder can't run
+//! again until the handler returns. [`IrqSpinLock`] masks interrupts on the
+//! current core for as long as it's locked, so that can't happen, and only
+//! spins for other cores.
+//!
+//! How interrupts get masked is up to an [`Interrupts`] implementation.
+//! With the `critical-section` feature, [`CriticalSection`] uses whatever
+//! implementation of the [`critical-section`] crate the program links in.
+//! Otherwise, implement it for the target.
+//!
+//! An interrupt handler that already runs with interrupts masked can skip
+//! masking them again, with [`IrqSpinLock::try_lock_from_isr`] and a
+//! [`Masked`] token. It doesn't spin, so a handler never waits on another
+//! core.
+//!
+//! [`SpinLock`]: crate::locks::SpinLock
+//! [`critical-section`]: https://docs.rs/critical-section
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+
+use crate::locks::raw::{RawLock, RawSpinLock};
+
+/// Masks and unmasks interrupts on the current core.
+///
+/// # Safety
+///
+/// From `disable` until the matching `restore`, no interrupt handler that
+/// could lock an [`IrqSpinLock`] using it may run on the current core.
+pub unsafe trait Interrupts {
+    /// Whatever `restore` needs to put the mask back as it was, so that
+    /// sections can nest.
+    type State;
+
+    fn disable() -> Self::State;
+
+    /// # Safety
+    ///
+    /// `state` must come from the latest `disable` on this core that hasn't
+    /// been restored yet.
+    unsafe fn restore(state: Self::State);
+}
+
+/// Masks interrupts with `critical_section::acquire` and `release`.
+#[cfg(feature = "critical-section")]
+#[derive(Debug)]
+pub struct CriticalSection;
+
+#[cfg(feature = "critical-section")]
+unsafe impl Interrupts for CriticalSection {
+    type State = critical_section::RestoreState;
+
+    fn disable() -> Self::State {
+        // Safety: `restore` is the matching release.
+        unsafe { critical_section::acquire() }
+    }
+
+    unsafe fn restore(state: Self::State) {
+        critical_section::release(state);
+    }
+}
+
+/// Proof that interrupts are masked on the current core for `'cs`.
+#[derive(Clone, Copy, Debug)]
+pub struct Masked<'cs>(PhantomData<&'cs ()>);
+
+impl Masked<'_> {
+    /// # Safety
+    ///
+    /// For as long as the token (or a guard locked with it) lives, no
+    /// interrupt handler that could lock the same [`IrqSpinLock`]s may run
+    /// on the current core, as after [`Interrupts::disable`]. Typically,
+    /// that's in a handler that the hardware entered with interrupts
+    /// masked.
+    pub unsafe fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+#[cfg(feature = "critical-section")]
+impl<'cs> From<critical_section::CriticalSection<'cs>> for Masked<'cs> {
+    fn from(_: critical_section::CriticalSection<'cs>) -> Self {
+        Self(PhantomData)
+    }
+}
+
+pub struct IrqSpinLock<T, I: Interrupts> {
+    raw: RawSpinLock,
+    value: UnsafeCell<T>,
+    interrupts: PhantomData<I>,
+}
+
+unsafe impl<T: Send, I: Interrupts> Sync for IrqSpinLock<T, I> {}
+
+/// Unlocks, then unmasks interrupts if locking masked them.
+pub struct IrqSpinLockGuard<'a, T, I: Interrupts> {
+    lock: &'a IrqSpinLock<T, I>,
+    restore: Option<I::State>,
+    /// Interrupts are masked on this core only, so it must be unlocked here.
+    not_send: PhantomData<*const ()>,
+}
+
+impl<T, I: Interrupts> IrqSpinLock<T, I> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            raw: RawSpinLock::INIT,
+            value: UnsafeCell::new(value),
+            interrupts: PhantomData,
+        }
+    }
+
+    /// Masks interrupts, then spins until it gets the lock.
+    pub fn lock(&self) -> IrqSpinLockGuard<'_, T, I> {
+        let state = I::disable();
+        self.raw.lock();
+        self.guard(Some(state))
+    }
+
+    /// Leaves interrupts as they were if the lock is taken.
+    pub fn try_lock(&self) -> Option<IrqSpinLockGuard<'_, T, I>> {
+        let state = I::disable();
+        if self.raw.try_lock() {
+            return Some(self.guard(Some(state)));
+        }
+        // Safety: It's the state `disable` just returned.
+        unsafe { I::restore(state) };
+        None
+    }
+
+    /// For interrupt handlers: takes the lock if it's free, without
+    /// touching the interrupt mask, since `masked` shows it's masked already.
+    /// Never spins, even if it's just another core holding the lock.
+    pub fn try_lock_from_isr<'a>(
+        &'a self,
+        masked: Masked<'a>,
+    ) -> Option<IrqSpinLockGuard<'a, T, I>> {
+        let _ = masked;
+        self.raw.try_lock().then(|| self.guard(None))
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// Only call this right after locking.
+    fn guard(&self, restore: Option<I::State>) -> IrqSpinLockGuard<'_, T, I> {
+        IrqSpinLockGuard {
+            lock: self,
+            restore,
+            not_send: PhantomData,
+        }
+    }
+}
+
+impl<T, I: Interrupts> Deref for IrqSpinLockGuard<'_, T, I> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safety: The very existence of this guard
+        // guarantees we've exclusively locked the lock.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T, I: Interrupts> DerefMut for IrqSpinLockGuard<'_, T, I> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: The very existence of this guard
+        // guarantees we've exclusively locked the lock.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T, I: Interrupts> Drop for IrqSpinLockGuard<'_, T, I> {
+    fn drop(&mut self) {
+        // Safety: We hold the lock.
+        unsafe { self.lock.raw.unlock() };
+        // Only after unlocking: an interrupt handler that runs as soon as
+        // they're unmasked may want the lock.
+        if let Some(state) = self.restore.take() {
+            // Safety: Guards can't leave the core, and are dropped in the
+            // reverse order of locking unless the caller goes out of their
+            // way, so this is the latest unrestored `disable`.
+            unsafe { I::restore(state) };
+        }
+    }
+}
+
+impl<T: Default, I: Interrupts> Default for IrqSpinLock<T, I> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// Doesn't spin: shows `<locked>` if the lock is taken.
+impl<T: fmt::Debug, I: Interrupts> fmt::Debug for IrqSpinLock<T, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("IrqSpinLock");
+        match self.try_lock() {
+            Some(guard) => d.field("data", &&*guard),
+            None => d.field("data", &format_args!("<locked>")),
+        };
+        d.finish()
+    }
+}
+
+impl<T: fmt::Debug, I: Interrupts> fmt::Debug for IrqSpinLockGuard<'_, T, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::cell::Cell;
+    use std::thread;
+
+    use crate::locks::irq_spin_lock::{Interrupts, IrqSpinLock, Masked};
+
+    std::thread_local! {
+        /// Whether this thread's pretend interrupts are masked.
+        static MASKED: Cell<bool> = const { Cell::new(false) };
+    }
+
+    /// Pretends every thread is a core.
+    struct FakeInterrupts;
+
+    unsafe impl Interrupts for FakeInterrupts {
+        type State = bool;
+
+        fn disable() -> bool {
+            MASKED.replace(true)
+        }
+
+        unsafe fn restore(was_masked: bool) {
+            assert!(MASKED.get(), "restoring unmasked interrupts");
+            MASKED.set(was_masked);
+        }
+    }
+
+    fn is_masked() -> bool {
+        MASKED.get()
+    }
+
+    #[test]
+    fn test_masks_while_locked() {
+        let a = IrqSpinLock::<_, FakeInterrupts>::new(1);
+        let b = IrqSpinLock::<_, FakeInterrupts>::new(2);
+        {
+            let ga = a.lock();
+            assert!(is_masked());
+            {
+                let gb = b.lock();
+                assert_eq!(*ga + *gb, 3);
+            }
+            // The inner guard put back the mask it found.
+            assert!(is_masked());
+            assert!(a.try_lock().is_none());
+            assert!(is_masked());
+        }
+        assert!(!is_masked());
+        assert!(a.try_lock().is_some());
+        assert!(!is_masked());
+    }
+
+    #[test]
+    fn test_try_lock_from_isr() {
+        let lock = IrqSpinLock::<_, FakeInterrupts>::new(0);
+        let guard = lock.lock();
+        // An interrupt handler on another core.
+        thread::scope(|s| {
+            s.spawn(|| {
+                let masked = unsafe { Masked::new() };
+                assert!(lock.try_lock_from_isr(masked).is_none());
+            });
+        });
+        drop(guard);
+
+        let masked = unsafe { Masked::new() };
+        *lock.try_lock_from_isr(masked).unwrap() += 1;
+        // It never masked anything, so it has nothing to restore.
+        assert!(!is_masked());
+        assert_eq!(lock.into_inner(), 1);
+    }
+
+    #[test]
+    fn test_cores_take_turns() {
+        const N: usize = if cfg!(miri) { 50 } else { 10_000 };
+        static COUNTER: IrqSpinLock<usize, FakeInterrupts> = IrqSpinLock::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..N {
+                        *COUNTER.lock() += 1;
+                        assert!(!is_masked());
+                    }
+                });
+            }
+        });
+        assert_eq!(*COUNTER.lock(), 4 * N);
+    }
+
+    #[test]
+    fn test_debug_does_not_spin() {
+        let x = IrqSpinLock::<_, FakeInterrupts>::new(5);
+        assert_eq!(format!("{x:?}"), "IrqSpinLock { data: 5 }");
+        let g = x.lock();
+        assert_eq!(format!("{x:?}"), "IrqSpinLock { data: <locked> }");
+        assert_eq!(format!("{g:?}"), "5");
+    }
+
+    #[test]
+    #[cfg(feature = "critical-section")]
+    fn test_critical_section() {
+        use crate::locks::irq_spin_lock::CriticalSection;
+
+        let lock = IrqSpinLock::<_, CriticalSection>::new(0);
+        *lock.lock() += 1;
+        critical_section::with(|cs| {
+            *lock.try_lock_from_isr(cs.into()).unwrap() += 1;
+        });
+        assert_eq!(lock.into_inner(), 2);
+    }
+}
@@ -0,0 +1,131 @@
+//! A gate that opens once and stays open, built on `platform::wait`.
+//!
+//! Once it's set, checking it is a single Acquire load, so a latch can sit
+//! on a hot path, like a shutdown flag that's checked between jobs. Only
+//! waiting while it's still closed touches the OS, and setting it only
+//! does when somebody is waiting.
+
+use core::fmt;
+
+use crate::platform::wait::{wait, wake_all};
+use crate::sync_shim::plain_atomic::AtomicU32;
+use crate::sync_shim::plain_atomic::Ordering::{Acquire, Relaxed, Release};
+
+const UNSET: u32 = 0;
+/// Not set, and there might be threads waiting.
+const WAITING: u32 = 1;
+const SET: u32 = 2;
+
+pub struct Latch {
+    state: AtomicU32,
+}
+
+impl Latch {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU32::new(UNSET),
+        }
+    }
+
+    /// Everything done before `set` is visible after `is_set` or `wait`
+    /// returned true.
+    pub fn is_set(&self) -> bool {
+        self.state.load(Acquire) == SET
+    }
+
+    /// Wakes every waiting thread. Setting it again does nothing.
+    pub fn set(&self) {
+        if self.state.swap(SET, Release) == WAITING {
+            wake_all(&self.state);
+        }
+    }
+
+    /// Blocks until it's set.
+    pub fn wait(&self) {
+        while !self.is_set() {
+            let _ = self
+                .state
+                .compare_exchange(UNSET, WAITING, Relaxed, Relaxed);
+            wait(&self.state, WAITING);
+        }
+    }
+
+    /// Blocks until it's set, for at most `timeout`. Returns whether it is.
+    #[cfg(feature = "std")]
+    pub fn wait_timeout(&self, timeout: core::time::Duration) -> bool {
+        let Some(deadline) = std::time::Instant::now().checked_add(timeout) else {
+            self.wait();
+            return true;
+        };
+        while !self.is_set() {
+            if std::time::Instant::now() >= deadline {
+                return false;
+            }
+            let _ = self
+                .state
+                .compare_exchange(UNSET, WAITING, Relaxed, Relaxed);
+            crate::platform::wait::wait_until(&self.state, WAITING, deadline);
+        }
+        true
+    }
+}
+
+impl Default for Latch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Latch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Latch")
+            .field("set", &self.is_set())
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::sync::atomic::AtomicU32;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::locks::latch::Latch;
+
+    #[test]
+    fn test_waiters_see_what_happened_before_set() {
+        let latch = Latch::new();
+        let data = AtomicU32::new(0);
+        thread::scope(|s| {
+            for _ in 0..3 {
+                s.spawn(|| {
+                    latch.wait();
+                    assert_eq!(data.load(Relaxed), 42);
+                });
+            }
+            thread::sleep(Duration::from_millis(10));
+            assert!(!latch.is_set());
+            data.store(42, Relaxed);
+            latch.set();
+        });
+        assert!(latch.is_set());
+        latch.set();
+        latch.wait();
+    }
+
+    #[test]
+    fn test_wait_timeout() {
+        let latch = Latch::new();
+        assert!(!latch.wait_timeout(Duration::from_millis(10)));
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                latch.set();
+            });
+            assert!(latch.wait_timeout(Duration::from_secs(10)));
+        });
+        assert!(latch.wait_timeout(Duration::MAX));
+        assert_eq!(format!("{latch:?}"), "Latch { set: true }");
+    }
+}
@@ -0,0 +1,1081 @@
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+#[cfg(feature = "trace")]
+use core::sync::atomic::Ordering::{Acquire, Release};
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "std")]
+use crate::cancel::CancellationToken;
+#[cfg(feature = "deadlock_detection")]
+use crate::deadlock;
+#[cfg(feature = "std")]
+use crate::errors::{LockTimeout, WaitError};
+#[cfg(feature = "lock_order")]
+use crate::lock_order;
+use crate::locks::raw::{RawLock, RawMutex, RawRwLock};
+#[cfg(feature = "stats")]
+use crate::stats::{LockStats, Recorder};
+#[cfg(feature = "trace")]
+use crate::trace::{self, Op};
+
+/// A mutex that's generic over its locking algorithm, so the algorithm can
+/// be swapped without touching the code that uses the lock.
+pub struct Lock<T, R: RawLock> {
+    raw: R,
+    #[cfg(feature = "stats")]
+    stats: Recorder,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T, R: RawLock + Sync> Sync for Lock<T, R> where T: Send {}
+
+/// `Send` if `T` is, but only `Sync` if `T` is too:
+///
+/// ```compile_fail,E0277
+/// use std::cell::Cell;
+///
+/// use atomics_and_locks::locks::MutexGuard;
+///
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<MutexGuard<'_, Cell<i32>>>();
+/// ```
+pub struct LockGuard<'a, T, R: RawLock> {
+    lock: &'a Lock<T, R>,
+    #[cfg(feature = "stats")]
+    acquired: Instant,
+    /// Through `lock`, it would be `Sync` whenever the lock is, which only
+    /// takes `T: Send`: see the impls below.
+    not_sync: PhantomData<*const ()>,
+}
+
+// Safety: Every `RawLock` can be unlocked on another thread, so it's
+// `Send` like a `&mut T`.
+unsafe impl<T: Send, R: RawLock + Sync> Send for LockGuard<'_, T, R> {}
+
+// Safety: A `&LockGuard` only gives a `&T`.
+unsafe impl<T: Sync, R: RawLock + Sync> Sync for LockGuard<'_, T, R> {}
+
+/// A `Lock` that sleeps while contended, and adds only a byte to `T`.
+pub type Mutex<T> = Lock<T, RawMutex>;
+
+pub type MutexGuard<'a, T> = LockGuard<'a, T, RawMutex>;
+
+impl<T, R: RawLock> Lock<T, R> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            raw: R::INIT,
+            #[cfg(feature = "stats")]
+            stats: Recorder::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> LockGuard<'_, T, R> {
+        #[cfg(feature = "lock_order")]
+        lock_order::before_lock(self.addr());
+        #[cfg(feature = "deadlock_detection")]
+        deadlock::waiting(self.addr());
+        #[cfg(not(feature = "stats"))]
+        self.raw.lock();
+        #[cfg(feature = "stats")]
+        self.stats.lock(|| self.raw.try_lock(), || self.raw.lock());
+        self.guard()
+    }
+
+    pub fn try_lock(&self) -> Option<LockGuard<'_, T, R>> {
+        self.raw.try_lock().then(|| self.guard())
+    }
+
+    /// Only call this right after locking.
+    fn guard(&self) -> LockGuard<'_, T, R> {
+        #[cfg(feature = "deadlock_detection")]
+        deadlock::acquired(self.addr());
+        #[cfg(feature = "lock_order")]
+        lock_order::acquired(self.addr());
+        #[cfg(feature = "trace")]
+        trace::record(Op::Lock, self.addr(), Acquire);
+        LockGuard {
+            lock: self,
+            #[cfg(feature = "stats")]
+            acquired: self.stats.acquired(),
+            not_sync: PhantomData,
+        }
+    }
+
+    #[cfg(any(
+        feature = "deadlock_detection",
+        feature = "lock_order",
+        feature = "trace"
+    ))]
+    fn addr(&self) -> usize {
+        core::ptr::from_ref(self).addr()
+    }
+
+    /// Waits for the lock for at most `timeout` before giving up.
+    #[cfg(feature = "std")]
+    pub fn try_lock_for(&self, timeout: Duration) -> Result<LockGuard<'_, T, R>, LockTimeout> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.try_lock_until(deadline),
+            None => Ok(self.lock()),
+        }
+    }
+
+    /// Waits for the lock until `deadline` before giving up. Sleeping locks
+    /// sleep meanwhile, see `RawLock::try_lock_until`.
+    #[cfg(feature = "std")]
+    pub fn try_lock_until(&self, deadline: Instant) -> Result<LockGuard<'_, T, R>, LockTimeout> {
+        if self.lock_with(|| self.raw.try_lock(), || self.raw.try_lock_until(deadline)) {
+            Ok(self.guard())
+        } else {
+            Err(LockTimeout)
+        }
+    }
+
+    /// Like `try_lock_until`, but also gives up once `token` is cancelled.
+    #[cfg(feature = "std")]
+    pub fn try_lock_until_cancellable(
+        &self,
+        deadline: Instant,
+        token: &CancellationToken,
+    ) -> Result<LockGuard<'_, T, R>, WaitError> {
+        if self.lock_with(
+            || self.raw.try_lock(),
+            || {
+                self.raw
+                    .try_lock_until_interrupted(deadline, token.interrupt())
+            },
+        ) {
+            return Ok(self.guard());
+        }
+        token.check()?;
+        Err(WaitError::Timeout)
+    }
+
+    /// What `lock` does around waiting, for the ways of locking that can
+    /// give up: `lock_until` returns whether it got the lock.
+    #[cfg(feature = "std")]
+    fn lock_with(
+        &self,
+        try_lock: impl FnOnce() -> bool,
+        lock_until: impl FnOnce() -> bool,
+    ) -> bool {
+        #[cfg(feature = "lock_order")]
+        lock_order::before_lock(self.addr());
+        #[cfg(feature = "deadlock_detection")]
+        deadlock::waiting(self.addr());
+        #[cfg(not(feature = "stats"))]
+        let locked = try_lock() || lock_until();
+        #[cfg(feature = "stats")]
+        let locked = self.stats.lock_until(try_lock, lock_until);
+        #[cfg(feature = "deadlock_detection")]
+        if !locked {
+            deadlock::gave_up(self.addr());
+        }
+        locked
+    }
+
+    /// A snapshot of the statistics since the lock was created or last reset.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> LockStats {
+        self.stats.snapshot()
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&self) {
+        self.stats.reset();
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    #[cfg(not(feature = "lock_order"))]
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// With `lock_order`, this has a `Drop` impl, so the value can't just be
+    /// moved out.
+    #[cfg(feature = "lock_order")]
+    pub fn into_inner(self) -> T {
+        let this = ManuallyDrop::new(self);
+        lock_order::forget(this.addr());
+        // Safety: `this` is never used or dropped again, so both fields are
+        // moved out exactly once.
+        let (raw, value) = unsafe { (core::ptr::read(&this.raw), core::ptr::read(&this.value)) };
+        drop(raw);
+        value.into_inner()
+    }
+}
+
+impl<T> Mutex<T> {
+    /// See [`RawMutex::debug_validate`].
+    pub fn debug_validate(&self) {
+        self.raw.debug_validate();
+    }
+}
+
+impl<'a, T, R: RawLock> LockGuard<'a, T, R> {
+    /// The raw lock this holds, for a condition variable to unlock and
+    /// relock while waiting.
+    pub(crate) fn raw(&self) -> &'a R {
+        &self.lock.raw
+    }
+}
+
+impl<T, R: RawLock> Deref for LockGuard<'_, T, R> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safety: The very existence of this Guard
+        // guarantees we've exclusively locked the lock.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T, R: RawLock> DerefMut for LockGuard<'_, T, R> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: The very existence of this Guard
+        // guarantees we've exclusively locked the lock.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T, R: RawLock> Drop for LockGuard<'_, T, R> {
+    fn drop(&mut self) {
+        #[cfg(feature = "deadlock_detection")]
+        deadlock::released(self.lock.addr());
+        #[cfg(feature = "lock_order")]
+        lock_order::released(self.lock.addr());
+        #[cfg(feature = "stats")]
+        self.lock.stats.released(self.acquired);
+        #[cfg(feature = "trace")]
+        trace::record(Op::Unlock, self.lock.addr(), Release);
+        // Safety: We hold the lock.
+        unsafe { self.lock.raw.unlock() }
+    }
+}
+
+/// A reader-writer lock that's generic over its locking algorithm.
+pub struct RwLock<T, R: RawRwLock> {
+    raw: R,
+    #[cfg(feature = "stats")]
+    stats: Recorder,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T, R: RawRwLock + Sync> Sync for RwLock<T, R> where T: Send + Sync {}
+
+pub struct ReadGuard<'a, T, R: RawRwLock> {
+    rwlock: &'a RwLock<T, R>,
+    #[cfg(feature = "stats")]
+    acquired: Instant,
+    /// Like `LockGuard`'s, so `Sync` only depends on `T: Sync`.
+    not_sync: PhantomData<*const ()>,
+}
+
+// Safety: Like `LockGuard`. The bounds are the ones it had through
+// `rwlock`.
+unsafe impl<T: Send + Sync, R: RawRwLock + Sync> Send for ReadGuard<'_, T, R> {}
+
+// Safety: A `&ReadGuard` only gives a `&T`.
+unsafe impl<T: Sync, R: RawRwLock + Sync> Sync for ReadGuard<'_, T, R> {}
+
+pub struct WriteGuard<'a, T, R: RawRwLock> {
+    rwlock: &'a RwLock<T, R>,
+    #[cfg(feature = "stats")]
+    acquired: Instant,
+    /// Like `LockGuard`'s.
+    not_sync: PhantomData<*const ()>,
+}
+
+// Safety: Like `ReadGuard`.
+unsafe impl<T: Send + Sync, R: RawRwLock + Sync> Send for WriteGuard<'_, T, R> {}
+
+// Safety: A `&WriteGuard` only gives a `&T`.
+unsafe impl<T: Sync, R: RawRwLock + Sync> Sync for WriteGuard<'_, T, R> {}
+
+impl<T, R: RawRwLock> RwLock<T, R> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            raw: R::INIT,
+            #[cfg(feature = "stats")]
+            stats: Recorder::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn read(&self) -> ReadGuard<'_, T, R> {
+        #[cfg(feature = "lock_order")]
+        lock_order::before_lock(self.addr());
+        #[cfg(feature = "deadlock_detection")]
+        deadlock::waiting(self.addr());
+        #[cfg(not(feature = "stats"))]
+        self.raw.read();
+        #[cfg(feature = "stats")]
+        self.stats.lock(|| self.raw.try_read(), || self.raw.read());
+        self.read_guard()
+    }
+
+    pub fn try_read(&self) -> Option<ReadGuard<'_, T, R>> {
+        self.raw.try_read().then(|| self.read_guard())
+    }
+
+    pub fn write(&self) -> WriteGuard<'_, T, R> {
+        #[cfg(feature = "lock_order")]
+        lock_order::before_lock(self.addr());
+        #[cfg(feature = "deadlock_detection")]
+        deadlock::waiting(self.addr());
+        #[cfg(not(feature = "stats"))]
+        self.raw.write();
+        #[cfg(feature = "stats")]
+        self.stats
+            .lock(|| self.raw.try_write(), || self.raw.write());
+        self.write_guard()
+    }
+
+    pub fn try_write(&self) -> Option<WriteGuard<'_, T, R>> {
+        self.raw.try_write().then(|| self.write_guard())
+    }
+
+    /// Waits for a read lock for at most `timeout` before giving up.
+    #[cfg(feature = "std")]
+    pub fn try_read_for(&self, timeout: Duration) -> Result<ReadGuard<'_, T, R>, LockTimeout> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.try_read_until(deadline),
+            None => Ok(self.read()),
+        }
+    }
+
+    /// Waits for a read lock until `deadline` before giving up.
+    #[cfg(feature = "std")]
+    pub fn try_read_until(&self, deadline: Instant) -> Result<ReadGuard<'_, T, R>, LockTimeout> {
+        if self.lock_with(|| self.raw.try_read(), || self.raw.try_read_until(deadline)) {
+            Ok(self.read_guard())
+        } else {
+            Err(LockTimeout)
+        }
+    }
+
+    /// Waits for the write lock for at most `timeout` before giving up.
+    #[cfg(feature = "std")]
+    pub fn try_write_for(&self, timeout: Duration) -> Result<WriteGuard<'_, T, R>, LockTimeout> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.try_write_until(deadline),
+            None => Ok(self.write()),
+        }
+    }
+
+    /// Waits for the write lock until `deadline` before giving up.
+    #[cfg(feature = "std")]
+    pub fn try_write_until(&self, deadline: Instant) -> Result<WriteGuard<'_, T, R>, LockTimeout> {
+        if self.lock_with(
+            || self.raw.try_write(),
+            || self.raw.try_write_until(deadline),
+        ) {
+            Ok(self.write_guard())
+        } else {
+            Err(LockTimeout)
+        }
+    }
+
+    /// Like `try_read_until`, but also gives up once `token` is cancelled.
+    #[cfg(feature = "std")]
+    pub fn try_read_until_cancellable(
+        &self,
+        deadline: Instant,
+        token: &CancellationToken,
+    ) -> Result<ReadGuard<'_, T, R>, WaitError> {
+        if self.lock_with(
+            || self.raw.try_read(),
+            || {
+                self.raw
+                    .try_read_until_interrupted(deadline, token.interrupt())
+            },
+        ) {
+            return Ok(self.read_guard());
+        }
+        token.check()?;
+        Err(WaitError::Timeout)
+    }
+
+    /// Like `try_write_until`, but also gives up once `token` is cancelled.
+    #[cfg(feature = "std")]
+    pub fn try_write_until_cancellable(
+        &self,
+        deadline: Instant,
+        token: &CancellationToken,
+    ) -> Result<WriteGuard<'_, T, R>, WaitError> {
+        if self.lock_with(
+            || self.raw.try_write(),
+            || {
+                self.raw
+                    .try_write_until_interrupted(deadline, token.interrupt())
+            },
+        ) {
+            return Ok(self.write_guard());
+        }
+        token.check()?;
+        Err(WaitError::Timeout)
+    }
+
+    /// What `read` and `write` do around waiting, for the ways of locking
+    /// that can give up: `lock_until` returns whether it got the lock.
+    #[cfg(feature = "std")]
+    fn lock_with(
+        &self,
+        try_lock: impl FnOnce() -> bool,
+        lock_until: impl FnOnce() -> bool,
+    ) -> bool {
+        #[cfg(feature = "lock_order")]
+        lock_order::before_lock(self.addr());
+        #[cfg(feature = "deadlock_detection")]
+        deadlock::waiting(self.addr());
+        #[cfg(not(feature = "stats"))]
+        let locked = try_lock() || lock_until();
+        #[cfg(feature = "stats")]
+        let locked = self.stats.lock_until(try_lock, lock_until);
+        #[cfg(feature = "deadlock_detection")]
+        if !locked {
+            deadlock::gave_up(self.addr());
+        }
+        locked
+    }
+
+    /// Only call this right after read-locking.
+    fn read_guard(&self) -> ReadGuard<'_, T, R> {
+        #[cfg(feature = "deadlock_detection")]
+        deadlock::acquired(self.addr());
+        #[cfg(feature = "lock_order")]
+        lock_order::acquired(self.addr());
+        #[cfg(feature = "trace")]
+        trace::record(Op::ReadLock, self.addr(), Acquire);
+        ReadGuard {
+            rwlock: self,
+            #[cfg(feature = "stats")]
+            acquired: self.stats.acquired(),
+            not_sync: PhantomData,
+        }
+    }
+
+    /// Only call this right after write-locking.
+    fn write_guard(&self) -> WriteGuard<'_, T, R> {
+        #[cfg(feature = "deadlock_detection")]
+        deadlock::acquired(self.addr());
+        #[cfg(feature = "lock_order")]
+        lock_order::acquired(self.addr());
+        #[cfg(feature = "trace")]
+        trace::record(Op::Lock, self.addr(), Acquire);
+        WriteGuard {
+            rwlock: self,
+            #[cfg(feature = "stats")]
+            acquired: self.stats.acquired(),
+            not_sync: PhantomData,
+        }
+    }
+
+    #[cfg(any(
+        feature = "deadlock_detection",
+        feature = "lock_order",
+        feature = "trace"
+    ))]
+    fn addr(&self) -> usize {
+        core::ptr::from_ref(self).addr()
+    }
+
+    /// A snapshot of the statistics since the lock was created or last reset.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> LockStats {
+        self.stats.snapshot()
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&self) {
+        self.stats.reset();
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    #[cfg(not(feature = "lock_order"))]
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// With `lock_order`, this has a `Drop` impl, so the value can't just be
+    /// moved out.
+    #[cfg(feature = "lock_order")]
+    pub fn into_inner(self) -> T {
+        let this = ManuallyDrop::new(self);
+        lock_order::forget(this.addr());
+        // Safety: `this` is never used or dropped again, so both fields are
+        // moved out exactly once.
+        let (raw, value) = unsafe { (core::ptr::read(&this.raw), core::ptr::read(&this.value)) };
+        drop(raw);
+        value.into_inner()
+    }
+}
+
+impl<'a, T, R: RawRwLock> ReadGuard<'a, T, R> {
+    /// Turns the read lock into the write lock if no one else holds a read
+    /// lock, or hands the read lock back. This is an associated function,
+    /// so it doesn't shadow a method of `T`.
+    pub fn try_upgrade(guard: Self) -> Result<WriteGuard<'a, T, R>, Self> {
+        // Safety: We hold a read lock.
+        if unsafe { guard.rwlock.raw.try_upgrade() } {
+            Ok(guard.upgraded())
+        } else {
+            Err(guard)
+        }
+    }
+
+    /// Like `try_upgrade`, but waits for the other readers to leave for at
+    /// most `timeout`. Two readers waiting to upgrade wait for each other,
+    /// so one of them has to give up, and drop its read lock, for the other
+    /// to get anywhere.
+    #[cfg(feature = "std")]
+    pub fn try_upgrade_for(
+        mut guard: Self,
+        timeout: Duration,
+    ) -> Result<WriteGuard<'a, T, R>, (Self, LockTimeout)> {
+        if let Some(deadline) = Instant::now().checked_add(timeout) {
+            return Self::try_upgrade_until(guard, deadline);
+        }
+        // Too long for an Instant. There's no blocking upgrade to fall back
+        // on, so it waits a day at a time, for as long as it takes.
+        loop {
+            match Self::try_upgrade_until(guard, Instant::now() + Duration::from_secs(86_400)) {
+                Ok(w) => return Ok(w),
+                Err((g, _)) => guard = g,
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn try_upgrade_until(
+        guard: Self,
+        deadline: Instant,
+    ) -> Result<WriteGuard<'a, T, R>, (Self, LockTimeout)> {
+        // Safety: We hold a read lock.
+        if unsafe { guard.rwlock.raw.try_upgrade_until(deadline) } {
+            Ok(guard.upgraded())
+        } else {
+            Err((guard, LockTimeout))
+        }
+    }
+
+    /// Only call this right after the raw lock was upgraded. It stays
+    /// locked the whole time, so as far as deadlock detection and the lock
+    /// order are concerned, nothing happened.
+    fn upgraded(self) -> WriteGuard<'a, T, R> {
+        let this = ManuallyDrop::new(self);
+        #[cfg(feature = "trace")]
+        {
+            trace::record(Op::ReadUnlock, this.rwlock.addr(), Release);
+            trace::record(Op::Lock, this.rwlock.addr(), Acquire);
+        }
+        WriteGuard {
+            rwlock: this.rwlock,
+            #[cfg(feature = "stats")]
+            acquired: this.acquired,
+            not_sync: PhantomData,
+        }
+    }
+}
+
+impl<T, R: RawRwLock> Deref for ReadGuard<'_, T, R> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safety: Readers only coexist with other readers.
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T, R: RawRwLock> Drop for ReadGuard<'_, T, R> {
+    fn drop(&mut self) {
+        #[cfg(feature = "deadlock_detection")]
+        deadlock::released(self.rwlock.addr());
+        #[cfg(feature = "lock_order")]
+        lock_order::released(self.rwlock.addr());
+        #[cfg(feature = "stats")]
+        self.rwlock.stats.released(self.acquired);
+        #[cfg(feature = "trace")]
+        trace::record(Op::ReadUnlock, self.rwlock.addr(), Release);
+        // Safety: We hold a read lock.
+        unsafe { self.rwlock.raw.read_unlock() }
+    }
+}
+
+impl<T, R: RawRwLock> Deref for WriteGuard<'_, T, R> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safety: The very existence of this Guard
+        // guarantees we've exclusively locked the lock.
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T, R: RawRwLock> DerefMut for WriteGuard<'_, T, R> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: The very existence of this Guard
+        // guarantees we've exclusively locked the lock.
+        unsafe { &mut *self.rwlock.value.get() }
+    }
+}
+
+impl<T, R: RawRwLock> Drop for WriteGuard<'_, T, R> {
+    fn drop(&mut self) {
+        #[cfg(feature = "deadlock_detection")]
+        deadlock::released(self.rwlock.addr());
+        #[cfg(feature = "lock_order")]
+        lock_order::released(self.rwlock.addr());
+        #[cfg(feature = "stats")]
+        self.rwlock.stats.released(self.acquired);
+        #[cfg(feature = "trace")]
+        trace::record(Op::Unlock, self.rwlock.addr(), Release);
+        // Safety: We hold the write lock.
+        unsafe { self.rwlock.raw.write_unlock() }
+    }
+}
+
+/// Forgets the lock's place in the lock order, so a new lock at the same
+/// address doesn't inherit it.
+#[cfg(feature = "lock_order")]
+impl<T, R: RawLock> Drop for Lock<T, R> {
+    fn drop(&mut self) {
+        lock_order::forget(self.addr());
+    }
+}
+
+#[cfg(feature = "lock_order")]
+impl<T, R: RawRwLock> Drop for RwLock<T, R> {
+    fn drop(&mut self) {
+        lock_order::forget(self.addr());
+    }
+}
+
+impl<T: Default, R: RawLock> Default for Lock<T, R> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// Doesn't block: shows `<locked>` if the lock is taken.
+impl<T: fmt::Debug, R: RawLock> fmt::Debug for Lock<T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("Lock");
+        match self.try_lock() {
+            Some(guard) => d.field("data", &&*guard),
+            None => d.field("data", &format_args!("<locked>")),
+        };
+        d.finish()
+    }
+}
+
+impl<T: fmt::Debug, R: RawLock> fmt::Debug for LockGuard<'_, T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: Default, R: RawRwLock> Default for RwLock<T, R> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// Doesn't block: shows `<locked>` if the lock is write-locked.
+impl<T: fmt::Debug, R: RawRwLock> fmt::Debug for RwLock<T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("RwLock");
+        match self.try_read() {
+            Some(guard) => d.field("data", &&*guard),
+            None => d.field("data", &format_args!("<locked>")),
+        };
+        d.finish()
+    }
+}
+
+impl<T: fmt::Debug, R: RawRwLock> fmt::Debug for ReadGuard<'_, T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: fmt::Debug, R: RawRwLock> fmt::Debug for WriteGuard<'_, T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::thread;
+    use std::time::Duration;
+    #[cfg(feature = "std")]
+    use std::time::Instant;
+
+    #[cfg(feature = "std")]
+    use crate::cancel::CancellationToken;
+    #[cfg(feature = "std")]
+    use crate::errors::WaitError;
+    use crate::locks::lock::{Lock, LockGuard, Mutex, ReadGuard, RwLock, WriteGuard};
+    use crate::locks::raw::{
+        RawFutexMutex, RawFutexRwLock, RawLock, RawMutex, RawRwLock, RawSpinLock, RawSpinRwLock,
+    };
+    use crate::platform::parking::{park, parked, unpark_one};
+
+    const N: usize = if cfg!(miri) { 100 } else { 1000 };
+
+    /// Generic over the algorithm, the way a benchmark or a user would be.
+    fn count_from_four_threads<R: RawLock + Sync>() {
+        let lock = Lock::<_, R>::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..N {
+                        *lock.lock() += 1;
+                    }
+                });
+            }
+        });
+        assert_eq!(lock.into_inner(), 4 * N);
+    }
+
+    fn readers_see_consistent_pairs<R: RawRwLock + Sync>() {
+        let rwlock = RwLock::<_, R>::new((0, 0));
+        thread::scope(|s| {
+            s.spawn(|| {
+                for _ in 0..N {
+                    let mut w = rwlock.write();
+                    w.0 += 1;
+                    w.1 += 1;
+                }
+            });
+            for _ in 0..2 {
+                s.spawn(|| {
+                    for _ in 0..N {
+                        let r = rwlock.read();
+                        assert_eq!(r.0, r.1);
+                    }
+                });
+            }
+        });
+        assert_eq!(rwlock.into_inner(), (N, N));
+    }
+
+    #[test]
+    fn test_spin_lock() {
+        count_from_four_threads::<RawSpinLock>();
+        let lock = Lock::<_, RawSpinLock>::new(());
+        let guard = lock.lock();
+        assert!(lock.try_lock().is_none());
+        #[cfg(feature = "std")]
+        assert!(lock.try_lock_for(Duration::from_millis(5)).is_err());
+        #[cfg(feature = "std")]
+        assert!(lock.try_lock_until(std::time::Instant::now()).is_err());
+        drop(guard);
+        assert!(lock.try_lock().is_some());
+    }
+
+    #[test]
+    fn test_guards_are_send_and_sync_like_std() {
+        fn assert_send<T: Send>() {}
+        fn assert_sync<T: Sync>() {}
+        assert_send::<LockGuard<'_, std::cell::Cell<i32>, RawMutex>>();
+        assert_sync::<LockGuard<'_, Vec<u8>, RawMutex>>();
+        assert_send::<ReadGuard<'_, Vec<u8>, RawFutexRwLock>>();
+        assert_sync::<ReadGuard<'_, Vec<u8>, RawFutexRwLock>>();
+        assert_send::<WriteGuard<'_, Vec<u8>, RawFutexRwLock>>();
+        assert_sync::<WriteGuard<'_, Vec<u8>, RawFutexRwLock>>();
+    }
+
+    #[test]
+    fn test_spin_rwlock() {
+        readers_see_consistent_pairs::<RawSpinRwLock>();
+        let rwlock = RwLock::<_, RawSpinRwLock>::new(());
+        let r1 = rwlock.read();
+        let r2 = rwlock.try_read();
+        assert!(r2.is_some());
+        assert!(rwlock.try_write().is_none());
+        drop((r1, r2));
+        let w = rwlock.try_write();
+        assert!(w.is_some());
+        assert!(rwlock.try_read().is_none());
+    }
+
+    #[test]
+    fn test_futex_rwlock() {
+        readers_see_consistent_pairs::<RawFutexRwLock>();
+        let rwlock = RwLock::<_, RawFutexRwLock>::new(0);
+        let r = rwlock.read();
+        thread::scope(|s| {
+            // Has to sleep until the read guard is dropped, and keeps new
+            // readers out in the meantime.
+            s.spawn(|| *rwlock.write() += 1);
+            thread::sleep(Duration::from_millis(10));
+            assert!(rwlock.try_read().is_none());
+            drop(r);
+        });
+        assert_eq!(*rwlock.read(), 1);
+    }
+
+    #[cfg(feature = "std")]
+    fn timed_and_upgrades<R: RawRwLock + Sync>() {
+        let rwlock = RwLock::<_, R>::new(0);
+        let r1 = rwlock.try_read_for(Duration::from_millis(5)).unwrap();
+        assert!(rwlock.try_write_for(Duration::from_millis(5)).is_err());
+        let r2 = rwlock.try_read_for(Duration::from_millis(5)).unwrap();
+        // Not while the other reader is there.
+        let r1 = ReadGuard::try_upgrade(r1).unwrap_err();
+        let (r1, _) = ReadGuard::try_upgrade_for(r1, Duration::from_millis(5)).unwrap_err();
+        // A writer that gave up doesn't keep readers out.
+        assert!(rwlock.try_read().is_some());
+        thread::scope(|s| {
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                drop(r2);
+            });
+            let mut w = ReadGuard::try_upgrade_for(r1, Duration::from_secs(10)).unwrap();
+            *w += 1;
+            assert!(rwlock.try_read_until(std::time::Instant::now()).is_err());
+        });
+        let w = rwlock.try_write_for(Duration::from_millis(5)).unwrap();
+        assert_eq!(*w, 1);
+        drop(w);
+        assert!(ReadGuard::try_upgrade(rwlock.read()).is_ok());
+        assert!(ReadGuard::try_upgrade_for(rwlock.read(), Duration::MAX).is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_timed_rwlock() {
+        timed_and_upgrades::<RawSpinRwLock>();
+        timed_and_upgrades::<RawFutexRwLock>();
+        // Timing out clears the waiting bit, which another writer was
+        // counting on too, so it has to be woken to set it again.
+        let rwlock = RwLock::<_, RawFutexRwLock>::new(0);
+        let r = rwlock.read();
+        thread::scope(|s| {
+            s.spawn(|| *rwlock.write() += 1);
+            thread::sleep(Duration::from_millis(10));
+            assert!(rwlock.try_write_for(Duration::from_millis(5)).is_err());
+            thread::sleep(Duration::from_millis(10));
+            assert!(rwlock.try_read().is_none());
+            drop(r);
+        });
+        assert_eq!(rwlock.into_inner(), 1);
+    }
+
+    /// One waiter gives up while the lock is held, and another, with time
+    /// left, gets it once it's unlocked.
+    #[cfg(feature = "std")]
+    fn timed_lock<R: RawLock + Sync>() {
+        let lock = Lock::<_, R>::new(0);
+        let guard = lock.lock();
+        assert!(lock.try_lock_until(Instant::now()).is_err());
+        thread::scope(|s| {
+            s.spawn(|| assert!(lock.try_lock_for(Duration::from_millis(5)).is_err()));
+            let waiter = s.spawn(|| *lock.try_lock_for(Duration::from_secs(10)).unwrap() += 1);
+            thread::sleep(Duration::from_millis(20));
+            drop(guard);
+            waiter.join().unwrap();
+        });
+        assert_eq!(*lock.try_lock_for(Duration::from_millis(5)).unwrap(), 1);
+        // Too long for an Instant, so it waits without a deadline.
+        assert_eq!(*lock.try_lock_for(Duration::MAX).unwrap(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_timed_lock() {
+        timed_lock::<RawSpinLock>();
+        timed_lock::<RawFutexMutex>();
+        timed_lock::<RawMutex>();
+        // A thread that gave up while parked took itself off the queue.
+        let mutex = Mutex::new(());
+        let guard = mutex.lock();
+        thread::scope(|s| {
+            s.spawn(|| assert!(mutex.try_lock_for(Duration::from_millis(5)).is_err()));
+        });
+        parked(mutex.raw.addr(), |n| assert_eq!(n, 0));
+        mutex.raw.debug_validate();
+        drop(guard);
+        assert!(mutex.try_lock().is_some());
+    }
+
+    /// A reader and a writer wait for a write lock that's held for a
+    /// minute, until they're cancelled.
+    #[cfg(feature = "std")]
+    fn cancelled_waits<R: RawRwLock + Sync>() {
+        let rwlock = RwLock::<_, R>::new(0);
+        let token = CancellationToken::new();
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let w = rwlock.write();
+        assert_eq!(
+            rwlock
+                .try_read_until_cancellable(Instant::now(), &token)
+                .err(),
+            Some(WaitError::Timeout)
+        );
+        thread::scope(|s| {
+            let reader = s.spawn(|| rwlock.try_read_until_cancellable(deadline, &token).err());
+            let writer = s.spawn(|| rwlock.try_write_until_cancellable(deadline, &token).err());
+            thread::sleep(Duration::from_millis(10));
+            token.cancel();
+            assert_eq!(reader.join().unwrap(), Some(WaitError::Cancelled));
+            assert_eq!(writer.join().unwrap(), Some(WaitError::Cancelled));
+        });
+        drop(w);
+        // It's only for waiting: a free lock is still taken.
+        assert!(rwlock.try_write_until_cancellable(deadline, &token).is_ok());
+    }
+
+    /// Same for a `Lock`. The waiter is asleep, if the lock can sleep, so
+    /// cancelling has to wake it.
+    #[cfg(feature = "std")]
+    fn cancelled_lock<R: RawLock + Sync>() {
+        let lock = Lock::<_, R>::new(0);
+        let token = CancellationToken::new();
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let guard = lock.lock();
+        thread::scope(|s| {
+            let timed_out = s.spawn(|| {
+                let deadline = Instant::now() + Duration::from_millis(5);
+                lock.try_lock_until_cancellable(deadline, &token).err()
+            });
+            assert_eq!(timed_out.join().unwrap(), Some(WaitError::Timeout));
+            let waiter = s.spawn(|| lock.try_lock_until_cancellable(deadline, &token).err());
+            thread::sleep(Duration::from_millis(10));
+            token.cancel();
+            assert_eq!(waiter.join().unwrap(), Some(WaitError::Cancelled));
+        });
+        drop(guard);
+        // It's only for waiting: a free lock is still taken.
+        assert!(lock.try_lock_until_cancellable(deadline, &token).is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_cancelled_timed_waits() {
+        cancelled_waits::<RawSpinRwLock>();
+        cancelled_waits::<RawFutexRwLock>();
+        cancelled_lock::<RawSpinLock>();
+        cancelled_lock::<RawFutexMutex>();
+        cancelled_lock::<RawMutex>();
+        // The cancelled waiter took itself off the queue.
+        let mutex = Mutex::new(());
+        let token = CancellationToken::new();
+        let guard = mutex.lock();
+        thread::scope(|s| {
+            let waiter = s.spawn(|| {
+                let deadline = Instant::now() + Duration::from_secs(60);
+                mutex.try_lock_until_cancellable(deadline, &token).err()
+            });
+            thread::sleep(Duration::from_millis(10));
+            token.cancel();
+            assert_eq!(waiter.join().unwrap(), Some(WaitError::Cancelled));
+        });
+        parked(mutex.raw.addr(), |n| assert_eq!(n, 0));
+        drop(guard);
+    }
+
+    #[test]
+    fn test_mutex() {
+        count_from_four_threads::<RawMutex>();
+        count_from_four_threads::<RawFutexMutex>();
+        #[cfg(not(feature = "stats"))]
+        assert_eq!(std::mem::size_of::<Mutex<u8>>(), 2);
+        let mutex = Mutex::new(());
+        let guard = mutex.lock();
+        assert!(mutex.try_lock().is_none());
+        thread::scope(|s| {
+            // Has to sleep until the guard is dropped.
+            s.spawn(|| drop(mutex.lock()));
+            thread::sleep(Duration::from_millis(10));
+            drop(guard);
+        });
+    }
+
+    #[test]
+    fn test_static() {
+        static MUTEX: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+        static RWLOCK: RwLock<u32, RawSpinRwLock> = RwLock::new(7);
+        thread::scope(|s| {
+            for i in 0..4 {
+                s.spawn(move || MUTEX.lock().push(*RWLOCK.read() + i));
+            }
+        });
+        MUTEX.lock().sort();
+        assert_eq!(*MUTEX.lock(), [7, 8, 9, 10]);
+    }
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_unlocking_unlocked_raw_locks_panics() {
+        fn panics(unlock: impl FnOnce()) -> &'static str {
+            let payload = panic::catch_unwind(AssertUnwindSafe(unlock)).unwrap_err();
+            *payload.downcast::<&str>().unwrap()
+        }
+        let spin = RawSpinLock::INIT;
+        assert_eq!(
+            panics(|| unsafe { spin.unlock() }),
+            "unlocking an unlocked lock"
+        );
+        let mutex = RawMutex::INIT;
+        assert_eq!(
+            panics(|| unsafe { mutex.unlock() }),
+            "unlocking an unlocked mutex"
+        );
+        let futex = RawFutexMutex::INIT;
+        assert_eq!(
+            panics(|| unsafe { futex.unlock() }),
+            "unlocking an unlocked mutex"
+        );
+        let rwlock = RawSpinRwLock::INIT;
+        rwlock.read();
+        assert_eq!(
+            panics(|| unsafe { rwlock.write_unlock() }),
+            "write-unlocking a lock that isn't write-locked"
+        );
+        unsafe { rwlock.read_unlock() };
+        assert_eq!(
+            panics(|| unsafe { rwlock.read_unlock() }),
+            "read-unlocking a lock that isn't read-locked"
+        );
+    }
+
+    #[test]
+    fn test_debug_validate() {
+        let mutex = Mutex::new(0);
+        let addr = mutex.raw.addr();
+        let parked_threads = || parked(addr, |n| n);
+        thread::scope(|s| {
+            let guard = mutex.lock();
+            s.spawn(|| *mutex.lock() += 1);
+            while parked_threads() == 0 {
+                thread::sleep(Duration::from_millis(1));
+            }
+            mutex.debug_validate();
+            drop(guard);
+        });
+        mutex.debug_validate();
+
+        // Parked without setting PARKED, so no unlock would wake it.
+        thread::scope(|s| {
+            s.spawn(|| park(addr, || true));
+            while parked_threads() == 0 {
+                thread::sleep(Duration::from_millis(1));
+            }
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                mutex.debug_validate();
+            }));
+            assert!(result.is_err());
+            unpark_one(addr, |_| {});
+        });
+    }
+}
@@ -0,0 +1,31 @@
+pub mod bitset;
+#[cfg(feature = "std")]
+pub mod bravo;
+#[cfg(feature = "std")]
+pub mod cohort;
+pub mod condvar;
+#[cfg(all(feature = "lock_elision", target_arch = "x86_64"))]
+pub mod elision;
+pub mod irq_spin_lock;
+pub mod latch;
+pub mod lock;
+pub mod once;
+pub mod optimistic;
+pub mod raw;
+pub mod spin_lock;
+
+pub use bitset::AtomicBitset;
+#[cfg(feature = "std")]
+pub use bravo::RawBravoRwLock;
+#[cfg(feature = "std")]
+pub use cohort::RawCohortLock;
+pub use condvar::Condvar;
+pub use irq_spin_lock::{IrqSpinLock, IrqSpinLockGuard};
+pub use latch::Latch;
+pub use lock::{Lock, LockGuard, Mutex, MutexGuard, ReadGuard, RwLock, WriteGuard};
+pub use once::{LazyLock, OnceLock, PanicPolicy};
+pub use optimistic::{OptimisticLock, Plain};
+pub use raw::{
+    RawFutexMutex, RawFutexRwLock, RawLock, RawMutex, RawRwLock, RawSpinLock, RawSpinRwLock,
+};
+pub use spin_lock::{Guard as SpinLockGuard, SpinLock};
@@ -0,0 +1,449 @@
+//! A cell that's written once, by whichever thread gets there first, like
+//! std's `OnceLock`, built on `platform::wait`.
+//!
+//! The state machine: `INCOMPLETE` until a thread claims the right to
+//! initialize it by moving it to `RUNNING`, which becomes `WAITING` once
+//! another thread needs to sleep until that's done, and `COMPLETE` when the
+//! value is there. Reading a complete cell is a single Acquire load.
+//!
+//! Initialization can fail, with [`OnceLock::get_or_try_init`]. Then the
+//! cell goes back to `INCOMPLETE`, the waiting threads wake up, and the
+//! first of them to claim it tries its own initializer, and so on, until
+//! one succeeds or nobody is left. A panicking initializer is handled the
+//! same way. Configuration that can't be read right now, because a file
+//! isn't there yet, is the use case.
+//!
+//! [`LazyLock`] initializes itself on first use, with the function it was
+//! created with. What happens when that panics is up to its
+//! [`PanicPolicy`]: the cell can go back to `INCOMPLETE` for the next
+//! caller to retry, or to `POISONED`, where it stays, and where every
+//! caller panics, waiting threads included.
+
+use core::cell::UnsafeCell;
+use core::convert::Infallible;
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::ops::Deref;
+
+use crate::platform::wait::{wait, wake_all};
+use crate::sync_shim::plain_atomic::AtomicU32;
+use crate::sync_shim::plain_atomic::Ordering::{Acquire, Relaxed, Release};
+
+const INCOMPLETE: u32 = 0;
+const RUNNING: u32 = 1;
+/// Running, and there might be threads waiting for it.
+const WAITING: u32 = 2;
+const COMPLETE: u32 = 3;
+/// The initializer of a `LazyLock` panicked, and it won't be retried.
+const POISONED: u32 = 4;
+
+pub struct OnceLock<T> {
+    state: AtomicU32,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send + Sync> Sync for OnceLock<T> {}
+unsafe impl<T: Send> Send for OnceLock<T> {}
+
+/// Puts the state back to `INCOMPLETE`, or to `POISONED`, if the
+/// initializer returned an error or panicked, and wakes the waiting
+/// threads, to try theirs or to find it poisoned.
+struct Reset<'a> {
+    state: &'a AtomicU32,
+    to: u32,
+}
+
+impl Drop for Reset<'_> {
+    fn drop(&mut self) {
+        if self.state.swap(self.to, Release) == WAITING {
+            wake_all(self.state);
+        }
+    }
+}
+
+enum Failed<E> {
+    Error(E),
+    Poisoned,
+}
+
+impl<T> OnceLock<T> {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU32::new(INCOMPLETE),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        if self.state.load(Acquire) == COMPLETE {
+            // Safety: It's complete, so the value was written, and it's
+            // never written again while shared.
+            Some(unsafe { (*self.value.get()).assume_init_ref() })
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        if *self.state.get_mut() == COMPLETE {
+            // Safety: As in `get`, and we have the only reference.
+            Some(unsafe { self.value.get_mut().assume_init_mut() })
+        } else {
+            None
+        }
+    }
+
+    /// Gives the value back if the cell was already set, or is being set
+    /// by another thread, which this waits for.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let mut value = Some(value);
+        self.get_or_init(|| value.take().unwrap());
+        match value {
+            None => Ok(()),
+            Some(value) => Err(value),
+        }
+    }
+
+    pub fn get_or_init(&self, f: impl FnOnce() -> T) -> &T {
+        match self.get_or_try_init(|| Ok::<T, Infallible>(f())) {
+            Ok(value) => value,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Initializes it with `f` if nobody has yet. If `f` fails, the cell
+    /// stays empty, and the error is returned to this caller only: the
+    /// threads that were waiting try again, with their own initializers.
+    pub fn get_or_try_init<E>(&self, f: impl FnOnce() -> Result<T, E>) -> Result<&T, E> {
+        match self.initialize(f, INCOMPLETE) {
+            Ok(value) => Ok(value),
+            Err(Failed::Error(e)) => Err(e),
+            Err(Failed::Poisoned) => unreachable!("only a LazyLock is poisoned"),
+        }
+    }
+
+    /// `get_or_try_init`, leaving the state at `on_panic` if `f` panics.
+    fn initialize<E>(
+        &self,
+        f: impl FnOnce() -> Result<T, E>,
+        on_panic: u32,
+    ) -> Result<&T, Failed<E>> {
+        let mut f = Some(f);
+        loop {
+            match self.state.load(Acquire) {
+                COMPLETE => break,
+                POISONED => return Err(Failed::Poisoned),
+                INCOMPLETE => {
+                    if self
+                        .state
+                        .compare_exchange(INCOMPLETE, RUNNING, Acquire, Relaxed)
+                        .is_err()
+                    {
+                        continue;
+                    }
+                    let mut reset = Reset {
+                        state: &self.state,
+                        to: on_panic,
+                    };
+                    let value = match (f.take().unwrap())() {
+                        Ok(value) => value,
+                        Err(e) => {
+                            reset.to = INCOMPLETE;
+                            return Err(Failed::Error(e));
+                        }
+                    };
+                    core::mem::forget(reset);
+                    // Safety: Being `RUNNING` gives us the only access.
+                    unsafe { (*self.value.get()).write(value) };
+                    if self.state.swap(COMPLETE, Release) == WAITING {
+                        wake_all(&self.state);
+                    }
+                    break;
+                }
+                RUNNING => {
+                    let _ = self
+                        .state
+                        .compare_exchange(RUNNING, WAITING, Relaxed, Relaxed);
+                }
+                _ => wait(&self.state, WAITING),
+            }
+        }
+        // Safety: It's complete.
+        Ok(unsafe { (*self.value.get()).assume_init_ref() })
+    }
+
+    /// Empties the cell, giving back its value, if it had one.
+    pub fn take(&mut self) -> Option<T> {
+        if *self.state.get_mut() != COMPLETE {
+            return None;
+        }
+        *self.state.get_mut() = INCOMPLETE;
+        // Safety: It was complete, and isn't anymore, so the value is read
+        // out only once.
+        Some(unsafe { self.value.get_mut().assume_init_read() })
+    }
+
+    pub fn into_inner(mut self) -> Option<T> {
+        self.take()
+    }
+}
+
+/// What a [`LazyLock`] does when its initializer panics.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Every access panics from then on, including the ones that were
+    /// waiting for the initializer, like std's `LazyLock`.
+    #[default]
+    Poison,
+    /// The next access runs the initializer again, and the ones that were
+    /// waiting for it wait for that.
+    Retry,
+}
+
+/// A value that's initialized on first access, by `F`, which has to be
+/// callable more than once for [`PanicPolicy::Retry`].
+pub struct LazyLock<T, F = fn() -> T> {
+    cell: OnceLock<T>,
+    init: F,
+    policy: PanicPolicy,
+}
+
+impl<T, F: Fn() -> T> LazyLock<T, F> {
+    /// Poisons if `init` panics.
+    pub const fn new(init: F) -> Self {
+        Self::with_policy(init, PanicPolicy::Poison)
+    }
+
+    pub const fn with_policy(init: F, policy: PanicPolicy) -> Self {
+        Self {
+            cell: OnceLock::new(),
+            init,
+            policy,
+        }
+    }
+
+    /// Initializes it if nobody has yet. Panics if it's poisoned.
+    pub fn force(this: &Self) -> &T {
+        let on_panic = match this.policy {
+            PanicPolicy::Poison => POISONED,
+            PanicPolicy::Retry => INCOMPLETE,
+        };
+        match this
+            .cell
+            .initialize(|| Ok::<T, Infallible>((this.init)()), on_panic)
+        {
+            Ok(value) => value,
+            Err(Failed::Poisoned) => panic!("LazyLock is poisoned: its initializer panicked"),
+            Err(Failed::Error(never)) => match never {},
+        }
+    }
+
+    /// The value, if it's been initialized, without initializing it.
+    pub fn get(this: &Self) -> Option<&T> {
+        this.cell.get()
+    }
+
+    pub fn is_poisoned(this: &Self) -> bool {
+        this.cell.state.load(Relaxed) == POISONED
+    }
+}
+
+impl<T, F: Fn() -> T> Deref for LazyLock<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        Self::force(self)
+    }
+}
+
+impl<T: fmt::Debug, F: Fn() -> T> fmt::Debug for LazyLock<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_tuple("LazyLock");
+        match Self::get(self) {
+            Some(value) => d.field(value),
+            None if Self::is_poisoned(self) => d.field(&format_args!("<poisoned>")),
+            None => d.field(&format_args!("<uninit>")),
+        };
+        d.finish()
+    }
+}
+
+impl<T> Drop for OnceLock<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == COMPLETE {
+            // Safety: It's complete, so there's a value, which nothing
+            // uses anymore.
+            unsafe { self.value.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+impl<T> Default for OnceLock<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> From<T> for OnceLock<T> {
+    fn from(value: T) -> Self {
+        Self {
+            state: AtomicU32::new(COMPLETE),
+            value: UnsafeCell::new(MaybeUninit::new(value)),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for OnceLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_tuple("OnceLock");
+        match self.get() {
+            Some(value) => d.field(value),
+            None => d.field(&format_args!("<uninit>")),
+        };
+        d.finish()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::locks::once::{LazyLock, OnceLock, PanicPolicy};
+
+    #[test]
+    fn test_initialized_once() {
+        let cell = OnceLock::new();
+        let calls = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for i in 0..4 {
+                let (cell, calls) = (&cell, &calls);
+                s.spawn(move || {
+                    let value = cell.get_or_init(|| {
+                        calls.fetch_add(1, Relaxed);
+                        thread::sleep(Duration::from_millis(10));
+                        i
+                    });
+                    assert_eq!(cell.get(), Some(value));
+                });
+            }
+        });
+        assert_eq!(calls.load(Relaxed), 1);
+        assert!(cell.set(10).is_err());
+        assert!(cell.get().is_some());
+    }
+
+    #[test]
+    fn test_failed_init_leaves_it_empty() {
+        let cell = OnceLock::new();
+        assert_eq!(cell.get_or_try_init(|| Err("not yet")), Err("not yet"));
+        assert_eq!(cell.get(), None);
+        assert_eq!(cell.get_or_try_init(|| Ok::<_, ()>(1)), Ok(&1));
+        assert_eq!(cell.get_or_try_init(|| Err(())), Ok(&1));
+    }
+
+    #[test]
+    fn test_waiters_retry_after_a_failure() {
+        let cell = OnceLock::new();
+        let attempts = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let result = cell.get_or_try_init(|| {
+                        thread::sleep(Duration::from_millis(5));
+                        // The first two attempts fail, wherever they are.
+                        match attempts.fetch_add(1, Relaxed) {
+                            0 | 1 => Err(()),
+                            n => Ok(n),
+                        }
+                    });
+                    if let Ok(&n) = result {
+                        assert!(n >= 2);
+                    }
+                });
+            }
+        });
+        // Two threads got an error, and another one initialized it.
+        assert_eq!(cell.get(), Some(&2));
+        assert_eq!(attempts.into_inner(), 3);
+    }
+
+    #[test]
+    fn test_panicking_init_leaves_it_empty() {
+        let cell = OnceLock::new();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            cell.get_or_init(|| panic!("no config"));
+        }));
+        assert!(result.is_err());
+        assert_eq!(cell.set(1), Ok(()));
+    }
+
+    #[test]
+    fn test_take_and_into_inner() {
+        let mut cell = OnceLock::from(String::from("config"));
+        assert_eq!(format!("{cell:?}"), r#"OnceLock("config")"#);
+        assert_eq!(cell.take().as_deref(), Some("config"));
+        assert_eq!(cell.take(), None);
+        assert_eq!(format!("{cell:?}"), "OnceLock(<uninit>)");
+        cell.get_or_init(|| "again".into());
+        cell.get_mut().unwrap().push('!');
+        assert_eq!(cell.into_inner().as_deref(), Some("again!"));
+        assert_eq!(OnceLock::<u8>::new().into_inner(), None);
+    }
+
+    #[test]
+    fn test_lazy() {
+        static TABLE: LazyLock<Vec<u32>> = LazyLock::new(|| (0..4).collect());
+        assert_eq!(LazyLock::get(&TABLE), None);
+        assert_eq!(TABLE[3], 3);
+        assert_eq!(format!("{TABLE:?}"), "LazyLock([0, 1, 2, 3])");
+    }
+
+    /// Has four threads access `lazy` at the same time, while the first
+    /// call to its initializer sleeps and then panics. Returns how many of
+    /// them panicked.
+    fn panic_while_others_wait(lazy: &LazyLock<u32, impl Fn() -> u32 + Sync>) -> usize {
+        thread::scope(|s| {
+            let threads: Vec<_> = (0..4).map(|_| s.spawn(|| **lazy)).collect();
+            let results: Vec<_> = threads.into_iter().map(|t| t.join()).collect();
+            for value in results.iter().flatten() {
+                assert_eq!(*value, 1);
+            }
+            results.iter().filter(|r| r.is_err()).count()
+        })
+    }
+
+    fn init_panicking_first(calls: &AtomicUsize) -> impl Fn() -> u32 + Sync + '_ {
+        move || {
+            thread::sleep(Duration::from_millis(10));
+            if calls.fetch_add(1, Relaxed) == 0 {
+                panic!("first call");
+            }
+            1
+        }
+    }
+
+    #[test]
+    fn test_lazy_poisons_everyone() {
+        let calls = AtomicUsize::new(0);
+        let lazy = LazyLock::new(init_panicking_first(&calls));
+        assert_eq!(panic_while_others_wait(&lazy), 4);
+        assert!(LazyLock::is_poisoned(&lazy));
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| *lazy)).is_err());
+        assert_eq!(calls.load(Relaxed), 1);
+        assert_eq!(format!("{lazy:?}"), "LazyLock(<poisoned>)");
+    }
+
+    #[test]
+    fn test_lazy_retries() {
+        let calls = AtomicUsize::new(0);
+        let lazy = LazyLock::with_policy(init_panicking_first(&calls), PanicPolicy::Retry);
+        assert_eq!(panic_while_others_wait(&lazy), 1);
+        assert!(!LazyLock::is_poisoned(&lazy));
+        assert_eq!(*lazy, 1);
+        assert_eq!(calls.load(Relaxed), 2);
+    }
+}
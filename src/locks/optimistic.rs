@@ -0,0 +1,336 @@
+//! A seqlock and a mutex in one: readers don't write to anything unless
+//! they have to, writers take the mutex.
+//!
+//! This is the optimistic lock that databases like LeanStore and Umbra put
+//! in their B-tree nodes. Writers change a copy of the value with the mutex
+//! locked, and store it back when they unlock. A version number is odd
+//! while they do that, and even otherwise. An optimistic read loads the
+//! version, copies the value, and checks that the version is still the
+//! same, even one. If it isn't, a writer got in the way, and the copy is
+//! thrown away and tried again. After [`OPTIMISTIC_RETRIES`] tries, or
+//! right away if a writer is storing, the reader locks the mutex like a
+//! writer would and reads the value there, so readers can't be starved by
+//! a steady stream of writers.
+//!
+//! The copy races with writers, so it's done with atomic loads, a word at a
+//! time if `T` is aligned for that and a byte at a time otherwise, and
+//! writers store the value the same way when they unlock. That only works
+//! for [`Plain`] types, without padding bytes, which would be uninitialized.
+//! Torn copies are never handed out, so any bit pattern is fine.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem::{align_of, size_of, MaybeUninit};
+use core::ops::{Deref, DerefMut};
+
+use crate::locks::raw::{RawLock, RawMutex};
+use crate::sync_shim::plain_atomic::Ordering::{Acquire, Relaxed, Release};
+use crate::sync_shim::plain_atomic::{fence, AtomicU32, AtomicU8, AtomicUsize};
+
+/// How many optimistic reads are tried before locking the mutex.
+pub const OPTIMISTIC_RETRIES: u32 = 4;
+
+/// A type that can be copied a byte or word at a time.
+///
+/// # Safety
+///
+/// The type can't have padding bytes, and has to be `Copy`, so copying it
+/// bytewise is as good as moving it.
+pub unsafe trait Plain: Copy {}
+
+unsafe impl Plain for u8 {}
+unsafe impl Plain for u16 {}
+unsafe impl Plain for u32 {}
+unsafe impl Plain for u64 {}
+unsafe impl Plain for u128 {}
+unsafe impl Plain for usize {}
+unsafe impl Plain for i8 {}
+unsafe impl Plain for i16 {}
+unsafe impl Plain for i32 {}
+unsafe impl Plain for i64 {}
+unsafe impl Plain for i128 {}
+unsafe impl Plain for isize {}
+unsafe impl Plain for f32 {}
+unsafe impl Plain for f64 {}
+unsafe impl Plain for bool {}
+unsafe impl Plain for char {}
+unsafe impl<T: Plain, const N: usize> Plain for [T; N] {}
+
+pub struct OptimisticLock<T: Plain> {
+    /// Odd while a writer stores its changes, even otherwise.
+    version: AtomicU32,
+    mutex: RawMutex,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Plain + Send> Sync for OptimisticLock<T> {}
+
+/// Changes a copy of the value, which is stored back when it's dropped.
+pub struct WriteGuard<'a, T: Plain> {
+    lock: &'a OptimisticLock<T>,
+    value: T,
+}
+
+/// Whether `T` can be copied a word at a time.
+const fn by_word<T>() -> bool {
+    align_of::<T>() >= align_of::<usize>() && size_of::<T>().is_multiple_of(size_of::<usize>())
+}
+
+/// Copies `*src` with atomic loads.
+///
+/// # Safety
+///
+/// `src` has to be valid for reads, and only ever be written to with
+/// `atomic_store`, while this might run.
+unsafe fn atomic_load<T: Plain>(src: *const T) -> MaybeUninit<T> {
+    let mut dst = MaybeUninit::<T>::uninit();
+    if by_word::<T>() {
+        let src = src.cast::<usize>().cast_mut();
+        let dst = dst.as_mut_ptr().cast::<usize>();
+        for i in 0..size_of::<T>() / size_of::<usize>() {
+            *dst.add(i) = AtomicUsize::from_ptr(src.add(i)).load(Relaxed);
+        }
+    } else {
+        let src = src.cast::<u8>().cast_mut();
+        let dst = dst.as_mut_ptr().cast::<u8>();
+        for i in 0..size_of::<T>() {
+            *dst.add(i) = AtomicU8::from_ptr(src.add(i)).load(Relaxed);
+        }
+    }
+    dst
+}
+
+/// Stores `value` into `*dst` with atomic stores.
+///
+/// # Safety
+///
+/// `dst` has to be valid for writes, and nothing else may write to it
+/// while this runs.
+unsafe fn atomic_store<T: Plain>(dst: *mut T, value: &T) {
+    if by_word::<T>() {
+        let src = core::ptr::from_ref(value).cast::<usize>();
+        let dst = dst.cast::<usize>();
+        for i in 0..size_of::<T>() / size_of::<usize>() {
+            AtomicUsize::from_ptr(dst.add(i)).store(*src.add(i), Relaxed);
+        }
+    } else {
+        let src = core::ptr::from_ref(value).cast::<u8>();
+        let dst = dst.cast::<u8>();
+        for i in 0..size_of::<T>() {
+            AtomicU8::from_ptr(dst.add(i)).store(*src.add(i), Relaxed);
+        }
+    }
+}
+
+impl<T: Plain> OptimisticLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            version: AtomicU32::new(0),
+            mutex: RawMutex::INIT,
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Calls `f` with a consistent copy of the value, taken without
+    /// locking if possible, and with the mutex locked otherwise.
+    pub fn optimistic_read<U>(&self, f: impl FnOnce(&T) -> U) -> U {
+        match self.try_optimistic_read() {
+            Some(value) => f(&value),
+            None => {
+                self.mutex.lock();
+                // Safety: Writers only write while holding the mutex.
+                let value = unsafe { *self.value.get() };
+                // Safety: We just locked it.
+                unsafe { self.mutex.unlock() };
+                f(&value)
+            }
+        }
+    }
+
+    /// A consistent copy of the value, unless `OPTIMISTIC_RETRIES`
+    /// optimistic reads all ran into a writer.
+    pub fn try_optimistic_read(&self) -> Option<T> {
+        for _ in 0..OPTIMISTIC_RETRIES {
+            let before = self.version.load(Acquire);
+            if before & 1 == 1 {
+                return None;
+            }
+            // Safety: Writers only write with `atomic_store`.
+            let copy = unsafe { atomic_load(self.value.get()) };
+            // Keeps the copy from moving below the second load, like the
+            // Acquire load keeps it from moving above the first.
+            fence(Acquire);
+            if self.version.load(Relaxed) == before {
+                // Safety: No writer was in while we copied it.
+                return Some(unsafe { copy.assume_init() });
+            }
+            core::hint::spin_loop();
+        }
+        None
+    }
+
+    pub fn write(&self) -> WriteGuard<'_, T> {
+        self.mutex.lock();
+        self.write_guard()
+    }
+
+    pub fn try_write(&self) -> Option<WriteGuard<'_, T>> {
+        self.mutex.try_lock().then(|| self.write_guard())
+    }
+
+    /// Only call this right after locking the mutex.
+    fn write_guard(&self) -> WriteGuard<'_, T> {
+        WriteGuard {
+            lock: self,
+            // Safety: Only we write to it now.
+            value: unsafe { *self.value.get() },
+        }
+    }
+
+    /// How many writers have stored their changes, times two, plus one if
+    /// one is doing that right now.
+    pub fn version(&self) -> u32 {
+        self.version.load(Relaxed)
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: Plain> Deref for WriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Plain> DerefMut for WriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T: Plain> Drop for WriteGuard<'_, T> {
+    fn drop(&mut self) {
+        let lock = self.lock;
+        // Only odd for as long as the store takes, so that readers only
+        // give up when there really is something to wait for.
+        lock.version.fetch_add(1, Relaxed);
+        // Keeps the stores below from moving above the odd version.
+        fence(Release);
+        // Safety: We hold the mutex, and write with `atomic_store`.
+        unsafe { atomic_store(lock.value.get(), &self.value) };
+        lock.version.fetch_add(1, Release);
+        // Safety: We hold the mutex.
+        unsafe { lock.mutex.unlock() };
+    }
+}
+
+impl<T: Plain + Default> Default for OptimisticLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// Doesn't block: shows `<locked>` if a writer kept it busy.
+impl<T: Plain + fmt::Debug> fmt::Debug for OptimisticLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("OptimisticLock");
+        match self.try_optimistic_read() {
+            Some(value) => d.field("data", &value),
+            None => d.field("data", &format_args!("<locked>")),
+        };
+        d.field("version", &self.version()).finish()
+    }
+}
+
+impl<T: Plain + fmt::Debug> fmt::Debug for WriteGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::thread;
+
+    use crate::locks::optimistic::{OptimisticLock, Plain};
+    use crate::platform::parking::parked;
+
+    fn readers_see_consistent_copies<T: Plain + Send>(
+        next: impl Fn(&mut T) + Sync,
+        consistent: impl Fn(&T) -> bool + Sync,
+        initial: T,
+    ) {
+        const N: u32 = if cfg!(miri) { 50 } else { 10_000 };
+        let lock = OptimisticLock::new(initial);
+        thread::scope(|s| {
+            s.spawn(|| {
+                for _ in 0..N {
+                    next(&mut lock.write());
+                }
+            });
+            for _ in 0..2 {
+                s.spawn(|| {
+                    for _ in 0..N {
+                        assert!(lock.optimistic_read(&consistent));
+                    }
+                });
+            }
+        });
+        assert_eq!(lock.version(), 2 * N);
+    }
+
+    #[test]
+    fn test_readers_see_consistent_copies() {
+        // Copied a word at a time.
+        readers_see_consistent_copies(
+            |v: &mut [u64; 4]| *v = [v[0] + 1; 4],
+            |v| v.iter().all(|&x| x == v[0]),
+            [0; 4],
+        );
+        // And a byte at a time.
+        readers_see_consistent_copies(
+            |v: &mut [u8; 7]| *v = [v[0].wrapping_add(1); 7],
+            |v| v.iter().all(|&x| x == v[0]),
+            [0; 7],
+        );
+    }
+
+    #[test]
+    fn test_readers_fall_back_to_the_mutex() {
+        let lock = OptimisticLock::new([1u32, 2]);
+        let mut w = lock.write();
+        w[0] = 3;
+        // Readers see the old value until the guard is dropped.
+        assert_eq!(lock.try_optimistic_read(), Some([1, 2]));
+        assert!(lock.try_write().is_none());
+        // As if the guard was halfway through storing it.
+        lock.version.fetch_add(1, Relaxed);
+        assert_eq!(lock.try_optimistic_read(), None);
+        assert_eq!(
+            format!("{lock:?}"),
+            "OptimisticLock { data: <locked>, version: 1 }"
+        );
+        thread::scope(|s| {
+            let reader = s.spawn(|| lock.optimistic_read(|v| *v));
+            while parked(lock.mutex.addr(), |n| n) == 0 {
+                thread::yield_now();
+            }
+            lock.version.fetch_sub(1, Relaxed);
+            drop(w);
+            assert_eq!(reader.join().unwrap(), [3, 2]);
+        });
+        assert_eq!(lock.version(), 2);
+        assert_eq!(
+            format!("{lock:?}"),
+            "OptimisticLock { data: [3, 2], version: 2 }"
+        );
+    }
+}
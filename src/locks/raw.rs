@@ -0,0 +1,846 @@
+use crate::platform::backoff::{spin_while_eq, Backoff};
+#[cfg(feature = "std")]
+use crate::platform::parking::park_until;
+use crate::platform::parking::{park, parked, unpark_one};
+use crate::platform::wait::{wait, wake_all, wake_one};
+#[cfg(feature = "std")]
+use crate::platform::wait::{wait_interruptible, wait_until, Interrupt};
+use crate::sync_shim::const_atomic;
+use crate::sync_shim::plain_atomic::Ordering::{Acquire, Relaxed, Release};
+use crate::sync_shim::plain_atomic::{AtomicBool, AtomicU32, AtomicU8};
+
+/// The locking part of a mutex, without the data it protects.
+///
+/// Implement this to plug a new lock algorithm into `Lock<T, R>`.
+///
+/// # Safety
+///
+/// Between a successful `lock` or `try_lock` and the matching `unlock`, no
+/// other thread may lock it, and `unlock` must synchronize with the next
+/// lock (Release/Acquire).
+pub unsafe trait RawLock {
+    /// An unlocked lock, usable to initialize statics.
+    const INIT: Self;
+
+    fn lock(&self);
+
+    fn try_lock(&self) -> bool;
+
+    /// # Safety
+    ///
+    /// Only call this while holding the lock.
+    unsafe fn unlock(&self);
+
+    /// Keeps trying to lock until `deadline`. The default retries
+    /// `try_lock`, which is fine for spin locks, so locks that can sleep
+    /// should override it.
+    #[cfg(feature = "std")]
+    fn try_lock_until(&self, deadline: std::time::Instant) -> bool {
+        retry_until(deadline, || self.try_lock())
+    }
+
+    /// Like `try_lock_until`, but also gives up once `interrupt` is raised.
+    /// The default retries `try_lock`, checking it in between.
+    #[cfg(feature = "std")]
+    fn try_lock_until_interrupted(
+        &self,
+        deadline: std::time::Instant,
+        interrupt: &Interrupt,
+    ) -> bool {
+        retry_until_interrupted(deadline, interrupt, || self.try_lock())
+    }
+}
+
+/// The locking part of a reader-writer lock, without the data it protects.
+///
+/// # Safety
+///
+/// While write-locked, it must not be locked in any other way, and while
+/// read-locked, it must not be write-locked. Unlocking must synchronize
+/// with the next lock.
+pub unsafe trait RawRwLock {
+    const INIT: Self;
+
+    fn read(&self);
+
+    fn try_read(&self) -> bool;
+
+    /// # Safety
+    ///
+    /// Only call this while holding a read lock.
+    unsafe fn read_unlock(&self);
+
+    fn write(&self);
+
+    fn try_write(&self) -> bool;
+
+    /// # Safety
+    ///
+    /// Only call this while holding the write lock.
+    unsafe fn write_unlock(&self);
+
+    /// Turns a read lock into the write lock, if it's the only read lock.
+    /// The default never does.
+    ///
+    /// # Safety
+    ///
+    /// Only call this while holding a read lock. If it returns true, that's
+    /// the write lock now.
+    unsafe fn try_upgrade(&self) -> bool {
+        false
+    }
+
+    /// Keeps trying to read-lock until `deadline`. The default retries
+    /// `try_read`, so locks that can sleep should override it.
+    #[cfg(feature = "std")]
+    fn try_read_until(&self, deadline: std::time::Instant) -> bool {
+        retry_until(deadline, || self.try_read())
+    }
+
+    #[cfg(feature = "std")]
+    fn try_write_until(&self, deadline: std::time::Instant) -> bool {
+        retry_until(deadline, || self.try_write())
+    }
+
+    /// Like `try_read_until`, but also gives up once `interrupt` is raised.
+    /// The default retries `try_read`, checking it in between.
+    #[cfg(feature = "std")]
+    fn try_read_until_interrupted(
+        &self,
+        deadline: std::time::Instant,
+        interrupt: &Interrupt,
+    ) -> bool {
+        retry_until_interrupted(deadline, interrupt, || self.try_read())
+    }
+
+    #[cfg(feature = "std")]
+    fn try_write_until_interrupted(
+        &self,
+        deadline: std::time::Instant,
+        interrupt: &Interrupt,
+    ) -> bool {
+        retry_until_interrupted(deadline, interrupt, || self.try_write())
+    }
+
+    /// # Safety
+    ///
+    /// Same as for `try_upgrade`.
+    #[cfg(feature = "std")]
+    unsafe fn try_upgrade_until(&self, deadline: std::time::Instant) -> bool {
+        // Safety: The caller holds a read lock.
+        retry_until(deadline, || unsafe { self.try_upgrade() })
+    }
+}
+
+/// Retries `f` with backoff, and then yielding, until it succeeds or it's
+/// `deadline`.
+#[cfg(feature = "std")]
+fn retry_until(deadline: std::time::Instant, mut f: impl FnMut() -> bool) -> bool {
+    let mut backoff = Backoff::new();
+    loop {
+        if f() {
+            return true;
+        }
+        if std::time::Instant::now() >= deadline {
+            return false;
+        }
+        if backoff.is_completed() {
+            std::thread::yield_now();
+        } else {
+            backoff.spin();
+        }
+    }
+}
+
+/// Like `retry_until`, but also stops once `interrupt` is raised.
+#[cfg(feature = "std")]
+fn retry_until_interrupted(
+    deadline: std::time::Instant,
+    interrupt: &Interrupt,
+    mut f: impl FnMut() -> bool,
+) -> bool {
+    let mut done = false;
+    retry_until(deadline, || {
+        done = f();
+        done || interrupt.is_raised()
+    });
+    done
+}
+
+/// The lock from chapter 4, as a `RawLock`.
+#[derive(Debug)]
+pub struct RawSpinLock {
+    locked: AtomicBool,
+}
+
+unsafe impl RawLock for RawSpinLock {
+    const INIT: Self = Self {
+        locked: AtomicBool::new(false),
+    };
+
+    fn lock(&self) {
+        while self.locked.swap(true, Acquire) {
+            spin_while_eq(&self.locked, true);
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        !self.locked.swap(true, Acquire)
+    }
+
+    unsafe fn unlock(&self) {
+        // Not the locking thread, necessarily: the cohort lock hands its
+        // global lock over.
+        debug_assert!(self.locked.load(Relaxed), "unlocking an unlocked lock");
+        self.locked.store(false, Release);
+    }
+}
+
+/// The mutex from chapter 9, which sleeps through the `platform::wait`
+/// backend instead of spinning.
+///
+/// The state is 0 when unlocked, 1 when locked, and 2 when locked with
+/// (possibly) other threads waiting, so unlocking an uncontended mutex
+/// doesn't need a wake call.
+#[derive(Debug)]
+pub struct RawFutexMutex {
+    state: AtomicU32,
+}
+
+unsafe impl RawLock for RawFutexMutex {
+    const INIT: Self = Self {
+        state: AtomicU32::new(0),
+    };
+
+    fn lock(&self) {
+        if self.state.compare_exchange(0, 1, Acquire, Relaxed).is_err() {
+            lock_contended(&self.state);
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        self.state.compare_exchange(0, 1, Acquire, Relaxed).is_ok()
+    }
+
+    unsafe fn unlock(&self) {
+        let s = self.state.swap(0, Release);
+        debug_assert!(s != 0, "unlocking an unlocked mutex");
+        if s == 2 {
+            wake_one(&self.state);
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn try_lock_until(&self, deadline: std::time::Instant) -> bool {
+        self.lock_until(deadline, None)
+    }
+
+    #[cfg(feature = "std")]
+    fn try_lock_until_interrupted(
+        &self,
+        deadline: std::time::Instant,
+        interrupt: &Interrupt,
+    ) -> bool {
+        self.lock_until(deadline, Some(interrupt))
+    }
+}
+
+impl RawFutexMutex {
+    /// `try_lock_until`, which also gives up once `interrupt` is raised if
+    /// there is one. Like `lock_contended`, but checking before each wait.
+    /// Giving up leaves the state at 2, which only costs the next unlock a
+    /// wake call.
+    #[cfg(feature = "std")]
+    fn lock_until(&self, deadline: std::time::Instant, interrupt: Option<&Interrupt>) -> bool {
+        if self.try_lock() {
+            return true;
+        }
+        while self.state.swap(2, Acquire) != 0 {
+            if gave_up(deadline, interrupt) {
+                return false;
+            }
+            sleep_until(&self.state, 2, deadline, interrupt);
+        }
+        true
+    }
+}
+
+#[cold]
+fn lock_contended(state: &AtomicU32) {
+    // Spin a little first, in case the lock is released soon, but not when
+    // there are waiters already, since then we'd likely wait anyway.
+    let mut spin_count = 0;
+    while state.load(Relaxed) == 1 && spin_count < 100 {
+        spin_count += 1;
+        core::hint::spin_loop();
+    }
+    if state.compare_exchange(0, 1, Acquire, Relaxed).is_ok() {
+        return;
+    }
+    while state.swap(2, Acquire) != 0 {
+        wait(state, 2);
+    }
+}
+
+const LOCKED: u8 = 1;
+/// There might be threads parked on the lock's address.
+const PARKED: u8 = 2;
+
+/// A sleeping mutex that takes up a single byte, so `Mutex<u8>` is only two.
+///
+/// Instead of the lock waiting on its own (word-sized) state like
+/// `RawFutexMutex`, contended threads park in the global table of
+/// `platform::parking`, and the lock only remembers whether anyone might be
+/// parked there. Unlocking doesn't hand the lock over: the woken thread
+/// competes for it like any other.
+#[derive(Debug)]
+pub struct RawMutex {
+    state: AtomicU8,
+}
+
+unsafe impl RawLock for RawMutex {
+    const INIT: Self = Self {
+        state: AtomicU8::new(0),
+    };
+
+    fn lock(&self) {
+        if self
+            .state
+            .compare_exchange(0, LOCKED, Acquire, Relaxed)
+            .is_err()
+        {
+            self.lock_slow();
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        let mut s = self.state.load(Relaxed);
+        let mut backoff = Backoff::new();
+        while s & LOCKED == 0 {
+            match self
+                .state
+                .compare_exchange_weak(s, s | LOCKED, Acquire, Relaxed)
+            {
+                Ok(_) => return true,
+                Err(e) => s = e,
+            }
+            backoff.spin();
+        }
+        false
+    }
+
+    unsafe fn unlock(&self) {
+        if self
+            .state
+            .compare_exchange(LOCKED, 0, Release, Relaxed)
+            .is_err()
+        {
+            self.unlock_slow();
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn try_lock_until(&self, deadline: std::time::Instant) -> bool {
+        self.try_lock() || self.lock_slow_until(deadline, None)
+    }
+
+    #[cfg(feature = "std")]
+    fn try_lock_until_interrupted(
+        &self,
+        deadline: std::time::Instant,
+        interrupt: &Interrupt,
+    ) -> bool {
+        self.try_lock() || self.lock_slow_until(deadline, Some(interrupt))
+    }
+}
+
+impl RawMutex {
+    pub(crate) fn addr(&self) -> usize {
+        core::ptr::from_ref(self).addr()
+    }
+
+    /// Marks it as having threads parked on it, if it's locked, so the
+    /// unlock will unpark them. For `Condvar::notify_all`, to call with the
+    /// bucket locked, right before it moves waiters over.
+    pub(crate) fn mark_parked(&self) -> bool {
+        self.state
+            .fetch_update(Relaxed, Relaxed, |s| {
+                (s & LOCKED != 0).then_some(s | PARKED)
+            })
+            .is_ok()
+    }
+
+    /// Panics if threads are parked on it without the state saying so,
+    /// which leaves them asleep until somebody else happens to contend, or
+    /// if the state isn't one it can be in. The waiters are counted with
+    /// their bucket locked, which every change to `PARKED` that matters
+    /// happens under too.
+    pub fn debug_validate(&self) {
+        parked(self.addr(), |n| {
+            let s = self.state.load(Relaxed);
+            assert!(s & !(LOCKED | PARKED) == 0, "unknown state {s:#x}");
+            assert!(
+                n == 0 || s & PARKED != 0,
+                "{n} threads parked, but the state is {s:#x}"
+            );
+        });
+    }
+
+    #[cold]
+    fn lock_slow(&self) {
+        let mut spin_count = 0;
+        let mut backoff = Backoff::new();
+        let mut s = self.state.load(Relaxed);
+        loop {
+            if s & LOCKED == 0 {
+                match self
+                    .state
+                    .compare_exchange_weak(s, s | LOCKED, Acquire, Relaxed)
+                {
+                    Ok(_) => return,
+                    Err(e) => s = e,
+                }
+                backoff.spin();
+                continue;
+            }
+            // Spin a little first, unless others are parked already.
+            if s & PARKED == 0 {
+                if spin_count < 100 {
+                    spin_count += 1;
+                    core::hint::spin_loop();
+                    s = self.state.load(Relaxed);
+                    continue;
+                }
+                if let Err(e) = self
+                    .state
+                    .compare_exchange_weak(s, s | PARKED, Relaxed, Relaxed)
+                {
+                    s = e;
+                    backoff.spin();
+                    continue;
+                }
+            }
+            // The unlocking thread clears LOCKED with the bucket locked, so
+            // it either sees us in the queue, or we see it unlocked here.
+            park(self.addr(), || self.state.load(Relaxed) == LOCKED | PARKED);
+            spin_count = 0;
+            s = self.state.load(Relaxed);
+        }
+    }
+
+    /// `lock_slow`, but giving up at `deadline`, or once `interrupt` is
+    /// raised if there is one. A woken thread always
+    /// tries once more before it looks at the clock: the unlock that woke
+    /// it only woke one thread, so if it left without the lock while it's
+    /// free, the others could sleep on. If someone else has it, their
+    /// unlock wakes the next one.
+    ///
+    /// A thread that gives up while parked takes itself off the queue, but
+    /// leaves `PARKED` set, which only costs the next unlock a look at it.
+    #[cfg(feature = "std")]
+    #[cold]
+    fn lock_slow_until(&self, deadline: std::time::Instant, interrupt: Option<&Interrupt>) -> bool {
+        let mut backoff = Backoff::new();
+        let mut s = self.state.load(Relaxed);
+        loop {
+            if s & LOCKED == 0 {
+                match self
+                    .state
+                    .compare_exchange_weak(s, s | LOCKED, Acquire, Relaxed)
+                {
+                    Ok(_) => return true,
+                    Err(e) => s = e,
+                }
+                backoff.spin();
+                continue;
+            }
+            if gave_up(deadline, interrupt) {
+                return false;
+            }
+            if s & PARKED == 0 {
+                if let Err(e) = self
+                    .state
+                    .compare_exchange_weak(s, s | PARKED, Relaxed, Relaxed)
+                {
+                    s = e;
+                    backoff.spin();
+                    continue;
+                }
+            }
+            park_until(
+                self.addr(),
+                || self.state.load(Relaxed) == LOCKED | PARKED,
+                deadline,
+                interrupt,
+            );
+            s = self.state.load(Relaxed);
+        }
+    }
+
+    #[cold]
+    fn unlock_slow(&self) {
+        // It wasn't just LOCKED, so it had better be LOCKED | PARKED.
+        debug_assert!(
+            self.state.load(Relaxed) & LOCKED != 0,
+            "unlocking an unlocked mutex"
+        );
+        unpark_one(self.addr(), |more_parked| {
+            let s = if more_parked { PARKED } else { 0 };
+            self.state.store(s, Release);
+        });
+    }
+}
+
+/// A spinning reader-writer lock.
+///
+/// The state is the number of readers, or `u32::MAX` if write-locked.
+/// Readers can starve a writer, which is fine for short critical sections.
+#[derive(Debug)]
+pub struct RawSpinRwLock {
+    state: const_atomic::AtomicU32,
+}
+
+const WRITE_LOCKED: u32 = u32::MAX;
+
+impl RawSpinRwLock {
+    /// For elision, which only has to check that there's no writer.
+    #[cfg(all(feature = "lock_elision", target_arch = "x86_64"))]
+    pub(crate) fn is_write_locked(&self) -> bool {
+        self.state.load(Relaxed) == WRITE_LOCKED
+    }
+}
+
+unsafe impl RawRwLock for RawSpinRwLock {
+    const INIT: Self = Self {
+        state: const_atomic::AtomicU32::new(0),
+    };
+
+    fn read(&self) {
+        let mut backoff = Backoff::new();
+        while !self.try_read() {
+            backoff.spin();
+        }
+    }
+
+    fn try_read(&self) -> bool {
+        let mut s = self.state.load(Relaxed);
+        // Readers only fail their CAS because of other readers, so they'd
+        // all retry at once.
+        let mut backoff = Backoff::new();
+        loop {
+            if s == WRITE_LOCKED {
+                return false;
+            }
+            assert!(s != WRITE_LOCKED - 1, "too many readers");
+            match self.state.compare_exchange_weak(s, s + 1, Acquire, Relaxed) {
+                Ok(_) => return true,
+                Err(e) => s = e,
+            }
+            backoff.spin();
+        }
+    }
+
+    unsafe fn read_unlock(&self) {
+        let s = self.state.fetch_sub(1, Release);
+        debug_assert!(
+            s != 0 && s != WRITE_LOCKED,
+            "read-unlocking a lock that isn't read-locked"
+        );
+    }
+
+    fn write(&self) {
+        let mut backoff = Backoff::new();
+        while !self.try_write() {
+            backoff.spin();
+        }
+    }
+
+    fn try_write(&self) -> bool {
+        self.state
+            .compare_exchange(0, WRITE_LOCKED, Acquire, Relaxed)
+            .is_ok()
+    }
+
+    unsafe fn write_unlock(&self) {
+        debug_assert!(
+            self.state.load(Relaxed) == WRITE_LOCKED,
+            "write-unlocking a lock that isn't write-locked"
+        );
+        self.state.store(0, Release);
+    }
+
+    unsafe fn try_upgrade(&self) -> bool {
+        self.state
+            .compare_exchange(1, WRITE_LOCKED, Acquire, Relaxed)
+            .is_ok()
+    }
+}
+
+/// The reader-writer lock from chapter 9, which sleeps through the
+/// `platform::wait` backend.
+///
+/// The state is twice the number of readers, plus one if a writer is
+/// waiting, or `u32::MAX` if write-locked. New readers wait while the
+/// state is odd, so writers aren't starved. Writers wait on a separate
+/// counter, which is bumped whenever one of them might get the lock, so
+/// waking a writer doesn't wake all the readers too.
+#[derive(Debug)]
+pub struct RawFutexRwLock {
+    state: AtomicU32,
+    writer_wake_counter: AtomicU32,
+}
+
+impl RawFutexRwLock {
+    fn wake_writer(&self) {
+        self.writer_wake_counter.fetch_add(1, Release);
+        wake_one(&self.writer_wake_counter);
+    }
+
+    /// `try_read_until`, which also gives up once `interrupt` is raised if
+    /// there is one. Same for `write_until`.
+    #[cfg(feature = "std")]
+    fn read_until(&self, deadline: std::time::Instant, interrupt: Option<&Interrupt>) -> bool {
+        loop {
+            if self.try_read() {
+                return true;
+            }
+            if gave_up(deadline, interrupt) {
+                return false;
+            }
+            let s = self.state.load(Relaxed);
+            if s & 1 == 1 {
+                sleep_until(&self.state, s, deadline, interrupt);
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn write_until(&self, deadline: std::time::Instant, interrupt: Option<&Interrupt>) -> bool {
+        let mut s = self.state.load(Relaxed);
+        loop {
+            if s <= 1 {
+                match self
+                    .state
+                    .compare_exchange(s, WRITE_LOCKED, Acquire, Relaxed)
+                {
+                    Ok(_) => return true,
+                    Err(e) => {
+                        s = e;
+                        continue;
+                    }
+                }
+            }
+            if gave_up(deadline, interrupt) {
+                self.stop_waiting();
+                return false;
+            }
+            if s & 1 == 0 {
+                if let Err(e) = self.state.compare_exchange(s, s + 1, Relaxed, Relaxed) {
+                    s = e;
+                    continue;
+                }
+            }
+            let w = self.writer_wake_counter.load(Acquire);
+            s = self.state.load(Relaxed);
+            if s >= 2 {
+                sleep_until(&self.writer_wake_counter, w, deadline, interrupt);
+                s = self.state.load(Relaxed);
+            }
+        }
+    }
+
+    /// Clears the waiting bit for a writer that gives up, since readers
+    /// would wait for it forever otherwise. It might have been another
+    /// writer's, so one of those is woken to set it again.
+    #[cfg(feature = "std")]
+    fn stop_waiting(&self) {
+        let cleared = self.state.fetch_update(Relaxed, Relaxed, |s| {
+            (s != WRITE_LOCKED && s & 1 == 1).then_some(s - 1)
+        });
+        if cleared.is_ok() {
+            self.wake_writer();
+            wake_all(&self.state);
+        }
+    }
+}
+
+/// Whether a timed wait should stop trying.
+#[cfg(feature = "std")]
+fn gave_up(deadline: std::time::Instant, interrupt: Option<&Interrupt>) -> bool {
+    interrupt.is_some_and(Interrupt::is_raised) || std::time::Instant::now() >= deadline
+}
+
+#[cfg(feature = "std")]
+fn sleep_until(
+    a: &AtomicU32,
+    expected: u32,
+    deadline: std::time::Instant,
+    interrupt: Option<&Interrupt>,
+) {
+    match interrupt {
+        Some(interrupt) => {
+            wait_interruptible(a, expected, Some(deadline), interrupt);
+        }
+        None => wait_until(a, expected, deadline),
+    }
+}
+
+unsafe impl RawRwLock for RawFutexRwLock {
+    const INIT: Self = Self {
+        state: AtomicU32::new(0),
+        writer_wake_counter: AtomicU32::new(0),
+    };
+
+    fn read(&self) {
+        let mut s = self.state.load(Relaxed);
+        loop {
+            if s & 1 == 0 {
+                assert!(s < WRITE_LOCKED - 2, "too many readers");
+                match self.state.compare_exchange_weak(s, s + 2, Acquire, Relaxed) {
+                    Ok(_) => return,
+                    Err(e) => s = e,
+                }
+            }
+            if s & 1 == 1 {
+                wait(&self.state, s);
+                s = self.state.load(Relaxed);
+            }
+        }
+    }
+
+    fn try_read(&self) -> bool {
+        let mut s = self.state.load(Relaxed);
+        while s & 1 == 0 {
+            assert!(s < WRITE_LOCKED - 2, "too many readers");
+            match self.state.compare_exchange_weak(s, s + 2, Acquire, Relaxed) {
+                Ok(_) => return true,
+                Err(e) => s = e,
+            }
+        }
+        false
+    }
+
+    unsafe fn read_unlock(&self) {
+        let s = self.state.fetch_sub(2, Release);
+        debug_assert!(
+            s >= 2 && s != WRITE_LOCKED,
+            "read-unlocking a lock that isn't read-locked"
+        );
+        if s == 3 {
+            self.wake_writer();
+        }
+    }
+
+    fn write(&self) {
+        let mut s = self.state.load(Relaxed);
+        loop {
+            if s <= 1 {
+                match self
+                    .state
+                    .compare_exchange(s, WRITE_LOCKED, Acquire, Relaxed)
+                {
+                    Ok(_) => return,
+                    Err(e) => {
+                        s = e;
+                        continue;
+                    }
+                }
+            }
+            if s & 1 == 0 {
+                if let Err(e) = self.state.compare_exchange(s, s + 1, Relaxed, Relaxed) {
+                    s = e;
+                    continue;
+                }
+            }
+            let w = self.writer_wake_counter.load(Acquire);
+            s = self.state.load(Relaxed);
+            if s >= 2 {
+                wait(&self.writer_wake_counter, w);
+                s = self.state.load(Relaxed);
+            }
+        }
+    }
+
+    fn try_write(&self) -> bool {
+        let mut s = self.state.load(Relaxed);
+        while s <= 1 {
+            match self
+                .state
+                .compare_exchange(s, WRITE_LOCKED, Acquire, Relaxed)
+            {
+                Ok(_) => return true,
+                Err(e) => s = e,
+            }
+        }
+        false
+    }
+
+    unsafe fn write_unlock(&self) {
+        let s = self.state.swap(0, Release);
+        debug_assert!(
+            s == WRITE_LOCKED,
+            "write-unlocking a lock that isn't write-locked"
+        );
+        self.wake_writer();
+        wake_all(&self.state);
+    }
+
+    unsafe fn try_upgrade(&self) -> bool {
+        // Just us, maybe with a writer waiting, which we go ahead of.
+        let mut s = self.state.load(Relaxed);
+        while s <= 3 {
+            match self
+                .state
+                .compare_exchange(s, WRITE_LOCKED, Acquire, Relaxed)
+            {
+                Ok(_) => return true,
+                Err(e) => s = e,
+            }
+        }
+        false
+    }
+
+    #[cfg(feature = "std")]
+    fn try_read_until(&self, deadline: std::time::Instant) -> bool {
+        self.read_until(deadline, None)
+    }
+
+    #[cfg(feature = "std")]
+    fn try_write_until(&self, deadline: std::time::Instant) -> bool {
+        self.write_until(deadline, None)
+    }
+
+    #[cfg(feature = "std")]
+    fn try_read_until_interrupted(
+        &self,
+        deadline: std::time::Instant,
+        interrupt: &Interrupt,
+    ) -> bool {
+        self.read_until(deadline, Some(interrupt))
+    }
+
+    #[cfg(feature = "std")]
+    fn try_write_until_interrupted(
+        &self,
+        deadline: std::time::Instant,
+        interrupt: &Interrupt,
+    ) -> bool {
+        self.write_until(deadline, Some(interrupt))
+    }
+
+    /// Sets the waiting bit so no new readers come in, but then polls for
+    /// the other readers to leave, since they only wake a writer when the
+    /// last of them does, and we're still one of them.
+    #[cfg(feature = "std")]
+    unsafe fn try_upgrade_until(&self, deadline: std::time::Instant) -> bool {
+        let _ = self
+            .state
+            .fetch_update(Relaxed, Relaxed, |s| (s & 1 == 0).then_some(s + 1));
+        // Safety: The caller holds a read lock.
+        if retry_until(deadline, || unsafe { self.try_upgrade() }) {
+            return true;
+        }
+        self.stop_waiting();
+        false
+    }
+}
@@ -0,0 +1,362 @@
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "std")]
+use crate::errors::LockTimeout;
+#[cfg(feature = "stats")]
+use crate::stats::{LockStats, Recorder};
+use crate::sync_shim::atomic::AtomicBool;
+use crate::sync_shim::atomic::Ordering::{Acquire, Release};
+#[cfg(debug_assertions)]
+use crate::sync_shim::plain_atomic::{AtomicUsize, Ordering::Relaxed};
+#[cfg(feature = "std")]
+use crate::sync_shim::spin_loop;
+use crate::sync_shim::{chaos_point, const_fn, spin_while_eq, trace_event};
+
+/// `Send` if `T` is, but only `Sync` if `T` is too:
+///
+/// ```compile_fail,E0277
+/// use std::cell::Cell;
+///
+/// use atomics_and_locks::SpinLockGuard;
+///
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<SpinLockGuard<'_, Cell<i32>>>();
+/// ```
+pub struct Guard<'a, T> {
+    lock: &'a SpinLock<T>,
+    #[cfg(feature = "stats")]
+    acquired: Instant,
+    /// Through `lock`, it would be `Sync` whenever the lock is, which only
+    /// takes `T: Send`: see the impls below.
+    not_sync: PhantomData<*const ()>,
+}
+
+// Safety: `Drop` unlocks from any thread, so it's `Send` like a `&mut T`.
+unsafe impl<T: Send> Send for Guard<'_, T> {}
+
+// Safety: A `&Guard` only gives a `&T`.
+unsafe impl<T: Sync> Sync for Guard<'_, T> {}
+
+impl<T> Deref for Guard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safety: The very existence of this Guard
+        // guarantees we've exclusively locked the lock.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for Guard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: The very existence of this Guard
+        // guarantees we've exclusively locked the lock.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for Guard<'_, T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "stats")]
+        self.lock.stats.released(self.acquired);
+        trace_event!(Unlock, core::ptr::from_ref(self.lock).addr(), Release);
+        // The guard may have been sent to another thread, which is fine.
+        #[cfg(debug_assertions)]
+        self.lock.owner.store(0, Relaxed);
+        self.lock.locked.store(false, Release);
+    }
+}
+
+pub struct SpinLock<T> {
+    locked: AtomicBool,
+    /// The thread that locked it, or 0, to catch `unlock` calls that
+    /// don't belong to a `lock`. Only in debug builds.
+    #[cfg(debug_assertions)]
+    owner: AtomicUsize,
+    #[cfg(feature = "stats")]
+    stats: Recorder,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for SpinLock<T> where T: Send {}
+
+impl<T> SpinLock<T> {
+    const_fn! {
+        pub fn new(value: T) -> Self {
+            Self {
+                locked: AtomicBool::new(false),
+                #[cfg(debug_assertions)]
+                owner: AtomicUsize::new(0),
+                #[cfg(feature = "stats")]
+                stats: Recorder::new(),
+                value: UnsafeCell::new(value),
+            }
+        }
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    pub fn lock(&self) -> Guard<'_, T> {
+        #[cfg(not(feature = "stats"))]
+        self.spin();
+        #[cfg(feature = "stats")]
+        self.stats
+            .lock(|| !self.locked.swap(true, Acquire), || self.spin());
+        chaos_point!("spin_lock::lock");
+        self.guard()
+    }
+
+    fn spin(&self) {
+        // Only retry once the lock looks free, so the waiting threads don't
+        // keep stealing the cache line from the one holding it.
+        while self.locked.swap(true, Acquire) {
+            spin_while_eq(&self.locked, true);
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<Guard<'_, T>> {
+        if self.locked.swap(true, Acquire) {
+            return None;
+        }
+        Some(self.guard())
+    }
+
+    /// Only call this right after locking.
+    fn guard(&self) -> Guard<'_, T> {
+        trace_event!(Lock, core::ptr::from_ref(self).addr(), Acquire);
+        #[cfg(debug_assertions)]
+        self.owner.store(current_thread(), Relaxed);
+        Guard {
+            lock: self,
+            #[cfg(feature = "stats")]
+            acquired: self.stats.acquired(),
+            not_sync: PhantomData,
+        }
+    }
+
+    /// A snapshot of the statistics since the lock was created or last reset.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> LockStats {
+        self.stats.snapshot()
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&self) {
+        self.stats.reset();
+    }
+
+    /// Spins for at most `timeout` before giving up.
+    #[cfg(feature = "std")]
+    pub fn try_lock_for(&self, timeout: Duration) -> Result<Guard<'_, T>, LockTimeout> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.try_lock_until(deadline),
+            None => Ok(self.lock()),
+        }
+    }
+
+    /// Spins until `deadline` at the latest before giving up.
+    #[cfg(feature = "std")]
+    pub fn try_lock_until(&self, deadline: Instant) -> Result<Guard<'_, T>, LockTimeout> {
+        let spin_until = || loop {
+            if !self.locked.swap(true, Acquire) {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            spin_loop();
+        };
+        #[cfg(not(feature = "stats"))]
+        let locked = spin_until();
+        #[cfg(feature = "stats")]
+        let locked = self
+            .stats
+            .lock_until(|| !self.locked.swap(true, Acquire), spin_until);
+        if locked {
+            Ok(self.guard())
+        } else {
+            Err(LockTimeout)
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The &mut T from lock() must be gone!
+    /// (And no cheating by keeping reference to fields of that T around!)
+    ///
+    /// In debug builds, this panics if the lock isn't locked, or was
+    /// locked by another thread.
+    pub unsafe fn unlock(&self) {
+        #[cfg(debug_assertions)]
+        {
+            match self.owner.load(Relaxed) {
+                0 => panic!("unlocking a SpinLock that isn't locked"),
+                owner => assert!(
+                    owner == current_thread(),
+                    "unlocking a SpinLock locked by another thread"
+                ),
+            }
+            self.owner.store(0, Relaxed);
+        }
+        trace_event!(Unlock, core::ptr::from_ref(self).addr(), Release);
+        self.locked.store(false, Release);
+    }
+}
+
+/// Different for every running thread. Without `std`, threads can't be told
+/// apart, so only unlocking an unlocked lock is caught.
+#[cfg(debug_assertions)]
+fn current_thread() -> usize {
+    #[cfg(feature = "std")]
+    {
+        std::thread_local! {
+            static ID: u8 = const { 0 };
+        }
+        ID.with(|id| core::ptr::from_ref(id).addr())
+    }
+    #[cfg(not(feature = "std"))]
+    1
+}
+
+impl<T: Default> Default for SpinLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// Doesn't spin: shows `<locked>` if the lock is taken.
+impl<T: fmt::Debug> fmt::Debug for SpinLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("SpinLock");
+        match self.try_lock() {
+            Some(guard) => d.field("data", &&*guard),
+            None => d.field("data", &format_args!("<locked>")),
+        };
+        d.finish()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Guard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    #[cfg(all(debug_assertions, feature = "std"))]
+    use std::panic::AssertUnwindSafe;
+    use std::thread;
+    #[cfg(feature = "std")]
+    use std::time::{Duration, Instant};
+
+    #[cfg(feature = "std")]
+    use crate::errors::LockTimeout;
+    use crate::locks::spin_lock::SpinLock;
+
+    #[test]
+    fn test_spin_lock() {
+        let x = SpinLock::new(Vec::new());
+        thread::scope(|s| {
+            s.spawn(|| x.lock().push(1));
+            s.spawn(|| {
+                let mut g = x.lock();
+                g.push(2);
+                g.push(2);
+            });
+        });
+        let g = x.lock();
+        assert!(g.as_slice() == [1, 2, 2] || g.as_slice() == [2, 2, 1]);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_try_lock_for_times_out() {
+        let x = SpinLock::new(());
+        let g = x.lock();
+        assert!(x.try_lock().is_none());
+        assert_eq!(
+            x.try_lock_for(Duration::from_millis(5)).err(),
+            Some(LockTimeout)
+        );
+        let deadline = Instant::now() + Duration::from_millis(5);
+        assert!(x.try_lock_until(deadline).is_err());
+        assert!(Instant::now() >= deadline);
+        drop(g);
+        assert!(x.try_lock_for(Duration::from_millis(5)).is_ok());
+    }
+
+    #[test]
+    fn test_debug_does_not_spin() {
+        let x = SpinLock::new(5);
+        assert_eq!(format!("{x:?}"), "SpinLock { data: 5 }");
+        let g = x.lock();
+        assert_eq!(format!("{x:?}"), "SpinLock { data: <locked> }");
+        assert_eq!(format!("{g:?}"), "5");
+    }
+
+    #[test]
+    fn test_static() {
+        static COUNTER: SpinLock<u32> = SpinLock::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| *COUNTER.lock() += 1);
+            }
+        });
+        assert_eq!(*COUNTER.lock(), 4);
+    }
+
+    #[test]
+    #[cfg(all(debug_assertions, feature = "std"))]
+    fn test_unlock_checks_the_owner() {
+        let x = SpinLock::new(());
+        core::mem::forget(x.lock());
+        let other = thread::scope(|s| s.spawn(|| unsafe { x.unlock() }).join());
+        let message = *other.unwrap_err().downcast::<&str>().unwrap();
+        assert_eq!(message, "unlocking a SpinLock locked by another thread");
+
+        unsafe { x.unlock() };
+        let again = std::panic::catch_unwind(AssertUnwindSafe(|| unsafe { x.unlock() }));
+        let message = *again.unwrap_err().downcast::<&str>().unwrap();
+        assert_eq!(message, "unlocking a SpinLock that isn't locked");
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::sync::Arc;
+
+    use crate::locks::spin_lock::SpinLock;
+    use crate::sync_shim::atomic::AtomicUsize;
+    use crate::sync_shim::atomic::Ordering::Relaxed;
+    use crate::sync_shim::thread;
+
+    #[test]
+    fn loom_lock_protects_data() {
+        crate::sync_shim::model(|| {
+            // Relaxed, so only the lock's orderings make the increments add
+            // up. `try_lock`, since loom never finishes exploring a spinning
+            // `lock`.
+            let shared = Arc::new((SpinLock::new(()), AtomicUsize::new(0)));
+            let threads: Vec<_> = (0..2)
+                .map(|_| {
+                    let shared = shared.clone();
+                    thread::spawn(move || {
+                        let Some(_guard) = shared.0.try_lock() else {
+                            return 0;
+                        };
+                        let n = shared.1.load(Relaxed);
+                        shared.1.store(n + 1, Relaxed);
+                        1
+                    })
+                })
+                .collect();
+            let locked: usize = threads.into_iter().map(|t| t.join().unwrap()).sum();
+            assert!(locked >= 1);
+            assert_eq!(shared.1.load(Relaxed), locked);
+        });
+    }
+}
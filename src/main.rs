@@ -1,8 +1,3 @@
-mod arc;
-mod channels;
-mod memory_ordering;
-mod spinning;
-
 fn main() {
     println!("Atomics and Locks! Oh My!");
 }
@@ -1,6 +1,7 @@
 #![allow(dead_code)]
 
 mod rel_acq {
+    use std::ptr::{addr_of, addr_of_mut};
     use std::sync::atomic::AtomicBool;
     use std::sync::atomic::Ordering::{Acquire, Release};
     use std::thread;
@@ -11,7 +12,7 @@ mod rel_acq {
     fn f() {
         if !LOCKED.swap(true, Acquire) {
             // Safety: We hold the exclusive lock, so nothing else is accessing DATA.
-            unsafe { DATA.push('!') };
+            unsafe { (*addr_of_mut!(DATA)).push('!') };
             LOCKED.store(false, Release);
         }
     }
@@ -22,7 +23,7 @@ mod rel_acq {
                 s.spawn(f);
             }
         });
-        unsafe { println!("{DATA}") }
+        unsafe { println!("{}", *addr_of!(DATA)) }
     }
 
     #[test]
@@ -32,20 +33,23 @@ mod rel_acq {
 }
 
 mod lazy_init {
+    use std::hash::{BuildHasher, RandomState};
     use std::sync::atomic::AtomicPtr;
     use std::sync::atomic::Ordering::{Acquire, Release};
+    #[cfg(test)]
     use std::thread;
+    #[cfg(test)]
     use std::thread::current;
 
-    use rand::RngCore;
-
     struct Data {
         id: u64,
     }
 
     fn generate_data() -> Data {
-        let mut rng = rand::thread_rng();
-        Data { id: rng.next_u64() }
+        // Randomly keyed, so that's a random id.
+        Data {
+            id: RandomState::new().hash_one(()),
+        }
     }
 
     fn get_data() -> &'static Data {
@@ -85,6 +89,7 @@ mod lazy_init {
 }
 
 mod seq_cst {
+    use std::ptr::{addr_of, addr_of_mut};
     use std::sync::atomic::AtomicBool;
     use std::sync::atomic::Ordering::SeqCst;
     use std::thread;
@@ -97,18 +102,18 @@ mod seq_cst {
         let a = thread::spawn(|| {
             A.store(true, SeqCst);
             if !B.load(SeqCst) {
-                unsafe { S.push('!') };
+                unsafe { (*addr_of_mut!(S)).push('!') };
             }
         });
         let b = thread::spawn(|| {
             B.store(true, SeqCst);
             if !A.load(SeqCst) {
-                unsafe { S.push('!') };
+                unsafe { (*addr_of_mut!(S)).push('!') };
             }
         });
         a.join().unwrap();
         b.join().unwrap();
-        unsafe { println!("{S}") }
+        unsafe { println!("{}", *addr_of!(S)) }
     }
 
     #[test]
@@ -124,8 +129,7 @@ mod fences {
     use std::time::Duration;
 
     static mut DATA: [u64; 10] = [0; 10];
-    const ATOMIC_FALSE: AtomicBool = AtomicBool::new(false);
-    static READY: [AtomicBool; 10] = [ATOMIC_FALSE; 10];
+    static READY: [AtomicBool; 10] = [const { AtomicBool::new(false) }; 10];
 
     fn some_calculation(index: usize) -> u64 {
         index as u64
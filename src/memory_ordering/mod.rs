@@ -1 +1 @@
-pub mod memory;
\ No newline at end of file
+pub mod memory;
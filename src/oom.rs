@@ -0,0 +1,148 @@
+//! What happens when the allocator runs out of memory.
+//!
+//! `Arc::new`, and the constructors that allocate as much as their
+//! arguments ask for, have a `try_` variant that returns an [`AllocError`]
+//! instead: [`Arc::try_new`], [`lockfree::try_deque`],
+//! [`ShardedChannel::try_with_shards`], [`PubSub::try_with_shards`] and
+//! [`pipe::try_pipe`]. That's what a server that would rather turn a
+//! request away, or firmware with a fixed heap, should call.
+//!
+//! Their variants without `try_` do what the [`OomPolicy`] says when the
+//! allocation fails: abort, like std, or panic, so a thread can catch it
+//! with `catch_unwind`. Without `std`, aborting means calling the program's
+//! `alloc_error_handler`, which panics unless it has its own.
+//!
+//! The work-stealing deque allocates a small header through
+//! `alloc::sync::Arc`, which has no fallible constructor on stable Rust,
+//! so only its slots are allocated fallibly.
+//!
+//! [`Arc::try_new`]: crate::Arc::try_new
+//! [`lockfree::try_deque`]: crate::lockfree::try_deque
+//! [`ShardedChannel::try_with_shards`]: crate::channels::ShardedChannel::try_with_shards
+//! [`PubSub::try_with_shards`]: crate::channels::PubSub::try_with_shards
+//! [`pipe::try_pipe`]: crate::channels::pipe::try_pipe
+
+use alloc::alloc::{alloc, handle_alloc_error};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::alloc::Layout;
+
+use crate::errors::AllocError;
+use crate::sync_shim::plain_atomic::AtomicU8;
+use crate::sync_shim::plain_atomic::Ordering::Relaxed;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OomPolicy {
+    /// `handle_alloc_error`, which aborts with `std`.
+    #[default]
+    Abort,
+    /// Panics with the [`AllocError`].
+    Panic,
+}
+
+static POLICY: AtomicU8 = AtomicU8::new(OomPolicy::Abort as u8);
+
+/// Sets the policy for the whole program.
+pub fn set_oom_policy(policy: OomPolicy) {
+    POLICY.store(policy as u8, Relaxed);
+}
+
+pub fn oom_policy() -> OomPolicy {
+    match POLICY.load(Relaxed) {
+        0 => OomPolicy::Abort,
+        _ => OomPolicy::Panic,
+    }
+}
+
+/// Fails the way the policy says. A size overflow always panics, as it
+/// does for a `Vec`.
+#[cold]
+pub(crate) fn handle(error: AllocError) -> ! {
+    match (oom_policy(), error.layout()) {
+        (OomPolicy::Abort, Some(layout)) => handle_alloc_error(layout),
+        _ => panic!("{error}"),
+    }
+}
+
+/// `Box::new`, but returns an error instead of failing.
+pub(crate) fn try_box<T>(value: T) -> Result<Box<T>, AllocError> {
+    let layout = Layout::new::<T>();
+    if layout.size() == 0 {
+        // Doesn't allocate.
+        return Ok(Box::new(value));
+    }
+    // Safety: The size isn't zero.
+    let ptr = unsafe { alloc(layout) }.cast::<T>();
+    if ptr.is_null() {
+        return Err(AllocError::new(layout));
+    }
+    // Safety: It's a fresh allocation of `T`'s layout from the global
+    // allocator, which is what a `Box` owns.
+    unsafe {
+        ptr.write(value);
+        Ok(Box::from_raw(ptr))
+    }
+}
+
+/// A boxed slice of `len` elements made by `f`, or an error instead of
+/// failing to allocate it.
+pub(crate) fn try_boxed_slice<T>(len: usize, f: impl FnMut() -> T) -> Result<Box<[T]>, AllocError> {
+    let layout = Layout::array::<T>(len).map_err(|_| AllocError::overflow())?;
+    let mut v = Vec::new();
+    v.try_reserve_exact(len)
+        .map_err(|_| AllocError::new(layout))?;
+    v.extend(core::iter::repeat_with(f).take(len));
+    // The capacity is exactly `len`, so this doesn't reallocate.
+    Ok(v.into_boxed_slice())
+}
+
+#[cfg(all(test, not(any(loom, feature = "shuttle"))))]
+mod tests {
+    use std::panic;
+
+    use crate::errors::AllocError;
+    use crate::oom::{handle, oom_policy, set_oom_policy, try_box, try_boxed_slice, OomPolicy};
+
+    #[test]
+    fn test_try_box_and_slice() {
+        assert_eq!(*try_box(5u64).unwrap(), 5);
+        assert!(try_box(()).is_ok());
+        let mut n = 0;
+        let slice = try_boxed_slice(3, || {
+            n += 1;
+            n
+        })
+        .unwrap();
+        assert_eq!(*slice, [1, 2, 3]);
+        assert_eq!(try_boxed_slice(0, || 0u8).unwrap().len(), 0);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "Miri stops instead of failing the allocation")]
+    fn test_errors_instead_of_failing() {
+        let err = try_boxed_slice(usize::MAX / 2, || 0u8).unwrap_err();
+        assert_eq!(err.layout().map(|l| l.size()), Some(usize::MAX / 2));
+        assert_eq!(
+            err.to_string(),
+            format!("failed to allocate {} bytes", usize::MAX / 2)
+        );
+        let err = try_boxed_slice(usize::MAX, || 0u64).unwrap_err();
+        assert_eq!(err, AllocError::overflow());
+        assert_eq!(err.to_string(), "capacity overflow");
+    }
+
+    #[test]
+    fn test_panic_policy() {
+        // Only this test changes the policy.
+        assert_eq!(oom_policy(), OomPolicy::Abort);
+        set_oom_policy(OomPolicy::Panic);
+        assert_eq!(oom_policy(), OomPolicy::Panic);
+        let layout = core::alloc::Layout::new::<u64>();
+        let panic = panic::catch_unwind(|| handle(AllocError::new(layout))).unwrap_err();
+        assert_eq!(
+            panic.downcast_ref::<String>().unwrap(),
+            "failed to allocate 8 bytes"
+        );
+        set_oom_policy(OomPolicy::Abort);
+    }
+}
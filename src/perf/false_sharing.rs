@@ -0,0 +1,172 @@
+//! Measuring false sharing: two threads that each write their own atomic,
+//! with the atomics on the same cache line or on lines of their own.
+//!
+//! [`measure`] runs an operation on two values at once, one per thread,
+//! and reports the time per operation, and [`compare`] does that for an
+//! adjacent and a padded layout of the same values. That's how to check
+//! that padding a new structure was worth it: measure its hot fields the
+//! way they're written, once as they were and once padded. [`counters`]
+//! does it for two plain counters, the textbook case.
+//!
+//! The difference only shows on two cores that are both free. On one core
+//! the threads take turns, and the cache line never has to move.
+
+use std::fmt;
+use std::mem::align_of;
+use std::thread;
+use std::time::Instant;
+
+use crate::cache_padded::CachePadded;
+use crate::platform::affinity::{available_cores, pin_current_thread};
+use crate::sync_shim::plain_atomic::AtomicU64;
+use crate::sync_shim::plain_atomic::Ordering::Relaxed;
+
+/// What [`CachePadded`] aligns to: the unit in which the lines move
+/// between cores, prefetched neighbours included.
+pub const LINE: usize = align_of::<CachePadded<u8>>();
+
+/// Whether `a` and `b` start within the same [`LINE`].
+pub fn same_line<A, B>(a: &A, b: &B) -> bool {
+    let a = std::ptr::from_ref(a).addr();
+    let b = std::ptr::from_ref(b).addr();
+    a / LINE == b / LINE
+}
+
+/// The time per operation, for one layout.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Measurement {
+    pub layout: &'static str,
+    pub ns_per_op: f64,
+}
+
+impl fmt::Display for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>10}: {:.2} ns/op", self.layout, self.ns_per_op)
+    }
+}
+
+/// Runs `op` `ops` times on `a` on one thread and on `b` on another, at
+/// the same time, and returns the time per operation of one thread.
+///
+/// The threads are pinned to different cores if there are two, so the
+/// scheduler can't put them on the same one.
+pub fn measure<T: Sync>(
+    layout: &'static str,
+    a: &T,
+    b: &T,
+    ops: u64,
+    op: impl Fn(&T) + Sync,
+) -> Measurement {
+    let pin = available_cores() >= 2 && !cfg!(miri);
+    let op = &op;
+    let start = Instant::now();
+    thread::scope(|s| {
+        for (core, value) in [a, b].into_iter().enumerate() {
+            s.spawn(move || {
+                if pin {
+                    // Not fatal: it only makes the numbers less reliable.
+                    let _ = pin_current_thread(core);
+                }
+                for _ in 0..ops {
+                    op(value);
+                }
+            });
+        }
+    });
+    Measurement {
+        layout,
+        ns_per_op: start.elapsed().as_nanos() as f64 / ops.max(1) as f64,
+    }
+}
+
+/// An adjacent and a padded layout of the same pair of values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Comparison {
+    pub adjacent: Measurement,
+    pub padded: Measurement,
+}
+
+impl Comparison {
+    /// How many times slower the adjacent layout was.
+    pub fn slowdown(&self) -> f64 {
+        self.adjacent.ns_per_op / self.padded.ns_per_op
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.adjacent)?;
+        writeln!(f, "{}", self.padded)?;
+        write!(f, "  slowdown: {:.1}x", self.slowdown())
+    }
+}
+
+/// [`measure`]s `op` on `adjacent`, which should share a line, and on
+/// `padded`, which shouldn't.
+pub fn compare<T: Sync>(
+    adjacent: [&T; 2],
+    padded: [&CachePadded<T>; 2],
+    ops: u64,
+    op: impl Fn(&T) + Sync,
+) -> Comparison {
+    Comparison {
+        adjacent: measure("adjacent", adjacent[0], adjacent[1], ops, &op),
+        padded: measure("padded", &**padded[0], &**padded[1], ops, &op),
+    }
+}
+
+/// Two counters, each incremented by a thread of its own.
+pub fn counters(ops: u64) -> Comparison {
+    let adjacent = [AtomicU64::new(0), AtomicU64::new(0)];
+    let padded = [
+        CachePadded::new(AtomicU64::new(0)),
+        CachePadded::new(AtomicU64::new(0)),
+    ];
+    debug_assert!(same_line(&adjacent[0], &adjacent[1]));
+    compare(
+        [&adjacent[0], &adjacent[1]],
+        [&padded[0], &padded[1]],
+        ops,
+        |counter| {
+            counter.fetch_add(1, Relaxed);
+        },
+    )
+}
+
+#[cfg(all(test, not(any(loom, feature = "shuttle"))))]
+mod tests {
+    use std::sync::atomic::AtomicU64;
+    use std::sync::atomic::Ordering::Relaxed;
+
+    use crate::cache_padded::CachePadded;
+    use crate::perf::false_sharing::{counters, measure, same_line};
+
+    #[test]
+    fn test_same_line() {
+        let adjacent = [AtomicU64::new(0), AtomicU64::new(0)];
+        let padded = [CachePadded::new(0u64), CachePadded::new(0u64)];
+        assert!(same_line(&adjacent[0], &adjacent[1]));
+        assert!(!same_line(&padded[0], &padded[1]));
+    }
+
+    #[test]
+    fn test_measure_runs_every_op() {
+        const OPS: u64 = if cfg!(miri) { 10 } else { 1000 };
+        let pair = [AtomicU64::new(0), AtomicU64::new(0)];
+        let m = measure("pair", &pair[0], &pair[1], OPS, |c| {
+            c.fetch_add(1, Relaxed);
+        });
+        assert_eq!(pair.map(|c| c.into_inner()), [OPS, OPS]);
+        assert_eq!(m.layout, "pair");
+        assert!(m.ns_per_op > 0.0);
+        assert!(counters(OPS).slowdown() > 0.0);
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_false_sharing`,
+    /// on a machine with two cores to spare.
+    #[test]
+    #[ignore]
+    fn bench_false_sharing() {
+        println!("{}", counters(10_000_000));
+    }
+}
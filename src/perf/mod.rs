@@ -0,0 +1,5 @@
+//! Measuring what the layout of the primitives, and the ways they block,
+//! cost.
+
+pub mod false_sharing;
+pub mod wakeup;
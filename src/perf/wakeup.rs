@@ -0,0 +1,256 @@
+//! Measuring how long a blocked consumer takes to notice a message, for
+//! each way the crate can block: `thread::park`, a `Condvar`, spinning,
+//! and the `platform::wait` backend this build picked.
+//!
+//! [`measure`] has a producer send numbered messages at a fixed interval
+//! to a consumer blocked on one [`Strategy`], and takes the time from the
+//! send to the consumer running again. [`compare`] does that for every
+//! strategy and interval, and displays as a table, to pick the backend
+//! features with numbers instead of guesses: spinning wakes fastest but
+//! keeps a core busy, and the others differ in how they get the kernel to
+//! reschedule the consumer, which matters less the longer it slept.
+//!
+//! The producer spins between messages rather than sleeping, so the
+//! intervals are what they say even when they're shorter than a sleep can
+//! be. It waits for the consumer to have seen a message before starting
+//! the next interval, so a slow strategy doesn't see messages queue up.
+
+use std::fmt;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::platform::wait::{self, wait, wake_one};
+use crate::sync_shim::plain_atomic::Ordering::{Acquire, Relaxed, Release};
+use crate::sync_shim::plain_atomic::{AtomicU32, AtomicU64};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// `thread::park`, and `Thread::unpark` to wake.
+    Park,
+    /// std's `Condvar`, which is what the `std-condvar` backend uses.
+    Condvar,
+    Spin,
+    /// `platform::wait`, with whichever backend is enabled.
+    Wait,
+}
+
+pub const STRATEGIES: [Strategy; 4] = [
+    Strategy::Park,
+    Strategy::Condvar,
+    Strategy::Spin,
+    Strategy::Wait,
+];
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Strategy::Park => f.pad("park"),
+            Strategy::Condvar => f.pad("condvar"),
+            Strategy::Spin => f.pad("spin"),
+            Strategy::Wait => f.pad(&format!("wait ({})", wait::BACKEND)),
+        }
+    }
+}
+
+/// The wakeup latencies of one strategy at one interval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Latency {
+    pub strategy: Strategy,
+    pub interval: Duration,
+    pub median: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl fmt::Display for Latency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>20} every {:>8.1?}: median {:.1?}, p99 {:.1?}, max {:.1?}",
+            self.strategy, self.interval, self.median, self.p99, self.max
+        )
+    }
+}
+
+/// Where the producer leaves a message, for every strategy.
+struct Mailbox {
+    /// The number of the last message sent.
+    sent: AtomicU32,
+    /// When it was sent, in nanoseconds since `start`.
+    sent_at: AtomicU64,
+    /// The number of the last message the consumer saw.
+    seen: AtomicU32,
+    start: Instant,
+    /// For `Strategy::Condvar`, a copy of `sent`.
+    mutex: Mutex<u32>,
+    condvar: Condvar,
+}
+
+impl Mailbox {
+    fn now(&self) -> u64 {
+        self.start.elapsed().as_nanos() as u64
+    }
+}
+
+/// Sends `messages` messages `interval` apart, and returns the latencies
+/// of the consumer blocked with `strategy`.
+pub fn measure(strategy: Strategy, interval: Duration, messages: u32) -> Latency {
+    let mailbox = Mailbox {
+        sent: AtomicU32::new(0),
+        sent_at: AtomicU64::new(0),
+        seen: AtomicU32::new(0),
+        start: Instant::now(),
+        mutex: Mutex::new(0),
+        condvar: Condvar::new(),
+    };
+    let mut latencies = thread::scope(|s| {
+        let consumer = s.spawn(|| receive(&mailbox, strategy, messages));
+        for n in 1..=messages {
+            let deadline = Instant::now() + interval;
+            while Instant::now() < deadline {
+                std::hint::spin_loop();
+            }
+            send(&mailbox, strategy, n, consumer.thread());
+            while mailbox.seen.load(Acquire) != n {
+                std::hint::spin_loop();
+            }
+        }
+        consumer.join().unwrap()
+    });
+    latencies.sort_unstable();
+    let at = |fraction: f64| {
+        let i = (latencies.len() as f64 * fraction) as usize;
+        latencies.get(i.min(latencies.len().saturating_sub(1)))
+    };
+    Latency {
+        strategy,
+        interval,
+        median: at(0.5).copied().unwrap_or_default(),
+        p99: at(0.99).copied().unwrap_or_default(),
+        max: latencies.last().copied().unwrap_or_default(),
+    }
+}
+
+fn send(mailbox: &Mailbox, strategy: Strategy, n: u32, consumer: &thread::Thread) {
+    mailbox.sent_at.store(mailbox.now(), Relaxed);
+    match strategy {
+        Strategy::Park => {
+            mailbox.sent.store(n, Release);
+            consumer.unpark();
+        }
+        Strategy::Condvar => {
+            *mailbox.mutex.lock().unwrap() = n;
+            mailbox.sent.store(n, Release);
+            mailbox.condvar.notify_one();
+        }
+        Strategy::Spin => mailbox.sent.store(n, Release),
+        Strategy::Wait => {
+            mailbox.sent.store(n, Release);
+            wake_one(&mailbox.sent);
+        }
+    }
+}
+
+fn receive(mailbox: &Mailbox, strategy: Strategy, messages: u32) -> Vec<Duration> {
+    let mut latencies = Vec::with_capacity(messages as usize);
+    for n in 1..=messages {
+        let last = n - 1;
+        match strategy {
+            Strategy::Park => {
+                while mailbox.sent.load(Acquire) == last {
+                    thread::park();
+                }
+            }
+            Strategy::Condvar => {
+                let guard = mailbox.mutex.lock().unwrap();
+                drop(mailbox.condvar.wait_while(guard, |sent| *sent == last));
+            }
+            Strategy::Spin => {
+                while mailbox.sent.load(Acquire) == last {
+                    std::hint::spin_loop();
+                }
+            }
+            Strategy::Wait => {
+                while mailbox.sent.load(Acquire) == last {
+                    wait(&mailbox.sent, last);
+                }
+            }
+        }
+        let now = mailbox.now();
+        // The send time is stored before `sent`, so it's this message's.
+        let sent_at = mailbox.sent_at.load(Relaxed);
+        latencies.push(Duration::from_nanos(now.saturating_sub(sent_at)));
+        mailbox.seen.store(n, Release);
+    }
+    latencies
+}
+
+/// Every strategy at every interval.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    pub rows: Vec<Latency>,
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>10}", "interval")?;
+        for strategy in STRATEGIES {
+            write!(f, " {strategy:>20}")?;
+        }
+        writeln!(f)?;
+        write!(f, "{:>10}", "")?;
+        for _ in STRATEGIES {
+            write!(f, " {:>20}", "median / p99")?;
+        }
+        let mut interval = None;
+        for row in &self.rows {
+            if interval != Some(row.interval) {
+                interval = Some(row.interval);
+                write!(f, "\n{:>10}", format!("{:.0?}", row.interval))?;
+            }
+            let cell = format!("{:.1?} / {:.1?}", row.median, row.p99);
+            write!(f, " {cell:>20}")?;
+        }
+        Ok(())
+    }
+}
+
+/// [`measure`]s every strategy at every interval, `messages` times each.
+pub fn compare(intervals: &[Duration], messages: u32) -> Table {
+    let rows = intervals
+        .iter()
+        .flat_map(|&interval| STRATEGIES.map(|s| measure(s, interval, messages)))
+        .collect();
+    Table { rows }
+}
+
+#[cfg(all(test, not(any(loom, feature = "shuttle"))))]
+mod tests {
+    use std::time::Duration;
+
+    use crate::perf::wakeup::{compare, measure, STRATEGIES};
+
+    #[test]
+    fn test_every_strategy_wakes_the_consumer() {
+        const MESSAGES: u32 = if cfg!(miri) { 3 } else { 100 };
+        for strategy in STRATEGIES {
+            let latency = measure(strategy, Duration::from_micros(10), MESSAGES);
+            assert!(latency.median <= latency.p99 && latency.p99 <= latency.max);
+            assert!(latency.max > Duration::ZERO, "{latency}");
+        }
+        let table = compare(&[Duration::ZERO], 1).to_string();
+        assert_eq!(table.lines().count(), 3, "{table}");
+        assert!(table.contains("condvar"), "{table}");
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_wakeup_latency`,
+    /// on a machine with two cores to spare. Compare the `wait` column
+    /// across `--no-default-features --features std,<backend>` builds.
+    #[test]
+    #[ignore]
+    fn bench_wakeup_latency() {
+        let intervals = [0, 10, 100, 1000, 10_000].map(Duration::from_micros);
+        println!("{}", compare(&intervals, 1000));
+    }
+}
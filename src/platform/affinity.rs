@@ -0,0 +1,219 @@
+//! Pinning threads to cores, so benchmarks and stress tests measure the
+//! primitives rather than the OS scheduler migrating threads around.
+
+use std::io;
+use std::thread;
+
+/// Number of cores the current process may run on, or 1 if unknown.
+pub fn available_cores() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Pins the calling thread to `core`.
+///
+/// On macOS this can only set an affinity *hint* (and Apple Silicon
+/// ignores even that), so it's best-effort there.
+pub fn pin_current_thread(core: usize) -> io::Result<()> {
+    imp::pin_current_thread(core)
+}
+
+/// Number of NUMA nodes, or 1 if unknown. Only known on Linux.
+pub fn numa_nodes() -> usize {
+    #[cfg(target_os = "linux")]
+    return imp::numa_nodes();
+    #[cfg(not(target_os = "linux"))]
+    1
+}
+
+/// The NUMA node the calling thread is running on, or 0 if unknown. The
+/// thread can be moved to another one right after, unless it's pinned.
+pub fn current_numa_node() -> usize {
+    #[cfg(target_os = "linux")]
+    return imp::current_numa_node();
+    #[cfg(not(target_os = "linux"))]
+    0
+}
+
+/// Spawns a thread that pins itself to `core` before running `f`.
+/// Failing to pin isn't fatal: `f` runs anyway, and the error is passed in.
+pub fn spawn_pinned<F, T>(core: usize, f: F) -> thread::JoinHandle<T>
+where
+    F: FnOnce(io::Result<()>) -> T + Send + 'static,
+    T: Send + 'static,
+{
+    thread::spawn(move || f(pin_current_thread(core)))
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::{fs, io, mem, ptr};
+
+    pub fn pin_current_thread(core: usize) -> io::Result<()> {
+        if core >= libc::CPU_SETSIZE as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "core index out of range",
+            ));
+        }
+        // Safety: cpu_set_t is a plain bit array, for which all zeroes is a valid
+        // (empty) set, and sched_setaffinity only reads it.
+        unsafe {
+            let mut set: libc::cpu_set_t = mem::zeroed();
+            libc::CPU_SET(core, &mut set);
+            if libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn numa_nodes() -> usize {
+        let Ok(entries) = fs::read_dir("/sys/devices/system/node") else {
+            return 1;
+        };
+        let nodes = entries
+            .filter_map(|e| e.ok()?.file_name().into_string().ok())
+            .filter(|name| {
+                name.strip_prefix("node")
+                    .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+            })
+            .count();
+        nodes.max(1)
+    }
+
+    pub fn current_numa_node() -> usize {
+        let mut node: libc::c_uint = 0;
+        // Safety: getcpu only writes the node, and skips the null CPU.
+        let r = unsafe {
+            libc::syscall(
+                libc::SYS_getcpu,
+                ptr::null_mut::<libc::c_uint>(),
+                &mut node as *mut libc::c_uint,
+                ptr::null_mut::<libc::c_void>(),
+            )
+        };
+        if r == 0 {
+            node as usize
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    use std::io;
+
+    pub fn pin_current_thread(core: usize) -> io::Result<()> {
+        // Threads with the same (non-zero) tag are kept on the same L2,
+        // different tags are spread out. That's as close as macOS gets.
+        let mut policy = libc::thread_affinity_policy {
+            affinity_tag: core as libc::integer_t + 1,
+        };
+        // Safety: The policy struct outlives the call, and the count matches its size.
+        let result = unsafe {
+            libc::thread_policy_set(
+                libc::pthread_mach_thread_np(libc::pthread_self()),
+                libc::THREAD_AFFINITY_POLICY as libc::thread_policy_flavor_t,
+                &mut policy as *mut _ as libc::thread_policy_t,
+                libc::THREAD_AFFINITY_POLICY_COUNT,
+            )
+        };
+        if result != libc::KERN_SUCCESS {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "thread affinity not supported",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::ffi::c_void;
+    use std::io;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCurrentThread() -> *mut c_void;
+        fn SetThreadAffinityMask(thread: *mut c_void, mask: usize) -> usize;
+    }
+
+    pub fn pin_current_thread(core: usize) -> io::Result<()> {
+        if core >= usize::BITS as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "core index out of range",
+            ));
+        }
+        // Safety: GetCurrentThread returns a pseudo handle that is always valid.
+        if unsafe { SetThreadAffinityMask(GetCurrentThread(), 1 << core) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod imp {
+    use std::io;
+
+    pub fn pin_current_thread(_core: usize) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "thread affinity not supported",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(target_os = "linux")]
+    use crate::platform::affinity::spawn_pinned;
+    use crate::platform::affinity::{
+        available_cores, current_numa_node, numa_nodes, pin_current_thread,
+    };
+
+    #[test]
+    fn test_available_cores() {
+        assert!(available_cores() >= 1);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "Miri can't call getcpu")]
+    fn test_current_numa_node_exists() {
+        assert!(current_numa_node() < numa_nodes());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[cfg_attr(miri, ignore = "Miri can't call sched_getcpu")]
+    fn test_pinned_thread_runs_on_its_core() {
+        // The process may be restricted to some of the cores (e.g. in a
+        // container), so pick the last one we're actually allowed to use.
+        // Safety: An all-zero cpu_set_t is a valid empty set for the kernel to fill in.
+        let core = unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            assert_eq!(
+                libc::sched_getaffinity(0, std::mem::size_of_val(&set), &mut set),
+                0
+            );
+            (0..libc::CPU_SETSIZE as usize)
+                .rev()
+                .find(|&c| libc::CPU_ISSET(c, &set))
+                .unwrap()
+        };
+        let on_core = spawn_pinned(core, |pinned| {
+            pinned.unwrap();
+            // Safety: sched_getcpu has no preconditions.
+            unsafe { libc::sched_getcpu() }
+        });
+        assert_eq!(on_core.join().unwrap(), core as i32);
+    }
+
+    #[test]
+    fn test_out_of_range_core_is_rejected() {
+        assert!(pin_current_thread(usize::MAX).is_err());
+    }
+}
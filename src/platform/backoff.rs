@@ -0,0 +1,225 @@
+//! Spinning that's kinder to the other cores, and to the one holding the
+//! lock.
+//!
+//! `core::hint::spin_loop` is already the best single hint on both x86
+//! (`pause`) and AArch64 (`isb`, a short pipeline flush), but a loop of
+//! them still reads the cache line as fast as it can. [`Backoff`] spins
+//! exponentially longer between tries, and [`spin_while_eq`] waits for a
+//! value to change. On AArch64 the latter does that without spinning at
+//! all: it loads the value with `ldxr`, which makes the core watch the
+//! cache line, and sleeps in `wfe` until another core writes to it. That's
+//! what the Linux kernel's `smp_cond_load_relaxed` does there.
+
+use crate::sync_shim::plain_atomic::{AtomicBool, AtomicU32};
+
+/// After this many steps, each spin is 2^SPIN_LIMIT hints long, and it's
+/// time to sleep instead.
+const SPIN_LIMIT: u32 = 6;
+
+/// Exponential backoff for spin loops, like crossbeam's.
+#[derive(Debug, Default)]
+pub struct Backoff {
+    step: u32,
+}
+
+impl Backoff {
+    pub const fn new() -> Self {
+        Self { step: 0 }
+    }
+
+    /// Spins twice as long as the time before, up to a limit.
+    pub fn spin(&mut self) {
+        for _ in 0..1 << self.step {
+            core::hint::spin_loop();
+        }
+        if self.step < SPIN_LIMIT {
+            self.step += 1;
+        }
+    }
+
+    /// Whether spinning has reached its limit, so a lock that can sleep
+    /// should do that instead.
+    pub fn is_completed(&self) -> bool {
+        self.step >= SPIN_LIMIT
+    }
+
+    pub fn reset(&mut self) {
+        self.step = 0;
+    }
+}
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// The atomics [`spin_while_eq`] can wait on.
+pub trait Watch: private::Sealed {
+    type Value: Copy + PartialEq;
+
+    /// A relaxed load, which on AArch64 also makes the core watch for
+    /// writes to the atomic's cache line.
+    #[doc(hidden)]
+    fn load_watched(&self) -> Self::Value;
+}
+
+/// `wfe` only returns early on an event, so it's only used where the core
+/// is sure to get one: when the watched cache line is written to.
+#[cfg(all(target_arch = "aarch64", not(miri)))]
+const WATCHES: bool = true;
+#[cfg(not(all(target_arch = "aarch64", not(miri))))]
+const WATCHES: bool = false;
+
+impl private::Sealed for AtomicU32 {}
+
+impl Watch for AtomicU32 {
+    type Value = u32;
+
+    fn load_watched(&self) -> u32 {
+        #[cfg(all(target_arch = "aarch64", not(miri)))]
+        {
+            let v: u32;
+            // Safety: Only reads the atomic, atomically.
+            unsafe {
+                core::arch::asm!(
+                    "ldxr {v:w}, [{a}]",
+                    a = in(reg) self.as_ptr(),
+                    v = out(reg) v,
+                    options(nostack, readonly, preserves_flags),
+                );
+            }
+            v
+        }
+        #[cfg(not(all(target_arch = "aarch64", not(miri))))]
+        self.load(core::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+impl private::Sealed for AtomicBool {}
+
+impl Watch for AtomicBool {
+    type Value = bool;
+
+    fn load_watched(&self) -> bool {
+        #[cfg(all(target_arch = "aarch64", not(miri)))]
+        {
+            let v: u32;
+            // Safety: Only reads the atomic, atomically.
+            unsafe {
+                core::arch::asm!(
+                    "ldxrb {v:w}, [{a}]",
+                    a = in(reg) self.as_ptr(),
+                    v = out(reg) v,
+                    options(nostack, readonly, preserves_flags),
+                );
+            }
+            v != 0
+        }
+        #[cfg(not(all(target_arch = "aarch64", not(miri))))]
+        self.load(core::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+/// Waits until the atomic no longer holds `value`. This is only a relaxed
+/// load, so follow it with the acquiring operation that takes the lock.
+pub fn spin_while_eq<A: Watch>(a: &A, value: A::Value) {
+    let mut backoff = Backoff::new();
+    while a.load_watched() == value {
+        if WATCHES {
+            // Safety: Just a hint, which returns on the next event at the
+            // latest, like a write to the line `load_watched` watches.
+            #[cfg(all(target_arch = "aarch64", not(miri)))]
+            unsafe {
+                core::arch::asm!("wfe", options(nomem, nostack, preserves_flags));
+            }
+        } else {
+            backoff.spin();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::platform::backoff::{spin_while_eq, Backoff, SPIN_LIMIT};
+    use crate::sync_shim::plain_atomic::Ordering::Relaxed;
+    use crate::sync_shim::plain_atomic::{AtomicBool, AtomicU32};
+
+    #[test]
+    fn test_backoff_completes() {
+        let mut backoff = Backoff::new();
+        let mut steps = 0;
+        while !backoff.is_completed() {
+            backoff.spin();
+            steps += 1;
+        }
+        assert_eq!(steps, SPIN_LIMIT);
+        backoff.reset();
+        assert!(!backoff.is_completed());
+    }
+
+    #[test]
+    fn test_spin_while_eq_returns_on_change() {
+        let (a, b) = (AtomicU32::new(0), AtomicBool::new(true));
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                a.store(1, Relaxed);
+                b.store(false, Relaxed);
+            });
+            spin_while_eq(&a, 0);
+            spin_while_eq(&b, true);
+        });
+        spin_while_eq(&a, 0);
+    }
+
+    /// Two threads passing a turn back and forth, each waiting for it with
+    /// the given strategy. Returns the time per pass.
+    fn ping_pong(wait_for_turn: impl Fn(&AtomicU32, u32) + Sync) -> Duration {
+        const PASSES: u32 = 100_000;
+        let turn = AtomicU32::new(0);
+        let start = Instant::now();
+        thread::scope(|s| {
+            for me in 0..2 {
+                let (turn, wait_for_turn) = (&turn, &wait_for_turn);
+                s.spawn(move || {
+                    for _ in 0..PASSES / 2 {
+                        wait_for_turn(turn, me);
+                        turn.store(1 - me, Relaxed);
+                    }
+                });
+            }
+        });
+        start.elapsed() / PASSES
+    }
+
+    type Strategy<'a> = &'a (dyn Fn(&AtomicU32, u32) + Sync);
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_spin_strategies`,
+    /// on a machine with two cores to spare, or every pass takes a time
+    /// slice.
+    #[test]
+    #[ignore]
+    fn bench_spin_strategies() {
+        println!("on {}:", std::env::consts::ARCH);
+        let strategies: [(&str, Strategy); 4] = [
+            ("no hint", &|t, me| while t.load(Relaxed) != me {}),
+            ("spin_loop", &|t, me| {
+                while t.load(Relaxed) != me {
+                    std::hint::spin_loop();
+                }
+            }),
+            ("Backoff", &|t, me| {
+                let mut backoff = Backoff::new();
+                while t.load(Relaxed) != me {
+                    backoff.spin();
+                }
+            }),
+            ("spin_while_eq", &|t, me| spin_while_eq(t, 1 - me)),
+        ];
+        for (name, strategy) in strategies {
+            println!("{name:>14}: {:?} per pass", ping_pong(strategy));
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! The Linux futex syscalls, for building other blocking primitives on.
+//!
+//! [`platform::wait`](crate::platform::wait) only needs waiting and waking
+//! one or all. This has the rest: waits with a deadline, waking any number
+//! of threads, and requeueing, which moves waiters from one futex to
+//! another without waking them. A condition variable uses that in
+//! `notify_all` to move its waiters onto the mutex, where unlocking wakes
+//! them one at a time, instead of waking all of them to fight over the
+//! mutex only for all but one to go back to sleep.
+//!
+//! These are private futexes, so they only work between the threads of one
+//! process, except for [`wait_shared`] and [`wake_shared`], which work on
+//! an atomic in memory shared with other processes.
+
+use core::ptr;
+use core::time::Duration;
+
+use crate::sync_shim::plain_atomic::AtomicU32;
+
+fn errno() -> i32 {
+    // Safety: Always returns a valid pointer to this thread's errno.
+    unsafe { *libc::__errno_location() }
+}
+
+fn count(n: u32) -> i32 {
+    i32::try_from(n).unwrap_or(i32::MAX)
+}
+
+/// The current time of the monotonic clock the deadlines are measured in.
+pub fn now() -> Duration {
+    let mut t = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // Safety: `t` is a valid timespec to write to.
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut t) };
+    Duration::new(t.tv_sec as u64, t.tv_nsec as u32)
+}
+
+/// Blocks until woken up or until `deadline` (see [`now`]) has passed,
+/// unless the atomic no longer holds `expected`. Returns `false` only if
+/// the deadline passed. Might also return spuriously.
+pub fn wait(a: &AtomicU32, expected: u32, deadline: Option<Duration>) -> bool {
+    wait_with(a, expected, deadline, libc::FUTEX_PRIVATE_FLAG)
+}
+
+/// [`wait`], for an atomic in shared memory, which other processes can
+/// wake it from with [`wake_shared`].
+pub fn wait_shared(a: &AtomicU32, expected: u32, deadline: Option<Duration>) -> bool {
+    wait_with(a, expected, deadline, 0)
+}
+
+fn wait_with(a: &AtomicU32, expected: u32, deadline: Option<Duration>, flags: i32) -> bool {
+    let deadline = deadline.map(|d| libc::timespec {
+        tv_sec: d.as_secs().try_into().unwrap_or(libc::time_t::MAX),
+        tv_nsec: d.subsec_nanos().into(),
+    });
+    // Safety: The kernel only reads the atomic (and compares it with
+    // `expected` before sleeping), and the deadline if there is one. Unlike
+    // FUTEX_WAIT's, FUTEX_WAIT_BITSET's timeout is absolute.
+    let r = unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            a as *const AtomicU32,
+            libc::FUTEX_WAIT_BITSET | flags,
+            expected,
+            deadline
+                .as_ref()
+                .map_or(ptr::null(), |d| d as *const libc::timespec),
+            ptr::null::<u32>(),
+            libc::FUTEX_BITSET_MATCH_ANY,
+        )
+    };
+    !(r == -1 && errno() == libc::ETIMEDOUT)
+}
+
+/// Wakes up to `n` threads waiting on the atomic, and returns how many it
+/// woke.
+pub fn wake(a: &AtomicU32, n: u32) -> usize {
+    wake_with(a, n, libc::FUTEX_PRIVATE_FLAG)
+}
+
+/// [`wake`], for threads in any process waiting with [`wait_shared`].
+pub fn wake_shared(a: &AtomicU32, n: u32) -> usize {
+    wake_with(a, n, 0)
+}
+
+fn wake_with(a: &AtomicU32, n: u32, flags: i32) -> usize {
+    // Safety: Waking never touches the atomic itself.
+    let r = unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            a as *const AtomicU32,
+            libc::FUTEX_WAKE | flags,
+            count(n),
+        )
+    };
+    r.try_into().unwrap_or(0)
+}
+
+/// If `from` still holds `expected`, wakes up to `wake` threads waiting on
+/// it, and moves up to `requeue` of the others to wait on `to` instead.
+/// Returns how many were woken and moved together, or `None` if `from`
+/// held something else.
+pub fn requeue(
+    from: &AtomicU32,
+    expected: u32,
+    wake: u32,
+    to: &AtomicU32,
+    requeue: u32,
+) -> Option<usize> {
+    // Safety: The kernel only reads `from` to compare it with `expected`.
+    // FUTEX_CMP_REQUEUE takes the requeue count in the timeout argument.
+    let r = unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            from as *const AtomicU32,
+            libc::FUTEX_CMP_REQUEUE | libc::FUTEX_PRIVATE_FLAG,
+            count(wake),
+            count(requeue) as libc::c_long,
+            to as *const AtomicU32,
+            expected,
+        )
+    };
+    r.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::platform::futex::{now, requeue, wait, wake};
+    use crate::sync_shim::plain_atomic::Ordering::{Acquire, Relaxed, Release};
+    use crate::sync_shim::plain_atomic::{AtomicU32, AtomicUsize};
+
+    #[test]
+    fn test_wait_times_out() {
+        let a = AtomicU32::new(0);
+        let start = now();
+        assert!(!wait(&a, 0, Some(start + Duration::from_millis(10))));
+        assert!(now() - start >= Duration::from_millis(10));
+        // A deadline in the past still checks the value first.
+        assert!(wait(&a, 1, Some(start)));
+    }
+
+    #[test]
+    fn test_wake_counts_woken_threads() {
+        let a = AtomicU32::new(0);
+        assert_eq!(wake(&a, u32::MAX), 0);
+        thread::scope(|s| {
+            for _ in 0..3 {
+                s.spawn(|| {
+                    while a.load(Relaxed) == 0 {
+                        wait(&a, 0, None);
+                    }
+                });
+            }
+            // As soon as one of them is asleep, this wakes just that one,
+            // which goes right back to sleep.
+            while wake(&a, 1) == 0 {
+                thread::yield_now();
+            }
+            a.store(1, Relaxed);
+            wake(&a, u32::MAX);
+        });
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "Miri doesn't support FUTEX_CMP_REQUEUE")]
+    fn test_requeue_checks_the_value() {
+        let (a, b) = (AtomicU32::new(0), AtomicU32::new(0));
+        assert_eq!(requeue(&a, 1, 1, &b, u32::MAX), None);
+        assert_eq!(requeue(&a, 0, 1, &b, u32::MAX), Some(0));
+    }
+
+    /// Waiters on a condition variable built from a futex mutex (as in
+    /// chapter 9) and a futex sequence number, all woken at once while the
+    /// mutex is held. Returns how many times a waiter woke up to find the
+    /// mutex locked.
+    fn futile_wakeups(requeue_onto_mutex: bool) -> usize {
+        const WAITERS: usize = 8;
+        // 0: unlocked, 1: locked, 2: locked with (maybe) waiters.
+        let mutex = AtomicU32::new(0);
+        let sequence = AtomicU32::new(0);
+        let asleep = AtomicUsize::new(0);
+        let futile = AtomicUsize::new(0);
+        let lock = || {
+            if mutex.compare_exchange(0, 1, Acquire, Relaxed).is_err() {
+                while mutex.swap(2, Acquire) != 0 {
+                    wait(&mutex, 2, None);
+                }
+            }
+        };
+        let unlock = || {
+            if mutex.swap(0, Release) == 2 {
+                wake(&mutex, 1);
+            }
+        };
+        thread::scope(|s| {
+            for _ in 0..WAITERS {
+                s.spawn(|| {
+                    lock();
+                    let seq = sequence.load(Relaxed);
+                    asleep.fetch_add(1, Relaxed);
+                    unlock();
+                    wait(&sequence, seq, None);
+                    // Relock as contended, since requeued waiters may be
+                    // waiting on the mutex.
+                    while mutex.swap(2, Acquire) != 0 {
+                        futile.fetch_add(1, Relaxed);
+                        wait(&mutex, 2, None);
+                    }
+                    unlock();
+                });
+            }
+            while asleep.load(Relaxed) < WAITERS {
+                thread::yield_now();
+            }
+            thread::sleep(Duration::from_millis(20));
+            lock();
+            let seq = sequence.fetch_add(1, Relaxed) + 1;
+            if requeue_onto_mutex {
+                // The requeued waiters are only woken by unlocking if the
+                // mutex is marked as contended.
+                mutex.store(2, Relaxed);
+                requeue(&sequence, seq, 1, &mutex, u32::MAX);
+            } else {
+                wake(&sequence, u32::MAX);
+            }
+            // Give every woken waiter the time to run into the mutex.
+            thread::sleep(Duration::from_millis(50));
+            unlock();
+        });
+        futile.into_inner()
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "Miri doesn't support FUTEX_CMP_REQUEUE")]
+    fn test_requeue_avoids_thundering_herd() {
+        let broadcast = futile_wakeups(false);
+        let requeued = futile_wakeups(true);
+        assert!(
+            requeued < broadcast,
+            "requeueing: {requeued} futile wakeups, waking all: {broadcast}"
+        );
+    }
+}
@@ -0,0 +1,13 @@
+#[cfg(feature = "std")]
+pub mod affinity;
+pub mod backoff;
+#[cfg(all(feature = "futex", target_os = "linux"))]
+pub mod futex;
+#[cfg(all(feature = "shm", any(unix, windows)))]
+pub mod named_event;
+pub mod parking;
+#[cfg(all(feature = "std", unix))]
+pub mod pthread;
+#[cfg(all(feature = "shm", any(unix, windows)))]
+pub mod shm;
+pub mod wait;
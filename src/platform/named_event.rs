@@ -0,0 +1,321 @@
+//! An event that unrelated processes can open by name, to signal each
+//! other.
+//!
+//! A [`NamedEvent`] is either set or not. Setting it wakes everyone waiting
+//! for it, in any process, and it stays set until it's reset. On Linux
+//! it's a word in [`shm`](crate::platform::shm) that's waited on with a
+//! shared futex, on Windows it's a named event object, and on other Unix
+//! systems it's the same word in shared memory, polled with sleeps that
+//! get longer up to a millisecond.
+
+use std::io;
+use std::time::{Duration, Instant};
+
+/// A manual-reset event, shared between processes by name.
+///
+/// On Windows, file mappings and events share a namespace, so the name
+/// can't also be used for a [`SharedMemory`](crate::platform::shm::SharedMemory)
+/// there.
+pub struct NamedEvent {
+    inner: imp::Event,
+}
+
+impl NamedEvent {
+    /// Opens the event called `name`, creating it (not set) if it doesn't
+    /// exist yet.
+    pub fn open(name: &str) -> io::Result<Self> {
+        Ok(Self {
+            inner: imp::Event::open(name)?,
+        })
+    }
+
+    /// Sets the event, waking everyone waiting for it.
+    pub fn set(&self) {
+        self.inner.set();
+    }
+
+    pub fn reset(&self) {
+        self.inner.reset();
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.inner.is_set()
+    }
+
+    /// Blocks until the event is set.
+    pub fn wait(&self) {
+        self.inner.wait(None);
+    }
+
+    /// Blocks until the event is set, for at most `timeout`. Returns
+    /// whether it was set.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        self.inner.wait(Instant::now().checked_add(timeout))
+    }
+
+    /// Blocks until the event is set, or until `deadline`. Returns whether
+    /// it was set.
+    pub fn wait_until(&self, deadline: Instant) -> bool {
+        self.inner.wait(Some(deadline))
+    }
+
+    /// Removes the name, like [`SharedMemory::unlink`], which is what this
+    /// does on Unix.
+    ///
+    /// [`SharedMemory::unlink`]: crate::platform::shm::SharedMemory::unlink
+    pub fn unlink(name: &str) -> io::Result<()> {
+        imp::unlink(name)
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::io;
+    use std::time::Instant;
+
+    use crate::platform::shm::{Shared, SharedMemory};
+    use crate::sync_shim::plain_atomic::AtomicU32;
+    use crate::sync_shim::plain_atomic::Ordering::{Acquire, Relaxed, Release};
+
+    #[repr(C)]
+    pub(super) struct State {
+        set: AtomicU32,
+    }
+
+    unsafe impl Shared for State {}
+
+    pub(super) struct Event {
+        state: SharedMemory<State>,
+    }
+
+    impl Event {
+        pub(super) fn open(name: &str) -> io::Result<Self> {
+            Ok(Self {
+                state: SharedMemory::open(name)?,
+            })
+        }
+
+        pub(super) fn set(&self) {
+            if self.state.set.swap(1, Release) == 0 {
+                wake_all(&self.state.set);
+            }
+        }
+
+        pub(super) fn reset(&self) {
+            self.state.set.store(0, Relaxed);
+        }
+
+        pub(super) fn is_set(&self) -> bool {
+            self.state.set.load(Acquire) == 1
+        }
+
+        pub(super) fn wait(&self, deadline: Option<Instant>) -> bool {
+            self.is_set() || wait_while_unset(&self.state.set, deadline)
+        }
+    }
+
+    #[cfg(all(feature = "futex", target_os = "linux"))]
+    fn wait_while_unset(set: &AtomicU32, deadline: Option<Instant>) -> bool {
+        use crate::platform::futex;
+        // The futex deadline is absolute too, on the same clock, so this
+        // is converted once, not again for every spurious wake-up.
+        let deadline = deadline
+            .map(|d| futex::now().saturating_add(d.saturating_duration_since(Instant::now())));
+        while set.load(Acquire) == 0 {
+            if !futex::wait_shared(set, 0, deadline) {
+                return set.load(Acquire) == 1;
+            }
+        }
+        true
+    }
+
+    #[cfg(all(feature = "futex", target_os = "linux"))]
+    fn wake_all(set: &AtomicU32) {
+        crate::platform::futex::wake_shared(set, u32::MAX);
+    }
+
+    #[cfg(not(all(feature = "futex", target_os = "linux")))]
+    fn wait_while_unset(set: &AtomicU32, deadline: Option<Instant>) -> bool {
+        use std::thread;
+        use std::time::Duration;
+        let mut sleep = Duration::from_micros(1);
+        while set.load(Acquire) == 0 {
+            let now = Instant::now();
+            if deadline.is_some_and(|d| now >= d) {
+                return false;
+            }
+            thread::sleep(deadline.map_or(sleep, |d| sleep.min(d - now)));
+            sleep = (sleep * 2).min(Duration::from_millis(1));
+        }
+        true
+    }
+
+    #[cfg(not(all(feature = "futex", target_os = "linux")))]
+    fn wake_all(_: &AtomicU32) {}
+
+    pub(super) fn unlink(name: &str) -> io::Result<()> {
+        SharedMemory::<State>::unlink(name)
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use core::ptr;
+    use std::io;
+    use std::time::Instant;
+
+    use windows_sys::Win32::Foundation::{CloseHandle, HANDLE, WAIT_OBJECT_0};
+    use windows_sys::Win32::System::Threading::{
+        CreateEventW, ResetEvent, SetEvent, WaitForSingleObject, INFINITE,
+    };
+
+    pub(super) struct Event {
+        handle: HANDLE,
+    }
+
+    // Safety: An event handle can be used from any thread.
+    unsafe impl Send for Event {}
+    unsafe impl Sync for Event {}
+
+    impl Event {
+        pub(super) fn open(name: &str) -> io::Result<Self> {
+            if name.is_empty() || name.contains(['/', '\\', '\0']) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "invalid event name",
+                ));
+            }
+            let name: Vec<u16> = format!("Local\\{name}").encode_utf16().chain([0]).collect();
+            // Safety: `name` is a valid wide C string. If the event exists,
+            // this opens it, and ignores the other arguments.
+            let handle = unsafe { CreateEventW(ptr::null(), 1, 0, name.as_ptr()) };
+            if handle.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self { handle })
+        }
+
+        pub(super) fn set(&self) {
+            // Safety: The handle is open.
+            unsafe { SetEvent(self.handle) };
+        }
+
+        pub(super) fn reset(&self) {
+            // Safety: The handle is open.
+            unsafe { ResetEvent(self.handle) };
+        }
+
+        pub(super) fn is_set(&self) -> bool {
+            // Safety: The handle is open.
+            unsafe { WaitForSingleObject(self.handle, 0) == WAIT_OBJECT_0 }
+        }
+
+        pub(super) fn wait(&self, deadline: Option<Instant>) -> bool {
+            loop {
+                // Rounded up, so it doesn't return before the deadline, and
+                // capped below INFINITE, in which case it waits again.
+                let ms = deadline.map_or(INFINITE, |d| {
+                    let left = d.saturating_duration_since(Instant::now());
+                    let ms = left.as_nanos().div_ceil(1_000_000);
+                    ms.try_into().unwrap_or(INFINITE - 1).min(INFINITE - 1)
+                });
+                // Safety: The handle is open.
+                if unsafe { WaitForSingleObject(self.handle, ms) } == WAIT_OBJECT_0 {
+                    return true;
+                }
+                if deadline.is_none_or(|d| Instant::now() >= d) {
+                    return false;
+                }
+            }
+        }
+    }
+
+    impl Drop for Event {
+        fn drop(&mut self) {
+            // Safety: We own the handle.
+            unsafe { CloseHandle(self.handle) };
+        }
+    }
+
+    pub(super) fn unlink(_: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process::{Command, Stdio};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::platform::named_event::NamedEvent;
+
+    fn name(test: &str) -> String {
+        format!("al-ev-{}-{test}", std::process::id())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "Miri doesn't support shm_open")]
+    fn test_set_wakes_waiters() {
+        let name = name("wake");
+        let event = NamedEvent::open(&name).unwrap();
+        assert!(!event.is_set());
+        thread::scope(|s| {
+            for _ in 0..3 {
+                s.spawn(|| NamedEvent::open(&name).unwrap().wait());
+            }
+            thread::sleep(Duration::from_millis(10));
+            event.set();
+        });
+        assert!(event.is_set());
+        assert!(event.wait_timeout(Duration::ZERO));
+        event.reset();
+        assert!(!event.is_set());
+        NamedEvent::unlink(&name).unwrap();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "Miri doesn't support shm_open")]
+    fn test_wait_timeout_gives_up() {
+        let name = name("timeout");
+        let event = NamedEvent::open(&name).unwrap();
+        let start = Instant::now();
+        assert!(!event.wait_timeout(Duration::from_millis(10)));
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        let deadline = Instant::now() + Duration::from_millis(10);
+        assert!(!event.wait_until(deadline));
+        assert!(Instant::now() >= deadline);
+        NamedEvent::unlink(&name).unwrap();
+    }
+
+    const CHILD_ENV: &str = "ATOMICS_AND_LOCKS_EVENT_CHILD";
+
+    #[test]
+    #[cfg_attr(miri, ignore = "Miri can't spawn processes")]
+    fn test_signals_another_process() {
+        let name = name("processes");
+        let event = NamedEvent::open(&name).unwrap();
+        let child = Command::new(env::current_exe().unwrap())
+            .args(["--exact", "platform::named_event::tests::child_sets"])
+            .args(["--ignored", "--test-threads=1"])
+            .env(CHILD_ENV, &name)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        assert!(event.wait_timeout(Duration::from_secs(10)));
+        let child = child.wait_with_output().unwrap();
+        assert!(child.status.success(), "{child:?}");
+        NamedEvent::unlink(&name).unwrap();
+    }
+
+    /// The other process in `test_signals_another_process`.
+    #[test]
+    #[ignore]
+    fn child_sets() {
+        if let Ok(name) = env::var(CHILD_ENV) {
+            NamedEvent::open(&name).unwrap().set();
+        }
+    }
+}
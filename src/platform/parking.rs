@@ -0,0 +1,304 @@
+//! A global table of parked threads, keyed by address, like the one in
+//! the `parking_lot` crate.
+//!
+//! Keeping the waiter queues out of the locks themselves lets a lock be as
+//! small as a single byte: it only needs a bit saying that there might be
+//! threads parked on its address. Each parked thread sleeps on its own
+//! token through `platform::wait`, so this works with every backend.
+
+use alloc::vec::Vec;
+use core::mem;
+
+use crate::locks::{Lock, RawSpinLock};
+use crate::platform::wait::{wait, wake_one};
+#[cfg(feature = "std")]
+use crate::platform::wait::{wait_interruptible, wait_until, Interrupt};
+use crate::sync_shim::plain_atomic::Ordering::{Acquire, Relaxed, Release};
+use crate::sync_shim::plain_atomic::{AtomicU32, AtomicUsize};
+
+const PARKED: u32 = 0;
+const UNPARKED: u32 = 1;
+
+struct Token {
+    state: AtomicU32,
+    /// The address the thread is parked on, which `requeue` can change.
+    addr: AtomicUsize,
+}
+
+struct Waiter {
+    addr: usize,
+    /// Lives on the stack of the parked thread, which doesn't return
+    /// before it's been removed from the queue.
+    token: *const Token,
+}
+
+// Safety: The token is only accessed through the bucket lock, while its
+// thread is parked.
+unsafe impl Send for Waiter {}
+
+/// Unrelated addresses can share a bucket, so the queues are small, and
+/// the buckets are only locked for a few instructions at a time. They use
+/// `RawSpinLock` rather than `SpinLock`, which is built on loom's atomics
+/// under `cfg(loom)`, and so can't be in a static there.
+static BUCKETS: [Lock<Vec<Waiter>, RawSpinLock>; 64] = [const { Lock::new(Vec::new()) }; 64];
+
+fn bucket_index(addr: usize) -> usize {
+    (addr >> 2) % BUCKETS.len()
+}
+
+fn bucket(addr: usize) -> &'static Lock<Vec<Waiter>, RawSpinLock> {
+    &BUCKETS[bucket_index(addr)]
+}
+
+/// Wakes a waiter that's been removed from its queue, while still holding
+/// the lock of the bucket it was in.
+fn unpark(waiter: Waiter) {
+    // Safety: The parked thread doesn't return before we release the
+    // bucket lock, so the token is still alive.
+    let token = unsafe { &*waiter.token };
+    token.state.store(UNPARKED, Release);
+    wake_one(&token.state);
+}
+
+/// Parks the current thread on `addr` until it's unparked, unless
+/// `validate` returns false.
+///
+/// `validate` is called while the bucket is locked, so it can't race with
+/// `unpark_one`'s callback. That's where the caller checks that it still
+/// needs to wait.
+pub fn park(addr: usize, validate: impl FnOnce() -> bool) {
+    let token = Token {
+        state: AtomicU32::new(PARKED),
+        addr: AtomicUsize::new(addr),
+    };
+    {
+        let mut queue = bucket(addr).lock();
+        if !validate() {
+            return;
+        }
+        queue.push(Waiter {
+            addr,
+            token: &token,
+        });
+    }
+    while token.state.load(Acquire) == PARKED {
+        wait(&token.state, PARKED);
+    }
+    // The unparking thread wakes us while holding the lock of the bucket
+    // we ended up in, so once we get that lock ourselves, it's done
+    // touching our token.
+    drop(bucket(token.addr.load(Relaxed)).lock());
+}
+
+/// Like `park`, but gives up at `deadline`, or once `interrupt` is raised
+/// if there is one. Returns whether it was unparked, rather than
+/// validation failing or it giving up.
+#[cfg(feature = "std")]
+pub fn park_until(
+    addr: usize,
+    validate: impl FnOnce() -> bool,
+    deadline: std::time::Instant,
+    interrupt: Option<&Interrupt>,
+) -> bool {
+    let token = Token {
+        state: AtomicU32::new(PARKED),
+        addr: AtomicUsize::new(addr),
+    };
+    {
+        let mut queue = bucket(addr).lock();
+        if !validate() {
+            return false;
+        }
+        queue.push(Waiter {
+            addr,
+            token: &token,
+        });
+    }
+    while token.state.load(Acquire) == PARKED {
+        if std::time::Instant::now() >= deadline {
+            break;
+        }
+        match interrupt {
+            Some(interrupt) => {
+                if wait_interruptible(&token.state, PARKED, Some(deadline), interrupt) {
+                    break;
+                }
+            }
+            None => wait_until(&token.state, PARKED, deadline),
+        }
+    }
+    // `requeue` changes our address with both buckets locked, so once we
+    // hold the lock of the bucket it names, it stays put.
+    let mut queue = loop {
+        let addr = token.addr.load(Relaxed);
+        let queue = bucket(addr).lock();
+        if token.addr.load(Relaxed) == addr {
+            break queue;
+        }
+    };
+    // With the lock, nobody can unpark us or move us anymore. If we're
+    // still in the queue, nobody did, so we take ourselves out.
+    if let Some(i) = queue.iter().position(|w| core::ptr::eq(w.token, &token)) {
+        queue.remove(i);
+        return false;
+    }
+    true
+}
+
+/// Unparks the thread that has been parked on `addr` the longest, if any.
+///
+/// Before it's woken, `callback` is called with the bucket locked, with
+/// whether there are other threads still parked on `addr`. Returns whether
+/// a thread was unparked.
+pub fn unpark_one(addr: usize, callback: impl FnOnce(bool)) -> bool {
+    let mut queue = bucket(addr).lock();
+    let Some(i) = queue.iter().position(|w| w.addr == addr) else {
+        callback(false);
+        return false;
+    };
+    let waiter = queue.remove(i);
+    callback(queue[i..].iter().any(|w| w.addr == addr));
+    unpark(waiter);
+    true
+}
+
+/// Unparks every thread parked on `addr`. Returns how many there were.
+pub fn unpark_all(addr: usize) -> usize {
+    let mut queue = bucket(addr).lock();
+    let (woken, rest): (Vec<_>, _) = mem::take(&mut *queue)
+        .into_iter()
+        .partition(|w| w.addr == addr);
+    *queue = rest;
+    let n = woken.len();
+    woken.into_iter().for_each(unpark);
+    n
+}
+
+/// Calls `f` with how many threads are parked on `addr`, with the bucket
+/// locked, so none park or are unparked before it returns.
+pub fn parked<R>(addr: usize, f: impl FnOnce(usize) -> R) -> R {
+    let queue = bucket(addr).lock();
+    f(queue.iter().filter(|w| w.addr == addr).count())
+}
+
+/// Moves the threads parked on `from` over to `to`, behind the ones parked
+/// there already, without waking them. Returns how many were moved.
+///
+/// `validate` is called with both buckets locked, with whether there are
+/// threads to move, and nothing is moved if it returns false. That's where
+/// the caller makes sure somebody will unpark them from `to`.
+pub fn requeue(from: usize, to: usize, validate: impl FnOnce(bool) -> bool) -> usize {
+    let (i, j) = (bucket_index(from), bucket_index(to));
+    // Always in the same order, so two requeues can't deadlock.
+    let mut low = BUCKETS[i.min(j)].lock();
+    let mut high = (i != j).then(|| BUCKETS[i.max(j)].lock());
+    let from_queue = match &mut high {
+        Some(high) if i > j => &mut **high,
+        _ => &mut *low,
+    };
+    if !validate(from_queue.iter().any(|w| w.addr == from)) {
+        return 0;
+    }
+    let (moved, rest): (Vec<_>, _) = mem::take(from_queue)
+        .into_iter()
+        .partition(|w| w.addr == from);
+    *from_queue = rest;
+    let to_queue = match &mut high {
+        Some(high) if j > i => &mut **high,
+        _ => &mut *low,
+    };
+    let n = moved.len();
+    for mut waiter in moved {
+        waiter.addr = to;
+        // Safety: It's still parked, and we hold the bucket lock.
+        unsafe { &*waiter.token }.addr.store(to, Relaxed);
+        to_queue.push(waiter);
+    }
+    n
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::platform::parking::{park, requeue, unpark_all, unpark_one};
+
+    #[test]
+    fn test_park_unpark() {
+        static FLAG: AtomicBool = AtomicBool::new(false);
+        let addr = ptr::from_ref(&FLAG).addr();
+        assert!(!unpark_one(addr, |more| assert!(!more)));
+        // Doesn't park if validation fails.
+        park(addr, || false);
+        thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(move || {
+                    while !FLAG.load(Relaxed) {
+                        park(addr, || !FLAG.load(Relaxed));
+                    }
+                });
+            }
+            thread::sleep(Duration::from_millis(10));
+            FLAG.store(true, Relaxed);
+            while unpark_one(addr, |_| {}) {}
+        });
+    }
+
+    #[test]
+    fn test_requeue() {
+        static FROM: AtomicBool = AtomicBool::new(false);
+        static TO: AtomicBool = AtomicBool::new(false);
+        let (from, to) = (ptr::from_ref(&FROM).addr(), ptr::from_ref(&TO).addr());
+        assert_eq!(requeue(from, to, |waiting| waiting), 0);
+        thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(move || {
+                    while !FROM.load(Relaxed) {
+                        park(from, || !FROM.load(Relaxed));
+                    }
+                });
+            }
+            thread::sleep(Duration::from_millis(10));
+            FROM.store(true, Relaxed);
+            assert_eq!(requeue(from, to, |_| false), 0);
+            // Whoever wasn't parked yet sees the flag instead.
+            let moved = requeue(from, to, |_| true);
+            assert!(!unpark_one(from, |_| {}));
+            assert_eq!(unpark_all(to), moved);
+        });
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_requeue_timed() {
+        use std::time::Instant;
+
+        use crate::platform::parking::{park_until, parked};
+
+        static FROM: AtomicBool = AtomicBool::new(false);
+        static TO: AtomicBool = AtomicBool::new(false);
+        let (from, to) = (ptr::from_ref(&FROM).addr(), ptr::from_ref(&TO).addr());
+        thread::scope(|s| {
+            let t = s.spawn(move || {
+                park_until(
+                    from,
+                    || true,
+                    Instant::now() + Duration::from_millis(50),
+                    None,
+                )
+            });
+            while parked(from, |n| n) == 0 {
+                thread::yield_now();
+            }
+            assert_eq!(requeue(from, to, |_| true), 1);
+            // It gives up from the queue it was moved to, and leaves it.
+            assert!(!t.join().unwrap());
+            assert_eq!(parked(to, |n| n), 0);
+            assert!(!unpark_one(to, |_| {}));
+        });
+    }
+}
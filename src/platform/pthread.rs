@@ -0,0 +1,394 @@
+//! libc's mutex and condition variable, to compare the hand-rolled ones
+//! against.
+//!
+//! [`RawPthreadMutex`] plugs `pthread_mutex_t` into `Lock`, so the same
+//! code can run on it and on `Mutex`, which is what chapter 9 of the book
+//! is measured against. A pthread mutex or condition variable may not be
+//! moved once it's been used, while a Rust value moves all the time, so
+//! each one lives in a box of its own. That box is only allocated when the
+//! lock is first used, which keeps `RawLock::INIT` (and `Lock::new`)
+//! const, the way std did before it had its own futex locks.
+//!
+//! The mutex is an error-checking one: unlocking it on another thread than
+//! the one that locked it is undefined behavior for a normal pthread mutex,
+//! and a `LockGuard` can be sent to another thread. That costs a check of
+//! the owner on every unlock.
+
+use alloc::boxed::Box;
+use core::mem::MaybeUninit;
+use core::ptr;
+#[cfg(not(target_vendor = "apple"))]
+use core::time::Duration;
+
+use crate::locks::{Lock, LockGuard, RawLock};
+#[cfg(not(target_vendor = "apple"))]
+use crate::platform::wait::Interrupt;
+use crate::sync_shim::plain_atomic::AtomicPtr;
+use crate::sync_shim::plain_atomic::Ordering::{Acquire, Release};
+
+/// Returns the object, after allocating and initializing it if this is its
+/// first use. `init` gets a pointer to uninitialized memory, and `destroy`
+/// an initialized object, for when another thread got there first.
+fn get_or_init<T>(p: &AtomicPtr<T>, init: unsafe fn(*mut T), destroy: unsafe fn(*mut T)) -> *mut T {
+    let existing = p.load(Acquire);
+    if !existing.is_null() {
+        return existing;
+    }
+    let new = Box::into_raw(Box::new(MaybeUninit::<T>::uninit())).cast::<T>();
+    // Safety: `new` is ours, and uninitialized.
+    unsafe { init(new) };
+    // Release publishes the initialized object, and Acquire on failure
+    // sees the one another thread published. Winning has nothing to see.
+    match p.compare_exchange(ptr::null_mut(), new, Release, Acquire) {
+        Ok(_) => new,
+        Err(existing) => {
+            // Safety: Nobody else has seen `new`.
+            unsafe {
+                destroy(new);
+                drop(Box::from_raw(new.cast::<MaybeUninit<T>>()));
+            }
+            existing
+        }
+    }
+}
+
+/// Frees an object allocated by `get_or_init`, after `destroy`ing it.
+///
+/// # Safety
+///
+/// `p` must be null or come from `get_or_init`, and not be used anymore.
+unsafe fn free<T>(p: *mut T, destroy: unsafe fn(*mut T)) {
+    if !p.is_null() {
+        destroy(p);
+        drop(Box::from_raw(p.cast::<MaybeUninit<T>>()));
+    }
+}
+
+unsafe fn init_mutex(m: *mut libc::pthread_mutex_t) {
+    let mut attr = MaybeUninit::<libc::pthread_mutexattr_t>::uninit();
+    let r = libc::pthread_mutexattr_init(attr.as_mut_ptr());
+    assert_eq!(r, 0, "pthread_mutexattr_init failed");
+    libc::pthread_mutexattr_settype(attr.as_mut_ptr(), libc::PTHREAD_MUTEX_ERRORCHECK);
+    let r = libc::pthread_mutex_init(m, attr.as_ptr());
+    libc::pthread_mutexattr_destroy(attr.as_mut_ptr());
+    assert_eq!(r, 0, "pthread_mutex_init failed");
+}
+
+unsafe fn destroy_mutex(m: *mut libc::pthread_mutex_t) {
+    libc::pthread_mutex_destroy(m);
+}
+
+/// A `pthread_mutex_t`, as a `RawLock`.
+#[derive(Debug)]
+pub struct RawPthreadMutex {
+    mutex: AtomicPtr<libc::pthread_mutex_t>,
+}
+
+impl RawPthreadMutex {
+    fn get(&self) -> *mut libc::pthread_mutex_t {
+        get_or_init(&self.mutex, init_mutex, destroy_mutex)
+    }
+}
+
+unsafe impl RawLock for RawPthreadMutex {
+    const INIT: Self = Self {
+        mutex: AtomicPtr::new(ptr::null_mut()),
+    };
+
+    /// Panics if this thread already holds the lock.
+    fn lock(&self) {
+        // Safety: The mutex is initialized, and stays put.
+        let r = unsafe { libc::pthread_mutex_lock(self.get()) };
+        assert_eq!(r, 0, "pthread_mutex_lock failed, relocking?");
+    }
+
+    fn try_lock(&self) -> bool {
+        // Safety: The mutex is initialized, and stays put.
+        unsafe { libc::pthread_mutex_trylock(self.get()) == 0 }
+    }
+
+    /// Panics if another thread locked it.
+    unsafe fn unlock(&self) {
+        let r = libc::pthread_mutex_unlock(self.get());
+        assert_eq!(r, 0, "pthread_mutex_unlock failed, on another thread?");
+    }
+
+    /// Sleeps in `pthread_mutex_timedlock`. macOS doesn't have that, so it
+    /// gets the default, which retries `try_lock`.
+    #[cfg(not(target_vendor = "apple"))]
+    fn try_lock_until(&self, deadline: std::time::Instant) -> bool {
+        // It takes a time on the realtime clock, so setting the clock
+        // meanwhile moves the deadline.
+        let left = deadline.saturating_duration_since(std::time::Instant::now());
+        let mut now = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // Safety: `now` is a valid timespec to write to.
+        unsafe { libc::clock_gettime(libc::CLOCK_REALTIME, &mut now) };
+        let at = Duration::new(now.tv_sec as u64, now.tv_nsec as u32).saturating_add(left);
+        let at = libc::timespec {
+            tv_sec: at.as_secs().try_into().unwrap_or(libc::time_t::MAX),
+            tv_nsec: at.subsec_nanos().into(),
+        };
+        // Safety: The mutex is initialized, and stays put.
+        match unsafe { libc::pthread_mutex_timedlock(self.get(), &at) } {
+            0 => true,
+            libc::ETIMEDOUT => false,
+            _ => panic!("pthread_mutex_timedlock failed, relocking?"),
+        }
+    }
+
+    /// Nothing can cut `pthread_mutex_timedlock` short, so this sleeps in
+    /// it for a millisecond at a time, and checks `interrupt` in between.
+    #[cfg(not(target_vendor = "apple"))]
+    fn try_lock_until_interrupted(
+        &self,
+        deadline: std::time::Instant,
+        interrupt: &Interrupt,
+    ) -> bool {
+        loop {
+            let slice = deadline.min(std::time::Instant::now() + Duration::from_millis(1));
+            if self.try_lock_until(slice) {
+                return true;
+            }
+            if interrupt.is_raised() || std::time::Instant::now() >= deadline {
+                return false;
+            }
+        }
+    }
+}
+
+impl Drop for RawPthreadMutex {
+    fn drop(&mut self) {
+        let m = *self.mutex.get_mut();
+        if m.is_null() {
+            return;
+        }
+        // Safety: Nothing else has a reference to the mutex anymore.
+        unsafe {
+            // Destroying a locked mutex is undefined behavior, and one can
+            // be left locked by forgetting its guard, so that one is leaked.
+            if libc::pthread_mutex_trylock(m) != 0 {
+                return;
+            }
+            libc::pthread_mutex_unlock(m);
+            free(m, destroy_mutex);
+        }
+    }
+}
+
+/// A `Lock` on a pthread mutex.
+pub type PthreadMutex<T> = Lock<T, RawPthreadMutex>;
+
+pub type PthreadMutexGuard<'a, T> = LockGuard<'a, T, RawPthreadMutex>;
+
+unsafe fn init_cond(c: *mut libc::pthread_cond_t) {
+    c.write(libc::PTHREAD_COND_INITIALIZER);
+}
+
+unsafe fn destroy_cond(c: *mut libc::pthread_cond_t) {
+    libc::pthread_cond_destroy(c);
+}
+
+/// A `pthread_cond_t`, to wait on with a [`PthreadMutex`].
+///
+/// Like with pthreads, all threads waiting at the same time must use the
+/// same mutex.
+#[derive(Debug)]
+pub struct PthreadCondvar {
+    cond: AtomicPtr<libc::pthread_cond_t>,
+}
+
+impl PthreadCondvar {
+    pub const fn new() -> Self {
+        Self {
+            cond: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    fn get(&self) -> *mut libc::pthread_cond_t {
+        get_or_init(&self.cond, init_cond, destroy_cond)
+    }
+
+    /// Unlocks the mutex while waiting for a notification, and locks it
+    /// again before returning. Might also return spuriously.
+    pub fn wait<'a, T>(&self, guard: PthreadMutexGuard<'a, T>) -> PthreadMutexGuard<'a, T> {
+        // Safety: Both are initialized and stay put, and the guard means
+        // this thread holds the mutex.
+        let r = unsafe { libc::pthread_cond_wait(self.get(), guard.raw().get()) };
+        assert_eq!(r, 0, "pthread_cond_wait failed");
+        guard
+    }
+
+    pub fn notify_one(&self) {
+        // Safety: The condition variable is initialized, and stays put.
+        unsafe { libc::pthread_cond_signal(self.get()) };
+    }
+
+    pub fn notify_all(&self) {
+        // Safety: The condition variable is initialized, and stays put.
+        unsafe { libc::pthread_cond_broadcast(self.get()) };
+    }
+}
+
+impl Default for PthreadCondvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for PthreadCondvar {
+    fn drop(&mut self) {
+        // Safety: Nothing can be waiting on it anymore.
+        unsafe { free(*self.cond.get_mut(), destroy_cond) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::cancel::CancellationToken;
+    use crate::errors::WaitError;
+    use crate::locks::{Lock, RawFutexMutex, RawLock, RawMutex, RawSpinLock};
+    use crate::platform::pthread::{PthreadCondvar, PthreadMutex, RawPthreadMutex};
+
+    #[test]
+    fn test_pthread_mutex() {
+        const N: usize = if cfg!(miri) { 100 } else { 10_000 };
+        let mutex = PthreadMutex::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..N {
+                        *mutex.lock() += 1;
+                    }
+                });
+            }
+        });
+        assert_eq!(mutex.into_inner(), 4 * N);
+
+        static STATIC: PthreadMutex<()> = PthreadMutex::new(());
+        let guard = STATIC.lock();
+        assert!(STATIC.try_lock().is_none());
+        drop(guard);
+        assert!(STATIC.try_lock().is_some());
+    }
+
+    #[test]
+    fn test_dropping_a_locked_mutex() {
+        let mutex = PthreadMutex::new(());
+        mem::forget(mutex.lock());
+        drop(mutex);
+        // Never used, so never allocated.
+        drop(PthreadMutex::new(()));
+    }
+
+    #[test]
+    #[should_panic = "relocking"]
+    fn test_relocking_panics() {
+        let raw = RawPthreadMutex::INIT;
+        raw.lock();
+        raw.lock();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "Miri doesn't support pthread_mutex_timedlock")]
+    fn test_timed_lock() {
+        let mutex = PthreadMutex::new(0);
+        let guard = mutex.lock();
+        thread::scope(|s| {
+            s.spawn(|| assert!(mutex.try_lock_for(Duration::from_millis(5)).is_err()));
+            let waiter = s.spawn(|| *mutex.try_lock_for(Duration::from_secs(10)).unwrap() += 1);
+            thread::sleep(Duration::from_millis(10));
+            drop(guard);
+            waiter.join().unwrap();
+        });
+        assert_eq!(mutex.into_inner(), 1);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "Miri doesn't support pthread_mutex_timedlock")]
+    fn test_cancelled_lock() {
+        let mutex = PthreadMutex::new(());
+        let token = CancellationToken::new();
+        let guard = mutex.lock();
+        thread::scope(|s| {
+            let waiter = s.spawn(|| {
+                let deadline = Instant::now() + Duration::from_secs(60);
+                mutex.try_lock_until_cancellable(deadline, &token).err()
+            });
+            thread::sleep(Duration::from_millis(10));
+            token.cancel();
+            assert_eq!(waiter.join().unwrap(), Some(WaitError::Cancelled));
+        });
+        drop(guard);
+    }
+
+    #[test]
+    fn test_condvar() {
+        let queue = PthreadMutex::new(Vec::new());
+        let condvar = PthreadCondvar::new();
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..10 {
+                    queue.lock().push(i);
+                    condvar.notify_one();
+                }
+            });
+            let mut received = Vec::new();
+            let mut guard = queue.lock();
+            while received.len() < 10 {
+                match guard.pop() {
+                    Some(i) => received.push(i),
+                    None => guard = condvar.wait(guard),
+                }
+            }
+            drop(guard);
+            received.sort_unstable();
+            assert_eq!(received, (0..10).collect::<Vec<_>>());
+        });
+        condvar.notify_all();
+    }
+
+    const INCREMENTS: u32 = 1_000_000;
+
+    /// Returns the time per increment of a counter, incremented by one
+    /// thread or by four at the same time.
+    fn increment<R: RawLock + Sync>(threads: u32) -> Duration {
+        let counter = Lock::<u32, R>::new(0);
+        let start = Instant::now();
+        thread::scope(|s| {
+            for _ in 0..threads {
+                s.spawn(|| {
+                    for _ in 0..INCREMENTS / threads {
+                        *counter.lock() += 1;
+                    }
+                });
+            }
+        });
+        start.elapsed() / INCREMENTS
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_against_pthread`.
+    #[test]
+    #[ignore]
+    fn bench_against_pthread() {
+        for threads in [1, 4] {
+            println!("{threads} thread(s):");
+            println!(
+                "  RawPthreadMutex: {:?}",
+                increment::<RawPthreadMutex>(threads)
+            );
+            println!("         RawMutex: {:?}", increment::<RawMutex>(threads));
+            println!(
+                "    RawFutexMutex: {:?}",
+                increment::<RawFutexMutex>(threads)
+            );
+            println!("      RawSpinLock: {:?}", increment::<RawSpinLock>(threads));
+        }
+    }
+}
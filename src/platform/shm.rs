@@ -0,0 +1,447 @@
+//! Named shared memory, to put primitives in that work across processes.
+//!
+//! [`SharedMemory::open`] maps the region with a given name, creating it if
+//! it doesn't exist yet, and hands out a `&T` into it. It's `shm_open` and
+//! `mmap` on Unix, and a file mapping backed by the paging file on Windows.
+//! The other processes might have been built from other code, so the region
+//! starts with a header recording the size, alignment and version of `T`,
+//! and a process that expects a different layout gets an error instead of
+//! a misread `T`. The types that can go in there implement [`Shared`].
+
+use core::marker::PhantomData;
+use core::mem::{align_of, size_of};
+use core::ops::Deref;
+use core::sync::atomic::Ordering::{Acquire, Release};
+// Always core's, even with `portable-atomic`: another process can only
+// share native ones, not a lock that stands in for them.
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64};
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A type that can live in shared memory.
+///
+/// # Safety
+///
+/// The type must be `#[repr(C)]` (or a primitive), so every program that
+/// uses it agrees on its layout. It can't contain pointers or anything
+/// else that only means something in one process, and all zero bytes must
+/// be a valid value of it, since that's what new shared memory holds.
+pub unsafe trait Shared: Sync {
+    /// Bump this when the layout changes, so that processes still using
+    /// the old one are turned away.
+    const VERSION: u32 = 0;
+}
+
+unsafe impl Shared for AtomicBool {}
+unsafe impl Shared for AtomicU32 {}
+unsafe impl Shared for AtomicU64 {}
+unsafe impl<T: Shared, const N: usize> Shared for [T; N] {
+    const VERSION: u32 = T::VERSION;
+}
+
+const MAGIC: u32 = u32::from_le_bytes(*b"ALsh");
+/// The version of the header itself.
+const HEADER_VERSION: u32 = 1;
+
+const UNINITIALIZED: u32 = 0;
+const INITIALIZING: u32 = 1;
+const READY: u32 = 2;
+
+/// How long to wait for another process to finish creating the region,
+/// before assuming it died while doing that.
+const CREATE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The start of the region. The other fields are written once, before
+/// `state` becomes `READY`.
+#[repr(C)]
+struct Header {
+    state: AtomicU32,
+    magic: u32,
+    header_version: u32,
+    version: u32,
+    size: u64,
+    align: u64,
+}
+
+/// A `T` in named shared memory.
+pub struct SharedMemory<T: Shared> {
+    map: imp::Mapping,
+    _t: PhantomData<T>,
+}
+
+// Safety: It's just a `&T`.
+unsafe impl<T: Shared> Send for SharedMemory<T> {}
+unsafe impl<T: Shared> Sync for SharedMemory<T> {}
+
+impl<T: Shared> SharedMemory<T> {
+    const OFFSET: usize = size_of::<Header>().next_multiple_of(align_of::<T>());
+    const LEN: usize = Self::OFFSET + size_of::<T>();
+
+    /// Maps the region called `name`, creating it (holding zeros) if it
+    /// doesn't exist yet. The name can't contain slashes or backslashes.
+    ///
+    /// Fails with `InvalidData` if the region was created for a `T` with a
+    /// different size, alignment or version.
+    pub fn open(name: &str) -> io::Result<Self> {
+        const { assert!(align_of::<T>() <= 4096, "alignment above a page") };
+        if name.is_empty() || name.contains(['/', '\\', '\0']) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid shared memory name",
+            ));
+        }
+        let this = Self {
+            map: imp::Mapping::open(name, Self::LEN)?,
+            _t: PhantomData,
+        };
+        this.init(name)?;
+        Ok(this)
+    }
+
+    fn init(&self, name: &str) -> io::Result<()> {
+        // The mapping is page aligned, and at least `LEN` long.
+        let header = self.map.ptr.cast::<Header>();
+        // Safety: Only the state is shared before it's `READY`.
+        let state = unsafe { &(*header).state };
+        if state
+            .compare_exchange(UNINITIALIZED, INITIALIZING, Acquire, Acquire)
+            .is_ok()
+        {
+            // Safety: Setting `INITIALIZING` gives us the rest of the header
+            // to ourselves, and the rest of the region stays zero, which is
+            // a valid `T`.
+            unsafe {
+                (*header).magic = MAGIC;
+                (*header).header_version = HEADER_VERSION;
+                (*header).version = T::VERSION;
+                (*header).size = size_of::<T>() as u64;
+                (*header).align = align_of::<T>() as u64;
+            }
+            state.store(READY, Release);
+            return Ok(());
+        }
+        wait_until(|| state.load(Acquire) == READY)?;
+        // Safety: The header isn't written to once it's `READY`.
+        let header = unsafe { &*header };
+        let expected = (MAGIC, HEADER_VERSION, T::VERSION);
+        let found = (header.magic, header.header_version, header.version);
+        let layout = (size_of::<T>() as u64, align_of::<T>() as u64);
+        if found != expected || (header.size, header.align) != layout {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "shared memory `{name}` holds a different layout: version {}, size {}, \
+                     alignment {}, instead of version {}, size {}, alignment {}",
+                    header.version,
+                    header.size,
+                    header.align,
+                    T::VERSION,
+                    layout.0,
+                    layout.1,
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Removes the name, so the next `open` creates a new region. Processes
+    /// that have it open keep using the old one. On Windows, a region goes
+    /// away by itself when the last process closes it, so this does nothing.
+    pub fn unlink(name: &str) -> io::Result<()> {
+        imp::unlink(name)
+    }
+}
+
+impl<T: Shared> Deref for SharedMemory<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: `init` checked (or wrote) the layout, and the memory
+        // holds a valid `T`, if only zeros.
+        unsafe { &*self.map.ptr.add(Self::OFFSET).cast::<T>() }
+    }
+}
+
+/// Waits, for at most `CREATE_TIMEOUT`, for the process creating a region
+/// to get far enough.
+fn wait_until(mut done: impl FnMut() -> bool) -> io::Result<()> {
+    let deadline = Instant::now() + CREATE_TIMEOUT;
+    while !done() {
+        if Instant::now() >= deadline {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "shared memory was never fully created",
+            ));
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+mod imp {
+    use core::mem::MaybeUninit;
+    use core::ptr;
+    use std::ffi::CString;
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    use super::wait_until;
+
+    pub(super) struct Mapping {
+        pub(super) ptr: *mut u8,
+        len: usize,
+    }
+
+    fn path(name: &str) -> io::Result<CString> {
+        Ok(CString::new(format!("/{name}"))?)
+    }
+
+    fn size(fd: &OwnedFd) -> io::Result<u64> {
+        let mut stat = MaybeUninit::<libc::stat>::uninit();
+        // Safety: `stat` is valid to write to, and written on success.
+        if unsafe { libc::fstat(fd.as_raw_fd(), stat.as_mut_ptr()) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unsafe { stat.assume_init() }.st_size as u64)
+    }
+
+    impl Mapping {
+        pub(super) fn open(name: &str, len: usize) -> io::Result<Self> {
+            let path = path(name)?;
+            let mode: libc::c_uint = 0o600;
+            // Whoever creates the region gives it its size, and the others
+            // wait for that, so it's never resized to a different layout.
+            // Safety: `path` is a valid C string.
+            let created = unsafe {
+                libc::shm_open(
+                    path.as_ptr(),
+                    libc::O_RDWR | libc::O_CREAT | libc::O_EXCL,
+                    mode,
+                )
+            };
+            let fd = if created != -1 {
+                // Safety: We just opened it.
+                let fd = unsafe { OwnedFd::from_raw_fd(created) };
+                // Safety: `fd` is open.
+                if unsafe { libc::ftruncate(fd.as_raw_fd(), len as libc::off_t) } == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                fd
+            } else {
+                let error = io::Error::last_os_error();
+                if error.kind() != io::ErrorKind::AlreadyExists {
+                    return Err(error);
+                }
+                // Safety: `path` is a valid C string.
+                let opened = unsafe { libc::shm_open(path.as_ptr(), libc::O_RDWR, mode) };
+                if opened == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                // Safety: We just opened it.
+                let fd = unsafe { OwnedFd::from_raw_fd(opened) };
+                wait_until(|| size(&fd).is_ok_and(|s| s != 0))?;
+                // It might be rounded up to a page.
+                if size(&fd)? < len as u64 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("shared memory `{name}` is too small for this layout"),
+                    ));
+                }
+                fd
+            };
+            // Safety: Maps `len` bytes of the region, which has at least
+            // that many, somewhere new.
+            let ptr = unsafe {
+                libc::mmap(
+                    ptr::null_mut(),
+                    len,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_SHARED,
+                    fd.as_raw_fd(),
+                    0,
+                )
+            };
+            if ptr == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self {
+                ptr: ptr.cast(),
+                len,
+            })
+        }
+    }
+
+    impl Drop for Mapping {
+        fn drop(&mut self) {
+            // Safety: Nothing borrows from the mapping anymore.
+            unsafe { libc::munmap(self.ptr.cast(), self.len) };
+        }
+    }
+
+    pub(super) fn unlink(name: &str) -> io::Result<()> {
+        let path = path(name)?;
+        // Safety: `path` is a valid C string.
+        if unsafe { libc::shm_unlink(path.as_ptr()) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use core::ptr;
+    use std::io;
+
+    use windows_sys::Win32::Foundation::{CloseHandle, HANDLE, INVALID_HANDLE_VALUE};
+    use windows_sys::Win32::System::Memory::{
+        CreateFileMappingW, MapViewOfFile, UnmapViewOfFile, FILE_MAP_ALL_ACCESS,
+        MEMORY_MAPPED_VIEW_ADDRESS, PAGE_READWRITE,
+    };
+
+    pub(super) struct Mapping {
+        pub(super) ptr: *mut u8,
+        handle: HANDLE,
+    }
+
+    impl Mapping {
+        pub(super) fn open(name: &str, len: usize) -> io::Result<Self> {
+            let name: Vec<u16> = format!("Local\\{name}").encode_utf16().chain([0]).collect();
+            let size = len as u64;
+            // Safety: `name` is a valid wide C string. If the mapping
+            // exists, this opens it, and ignores the size, so mapping more
+            // than it has fails below.
+            let handle = unsafe {
+                CreateFileMappingW(
+                    INVALID_HANDLE_VALUE,
+                    ptr::null(),
+                    PAGE_READWRITE,
+                    (size >> 32) as u32,
+                    size as u32,
+                    name.as_ptr(),
+                )
+            };
+            if handle.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            // Safety: `handle` is a valid file mapping.
+            let view = unsafe { MapViewOfFile(handle, FILE_MAP_ALL_ACCESS, 0, 0, len) };
+            if view.Value.is_null() {
+                let error = io::Error::last_os_error();
+                // Safety: We own the handle.
+                unsafe { CloseHandle(handle) };
+                return Err(error);
+            }
+            Ok(Self {
+                ptr: view.Value.cast(),
+                handle,
+            })
+        }
+    }
+
+    impl Drop for Mapping {
+        fn drop(&mut self) {
+            // Safety: Nothing borrows from the mapping anymore, and we own
+            // the handle.
+            unsafe {
+                UnmapViewOfFile(MEMORY_MAPPED_VIEW_ADDRESS {
+                    Value: self.ptr.cast(),
+                });
+                CloseHandle(self.handle);
+            }
+        }
+    }
+
+    pub(super) fn unlink(_: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::io;
+    use std::process::Command;
+    use std::sync::atomic::AtomicU32;
+    use std::sync::atomic::Ordering::Relaxed;
+
+    use crate::platform::shm::{Shared, SharedMemory};
+
+    /// A name no other test run uses at the same time.
+    fn name(test: &str) -> String {
+        format!("al-{}-{test}", std::process::id())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "Miri doesn't support shm_open")]
+    fn test_mappings_share_memory() {
+        let name = name("share");
+        let a = SharedMemory::<AtomicU32>::open(&name).unwrap();
+        let b = SharedMemory::<AtomicU32>::open(&name).unwrap();
+        assert_eq!(b.load(Relaxed), 0);
+        a.store(42, Relaxed);
+        assert_eq!(b.load(Relaxed), 42);
+        SharedMemory::<AtomicU32>::unlink(&name).unwrap();
+        // Still mapped after unlinking, but a new open gets a new region.
+        assert_eq!(a.load(Relaxed), 42);
+        let c = SharedMemory::<AtomicU32>::open(&name).unwrap();
+        assert_eq!(c.load(Relaxed), if cfg!(windows) { 42 } else { 0 });
+        SharedMemory::<AtomicU32>::unlink(&name).unwrap();
+    }
+
+    #[repr(C)]
+    struct NewCounter(AtomicU32);
+
+    unsafe impl Shared for NewCounter {
+        const VERSION: u32 = 1;
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "Miri doesn't support shm_open")]
+    fn test_rejects_other_layouts() {
+        let name = name("layout");
+        let _old = SharedMemory::<AtomicU32>::open(&name).unwrap();
+        let e = SharedMemory::<NewCounter>::open(&name).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(SharedMemory::<[AtomicU32; 2048]>::open(&name).is_err());
+        assert_eq!(
+            SharedMemory::<AtomicU32>::open("no/slashes")
+                .err()
+                .unwrap()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+        SharedMemory::<AtomicU32>::unlink(&name).unwrap();
+    }
+
+    const CHILD_ENV: &str = "ATOMICS_AND_LOCKS_SHM_CHILD";
+
+    #[test]
+    #[cfg_attr(miri, ignore = "Miri can't spawn processes")]
+    fn test_shared_between_processes() {
+        let name = name("processes");
+        let counter = SharedMemory::<AtomicU32>::open(&name).unwrap();
+        counter.store(1, Relaxed);
+        let child = Command::new(env::current_exe().unwrap())
+            .args(["--exact", "platform::shm::tests::child_increments"])
+            .args(["--ignored", "--test-threads=1"])
+            .env(CHILD_ENV, &name)
+            .output()
+            .unwrap();
+        assert!(child.status.success(), "{child:?}");
+        assert_eq!(counter.load(Relaxed), 2);
+        SharedMemory::<AtomicU32>::unlink(&name).unwrap();
+    }
+
+    /// The other process in `test_shared_between_processes`.
+    #[test]
+    #[ignore]
+    fn child_increments() {
+        if let Ok(name) = env::var(CHILD_ENV) {
+            let counter = SharedMemory::<AtomicU32>::open(&name).unwrap();
+            counter.fetch_add(1, Relaxed);
+        }
+    }
+}
@@ -0,0 +1,654 @@
+//! The wait/wake primitive the blocking locks are built on, like the
+//! `atomic-wait` crate from chapter 8.
+//!
+//! `wait` blocks while the atomic still holds `expected`, `wait_timeout`
+//! and `wait_until` do too but give up after a while or at a deadline, and
+//! `wake_one` and `wake_all` wake threads waiting on it. Waits can return spuriously, so
+//! callers always check the value again. The backend is picked by feature:
+//!
+//! - `spin-only`: `wait` spins until the value changes, and waking does
+//!   nothing. Works without an OS, but only makes sense if lock holders
+//!   don't get preempted.
+//! - `futex`: the Linux futex syscalls, see [`platform::futex`](crate::platform::futex)
+//!   for the rest of them. Ignored on other platforms.
+//! - `wait-on-address`: `WaitOnAddress` and `WakeByAddressSingle`/`All`,
+//!   the Windows equivalent. Ignored on other platforms.
+//! - `os-sync`: `os_sync_wait_on_address` and friends on macOS 14.4 and
+//!   later, or the `__ulock_wait`/`__ulock_wake` they replace (which
+//!   libc++ uses too) on older versions. Ignored on other platforms.
+//! - `wasm-atomic-wait`: the `memory.atomic.wait32` and
+//!   `memory.atomic.notify` instructions of threaded WebAssembly. Ignored
+//!   unless the target is `wasm32` with the `atomics` target feature,
+//!   which takes a nightly compiler, and rebuilding std with it:
+//!   `RUSTFLAGS="-C target-feature=+atomics,+bulk-memory" cargo +nightly
+//!   build --target wasm32-unknown-unknown -Z build-std=panic_abort,std`.
+//!   Browsers don't let the main thread wait, so only block on workers.
+//! - `std-condvar`: a global table of `Mutex`/`Condvar` pairs keyed by the
+//!   address of the atomic. Works wherever std does, except on `wasm32`
+//!   without shared memory, where std's `Condvar` can't block.
+//!
+//! `spin-only` wins if enabled, then `futex` (on Linux), `wait-on-address`
+//! (on Windows), `os-sync` (on macOS) or `wasm-atomic-wait` (on threaded
+//! `wasm32`), then `std-condvar`. With none of them applicable, it falls
+//! back to spinning. That's what `wasm32` without shared memory gets,
+//! where there's only one thread, so nothing can wake a waiter anyway.
+//!
+//! With `std`, a wait can also be cut short by an [`Interrupt`], with
+//! `wait_interruptible`. A thread can only sleep on one address, so the
+//! interrupt keeps a list of how to wake each thread waiting with it, and
+//! raising it wakes them all.
+
+#[cfg(feature = "std")]
+use core::sync::atomic::Ordering::SeqCst;
+use core::time::Duration;
+
+use crate::sync_shim::plain_atomic::AtomicU32;
+
+/// Which backend this build uses, for benchmarks and bug reports.
+pub const BACKEND: &str = imp::BACKEND;
+
+/// Blocks until woken up, unless the atomic no longer holds `expected`.
+/// Might also return spuriously.
+#[inline]
+pub fn wait(a: &AtomicU32, expected: u32) {
+    imp::wait(a, expected);
+}
+
+/// Like `wait`, but gives up after about `timeout`. Only spinning can't
+/// tell time, so with `spin-only`, this returns right away, and the caller
+/// has to keep track of the time, like it does for spurious wake-ups.
+#[inline]
+pub fn wait_timeout(a: &AtomicU32, expected: u32, timeout: Duration) {
+    imp::wait_timeout(a, expected, timeout);
+}
+
+/// Like `wait_timeout`, but gives up at `deadline`, so a caller that waits
+/// in a loop can pass the same deadline every time instead of adding up
+/// timeouts that each overshoot a little. Doesn't wait once it has passed.
+#[cfg(feature = "std")]
+#[inline]
+pub fn wait_until(a: &AtomicU32, expected: u32, deadline: std::time::Instant) {
+    let left = deadline.saturating_duration_since(std::time::Instant::now());
+    if !left.is_zero() {
+        imp::wait_timeout(a, expected, left);
+    }
+}
+
+#[inline]
+pub fn wake_one(a: &AtomicU32) {
+    imp::wake_one(a);
+}
+
+#[inline]
+pub fn wake_all(a: &AtomicU32) {
+    imp::wake_all(a);
+}
+
+/// A flag that, once raised, wakes every thread waiting with it, whatever
+/// it's waiting on. Each waiter registers how to wake it for as long as it
+/// waits, with `while_registered`.
+///
+/// A waiter can check the flag and go to sleep right after `raise` called
+/// its wake function, missing it, so `raise` keeps calling the wake
+/// functions until every waiter has seen the flag and unregistered.
+#[cfg(feature = "std")]
+pub struct Interrupt {
+    raised: crate::sync_shim::plain_atomic::AtomicBool,
+    wakers: std::sync::Mutex<Vec<WakeFn>>,
+}
+
+/// A registered wake function, with its lifetime erased. It's only called
+/// while registered, and `while_registered` unregisters it before it's gone.
+#[cfg(feature = "std")]
+struct WakeFn(*const (dyn Fn() + Sync + 'static));
+
+// Safety: The function is Sync, so calling it from the raising thread is
+// fine.
+#[cfg(feature = "std")]
+unsafe impl Send for WakeFn {}
+
+#[cfg(feature = "std")]
+impl Interrupt {
+    pub const fn new() -> Self {
+        Self {
+            raised: crate::sync_shim::plain_atomic::AtomicBool::new(false),
+            wakers: std::sync::Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn is_raised(&self) -> bool {
+        self.raised.load(SeqCst)
+    }
+
+    /// Raises the flag, and wakes everyone waiting with it. Returns once
+    /// they've all stopped waiting. Only the first call does anything.
+    pub fn raise(&self) {
+        if self.raised.swap(true, SeqCst) {
+            return;
+        }
+        loop {
+            let wakers = self.wakers();
+            if wakers.is_empty() {
+                return;
+            }
+            for wake in wakers.iter() {
+                // Safety: It's still registered, so it's still there.
+                unsafe { (*wake.0)() };
+            }
+            drop(wakers);
+            std::thread::yield_now();
+        }
+    }
+
+    /// Runs `f` with `wake` registered, for `raise` to call. `f` should
+    /// check `is_raised` before every time it goes to sleep, and return
+    /// once it's raised.
+    pub fn while_registered<R>(&self, wake: &(dyn Fn() + Sync), f: impl FnOnce() -> R) -> R {
+        struct Registered<'a>(&'a Interrupt, *const (dyn Fn() + Sync + 'static));
+
+        impl Drop for Registered<'_> {
+            fn drop(&mut self) {
+                let mut wakers = self.0.wakers();
+                let i = wakers
+                    .iter()
+                    .position(|w| core::ptr::addr_eq(w.0, self.1))
+                    .unwrap();
+                wakers.swap_remove(i);
+            }
+        }
+
+        // Safety: Only the lifetime changes, and it's unregistered before
+        // `wake` goes away, even if `f` panics.
+        let wake: *const (dyn Fn() + Sync + 'static) =
+            unsafe { core::mem::transmute(core::ptr::from_ref(wake)) };
+        self.wakers().push(WakeFn(wake));
+        let _registered = Registered(self, wake);
+        // The flag is read after registering, and `raise` sets it before
+        // looking at the list, so either we see it, or it sees us.
+        f()
+    }
+
+    fn wakers(&self) -> std::sync::MutexGuard<'_, Vec<WakeFn>> {
+        // Nothing panics while holding it, so poisoning doesn't matter.
+        self.wakers
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for Interrupt {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl core::fmt::Debug for Interrupt {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Interrupt")
+            .field("raised", &self.is_raised())
+            .field("waiting", &self.wakers().len())
+            .finish()
+    }
+}
+
+/// Like `wait`, or `wait_until` with a deadline, but also returns once
+/// `interrupt` is raised. Returns whether it was. With `spin-only`, it
+/// doesn't wait at all, since spinning on the atomic would never notice
+/// the interrupt, so the caller polls.
+#[cfg(feature = "std")]
+pub fn wait_interruptible(
+    a: &AtomicU32,
+    expected: u32,
+    deadline: Option<std::time::Instant>,
+    interrupt: &Interrupt,
+) -> bool {
+    interrupt.while_registered(&|| wake_all(a), || {
+        if interrupt.is_raised() {
+            return true;
+        }
+        match deadline {
+            Some(deadline) => wait_until(a, expected, deadline),
+            None if BACKEND == "spin-only" => wait_timeout(a, expected, Duration::ZERO),
+            None => wait(a, expected),
+        }
+        interrupt.is_raised()
+    })
+}
+
+#[cfg(all(not(feature = "spin-only"), feature = "futex", target_os = "linux"))]
+mod imp {
+    use core::time::Duration;
+
+    use crate::platform::futex;
+    use crate::sync_shim::plain_atomic::AtomicU32;
+
+    pub const BACKEND: &str = "futex";
+
+    pub fn wait(a: &AtomicU32, expected: u32) {
+        futex::wait(a, expected, None);
+    }
+
+    pub fn wait_timeout(a: &AtomicU32, expected: u32, timeout: Duration) {
+        futex::wait(a, expected, futex::now().checked_add(timeout));
+    }
+
+    pub fn wake_one(a: &AtomicU32) {
+        futex::wake(a, 1);
+    }
+
+    pub fn wake_all(a: &AtomicU32) {
+        futex::wake(a, u32::MAX);
+    }
+}
+
+#[cfg(all(not(feature = "spin-only"), feature = "wait-on-address", windows))]
+mod imp {
+    use core::ptr;
+    use core::time::Duration;
+
+    use windows_sys::Win32::System::Threading::{
+        WaitOnAddress, WakeByAddressAll, WakeByAddressSingle, INFINITE,
+    };
+
+    use crate::sync_shim::plain_atomic::AtomicU32;
+
+    pub const BACKEND: &str = "wait-on-address";
+
+    pub fn wait(a: &AtomicU32, expected: u32) {
+        wait_ms(a, expected, INFINITE);
+    }
+
+    pub fn wait_timeout(a: &AtomicU32, expected: u32, timeout: Duration) {
+        // Rounded up, so it doesn't turn into a busy loop under a
+        // millisecond before the caller's deadline.
+        let ms = timeout.as_nanos().div_ceil(1_000_000);
+        wait_ms(a, expected, ms.try_into().unwrap_or(INFINITE - 1));
+    }
+
+    fn wait_ms(a: &AtomicU32, expected: u32, ms: u32) {
+        // Safety: Windows only reads the four bytes of the atomic (and
+        // compares them with `expected` before sleeping).
+        unsafe {
+            WaitOnAddress(
+                ptr::from_ref(a).cast(),
+                ptr::from_ref(&expected).cast(),
+                4,
+                ms,
+            );
+        }
+    }
+
+    pub fn wake_one(a: &AtomicU32) {
+        // Safety: Waking never touches the atomic itself.
+        unsafe { WakeByAddressSingle(ptr::from_ref(a).cast()) };
+    }
+
+    pub fn wake_all(a: &AtomicU32) {
+        // Safety: Waking never touches the atomic itself.
+        unsafe { WakeByAddressAll(ptr::from_ref(a).cast()) };
+    }
+}
+
+#[cfg(all(not(feature = "spin-only"), feature = "os-sync", target_os = "macos"))]
+mod imp {
+    use core::ffi::{c_int, c_void, CStr};
+    use core::mem;
+    use core::ptr;
+    use core::time::Duration;
+
+    use crate::sync_shim::plain_atomic::Ordering::Relaxed;
+    use crate::sync_shim::plain_atomic::{AtomicPtr, AtomicU32};
+
+    pub const BACKEND: &str = "os-sync";
+
+    // From <os/os_sync_wait_on_address.h>. They're only there since macOS
+    // 14.4, so they're looked up when first used instead of linked to.
+    type Wait = unsafe extern "C" fn(*mut c_void, u64, usize, u32) -> c_int;
+    type WaitWithTimeout = unsafe extern "C" fn(*mut c_void, u64, usize, u32, u32, u64) -> c_int;
+    type Wake = unsafe extern "C" fn(*mut c_void, usize, u32) -> c_int;
+    const OS_CLOCK_MACH_ABSOLUTE_TIME: u32 = 32;
+
+    static WAIT: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+    static WAIT_WITH_TIMEOUT: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+    static WAKE_ANY: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+    static WAKE_ALL: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+
+    // The private API they replace, which has been there since macOS 10.12.
+    extern "C" {
+        fn __ulock_wait(operation: u32, addr: *mut c_void, value: u64, timeout_us: u32) -> c_int;
+        fn __ulock_wake(operation: u32, addr: *mut c_void, wake_value: u64) -> c_int;
+    }
+    const UL_COMPARE_AND_WAIT: u32 = 1;
+    const ULF_WAKE_ALL: u32 = 0x100;
+    const ULF_NO_ERRNO: u32 = 0x100_0000;
+
+    /// Looks `name` up the first time, and remembers whether it's there.
+    ///
+    /// Safety: `F` must be the function pointer type of `name`.
+    unsafe fn lookup<F: Copy>(cache: &AtomicPtr<c_void>, name: &CStr) -> Option<F> {
+        // Null is not looked up yet, dangling is not there.
+        let mut f = cache.load(Relaxed);
+        if f.is_null() {
+            f = libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr());
+            if f.is_null() {
+                f = ptr::dangling_mut();
+            }
+            cache.store(f, Relaxed);
+        }
+        (f != ptr::dangling_mut()).then(|| mem::transmute_copy(&f))
+    }
+
+    fn addr(a: &AtomicU32) -> *mut c_void {
+        ptr::from_ref(a).cast_mut().cast()
+    }
+
+    // Safety (for all of these): The functions have the declared types, and
+    // only read the four bytes of the atomic (to compare them with
+    // `expected` before sleeping), or don't touch the atomic at all.
+
+    pub fn wait(a: &AtomicU32, expected: u32) {
+        unsafe {
+            match lookup::<Wait>(&WAIT, c"os_sync_wait_on_address") {
+                Some(f) => f(addr(a), expected.into(), 4, 0),
+                None => __ulock_wait(
+                    UL_COMPARE_AND_WAIT | ULF_NO_ERRNO,
+                    addr(a),
+                    expected.into(),
+                    0,
+                ),
+            };
+        }
+    }
+
+    pub fn wait_timeout(a: &AtomicU32, expected: u32, timeout: Duration) {
+        if timeout.is_zero() {
+            // Zero means no timeout to `__ulock_wait`.
+            return;
+        }
+        unsafe {
+            match lookup::<WaitWithTimeout>(
+                &WAIT_WITH_TIMEOUT,
+                c"os_sync_wait_on_address_with_timeout",
+            ) {
+                Some(f) => f(
+                    addr(a),
+                    expected.into(),
+                    4,
+                    0,
+                    OS_CLOCK_MACH_ABSOLUTE_TIME,
+                    timeout.as_nanos().try_into().unwrap_or(u64::MAX),
+                ),
+                None => __ulock_wait(
+                    UL_COMPARE_AND_WAIT | ULF_NO_ERRNO,
+                    addr(a),
+                    expected.into(),
+                    timeout.as_micros().try_into().unwrap_or(u32::MAX).max(1),
+                ),
+            };
+        }
+    }
+
+    pub fn wake_one(a: &AtomicU32) {
+        unsafe {
+            match lookup::<Wake>(&WAKE_ANY, c"os_sync_wake_by_address_any") {
+                Some(f) => f(addr(a), 4, 0),
+                None => __ulock_wake(UL_COMPARE_AND_WAIT | ULF_NO_ERRNO, addr(a), 0),
+            };
+        }
+    }
+
+    pub fn wake_all(a: &AtomicU32) {
+        unsafe {
+            match lookup::<Wake>(&WAKE_ALL, c"os_sync_wake_by_address_all") {
+                Some(f) => f(addr(a), 4, 0),
+                None => __ulock_wake(
+                    UL_COMPARE_AND_WAIT | ULF_WAKE_ALL | ULF_NO_ERRNO,
+                    addr(a),
+                    0,
+                ),
+            };
+        }
+    }
+}
+
+#[cfg(all(
+    not(feature = "spin-only"),
+    feature = "wasm-atomic-wait",
+    target_arch = "wasm32",
+    target_feature = "atomics"
+))]
+mod imp {
+    use core::arch::wasm32::{memory_atomic_notify, memory_atomic_wait32};
+    use core::time::Duration;
+
+    use crate::sync_shim::plain_atomic::AtomicU32;
+
+    pub const BACKEND: &str = "wasm-atomic-wait";
+
+    pub fn wait(a: &AtomicU32, expected: u32) {
+        wait_ns(a, expected, -1);
+    }
+
+    pub fn wait_timeout(a: &AtomicU32, expected: u32, timeout: Duration) {
+        wait_ns(
+            a,
+            expected,
+            timeout.as_nanos().try_into().unwrap_or(i64::MAX),
+        );
+    }
+
+    /// A negative timeout waits forever.
+    fn wait_ns(a: &AtomicU32, expected: u32, ns: i64) {
+        // Safety: The instruction only reads the four bytes of the atomic,
+        // atomically, and compares them, as an `i32`, with `expected`
+        // before sleeping.
+        unsafe { memory_atomic_wait32(a.as_ptr().cast(), expected as i32, ns) };
+    }
+
+    pub fn wake_one(a: &AtomicU32) {
+        // Safety: Waking never touches the atomic itself.
+        unsafe { memory_atomic_notify(a.as_ptr().cast(), 1) };
+    }
+
+    pub fn wake_all(a: &AtomicU32) {
+        // Safety: Waking never touches the atomic itself.
+        unsafe { memory_atomic_notify(a.as_ptr().cast(), u32::MAX) };
+    }
+}
+
+#[cfg(all(
+    not(feature = "spin-only"),
+    not(all(feature = "futex", target_os = "linux")),
+    not(all(feature = "wait-on-address", windows)),
+    not(all(feature = "os-sync", target_os = "macos")),
+    not(all(
+        feature = "wasm-atomic-wait",
+        target_arch = "wasm32",
+        target_feature = "atomics"
+    )),
+    not(all(target_arch = "wasm32", not(target_feature = "atomics"))),
+    feature = "std-condvar"
+))]
+mod imp {
+    use std::ptr;
+    use std::sync::{Condvar, Mutex, PoisonError};
+    use std::time::Duration;
+
+    use crate::sync_shim::plain_atomic::AtomicU32;
+    use crate::sync_shim::plain_atomic::Ordering::Relaxed;
+
+    pub const BACKEND: &str = "std-condvar";
+
+    struct Bucket {
+        mutex: Mutex<()>,
+        condvar: Condvar,
+    }
+
+    /// Unrelated atomics can share a bucket, so waking always wakes
+    /// everyone in it, and the others see a spurious wake-up.
+    static BUCKETS: [Bucket; 64] = [const {
+        Bucket {
+            mutex: Mutex::new(()),
+            condvar: Condvar::new(),
+        }
+    }; 64];
+
+    fn bucket(a: &AtomicU32) -> &'static Bucket {
+        let addr = ptr::from_ref(a).addr();
+        &BUCKETS[(addr >> 2) % BUCKETS.len()]
+    }
+
+    pub fn wait(a: &AtomicU32, expected: u32) {
+        let bucket = bucket(a);
+        // Nothing panics while holding these, so poisoning doesn't matter.
+        let guard = bucket.mutex.lock().unwrap_or_else(PoisonError::into_inner);
+        // A waker changes the value before locking the bucket, so we either
+        // see the new value here, or are already waiting when it notifies.
+        if a.load(Relaxed) == expected {
+            drop(bucket.condvar.wait(guard));
+        }
+    }
+
+    pub fn wait_timeout(a: &AtomicU32, expected: u32, timeout: Duration) {
+        let bucket = bucket(a);
+        let guard = bucket.mutex.lock().unwrap_or_else(PoisonError::into_inner);
+        if a.load(Relaxed) == expected {
+            drop(bucket.condvar.wait_timeout(guard, timeout));
+        }
+    }
+
+    pub fn wake_one(a: &AtomicU32) {
+        wake_all(a);
+    }
+
+    pub fn wake_all(a: &AtomicU32) {
+        let bucket = bucket(a);
+        drop(bucket.mutex.lock().unwrap_or_else(PoisonError::into_inner));
+        bucket.condvar.notify_all();
+    }
+}
+
+#[cfg(not(any(
+    all(not(feature = "spin-only"), feature = "futex", target_os = "linux"),
+    all(not(feature = "spin-only"), feature = "wait-on-address", windows),
+    all(not(feature = "spin-only"), feature = "os-sync", target_os = "macos"),
+    all(
+        not(feature = "spin-only"),
+        feature = "wasm-atomic-wait",
+        target_arch = "wasm32",
+        target_feature = "atomics"
+    ),
+    all(
+        not(feature = "spin-only"),
+        feature = "std-condvar",
+        not(all(target_arch = "wasm32", not(target_feature = "atomics")))
+    )
+)))]
+mod imp {
+    use core::time::Duration;
+
+    use crate::sync_shim::plain_atomic::AtomicU32;
+    use crate::sync_shim::plain_atomic::Ordering::Relaxed;
+
+    pub const BACKEND: &str = "spin-only";
+
+    pub fn wait(a: &AtomicU32, expected: u32) {
+        while a.load(Relaxed) == expected {
+            core::hint::spin_loop();
+        }
+    }
+
+    pub fn wait_timeout(_: &AtomicU32, _: u32, _: Duration) {
+        core::hint::spin_loop();
+    }
+
+    pub fn wake_one(_: &AtomicU32) {}
+
+    pub fn wake_all(_: &AtomicU32) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::platform::wait::{wait, wait_timeout, wake_all, wake_one};
+    #[cfg(feature = "std")]
+    use crate::platform::wait::{wait_interruptible, wait_until, Interrupt};
+    use crate::sync_shim::plain_atomic::AtomicU32;
+    use crate::sync_shim::plain_atomic::Ordering::Relaxed;
+
+    #[test]
+    fn test_wait_returns_if_the_value_changed() {
+        let a = AtomicU32::new(1);
+        wait(&a, 0);
+    }
+
+    #[test]
+    fn test_wait_timeout_gives_up() {
+        let a = AtomicU32::new(0);
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(10) {
+            wait_timeout(&a, 0, Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_wait_until_gives_up_at_the_deadline() {
+        let a = AtomicU32::new(0);
+        let deadline = Instant::now() + Duration::from_millis(10);
+        while Instant::now() < deadline {
+            wait_until(&a, 0, deadline);
+        }
+        // Doesn't wait at all once it has passed.
+        wait_until(&a, 0, deadline);
+    }
+
+    #[test]
+    fn test_wake() {
+        let a = AtomicU32::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    while a.load(Relaxed) == 0 {
+                        wait(&a, 0);
+                    }
+                });
+            }
+            thread::sleep(Duration::from_millis(10));
+            a.store(1, Relaxed);
+            wake_one(&a);
+            wake_all(&a);
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_interrupt_wakes_every_waiter() {
+        let a = AtomicU32::new(0);
+        let b = AtomicU32::new(0);
+        let interrupt = Interrupt::new();
+        thread::scope(|s| {
+            for atomic in [&a, &a, &b] {
+                s.spawn(|| while !wait_interruptible(atomic, 0, None, &interrupt) {});
+            }
+            s.spawn(|| {
+                let deadline = Instant::now() + Duration::from_secs(60);
+                while !wait_interruptible(&b, 0, Some(deadline), &interrupt) {
+                    assert!(Instant::now() < deadline);
+                }
+            });
+            thread::sleep(Duration::from_millis(10));
+            interrupt.raise();
+            assert_eq!(
+                format!("{interrupt:?}"),
+                "Interrupt { raised: true, waiting: 0 }"
+            );
+        });
+        // It stays raised.
+        assert!(wait_interruptible(&a, 0, None, &interrupt));
+    }
+}
@@ -0,0 +1,133 @@
+use std::fmt;
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::channels::channel::sender_receiver_channel_with_arc::{channel, Receiver};
+use crate::pool::thread_pool::ThreadPool;
+
+/// The result of a job started with `ThreadPool::spawn`.
+///
+/// The job unparks the thread that spawned it once the result is in,
+/// so the handle has to stay on that thread (it's not `Send`).
+pub struct JobHandle<T> {
+    receiver: Receiver<thread::Result<T>>,
+    _no_send: PhantomData<*const ()>,
+}
+
+impl ThreadPool {
+    pub fn spawn<F, T>(&self, f: F) -> JobHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = channel();
+        let spawner = thread::current();
+        self.execute(move || {
+            // Catching the panic here means it ends up in the handle
+            // instead of in the pool's panicked job count.
+            sender.send(panic::catch_unwind(AssertUnwindSafe(f)));
+            spawner.unpark();
+        });
+        JobHandle {
+            receiver,
+            _no_send: PhantomData,
+        }
+    }
+}
+
+impl<T> JobHandle<T> {
+    pub fn is_finished(&self) -> bool {
+        self.receiver.is_ready()
+    }
+
+    /// Blocks until the job is done. Returns `Err` with the panic payload
+    /// if the job panicked.
+    pub fn join(self) -> thread::Result<T> {
+        while !self.receiver.is_ready() {
+            thread::park();
+        }
+        self.receiver.receive_or_panic()
+    }
+
+    /// Gives the handle back if the job hasn't finished yet.
+    pub fn try_join(self) -> Result<thread::Result<T>, Self> {
+        if self.receiver.is_ready() {
+            Ok(self.receiver.receive_or_panic())
+        } else {
+            Err(self)
+        }
+    }
+
+    /// Gives the handle back if the job didn't finish within `timeout`.
+    pub fn join_timeout(self, timeout: Duration) -> Result<thread::Result<T>, Self> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.join_until(deadline),
+            None => Ok(self.join()),
+        }
+    }
+
+    /// Gives the handle back if the job didn't finish by `deadline`.
+    pub fn join_until(self, deadline: Instant) -> Result<thread::Result<T>, Self> {
+        while !self.receiver.is_ready() {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(self);
+            }
+            thread::park_timeout(deadline - now);
+        }
+        Ok(self.receiver.receive_or_panic())
+    }
+}
+
+impl<T> fmt::Debug for JobHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JobHandle")
+            .field("finished", &self.is_finished())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    use crate::pool::job_handle::JobHandle;
+    use crate::pool::thread_pool::ThreadPool;
+
+    #[test]
+    fn test_join_returns_result() {
+        let pool = ThreadPool::new(2);
+        let handles: Vec<_> = (0..10).map(|i| pool.spawn(move || i * i)).collect();
+        let squares: Vec<i32> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(squares, [0, 1, 4, 9, 16, 25, 36, 49, 64, 81]);
+    }
+
+    #[test]
+    fn test_join_returns_panic_payload() {
+        let pool = ThreadPool::new(1);
+        let handle: JobHandle<()> = pool.spawn(|| panic!("job failed"));
+        let payload = handle.join().unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"job failed"));
+        assert_eq!(pool.panicked_jobs(), 0);
+    }
+
+    #[test]
+    fn test_try_join_and_join_timeout() {
+        let pool = ThreadPool::new(1);
+        let (tx, rx) = mpsc::channel::<()>();
+        let handle = pool.spawn(move || {
+            rx.recv().unwrap();
+            "done"
+        });
+        let handle = handle.try_join().unwrap_err();
+        let handle = handle.join_timeout(Duration::from_millis(20)).unwrap_err();
+        let handle = handle.join_until(Instant::now()).unwrap_err();
+        assert!(!handle.is_finished());
+        tx.send(()).unwrap();
+        let result = handle.join_timeout(Duration::from_secs(10)).ok().unwrap();
+        assert_eq!(result.unwrap(), "done");
+    }
+}
@@ -0,0 +1,14 @@
+pub mod job_handle;
+pub mod parallel;
+pub mod scope;
+pub mod thread_pool;
+pub mod wait_group;
+pub mod work_stealing;
+pub mod work_stealing_scope;
+
+pub use job_handle::JobHandle;
+pub use scope::Scope;
+pub use thread_pool::ThreadPool;
+pub use wait_group::WaitGroup;
+pub use work_stealing::{Spawner, WorkStealingPool};
+pub use work_stealing_scope::{scope, WorkStealingScope};
@@ -0,0 +1,129 @@
+use crate::pool::thread_pool::ThreadPool;
+
+impl ThreadPool {
+    /// Runs `b` on the pool while `a` runs on the calling thread, and
+    /// returns both results.
+    pub fn join<A, B, RA, RB>(&self, a: A, b: B) -> (RA, RB)
+    where
+        A: FnOnce() -> RA,
+        B: FnOnce() -> RB + Send,
+        RB: Send,
+    {
+        let mut rb = None;
+        let ra = self.scope(|s| {
+            s.spawn(|| rb = Some(b()));
+            a()
+        });
+        // The scope only returns normally when the spawned job succeeded.
+        (ra, rb.unwrap())
+    }
+
+    /// Calls `f` on every item, splitting the slice into one chunk per worker.
+    pub fn parallel_for_each<T, F>(&self, items: &[T], f: F)
+    where
+        T: Sync,
+        F: Fn(&T) + Sync,
+    {
+        if items.is_empty() {
+            return;
+        }
+        let chunk_size = items.len().div_ceil(self.size());
+        let f = &f;
+        self.scope(|s| {
+            for chunk in items.chunks(chunk_size) {
+                s.spawn(move || chunk.iter().for_each(f));
+            }
+        });
+    }
+
+    /// Maps every `chunk_size` chunk of `items` in parallel, then folds the
+    /// per-chunk results in order with `reduce`. Returns `None` for an
+    /// empty slice.
+    ///
+    /// Panics if `chunk_size` is zero.
+    pub fn map_reduce<T, R, M, F>(
+        &self,
+        items: &[T],
+        chunk_size: usize,
+        map: M,
+        reduce: F,
+    ) -> Option<R>
+    where
+        T: Sync,
+        R: Send,
+        M: Fn(&[T]) -> R + Sync,
+        F: FnMut(R, R) -> R,
+    {
+        assert!(chunk_size > 0, "chunk size must be non-zero");
+        let mut results: Vec<Option<R>> = items.chunks(chunk_size).map(|_| None).collect();
+        let map = &map;
+        self.scope(|s| {
+            for (chunk, result) in items.chunks(chunk_size).zip(&mut results) {
+                s.spawn(move || *result = Some(map(chunk)));
+            }
+        });
+        results.into_iter().map(Option::unwrap).reduce(reduce)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::Relaxed;
+
+    use crate::pool::thread_pool::ThreadPool;
+
+    #[test]
+    fn test_join_returns_both_results() {
+        let pool = ThreadPool::new(2);
+        let words = ["hello", "world"];
+        let (a, b) = pool.join(|| words[0].len(), || words[1].to_uppercase());
+        assert_eq!(a, 5);
+        assert_eq!(b, "WORLD");
+    }
+
+    #[test]
+    fn test_join_splits_recursive_work() {
+        fn fib(n: u64) -> u64 {
+            if n < 2 {
+                n
+            } else {
+                fib(n - 1) + fib(n - 2)
+            }
+        }
+        let pool = ThreadPool::new(2);
+        let (a, b) = pool.join(|| fib(19), || fib(18));
+        assert_eq!(a + b, fib(20));
+    }
+
+    #[test]
+    fn test_parallel_for_each_visits_every_item() {
+        let pool = ThreadPool::new(3);
+        let items: Vec<usize> = (1..=100).collect();
+        let sum = AtomicUsize::new(0);
+        pool.parallel_for_each(&items, |i| {
+            sum.fetch_add(*i, Relaxed);
+        });
+        assert_eq!(sum.into_inner(), 5050);
+        pool.parallel_for_each(&[] as &[usize], |_| unreachable!());
+    }
+
+    #[test]
+    fn test_map_reduce_keeps_chunk_order() {
+        let pool = ThreadPool::new(4);
+        let items: Vec<u64> = (1..=1000).collect();
+        let sum = pool.map_reduce(&items, 64, |chunk| chunk.iter().sum::<u64>(), |a, b| a + b);
+        assert_eq!(sum, Some(500500));
+        let firsts = pool.map_reduce(
+            &items,
+            250,
+            |chunk| vec![chunk[0]],
+            |mut a, b| {
+                a.extend(b);
+                a
+            },
+        );
+        assert_eq!(firsts, Some(vec![1, 251, 501, 751]));
+        assert_eq!(pool.map_reduce(&[] as &[u64], 8, |_| 0, |a, b| a + b), None);
+    }
+}
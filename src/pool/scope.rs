@@ -0,0 +1,141 @@
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+
+use crate::pool::thread_pool::ThreadPool;
+use crate::pool::wait_group::WaitGroup;
+use crate::sync_shim::plain_atomic::AtomicBool;
+use crate::sync_shim::plain_atomic::Ordering::Relaxed;
+
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'env ThreadPool,
+    wait_group: Arc<WaitGroup>,
+    a_job_panicked: Arc<AtomicBool>,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+impl ThreadPool {
+    /// Like `std::thread::scope`, but the spawned jobs run on this pool.
+    ///
+    /// All jobs spawned in the scope have finished by the time this returns.
+    /// If any of them panicked, this panics as well.
+    ///
+    /// Calling this from inside one of the pool's own jobs can deadlock
+    /// when every worker ends up waiting on a scope.
+    pub fn scope<'env, F, T>(&'env self, f: F) -> T
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+    {
+        let scope = Scope {
+            pool: self,
+            wait_group: Arc::new(WaitGroup::new()),
+            a_job_panicked: Arc::new(AtomicBool::new(false)),
+            scope: PhantomData,
+            env: PhantomData,
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        // Even if `f` panicked, jobs may still be borrowing from the stack,
+        // so we can't unwind past this point before they're all done.
+        scope.wait_group.wait();
+        match result {
+            Err(e) => panic::resume_unwind(e),
+            Ok(_) if scope.a_job_panicked.load(Relaxed) => panic!("a scoped job panicked"),
+            Ok(result) => result,
+        }
+    }
+}
+
+impl<'scope> Scope<'scope, '_> {
+    pub fn spawn<F>(&'scope self, f: F)
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        self.wait_group.add(1);
+        let wait_group = self.wait_group.clone();
+        let a_job_panicked = self.a_job_panicked.clone();
+        // The job keeps running for a bit after `done`, when the scope may
+        // have ended. References inside a `MaybeUninit` don't have to stay
+        // valid for as long as the job runs, like they would if the job
+        // captured `f` directly, and Miri would tell us about it.
+        let f = MaybeUninit::new(f);
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            // Safety: It's initialized right above, and only taken out once.
+            let f = unsafe { f.assume_init() };
+            if panic::catch_unwind(AssertUnwindSafe(f)).is_err() {
+                a_job_panicked.store(true, Relaxed);
+            }
+            wait_group.done();
+        });
+        // Safety: `ThreadPool::scope` doesn't return before the wait group
+        // reaches zero, which only happens once this job has run, so nothing
+        // borrowed for 'scope is used after it ends.
+        let job: Box<dyn FnOnce() + Send + 'static> = unsafe { std::mem::transmute(job) };
+        self.pool.execute(job);
+    }
+}
+
+impl fmt::Debug for Scope<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scope")
+            .field("pool", &self.pool)
+            .field("a_job_panicked", &self.a_job_panicked.load(Relaxed))
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::Relaxed;
+
+    use crate::pool::thread_pool::ThreadPool;
+
+    #[test]
+    fn test_scope_borrows_from_stack() {
+        let pool = ThreadPool::new(4);
+        let mut numbers = vec![1, 2, 3, 4];
+        let sum = AtomicUsize::new(0);
+        pool.scope(|s| {
+            for n in &numbers {
+                let sum = &sum;
+                s.spawn(move || {
+                    sum.fetch_add(*n, Relaxed);
+                });
+            }
+        });
+        // The borrows above have ended, so this is allowed again.
+        numbers.push(5);
+        assert_eq!(sum.into_inner(), 10);
+        assert_eq!(numbers.len(), 5);
+    }
+
+    #[test]
+    fn test_scope_mutably_borrows_disjoint_chunks() {
+        let pool = ThreadPool::new(3);
+        let mut data = [1u64; 9];
+        pool.scope(|s| {
+            for chunk in data.chunks_mut(3) {
+                s.spawn(move || chunk.iter_mut().for_each(|x| *x *= 2));
+            }
+        });
+        assert_eq!(data, [2; 9]);
+    }
+
+    #[test]
+    fn test_scope_returns_closure_result() {
+        let pool = ThreadPool::new(1);
+        assert_eq!(pool.scope(|_| 42), 42);
+    }
+
+    #[test]
+    #[should_panic(expected = "a scoped job panicked")]
+    fn test_job_panic_propagates_to_scope() {
+        let pool = ThreadPool::new(2);
+        pool.scope(|s| {
+            s.spawn(|| panic!("job failed"));
+        });
+    }
+}
@@ -0,0 +1,193 @@
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread;
+
+use crate::channels::channel::priority_channel::{Channel, Priority};
+use crate::sync_shim::plain_atomic::AtomicUsize;
+use crate::sync_shim::plain_atomic::Ordering::Relaxed;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+enum Message {
+    Job(Job),
+    Terminate,
+}
+
+struct Shared {
+    channel: Channel<Message>,
+    panicked_jobs: AtomicUsize,
+}
+
+pub struct ThreadPool {
+    workers: Vec<thread::JoinHandle<()>>,
+    shared: Arc<Shared>,
+}
+
+impl ThreadPool {
+    /// Panics if `size` is zero.
+    pub fn new(size: usize) -> Self {
+        assert!(size > 0, "a thread pool needs at least one worker");
+        let shared = Arc::new(Shared {
+            channel: Channel::new(),
+            panicked_jobs: AtomicUsize::new(0),
+        });
+        let workers = (0..size)
+            .map(|id| {
+                let shared = shared.clone();
+                thread::Builder::new()
+                    .name(format!("pool-worker-{id}"))
+                    .spawn(move || worker_loop(&shared))
+                    .expect("failed to spawn worker thread")
+            })
+            .collect();
+        Self { workers, shared }
+    }
+
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Runs `f` with `Priority::Normal`.
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.execute_with_priority(Priority::Normal, f);
+    }
+
+    /// Queued jobs with a higher priority are picked up first, unless a
+    /// lower priority job has been waiting for longer than the aging
+    /// threshold (see `priority_channel`).
+    pub fn execute_with_priority<F>(&self, priority: Priority, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.shared
+            .channel
+            .send(priority, Message::Job(Box::new(f)));
+    }
+
+    /// Number of jobs that panicked so far. A panicking job doesn't take
+    /// its worker down with it.
+    pub fn panicked_jobs(&self) -> usize {
+        self.shared.panicked_jobs.load(Relaxed)
+    }
+
+    /// Runs every job submitted so far to completion and joins the workers.
+    pub fn shutdown(mut self) {
+        self.terminate();
+    }
+
+    fn terminate(&mut self) {
+        // Sent last and at the lowest priority, a `Terminate`
+        // is only picked up once every job queued before it has been.
+        for _ in &self.workers {
+            self.shared.channel.send(Priority::Low, Message::Terminate);
+        }
+        for worker in self.workers.drain(..) {
+            // Job panics are caught inside the worker, so this can't fail.
+            worker.join().unwrap();
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.terminate();
+    }
+}
+
+fn worker_loop(shared: &Shared) {
+    loop {
+        match shared.channel.receive() {
+            Message::Job(job) => {
+                if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                    shared.panicked_jobs.fetch_add(1, Relaxed);
+                }
+            }
+            Message::Terminate => return,
+        }
+    }
+}
+
+impl fmt::Debug for ThreadPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ThreadPool")
+            .field("size", &self.size())
+            .field("panicked_jobs", &self.panicked_jobs())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::sync::{mpsc, Arc, Mutex};
+
+    use crate::channels::channel::priority_channel::Priority;
+    use crate::pool::thread_pool::ThreadPool;
+
+    #[test]
+    fn test_shutdown_runs_all_jobs() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPool::new(4);
+        for _ in 0..1000 {
+            let counter = counter.clone();
+            pool.execute(move || {
+                counter.fetch_add(1, Relaxed);
+            });
+        }
+        pool.shutdown();
+        assert_eq!(counter.load(Relaxed), 1000);
+    }
+
+    #[test]
+    fn test_panicking_job_does_not_kill_worker() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPool::new(1);
+        pool.execute(|| panic!("job failed"));
+        let c = counter.clone();
+        pool.execute(move || {
+            c.fetch_add(1, Relaxed);
+        });
+        drop(pool);
+        assert_eq!(counter.load(Relaxed), 1);
+    }
+
+    #[test]
+    fn test_panicked_jobs_are_counted() {
+        let pool = ThreadPool::new(1);
+        for _ in 0..3 {
+            pool.execute(|| panic!("job failed"));
+        }
+        // A single worker runs jobs in order, so once this one has run,
+        // all the panicking jobs before it have too.
+        let (tx, rx) = mpsc::channel();
+        pool.execute(move || tx.send(()).unwrap());
+        rx.recv().unwrap();
+        assert_eq!(pool.panicked_jobs(), 3);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "the low priority job ages past the others under Miri")]
+    fn test_high_priority_jobs_run_first() {
+        let pool = ThreadPool::new(1);
+        let order = Arc::new(Mutex::new(Vec::new()));
+        // Keep the only worker busy until everything is queued.
+        let (tx, rx) = mpsc::channel::<()>();
+        pool.execute(move || rx.recv().unwrap());
+        for (priority, name) in [
+            (Priority::Low, "low"),
+            (Priority::Normal, "normal"),
+            (Priority::High, "high"),
+        ] {
+            let order = order.clone();
+            pool.execute_with_priority(priority, move || order.lock().unwrap().push(name));
+        }
+        tx.send(()).unwrap();
+        pool.shutdown();
+        assert_eq!(*order.lock().unwrap(), ["high", "normal", "low"]);
+    }
+}
@@ -0,0 +1,98 @@
+use std::fmt;
+use std::sync::{Condvar, Mutex};
+
+/// Go-style wait group: `add` registers outstanding work, `done` marks one
+/// unit of it finished, and `wait` blocks until nothing is outstanding.
+pub struct WaitGroup {
+    count: Mutex<usize>,
+    all_done: Condvar,
+}
+
+impl WaitGroup {
+    pub const fn new() -> Self {
+        Self {
+            count: Mutex::new(0),
+            all_done: Condvar::new(),
+        }
+    }
+
+    pub fn add(&self, n: usize) {
+        *self.count.lock().unwrap() += n;
+    }
+
+    /// Panics when called more often than `add` accounted for.
+    pub fn done(&self) {
+        let mut count = self.count.lock().unwrap();
+        *count = count.checked_sub(1).expect("done() called too often");
+        if *count == 0 {
+            self.all_done.notify_all();
+        }
+    }
+
+    /// Whether nothing is outstanding. It may change right after.
+    pub fn is_done(&self) -> bool {
+        *self.count.lock().unwrap() == 0
+    }
+
+    pub fn wait(&self) {
+        let mut count = self.count.lock().unwrap();
+        while *count > 0 {
+            count = self.all_done.wait(count).unwrap();
+        }
+    }
+}
+
+impl Default for WaitGroup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Doesn't block: shows `<locked>` if the count is being updated.
+impl fmt::Debug for WaitGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("WaitGroup");
+        match self.count.try_lock() {
+            Ok(count) => d.field("count", &*count),
+            Err(_) => d.field("count", &format_args!("<locked>")),
+        };
+        d.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::pool::wait_group::WaitGroup;
+
+    #[test]
+    fn test_wait_blocks_until_all_done() {
+        static WG: WaitGroup = WaitGroup::new();
+        static DONE: AtomicUsize = AtomicUsize::new(0);
+        WG.add(8);
+        for _ in 0..8 {
+            thread::spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                DONE.fetch_add(1, Relaxed);
+                WG.done();
+            });
+        }
+        WG.wait();
+        assert_eq!(DONE.load(Relaxed), 8);
+    }
+
+    #[test]
+    fn test_wait_without_work_returns_immediately() {
+        WaitGroup::new().wait();
+    }
+
+    #[test]
+    #[should_panic(expected = "done() called too often")]
+    fn test_unbalanced_done_panics() {
+        WaitGroup::new().done();
+    }
+}
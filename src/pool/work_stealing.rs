@@ -0,0 +1,386 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use crate::lockfree::deque::{self, Steal, Stealer, Worker};
+use crate::platform::backoff::Backoff;
+use crate::sync_shim::plain_atomic::Ordering::{Acquire, Relaxed, Release, SeqCst};
+use crate::sync_shim::plain_atomic::{AtomicBool, AtomicUsize};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+const LOCAL_QUEUE_CAPACITY: usize = 256;
+
+struct Shared {
+    /// Jobs submitted from outside the pool, or that didn't fit a local deque.
+    injector: Mutex<VecDeque<Job>>,
+    stealers: Vec<Stealer<Job>>,
+    /// Number of jobs sitting in any queue, used to decide when to sleep.
+    pending: AtomicUsize,
+    sleepers: AtomicUsize,
+    sleep_lock: Mutex<()>,
+    wake_up: Condvar,
+    shutdown: AtomicBool,
+    panicked_jobs: AtomicUsize,
+}
+
+struct Local {
+    shared: Arc<Shared>,
+    index: usize,
+    worker: Worker<Job>,
+}
+
+thread_local! {
+    static LOCAL: RefCell<Option<Local>> = const { RefCell::new(None) };
+}
+
+/// A thread pool where every worker owns a Chase–Lev deque.
+///
+/// Jobs spawned from inside a job go to the spawning worker's own deque,
+/// and idle workers steal from their peers before going to sleep.
+pub struct WorkStealingPool {
+    workers: Vec<thread::JoinHandle<()>>,
+    shared: Arc<Shared>,
+}
+
+/// A cloneable handle that can submit jobs from anywhere, including from
+/// inside the pool's own jobs.
+#[derive(Clone)]
+pub struct Spawner {
+    shared: Arc<Shared>,
+}
+
+impl WorkStealingPool {
+    /// Panics if `size` is zero.
+    pub fn new(size: usize) -> Self {
+        assert!(size > 0, "a thread pool needs at least one worker");
+        let (workers, stealers): (Vec<_>, Vec<_>) = (0..size)
+            .map(|_| deque::deque(LOCAL_QUEUE_CAPACITY))
+            .unzip();
+        let shared = Arc::new(Shared {
+            injector: Mutex::new(VecDeque::new()),
+            stealers,
+            pending: AtomicUsize::new(0),
+            sleepers: AtomicUsize::new(0),
+            sleep_lock: Mutex::new(()),
+            wake_up: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+            panicked_jobs: AtomicUsize::new(0),
+        });
+        let workers = workers
+            .into_iter()
+            .enumerate()
+            .map(|(index, worker)| {
+                let shared = shared.clone();
+                thread::Builder::new()
+                    .name(format!("ws-worker-{index}"))
+                    .spawn(move || {
+                        LOCAL.with(|local| {
+                            *local.borrow_mut() = Some(Local {
+                                shared,
+                                index,
+                                worker,
+                            })
+                        });
+                        worker_loop();
+                    })
+                    .expect("failed to spawn worker thread")
+            })
+            .collect();
+        Self { workers, shared }
+    }
+
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.shared.push(Box::new(f));
+    }
+
+    pub fn spawner(&self) -> Spawner {
+        Spawner {
+            shared: self.shared.clone(),
+        }
+    }
+
+    pub fn panicked_jobs(&self) -> usize {
+        self.shared.panicked_jobs.load(Relaxed)
+    }
+
+    /// Whether the calling thread is one of this pool's workers.
+    pub(crate) fn is_worker(&self) -> bool {
+        LOCAL.with(|local| {
+            matches!(&*local.borrow(), Some(local) if Arc::ptr_eq(&local.shared, &self.shared))
+        })
+    }
+
+    /// Runs one queued job on the calling worker, if there's one, so a job
+    /// that waits for others can help with them instead of holding up its
+    /// worker. Returns whether it ran one.
+    pub(crate) fn run_one(&self) -> bool {
+        let job = LOCAL.with(|local| match &*local.borrow() {
+            Some(local) if Arc::ptr_eq(&local.shared, &self.shared) => self.shared.find_job(local),
+            _ => None,
+        });
+        match job {
+            Some(job) => {
+                self.shared.run(job);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Runs every queued job to completion (including the ones they spawn)
+    /// and joins the workers.
+    pub fn shutdown(mut self) {
+        self.terminate();
+    }
+
+    fn terminate(&mut self) {
+        // Release, so a worker that sees it also sees every job counted in
+        // `pending` before it. It's only read next to SeqCst operations, not
+        // as part of their store-load pattern, so it needn't be SeqCst.
+        self.shared.shutdown.store(true, Release);
+        {
+            let _guard = self.shared.sleep_lock.lock().unwrap();
+            self.shared.wake_up.notify_all();
+        }
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+    }
+}
+
+impl Drop for WorkStealingPool {
+    fn drop(&mut self) {
+        self.terminate();
+    }
+}
+
+impl Spawner {
+    /// Jobs submitted after the pool has shut down are never run.
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.shared.push(Box::new(f));
+    }
+}
+
+impl Shared {
+    fn push(self: &Arc<Self>, job: Job) {
+        // Counted before the job becomes visible, so a thief can never
+        // decrement `pending` below zero.
+        self.pending.fetch_add(1, SeqCst);
+        let job = LOCAL.with(|local| match &*local.borrow() {
+            Some(local) if Arc::ptr_eq(&local.shared, self) => local.worker.push(job).err(),
+            _ => Some(job),
+        });
+        if let Some(job) = job {
+            self.injector.lock().unwrap().push_back(job);
+        }
+        // Pairs with the SeqCst operations in `sleep`: either we see the
+        // sleeper and wake it, or it sees our job and doesn't go to sleep.
+        if self.sleepers.load(SeqCst) > 0 {
+            let _guard = self.sleep_lock.lock().unwrap();
+            self.wake_up.notify_one();
+        }
+    }
+
+    fn find_job(&self, local: &Local) -> Option<Job> {
+        let job = local
+            .worker
+            .pop()
+            .or_else(|| self.injector.lock().unwrap().pop_front())
+            .or_else(|| self.steal(local));
+        if job.is_some() {
+            self.pending.fetch_sub(1, SeqCst);
+        }
+        job
+    }
+
+    /// Takes half of a peer's jobs at once, so a worker that ran dry
+    /// doesn't come back to steal again for every single job.
+    fn steal(&self, local: &Local) -> Option<Job> {
+        let n = self.stealers.len();
+        // A retry means another thief took the item we were after.
+        let mut backoff = Backoff::new();
+        loop {
+            let mut retry = false;
+            for i in 1..n {
+                match self.stealers[(local.index + i) % n].steal_batch_and_pop(&local.worker) {
+                    Steal::Success(job) => return Some(job),
+                    Steal::Retry => retry = true,
+                    Steal::Empty => {}
+                }
+            }
+            if !retry {
+                return None;
+            }
+            backoff.spin();
+        }
+    }
+
+    fn run(&self, job: Job) {
+        if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+            self.panicked_jobs.fetch_add(1, Relaxed);
+        }
+    }
+
+    fn sleep(&self) {
+        let mut guard = self.sleep_lock.lock().unwrap();
+        self.sleepers.fetch_add(1, SeqCst);
+        while self.pending.load(SeqCst) == 0 && !self.shutdown.load(Acquire) {
+            guard = self.wake_up.wait(guard).unwrap();
+        }
+        self.sleepers.fetch_sub(1, SeqCst);
+    }
+}
+
+fn worker_loop() {
+    loop {
+        let (job, shared) = LOCAL.with(|local| {
+            let local = local.borrow();
+            let local = local.as_ref().unwrap();
+            (local.shared.find_job(local), local.shared.clone())
+        });
+        match job {
+            // The thread-local isn't borrowed here, so the job can spawn.
+            Some(job) => shared.run(job),
+            None if shared.shutdown.load(Acquire) && shared.pending.load(SeqCst) == 0 => break,
+            None => shared.sleep(),
+        }
+    }
+    // Drop our deque (and the Arc<Shared> it keeps) while the thread is still alive.
+    LOCAL.with(|local| local.borrow_mut().take());
+}
+
+impl fmt::Debug for WorkStealingPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WorkStealingPool")
+            .field("size", &self.size())
+            .field("pending", &self.shared.pending.load(Relaxed))
+            .field("panicked_jobs", &self.panicked_jobs())
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Debug for Spawner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Spawner").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    use crate::pool::scope::Scope;
+    use crate::pool::thread_pool::ThreadPool;
+    use crate::pool::wait_group::WaitGroup;
+    use crate::pool::work_stealing::{Spawner, WorkStealingPool};
+
+    #[test]
+    fn test_runs_external_jobs() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let pool = WorkStealingPool::new(4);
+        for _ in 0..1000 {
+            let counter = counter.clone();
+            pool.execute(move || {
+                counter.fetch_add(1, Relaxed);
+            });
+        }
+        pool.shutdown();
+        assert_eq!(counter.load(Relaxed), 1000);
+    }
+
+    #[test]
+    fn test_shutdown_waits_for_nested_jobs() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let pool = WorkStealingPool::new(2);
+        let spawner = pool.spawner();
+        let c = counter.clone();
+        pool.execute(move || {
+            for _ in 0..1000 {
+                let c = c.clone();
+                spawner.execute(move || {
+                    c.fetch_add(1, Relaxed);
+                });
+            }
+        });
+        pool.shutdown();
+        assert_eq!(counter.load(Relaxed), 1000);
+    }
+
+    #[test]
+    fn test_panicking_job_does_not_kill_worker() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let pool = WorkStealingPool::new(1);
+        pool.execute(|| panic!("job failed"));
+        let c = counter.clone();
+        pool.execute(move || {
+            c.fetch_add(1, Relaxed);
+        });
+        drop(pool);
+        assert_eq!(counter.load(Relaxed), 1);
+    }
+
+    fn fork_join_work_stealing(spawner: Spawner, wg: Arc<WaitGroup>, depth: u32) {
+        if depth == 0 {
+            wg.done();
+            return;
+        }
+        for _ in 0..2 {
+            let (spawner2, wg) = (spawner.clone(), wg.clone());
+            spawner.execute(move || fork_join_work_stealing(spawner2, wg, depth - 1));
+        }
+    }
+
+    fn fork_join_shared_queue<'scope>(
+        s: &'scope Scope<'scope, '_>,
+        leaves: &'scope AtomicUsize,
+        depth: u32,
+    ) {
+        if depth == 0 {
+            leaves.fetch_add(1, Relaxed);
+            return;
+        }
+        for _ in 0..2 {
+            s.spawn(move || fork_join_shared_queue(s, leaves, depth - 1));
+        }
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_fork_join`.
+    #[test]
+    #[ignore]
+    fn bench_fork_join() {
+        const DEPTH: u32 = 18;
+        let threads = std::thread::available_parallelism().map_or(4, |n| n.get());
+
+        let pool = ThreadPool::new(threads);
+        let leaves = AtomicUsize::new(0);
+        let start = Instant::now();
+        pool.scope(|s| fork_join_shared_queue(s, &leaves, DEPTH));
+        println!("shared queue:  {:?}", start.elapsed());
+        assert_eq!(leaves.into_inner(), 1 << DEPTH);
+
+        let pool = WorkStealingPool::new(threads);
+        let wg = Arc::new(WaitGroup::new());
+        wg.add(1 << DEPTH);
+        let start = Instant::now();
+        fork_join_work_stealing(pool.spawner(), wg.clone(), DEPTH);
+        wg.wait();
+        println!("work stealing: {:?}", start.elapsed());
+    }
+}
@@ -0,0 +1,211 @@
+//! Rayon-style scopes on the work-stealing pool.
+//!
+//! [`scope`] runs its closure on the calling thread, with a
+//! [`WorkStealingScope`] to spawn jobs on. The jobs can borrow from the
+//! caller's stack, and spawn more jobs on the same scope, since each gets
+//! the scope too. It returns once they've all finished, and if any of them
+//! panicked, it panics with that job's panic.
+//!
+//! The jobs go on the worker deques of a [`WorkStealingPool`], and are
+//! counted with a [`WaitGroup`]. A scope opened inside one of the pool's
+//! jobs doesn't just block its worker until its jobs are done: it runs
+//! queued jobs in the meantime, its own or anyone's, so nested scopes
+//! can't tie up every worker and deadlock, like they can with
+//! `ThreadPool::scope`.
+
+use std::any::Any;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+
+use crate::platform::backoff::Backoff;
+use crate::pool::wait_group::WaitGroup;
+use crate::pool::work_stealing::WorkStealingPool;
+
+pub struct WorkStealingScope<'scope, 'env: 'scope> {
+    pool: &'env WorkStealingPool,
+    state: Arc<State>,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+struct State {
+    wait_group: WaitGroup,
+    /// The panic of the first job that panicked.
+    panic: Mutex<Option<Box<dyn Any + Send>>>,
+}
+
+/// Like [`WorkStealingPool::scope`], on a pool shared by the whole program,
+/// with a worker per core, started the first time it's used.
+pub fn scope<'env, F, T>(f: F) -> T
+where
+    F: for<'scope> FnOnce(&'scope WorkStealingScope<'scope, 'env>) -> T,
+{
+    static POOL: OnceLock<WorkStealingPool> = OnceLock::new();
+    POOL.get_or_init(|| {
+        WorkStealingPool::new(thread::available_parallelism().map_or(1, |n| n.get()))
+    })
+    .scope(f)
+}
+
+impl WorkStealingPool {
+    /// Like `std::thread::scope`, but the spawned jobs run on this pool.
+    ///
+    /// All jobs spawned in the scope, and the ones they spawn, have finished
+    /// by the time this returns. If any of them panicked, this panics with
+    /// the first one's panic.
+    pub fn scope<'env, F, T>(&'env self, f: F) -> T
+    where
+        F: for<'scope> FnOnce(&'scope WorkStealingScope<'scope, 'env>) -> T,
+    {
+        let scope = WorkStealingScope {
+            pool: self,
+            state: Arc::new(State {
+                wait_group: WaitGroup::new(),
+                panic: Mutex::new(None),
+            }),
+            scope: PhantomData,
+            env: PhantomData,
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        // Even if `f` panicked, jobs may still be borrowing from the stack,
+        // so we can't unwind past this point before they're all done.
+        scope.wait();
+        let job_panic = scope.state.panic.lock().unwrap().take();
+        match (result, job_panic) {
+            (Err(e), _) | (Ok(_), Some(e)) => panic::resume_unwind(e),
+            (Ok(result), None) => result,
+        }
+    }
+}
+
+impl<'scope, 'env> WorkStealingScope<'scope, 'env> {
+    /// Runs `f` on the pool. It gets the scope, to spawn more jobs on.
+    pub fn spawn<F>(&'scope self, f: F)
+    where
+        F: FnOnce(&'scope WorkStealingScope<'scope, 'env>) + Send + 'scope,
+    {
+        self.state.wait_group.add(1);
+        let state = self.state.clone();
+        // As in `ThreadPool`'s `Scope::spawn`: the job keeps running for a
+        // bit after `done`, when the scope may have ended, so the references
+        // it holds mustn't have to stay valid until it's dropped.
+        let f = MaybeUninit::new((f, self));
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            // Safety: It's initialized right above, and only taken out once.
+            let (f, scope) = unsafe { f.assume_init() };
+            if let Err(e) = panic::catch_unwind(AssertUnwindSafe(|| f(scope))) {
+                state.panic.lock().unwrap().get_or_insert(e);
+            }
+            state.wait_group.done();
+        });
+        // Safety: `WorkStealingPool::scope` doesn't return before the wait
+        // group reaches zero, which only happens once this job has run, so
+        // nothing borrowed for 'scope is used after it ends.
+        let job: Box<dyn FnOnce() + Send + 'static> = unsafe { std::mem::transmute(job) };
+        self.pool.execute(job);
+    }
+
+    /// Waits for every job. On one of the pool's own workers, runs queued
+    /// jobs while it waits, instead of blocking.
+    fn wait(&self) {
+        let wait_group = &self.state.wait_group;
+        if !self.pool.is_worker() {
+            return wait_group.wait();
+        }
+        let mut backoff = Backoff::new();
+        while !wait_group.is_done() {
+            if self.pool.run_one() {
+                backoff.reset();
+            } else if backoff.is_completed() {
+                // Our jobs are running elsewhere.
+                thread::yield_now();
+            } else {
+                backoff.spin();
+            }
+        }
+    }
+}
+
+impl fmt::Debug for WorkStealingScope<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WorkStealingScope")
+            .field("pool", &self.pool)
+            .field("wait_group", &self.state.wait_group)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::Relaxed;
+
+    use crate::pool::work_stealing::WorkStealingPool;
+    use crate::pool::work_stealing_scope::{scope, WorkStealingScope};
+
+    fn count_leaves<'scope>(
+        s: &'scope WorkStealingScope<'scope, '_>,
+        leaves: &'scope AtomicUsize,
+        depth: u32,
+    ) {
+        if depth == 0 {
+            leaves.fetch_add(1, Relaxed);
+            return;
+        }
+        for _ in 0..2 {
+            s.spawn(move |s| count_leaves(s, leaves, depth - 1));
+        }
+    }
+
+    #[test]
+    fn test_nested_spawns_borrow_from_stack() {
+        let depth = if cfg!(miri) { 4 } else { 12 };
+        let leaves = AtomicUsize::new(0);
+        let mut data = [1u64; 8];
+        scope(|s| {
+            count_leaves(s, &leaves, depth);
+            for chunk in data.chunks_mut(2) {
+                s.spawn(move |_| chunk.iter_mut().for_each(|x| *x += 1));
+            }
+        });
+        assert_eq!(leaves.into_inner(), 1 << depth);
+        assert_eq!(data, [2; 8]);
+        assert_eq!(scope(|_| 42), 42);
+    }
+
+    #[test]
+    fn test_nested_scopes_on_one_worker() {
+        // Its only worker waits on the inner scopes, so it has to run their
+        // jobs itself.
+        let pool = WorkStealingPool::new(1);
+        let sum = AtomicUsize::new(0);
+        pool.scope(|s| {
+            for i in 0..4 {
+                let (pool, sum) = (&pool, &sum);
+                s.spawn(move |_| {
+                    pool.scope(|inner| {
+                        inner.spawn(move |_| {
+                            sum.fetch_add(i, Relaxed);
+                        });
+                    });
+                });
+            }
+        });
+        assert_eq!(sum.into_inner(), 6);
+        assert_eq!(pool.panicked_jobs(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "job failed")]
+    fn test_job_panic_propagates_to_scope() {
+        let pool = WorkStealingPool::new(2);
+        pool.scope(|s| {
+            s.spawn(|s| s.spawn(|_| panic!("job failed")));
+            s.spawn(|_| {});
+        });
+    }
+}
@@ -0,0 +1,17 @@
+//! The most commonly used items, to glob import with
+//! `use atomics_and_locks::prelude::*;`.
+//!
+//! The channel traits are imported anonymously, so their methods are
+//! available without their names clashing with the channel types.
+
+pub use crate::arc::{Arc, Weak};
+pub use crate::channels::channel::sender_receiver_channel_with_arc::channel;
+#[cfg(feature = "std")]
+pub use crate::channels::traits::{Receiver as _, Sender as _};
+pub use crate::channels::OneShotChannel;
+#[cfg(feature = "std")]
+pub use crate::channels::{Channel, Priority, PriorityChannel};
+pub use crate::locks::{Condvar, Latch, Mutex, MutexGuard, SpinLock, SpinLockGuard};
+pub use crate::platform::backoff::Backoff;
+#[cfg(feature = "std")]
+pub use crate::pool::{ThreadPool, WaitGroup};
@@ -0,0 +1,259 @@
+use alloc::boxed::Box;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::Deref;
+use core::ptr::NonNull;
+
+use crate::locks::Mutex;
+use crate::platform::wait::{wait, wake_all};
+use crate::sync_shim::plain_atomic::Ordering::{Acquire, Relaxed, SeqCst};
+use crate::sync_shim::plain_atomic::{AtomicBool, AtomicPtr, AtomicU32};
+
+/// A value that's read without locking, and replaced as a whole.
+///
+/// Readers pin one of two phases by counting themselves in it, and then
+/// load the pointer. A writer swaps in the new pointer, flips the phase,
+/// and waits for the readers of the old phase to leave before it drops the
+/// old value. Readers that pin after the flip can only have seen the new
+/// pointer. Writers are serialized by a mutex, so there are never more than
+/// two values around.
+///
+/// Replacing the value from a thread that's reading it waits forever.
+pub struct RcuCell<T> {
+    ptr: AtomicPtr<T>,
+    /// Only the lowest bit matters: the phase new readers pin.
+    phase: AtomicU32,
+    /// How many readers pinned each phase.
+    readers: [AtomicU32; 2],
+    /// Whether a writer is waiting for readers, so the last reader to
+    /// leave has to wake it.
+    synchronizing: AtomicBool,
+    writer: Mutex<()>,
+    _t: PhantomData<Box<T>>,
+}
+
+unsafe impl<T: Send> Send for RcuCell<T> {}
+unsafe impl<T: Send + Sync> Sync for RcuCell<T> {}
+
+/// A pinned reference to the value an `RcuCell` held when it was read. A
+/// writer that replaces the value waits for this to be dropped.
+pub struct RcuGuard<'a, T> {
+    cell: &'a RcuCell<T>,
+    phase: usize,
+    /// Not a reference, which would have to stay valid until the guard's
+    /// `drop` returns, while the writer might drop the value as soon as
+    /// it unpins.
+    value: NonNull<T>,
+}
+
+impl<T> RcuCell<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            ptr: AtomicPtr::new(Box::into_raw(Box::new(value))),
+            phase: AtomicU32::new(0),
+            readers: [AtomicU32::new(0), AtomicU32::new(0)],
+            synchronizing: AtomicBool::new(false),
+            writer: Mutex::new(()),
+            _t: PhantomData,
+        }
+    }
+
+    pub fn read(&self) -> RcuGuard<'_, T> {
+        let phase = self.pin();
+        // Safety: It's a `Box`, so never null.
+        let value = unsafe { NonNull::new_unchecked(self.ptr.load(Acquire)) };
+        RcuGuard {
+            cell: self,
+            phase,
+            value,
+        }
+    }
+
+    /// Counts us as a reader of the current phase, and returns it.
+    fn pin(&self) -> usize {
+        loop {
+            let phase = (self.phase.load(SeqCst) & 1) as usize;
+            self.readers[phase].fetch_add(1, SeqCst);
+            // If the phase flipped in the meantime, the writer might have
+            // missed us and dropped the value we'd load.
+            if (self.phase.load(SeqCst) & 1) as usize == phase {
+                return phase;
+            }
+            self.unpin(phase);
+        }
+    }
+
+    fn unpin(&self, phase: usize) {
+        self.readers[phase].fetch_sub(1, SeqCst);
+        if self.synchronizing.load(SeqCst) {
+            wake_all(&self.readers[phase]);
+        }
+    }
+
+    /// Replaces the value, and drops the old one once no reader can see it
+    /// anymore.
+    pub fn store(&self, value: T) {
+        let _writer = self.writer.lock();
+        self.publish(value);
+    }
+
+    /// Clones the value, changes the clone with `f`, and stores it. Other
+    /// writers wait in the meantime, so no changes are lost.
+    pub fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> R
+    where
+        T: Clone,
+    {
+        let _writer = self.writer.lock();
+        // Safety: Only writers drop values, and we're the only one.
+        let mut value = unsafe { (*self.ptr.load(Relaxed)).clone() };
+        let result = f(&mut value);
+        self.publish(value);
+        result
+    }
+
+    /// Only call this while holding the writer lock.
+    fn publish(&self, value: T) {
+        let old = self.ptr.swap(Box::into_raw(Box::new(value)), SeqCst);
+        self.synchronize();
+        // Safety: Nobody can see it anymore.
+        drop(unsafe { Box::from_raw(old) });
+    }
+
+    /// Waits for the readers that might still see the old value.
+    fn synchronize(&self) {
+        let old = (self.phase.fetch_add(1, SeqCst) & 1) as usize;
+        let readers = &self.readers[old];
+        self.synchronizing.store(true, SeqCst);
+        loop {
+            let n = readers.load(SeqCst);
+            if n == 0 {
+                break;
+            }
+            wait(readers, n);
+        }
+        self.synchronizing.store(false, Relaxed);
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        // Safety: We have the only reference to the cell.
+        unsafe { &mut *self.ptr.load(Relaxed) }
+    }
+
+    pub fn into_inner(self) -> T {
+        let this = core::mem::ManuallyDrop::new(self);
+        // Safety: `this` isn't dropped, so the value is only dropped once.
+        *unsafe { Box::from_raw(this.ptr.load(Relaxed)) }
+    }
+}
+
+impl<T> Drop for RcuCell<T> {
+    fn drop(&mut self) {
+        // Safety: No guard can outlive the cell.
+        drop(unsafe { Box::from_raw(*self.ptr.get_mut()) });
+    }
+}
+
+impl<T> Deref for RcuGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safety: It isn't dropped while we're pinned.
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<T> Drop for RcuGuard<'_, T> {
+    fn drop(&mut self) {
+        self.cell.unpin(self.phase);
+    }
+}
+
+impl<T: Default> Default for RcuCell<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for RcuCell<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RcuCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RcuCell")
+            .field("data", &&*self.read())
+            .finish()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RcuGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::rcu::RcuCell;
+
+    #[test]
+    fn test_readers_see_whole_values() {
+        const N: usize = if cfg!(miri) { 50 } else { 10_000 };
+        let cell = RcuCell::new(vec![0; 8]);
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 1..=N {
+                    if i % 2 == 0 {
+                        cell.store(vec![i; 8]);
+                    } else {
+                        cell.update(|v| v.fill(i));
+                    }
+                }
+            });
+            for _ in 0..2 {
+                s.spawn(|| {
+                    for _ in 0..N {
+                        let v = cell.read();
+                        assert!(v.iter().all(|&x| x == v[0]));
+                    }
+                });
+            }
+        });
+        assert_eq!(cell.into_inner(), vec![N; 8]);
+    }
+
+    #[test]
+    fn test_old_values_outlive_their_readers() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        #[derive(Debug)]
+        struct Counted(u32);
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, Relaxed);
+            }
+        }
+
+        let cell = RcuCell::new(Counted(1));
+        let old = cell.read();
+        thread::scope(|s| {
+            let writer = s.spawn(|| cell.store(Counted(2)));
+            thread::sleep(Duration::from_millis(10));
+            // Still waiting for us.
+            assert!(!writer.is_finished());
+            assert_eq!(old.0, 1);
+            assert_eq!(DROPS.load(Relaxed), 0);
+            drop(old);
+        });
+        assert_eq!(DROPS.load(Relaxed), 1);
+        assert_eq!(cell.read().0, 2);
+        assert_eq!(format!("{cell:?}"), "RcuCell { data: Counted(2) }");
+        drop(cell);
+        assert_eq!(DROPS.load(Relaxed), 2);
+    }
+}
@@ -0,0 +1,247 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::fmt;
+
+use crate::rcu::{RcuCell, RcuGuard};
+
+/// A `Vec` that's read without locking, and copied for every change.
+///
+/// `read` returns a snapshot to iterate, which doesn't change while it's
+/// held. Every change clones the whole `Vec`, so this is for lists that
+/// are read far more often than they change, like a list of subscribers.
+pub struct RcuVec<T> {
+    cell: RcuCell<Vec<T>>,
+}
+
+impl<T: Clone> RcuVec<T> {
+    pub fn new() -> Self {
+        Self {
+            cell: RcuCell::new(Vec::new()),
+        }
+    }
+
+    pub fn read(&self) -> RcuGuard<'_, Vec<T>> {
+        self.cell.read()
+    }
+
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+
+    pub fn push(&self, value: T) {
+        self.update(|v| v.push(value));
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        self.update(Vec::pop)
+    }
+
+    pub fn retain(&self, f: impl FnMut(&T) -> bool) {
+        self.update(|v| v.retain(f));
+    }
+
+    /// Changes a copy with `f`, and publishes it. Other writers wait in
+    /// the meantime, so no changes are lost.
+    pub fn update<R>(&self, f: impl FnOnce(&mut Vec<T>) -> R) -> R {
+        self.cell.update(f)
+    }
+
+    pub fn into_inner(self) -> Vec<T> {
+        self.cell.into_inner()
+    }
+}
+
+/// A `BTreeMap` that's read without locking, and copied for every change.
+///
+/// Like [`RcuVec`], for maps that rarely change, like routing tables.
+pub struct RcuMap<K, V> {
+    cell: RcuCell<BTreeMap<K, V>>,
+}
+
+impl<K: Ord + Clone, V: Clone> RcuMap<K, V> {
+    pub fn new() -> Self {
+        Self {
+            cell: RcuCell::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn read(&self) -> RcuGuard<'_, BTreeMap<K, V>> {
+        self.cell.read()
+    }
+
+    /// A clone of the value for `key`. To avoid the clone, look it up in
+    /// `read` instead, and hold on to the snapshot.
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.read().get(key).cloned()
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.read().contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.update(|m| m.insert(key, value))
+    }
+
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.update(|m| m.remove(key))
+    }
+
+    /// Changes a copy with `f`, and publishes it. Other writers wait in
+    /// the meantime, so no changes are lost.
+    pub fn update<R>(&self, f: impl FnOnce(&mut BTreeMap<K, V>) -> R) -> R {
+        self.cell.update(f)
+    }
+
+    pub fn into_inner(self) -> BTreeMap<K, V> {
+        self.cell.into_inner()
+    }
+}
+
+impl<T: Clone> Default for RcuVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> From<Vec<T>> for RcuVec<T> {
+    fn from(v: Vec<T>) -> Self {
+        Self {
+            cell: RcuCell::new(v),
+        }
+    }
+}
+
+impl<T: Clone> FromIterator<T> for RcuVec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Vec::from_iter(iter).into()
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> Default for RcuMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> From<BTreeMap<K, V>> for RcuMap<K, V> {
+    fn from(m: BTreeMap<K, V>) -> Self {
+        Self {
+            cell: RcuCell::new(m),
+        }
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> FromIterator<(K, V)> for RcuMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        BTreeMap::from_iter(iter).into()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RcuVec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.cell.read().iter()).finish()
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for RcuMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.cell.read().iter()).finish()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::thread;
+
+    use crate::rcu::{RcuMap, RcuVec};
+
+    #[test]
+    fn test_vec_snapshots() {
+        const N: usize = if cfg!(miri) { 20 } else { 1000 };
+        let subscribers: RcuVec<usize> = (0..4).collect();
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 4..4 + N {
+                    subscribers.push(i);
+                    if i % 3 == 0 {
+                        subscribers.retain(|&x| x != i - 1);
+                    }
+                }
+            });
+            s.spawn(|| {
+                for _ in 0..N {
+                    // The snapshot doesn't change while we iterate it.
+                    let snapshot = subscribers.read();
+                    let len = snapshot.len();
+                    assert!(snapshot.iter().is_sorted());
+                    assert_eq!(snapshot.iter().count(), len);
+                }
+            });
+        });
+        let mut expected: Vec<_> = (0..4).collect();
+        for i in 4..4 + N {
+            expected.push(i);
+            if i % 3 == 0 {
+                expected.retain(|&x| x != i - 1);
+            }
+        }
+        assert_eq!(subscribers.pop(), expected.pop());
+        assert_eq!(subscribers.into_inner(), expected);
+        assert_eq!(format!("{:?}", RcuVec::from(vec![1, 2])), "[1, 2]");
+    }
+
+    #[test]
+    fn test_map() {
+        const N: u32 = if cfg!(miri) { 20 } else { 1000 };
+        let routes = RcuMap::new();
+        routes.insert("/".to_string(), 0);
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 1..=N {
+                    routes.insert(format!("/{i}"), i);
+                    routes.update(|m| *m.get_mut("/").unwrap() = i);
+                }
+            });
+            s.spawn(|| {
+                for _ in 0..N {
+                    let snapshot = routes.read();
+                    // Every route is in before the root points at it.
+                    let root = snapshot["/"];
+                    assert!(root == 0 || snapshot.contains_key(&format!("/{root}")));
+                }
+            });
+        });
+        assert_eq!(routes.len(), N as usize + 1);
+        assert_eq!(routes.get("/"), Some(N));
+        assert_eq!(routes.remove("/1"), Some(1));
+        assert!(!routes.contains_key("/1"));
+        let small: RcuMap<_, _> = [(1, 'a')].into_iter().collect();
+        assert_eq!(format!("{small:?}"), "{1: 'a'}");
+    }
+}
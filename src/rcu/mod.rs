@@ -0,0 +1,19 @@
+//! Read-copy-update: readers see a snapshot without locking, writers copy
+//! it, change the copy, and publish that.
+//!
+//! [`RcuCell`] holds the current version of a value. Reading it pins the
+//! version it saw, which stays valid for as long as the guard lives, even
+//! after a writer has published a new one: the writer waits for the
+//! readers of the old version to leave before it drops that. So reading
+//! costs an increment and a decrement of a counter in the cell, and
+//! writing costs a copy and a wait for the slowest reader.
+//!
+//! [`RcuVec`] and [`RcuMap`] are that for a `Vec` and a `BTreeMap`, with
+//! the usual methods for changing them, each one a clone of the whole
+//! collection.
+
+pub mod cell;
+pub mod collections;
+
+pub use cell::{RcuCell, RcuGuard};
+pub use collections::{RcuMap, RcuVec};
@@ -0,0 +1,500 @@
+//! Recording the order in which threads went through the primitives, and
+//! running them through it again, to turn a race that shows up once in a
+//! million runs into a test that fails every time.
+//!
+//! With the `replay` feature, every `chaos` point is a step, and so is
+//! every operation `trace` would record, whether that's on or not. While [`record`] runs a closure, the steps the
+//! threads take are written down, in order, as a [`Schedule`]: which thread,
+//! at which point, and on which object. While [`replay`] runs it, a thread
+//! that gets to a step waits until it's that step's turn, and until the
+//! thread that took the previous step got to its next one. Between two
+//! steps only one thread runs, so all of them see the same values they saw
+//! when recording.
+//!
+//! Only threads that [`register`] take part, by name, as thread ids and
+//! addresses change from run to run. The objects are numbered in the order
+//! the steps first touched them. A stress test records every round, and
+//! prints the schedule of the one that failed:
+//!
+//! ```ignore
+//! let (failed, schedule) = replay::record(|| run_round());
+//! assert!(!failed, "failed with this schedule:\n{schedule}");
+//! ```
+//!
+//! which a unit test can then replay, to fail the same way:
+//!
+//! ```ignore
+//! let schedule: Schedule = SCHEDULE.parse().unwrap();
+//! assert_eq!(replay::replay(&schedule, || run_round()), Ok(true));
+//! ```
+//!
+//! Code between steps isn't recorded, so a thread that reads shared state
+//! without passing a step on the way can still see it change while it
+//! waits, and the run goes some other way. When a thread gets to a
+//! different step than it did before, or the step whose turn it is isn't
+//! taken within [`TIMEOUT`], the replay gives up, and lets every thread run
+//! freely from there on.
+//!
+//! One recording or replay runs at a time. Others wait for it.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::str::FromStr;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// How long a replay waits for a step to be taken, before giving up.
+pub const TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    /// The name it was registered with.
+    pub thread: String,
+    /// The `chaos` point, or the operation, like `Lock`.
+    pub point: String,
+    /// The object an operation was on, numbered from zero in the order
+    /// they first showed up. `None` for `chaos` points.
+    pub object: Option<u32>,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.thread, self.point)?;
+        if let Some(object) = self.object {
+            write!(f, " @{object}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Step {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (thread, rest) = s
+            .trim()
+            .rsplit_once(": ")
+            .ok_or_else(|| format!("{s:?} isn't thread: point"))?;
+        let (point, object) = match rest.split_once(" @") {
+            Some((point, object)) => {
+                let object = object
+                    .parse()
+                    .map_err(|_| format!("{object:?} isn't a number"))?;
+                (point, Some(object))
+            }
+            None => (rest, None),
+        };
+        Ok(Step {
+            thread: thread.into(),
+            point: point.into(),
+            object,
+        })
+    }
+}
+
+/// The steps of one run, one per line when displayed, which is also what
+/// it parses.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Schedule {
+    steps: Vec<Step>,
+}
+
+impl Schedule {
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in &self.steps {
+            writeln!(f, "{step}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let steps = s
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()?;
+        Ok(Schedule { steps })
+    }
+}
+
+/// Why a replay gave up, at which step.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    /// A thread got to another step than its next one in the schedule, or
+    /// to one after its last.
+    Unexpected {
+        at: usize,
+        expected: Option<Step>,
+        found: Step,
+    },
+    /// The step whose turn it was wasn't taken within [`TIMEOUT`].
+    Stuck { at: usize, expected: Step },
+    /// The closure returned before all steps were taken.
+    Unfinished { at: usize },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Divergence::Unexpected {
+                at,
+                expected: Some(expected),
+                found,
+            } => write!(f, "step {at}: expected {expected}, found {found}"),
+            Divergence::Unexpected {
+                at,
+                expected: None,
+                found,
+            } => write!(f, "step {at}: {found}, after its thread's last step"),
+            Divergence::Stuck { at, expected } => {
+                write!(f, "step {at}: {expected} wasn't taken")
+            }
+            Divergence::Unfinished { at } => write!(f, "returned at step {at}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Off,
+    Recording,
+    Replaying,
+}
+
+struct State {
+    mode: Mode,
+    steps: Vec<Step>,
+    /// The next step to take, when replaying.
+    next: usize,
+    /// The thread that took the last step, until it gets to its next one.
+    running: Option<String>,
+    diverged: Option<Divergence>,
+    objects: BTreeMap<usize, u32>,
+}
+
+impl State {
+    /// The number `addr` has, or would get if it were new.
+    fn object(&self, addr: usize) -> u32 {
+        self.objects
+            .get(&addr)
+            .copied()
+            .unwrap_or(self.objects.len() as u32)
+    }
+
+    fn step(&self, thread: String, point: &str, addr: Option<usize>) -> Step {
+        Step {
+            object: addr.map(|a| self.object(a)),
+            thread,
+            point: point.into(),
+        }
+    }
+
+    fn take(&mut self, step: &Step, addr: Option<usize>) {
+        if let Some(addr) = addr {
+            let n = self.objects.len() as u32;
+            self.objects.entry(addr).or_insert(n);
+        }
+        self.running = Some(step.thread.clone());
+    }
+
+    fn diverge(&mut self, divergence: Divergence) {
+        self.diverged.get_or_insert(divergence);
+        CHANGED.notify_all();
+    }
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    mode: Mode::Off,
+    steps: Vec::new(),
+    next: 0,
+    running: None,
+    diverged: None,
+    objects: BTreeMap::new(),
+});
+static CHANGED: Condvar = Condvar::new();
+/// Held for as long as a recording or replay runs.
+static SESSION: Mutex<()> = Mutex::new(());
+
+fn state() -> MutexGuard<'static, State> {
+    // A replay that gives up doesn't leave the state half updated, so
+    // poisoning doesn't matter.
+    STATE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+thread_local! {
+    static NAME: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Makes the current thread take part under `name`, until the
+/// registration is dropped. The name has to be unique among the threads
+/// that take part, and the same in every run.
+///
+/// A thread that's done should drop it before waiting for the others, or
+/// they wait for it to get to its next step.
+pub fn register(name: impl Into<String>) -> Registration {
+    NAME.with(|n| *n.borrow_mut() = Some(name.into()));
+    Registration {
+        _not_send: PhantomData,
+    }
+}
+
+#[must_use = "the thread stops taking part when this is dropped"]
+pub struct Registration {
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let name = NAME.with(|n| n.borrow_mut().take());
+        let mut state = state();
+        if name.is_some() && state.running == name {
+            state.running = None;
+            CHANGED.notify_all();
+        }
+    }
+}
+
+/// Runs `f`, writing down the steps of the registered threads.
+pub fn record<R>(f: impl FnOnce() -> R) -> (R, Schedule) {
+    let _session = SESSION.lock().unwrap_or_else(PoisonError::into_inner);
+    start(Mode::Recording, Vec::new());
+    let r = f();
+    let mut state = state();
+    state.mode = Mode::Off;
+    let steps = std::mem::take(&mut state.steps);
+    (r, Schedule { steps })
+}
+
+/// Runs `f`, making the registered threads take the steps in `schedule`,
+/// in order. Returns why it gave up, if it did.
+pub fn replay<R>(schedule: &Schedule, f: impl FnOnce() -> R) -> Result<R, Divergence> {
+    let _session = SESSION.lock().unwrap_or_else(PoisonError::into_inner);
+    start(Mode::Replaying, schedule.steps.clone());
+    let r = f();
+    let mut state = state();
+    state.mode = Mode::Off;
+    if state.next < state.steps.len() {
+        let at = state.next;
+        state.diverge(Divergence::Unfinished { at });
+    }
+    match state.diverged.take() {
+        Some(divergence) => Err(divergence),
+        None => Ok(r),
+    }
+}
+
+fn start(mode: Mode, steps: Vec<Step>) {
+    let mut state = state();
+    state.mode = mode;
+    state.steps = steps;
+    state.next = 0;
+    state.running = None;
+    state.diverged = None;
+    state.objects.clear();
+}
+
+fn current() -> Option<String> {
+    NAME.with(|n| n.borrow().clone())
+}
+
+/// Lets the next step be taken, if the current thread took the last one.
+/// `chaos` calls this before pausing, so a pause at a point gives the
+/// other threads a chance to go first.
+pub(crate) fn arrive() {
+    if let Some(thread) = current() {
+        arrived(&mut state(), &thread);
+    }
+}
+
+fn arrived(state: &mut State, thread: &String) {
+    if state.running.as_ref() == Some(thread) {
+        state.running = None;
+        CHANGED.notify_all();
+    }
+}
+
+/// A step, on the object at `addr` if it's an operation.
+///
+/// When recording, it waits until the thread that took the last step got
+/// to its next one too, so the steps are taken one at a time, like when
+/// replaying. Unless that thread doesn't get there within [`TIMEOUT`],
+/// because it's waiting for this one, outside of any step.
+pub(crate) fn point(name: &str, addr: Option<usize>) {
+    let Some(thread) = current() else {
+        return;
+    };
+    let mut state = state();
+    arrived(&mut state, &thread);
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        let timed_out = Instant::now() >= deadline;
+        let found = state.step(thread.clone(), name, addr);
+        match state.mode {
+            Mode::Off => return,
+            Mode::Recording => {
+                if state.running.is_none() || timed_out {
+                    state.take(&found, addr);
+                    state.steps.push(found);
+                    return;
+                }
+            }
+            Mode::Replaying => {
+                if state.diverged.is_some() {
+                    return;
+                }
+                let at = state.next;
+                let ours = (at..state.steps.len()).find(|&i| state.steps[i].thread == thread);
+                match ours {
+                    Some(i) if state.steps[i] != found => {
+                        let expected = Some(state.steps[i].clone());
+                        state.diverge(Divergence::Unexpected {
+                            at,
+                            expected,
+                            found,
+                        });
+                        return;
+                    }
+                    None => {
+                        state.diverge(Divergence::Unexpected {
+                            at,
+                            expected: None,
+                            found,
+                        });
+                        return;
+                    }
+                    Some(i) if i == at && state.running.is_none() => {
+                        state.take(&found, addr);
+                        state.next += 1;
+                        return;
+                    }
+                    Some(_) if timed_out => {
+                        let expected = state.steps[at].clone();
+                        state.diverge(Divergence::Stuck { at, expected });
+                        return;
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        state = CHANGED
+            .wait_timeout(state, timeout)
+            .unwrap_or_else(PoisonError::into_inner)
+            .0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+    use std::sync::atomic::{AtomicBool, AtomicU32};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::chaos::{self, Pause, RaceConfig};
+    use crate::locks::SpinLock;
+    use crate::replay::{self, record, register, Divergence, Schedule, Step};
+
+    /// Two receivers racing for one message, which both get it if they
+    /// both check the flag before either clears it. Returns how many got
+    /// it.
+    fn check_then_take() -> u32 {
+        let ready = AtomicBool::new(false);
+        let received = AtomicU32::new(0);
+        thread::scope(|s| {
+            for name in ["a", "b"] {
+                let (ready, received) = (&ready, &received);
+                s.spawn(move || {
+                    let _r = register(name);
+                    while received.load(Relaxed) == 0 {
+                        chaos::point("replay::check");
+                        if ready.load(Acquire) {
+                            chaos::point("replay::checked");
+                            ready.store(false, Relaxed);
+                            received.fetch_add(1, Relaxed);
+                        }
+                        thread::yield_now();
+                    }
+                });
+            }
+            s.spawn(|| {
+                let _r = register("sender");
+                chaos::point("replay::send");
+                ready.store(true, Release);
+            });
+        });
+        received.into_inner()
+    }
+
+    #[test]
+    fn test_replay_reproduces_a_race() {
+        let config = RaceConfig::new()
+            .sleep_percent(50)
+            .max_sleep(Duration::from_micros(100));
+        chaos::pause_at("replay::checked", Pause::Random(config));
+        let failed = (0..1000)
+            .map(|_| record(check_then_take))
+            .find(|&(received, _)| received > 1);
+        chaos::resume_at("replay::checked");
+        let (_, schedule) = failed.expect("the race never showed");
+
+        let text = schedule.to_string();
+        assert_eq!(text.parse::<Schedule>(), Ok(schedule.clone()));
+        for _ in 0..if cfg!(miri) { 2 } else { 20 } {
+            assert_eq!(replay::replay(&schedule, check_then_take), Ok(2));
+        }
+    }
+
+    #[test]
+    fn test_objects_and_divergence() {
+        let locks = [SpinLock::new(()), SpinLock::new(())];
+        let run = || {
+            thread::scope(|s| {
+                s.spawn(|| {
+                    let _r = register("t");
+                    drop(locks[1].lock());
+                    drop(locks[0].lock());
+                });
+            })
+        };
+        let ((), schedule) = record(run);
+        let shown: Vec<_> = schedule.steps().iter().map(Step::to_string).collect();
+        assert_eq!(
+            shown,
+            [
+                "t: spin_lock::lock",
+                "t: Lock @0",
+                "t: Unlock @0",
+                "t: spin_lock::lock",
+                "t: Lock @1",
+                "t: Unlock @1",
+            ]
+        );
+        assert_eq!(replay::replay(&schedule, run), Ok(()));
+
+        let mut longer = schedule.clone();
+        longer.steps.push(schedule.steps[0].clone());
+        assert_eq!(
+            replay::replay(&longer, run),
+            Err(Divergence::Unfinished { at: shown.len() })
+        );
+        let mut other = schedule.clone();
+        other.steps[0].point = "somewhere else".into();
+        assert!(matches!(
+            replay::replay(&other, run),
+            Err(Divergence::Unexpected { at: 0, .. })
+        ));
+    }
+}
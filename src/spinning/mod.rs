@@ -1 +0,0 @@
-pub mod spin_lock;
@@ -0,0 +1,430 @@
+//! Contention statistics for `Lock`, `RwLock`, `SpinLock` and `Arc`, and
+//! wait statistics for `Condvar`.
+//!
+//! With the `stats` feature, every lock counts how often it's taken, how
+//! often that meant waiting, and for how long it was waited for and held.
+//! Call `stats()` on a lock to get a snapshot, and `reset_stats()` to start
+//! over, to find the hottest lock without an external profiler.
+//!
+//! A lock counts as contended when taking it didn't succeed right away, so
+//! with this feature, `lock()` tries a `try_lock()` first. For an `RwLock`,
+//! reads and writes are counted together, and the hold times of readers
+//! holding it at the same time add up.
+//!
+//! A `Condvar` counts its waits and notifications, how many of the wakeups
+//! found nothing to do, and how many waiters `notify_all` moved over to the
+//! mutex instead of waking them. A lot of the second means notifying too
+//! eagerly, or more waiters than there's work for.
+//!
+//! An `Arc`'s allocation counts the compare-exchanges that failed in
+//! `Weak::upgrade` and `Arc::get_mut`, and how high the strong count got,
+//! for all its `Arc`s and `Weak`s together. Many failed upgrades mean many
+//! threads upgrading the same `Weak` at once.
+
+use std::time::{Duration, Instant};
+
+use crate::sync_shim::plain_atomic::Ordering::Relaxed;
+use crate::sync_shim::plain_atomic::{AtomicU64, AtomicUsize};
+
+/// A snapshot of a lock's statistics.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LockStats {
+    pub acquisitions: u64,
+    /// The acquisitions that had to wait.
+    pub contended: u64,
+    pub total_wait: Duration,
+    pub max_wait: Duration,
+    pub total_hold: Duration,
+    pub max_hold: Duration,
+}
+
+impl LockStats {
+    pub fn average_wait(&self) -> Duration {
+        average(self.total_wait, self.contended)
+    }
+
+    pub fn average_hold(&self) -> Duration {
+        average(self.total_hold, self.acquisitions)
+    }
+}
+
+fn average(total: Duration, n: u64) -> Duration {
+    total
+        .checked_div(n.try_into().unwrap_or(u32::MAX))
+        .unwrap_or_default()
+}
+
+/// The statistics as they're being recorded, inside a lock.
+#[derive(Debug)]
+pub(crate) struct Recorder {
+    acquisitions: AtomicU64,
+    contended: AtomicU64,
+    total_wait_ns: AtomicU64,
+    max_wait_ns: AtomicU64,
+    total_hold_ns: AtomicU64,
+    max_hold_ns: AtomicU64,
+}
+
+fn nanos(d: Duration) -> u64 {
+    d.as_nanos().try_into().unwrap_or(u64::MAX)
+}
+
+impl Recorder {
+    pub(crate) const fn new() -> Self {
+        Self {
+            acquisitions: AtomicU64::new(0),
+            contended: AtomicU64::new(0),
+            total_wait_ns: AtomicU64::new(0),
+            max_wait_ns: AtomicU64::new(0),
+            total_hold_ns: AtomicU64::new(0),
+            max_hold_ns: AtomicU64::new(0),
+        }
+    }
+
+    /// Locks through `try_lock` if that works, or `lock` otherwise, and
+    /// records the wait in the latter case. Call `acquired` after.
+    pub(crate) fn lock(&self, try_lock: impl FnOnce() -> bool, lock: impl FnOnce()) {
+        self.lock_until(try_lock, || {
+            lock();
+            true
+        });
+    }
+
+    /// Like `lock`, but `lock_until` can give up, and returns whether it
+    /// got the lock. A wait that gave up isn't recorded.
+    pub(crate) fn lock_until(
+        &self,
+        try_lock: impl FnOnce() -> bool,
+        lock_until: impl FnOnce() -> bool,
+    ) -> bool {
+        if try_lock() {
+            return true;
+        }
+        let start = Instant::now();
+        if !lock_until() {
+            return false;
+        }
+        let wait = nanos(start.elapsed());
+        self.contended.fetch_add(1, Relaxed);
+        self.total_wait_ns.fetch_add(wait, Relaxed);
+        self.max_wait_ns.fetch_max(wait, Relaxed);
+        true
+    }
+
+    /// Returns when the lock was taken, to pass to `released`.
+    pub(crate) fn acquired(&self) -> Instant {
+        self.acquisitions.fetch_add(1, Relaxed);
+        Instant::now()
+    }
+
+    pub(crate) fn released(&self, acquired: Instant) {
+        let hold = nanos(acquired.elapsed());
+        self.total_hold_ns.fetch_add(hold, Relaxed);
+        self.max_hold_ns.fetch_max(hold, Relaxed);
+    }
+
+    /// Not a consistent snapshot if the lock is in use, but each field is
+    /// right.
+    pub(crate) fn snapshot(&self) -> LockStats {
+        LockStats {
+            acquisitions: self.acquisitions.load(Relaxed),
+            contended: self.contended.load(Relaxed),
+            total_wait: Duration::from_nanos(self.total_wait_ns.load(Relaxed)),
+            max_wait: Duration::from_nanos(self.max_wait_ns.load(Relaxed)),
+            total_hold: Duration::from_nanos(self.total_hold_ns.load(Relaxed)),
+            max_hold: Duration::from_nanos(self.max_hold_ns.load(Relaxed)),
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        for a in [
+            &self.acquisitions,
+            &self.contended,
+            &self.total_wait_ns,
+            &self.max_wait_ns,
+            &self.total_hold_ns,
+            &self.max_hold_ns,
+        ] {
+            a.store(0, Relaxed);
+        }
+    }
+}
+
+/// A snapshot of a condition variable's statistics.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CondvarStats {
+    pub waits: u64,
+    /// `notify_one` and `notify_all` calls, whether anyone was waiting or
+    /// not.
+    pub notifications: u64,
+    /// Wakeups in `wait_while` and `wait_while_until` after which the
+    /// condition still held, and the deadline hadn't passed. A plain `wait`
+    /// can't tell.
+    pub spurious_wakeups: u64,
+    /// Waiters that `notify_all` moved to the mutex's queue, to be woken
+    /// one by one as it's unlocked, rather than all at once.
+    pub requeued: u64,
+}
+
+#[derive(Debug)]
+pub(crate) struct CondvarRecorder {
+    pub(crate) waits: AtomicU64,
+    pub(crate) notifications: AtomicU64,
+    pub(crate) spurious_wakeups: AtomicU64,
+    pub(crate) requeued: AtomicU64,
+}
+
+impl CondvarRecorder {
+    pub(crate) const fn new() -> Self {
+        Self {
+            waits: AtomicU64::new(0),
+            notifications: AtomicU64::new(0),
+            spurious_wakeups: AtomicU64::new(0),
+            requeued: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> CondvarStats {
+        CondvarStats {
+            waits: self.waits.load(Relaxed),
+            notifications: self.notifications.load(Relaxed),
+            spurious_wakeups: self.spurious_wakeups.load(Relaxed),
+            requeued: self.requeued.load(Relaxed),
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        for a in [
+            &self.waits,
+            &self.notifications,
+            &self.spurious_wakeups,
+            &self.requeued,
+        ] {
+            a.store(0, Relaxed);
+        }
+    }
+}
+
+/// A snapshot of the statistics of an `Arc`'s allocation.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ArcStats {
+    /// Compare-exchanges in `Weak::upgrade` that lost to another thread
+    /// changing the count, and had to retry.
+    pub failed_upgrades: u64,
+    /// `Arc::get_mut` calls that found other `Arc`s or `Weak`s.
+    pub failed_get_muts: u64,
+    /// The highest the strong count was raised to, or 1 for a new `Arc`.
+    pub peak_strong: usize,
+}
+
+impl ArcStats {
+    /// How close the peak strong count came to where cloning aborts, from
+    /// 0 to 1. Anything noticeably above 0 means `Arc`s are leaking.
+    pub fn abort_proximity(&self) -> f64 {
+        self.peak_strong as f64 / (usize::MAX / 2) as f64
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ArcRecorder {
+    pub(crate) failed_upgrades: AtomicU64,
+    pub(crate) failed_get_muts: AtomicU64,
+    peak_strong: AtomicUsize,
+}
+
+impl ArcRecorder {
+    pub(crate) const fn new() -> Self {
+        Self {
+            failed_upgrades: AtomicU64::new(0),
+            failed_get_muts: AtomicU64::new(0),
+            peak_strong: AtomicUsize::new(1),
+        }
+    }
+
+    /// The strong count was just raised to `n`.
+    pub(crate) fn strong(&self, n: usize) {
+        self.peak_strong.fetch_max(n, Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> ArcStats {
+        ArcStats {
+            failed_upgrades: self.failed_upgrades.load(Relaxed),
+            failed_get_muts: self.failed_get_muts.load(Relaxed),
+            peak_strong: self.peak_strong.load(Relaxed),
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        self.failed_upgrades.store(0, Relaxed);
+        self.failed_get_muts.store(0, Relaxed);
+        self.peak_strong.store(0, Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::locks::{Condvar, Mutex, RawSpinRwLock, RwLock, SpinLock};
+    use crate::stats::{ArcStats, CondvarStats, LockStats};
+    use crate::Arc;
+
+    #[test]
+    fn test_counts_contention() {
+        let mutex = Mutex::new(());
+        drop(mutex.lock());
+        assert!(mutex.try_lock().is_some());
+        let stats = mutex.stats();
+        assert_eq!((stats.acquisitions, stats.contended), (2, 0));
+
+        thread::scope(|s| {
+            let guard = mutex.lock();
+            s.spawn(|| drop(mutex.lock()));
+            thread::sleep(Duration::from_millis(20));
+            drop(guard);
+        });
+        let stats = mutex.stats();
+        assert_eq!((stats.acquisitions, stats.contended), (4, 1));
+        assert!(stats.max_wait >= Duration::from_millis(10));
+        assert!(stats.max_hold >= Duration::from_millis(10));
+        assert_eq!(stats.average_wait(), stats.total_wait);
+
+        mutex.reset_stats();
+        assert_eq!(mutex.stats(), LockStats::default());
+    }
+
+    #[test]
+    fn test_counts_timed_locking() {
+        let mutex = Mutex::new(());
+        thread::scope(|s| {
+            let guard = mutex.lock();
+            // Gives up, so it's not an acquisition.
+            s.spawn(|| assert!(mutex.try_lock_for(Duration::from_millis(5)).is_err()))
+                .join()
+                .unwrap();
+            s.spawn(|| drop(mutex.try_lock_for(Duration::from_secs(10)).unwrap()));
+            thread::sleep(Duration::from_millis(20));
+            drop(guard);
+        });
+        let stats = mutex.stats();
+        assert_eq!((stats.acquisitions, stats.contended), (2, 1));
+        assert!(stats.max_wait >= Duration::from_millis(10));
+
+        let rwlock = RwLock::<_, RawSpinRwLock>::new(());
+        drop(rwlock.try_read_for(Duration::from_secs(10)).unwrap());
+        drop(rwlock.try_write_for(Duration::from_secs(10)).unwrap());
+        let spin_lock = SpinLock::new(());
+        drop(spin_lock.try_lock_for(Duration::from_secs(10)).unwrap());
+        assert_eq!(rwlock.stats().acquisitions, 2);
+        assert_eq!(spin_lock.stats().acquisitions, 1);
+    }
+
+    #[test]
+    fn test_other_locks() {
+        let rwlock = RwLock::<_, RawSpinRwLock>::new(());
+        let (r1, r2) = (rwlock.read(), rwlock.read());
+        drop((r1, r2));
+        drop(rwlock.write());
+        assert_eq!(rwlock.stats().acquisitions, 3);
+
+        let spin_lock = SpinLock::new(());
+        drop(spin_lock.lock());
+        assert!(spin_lock.try_lock().is_some());
+        assert_eq!(spin_lock.stats().acquisitions, 2);
+    }
+
+    #[test]
+    fn test_condvar() {
+        let state = Mutex::new(false);
+        let condvar = Condvar::new();
+        thread::scope(|s| {
+            for _ in 0..3 {
+                s.spawn(|| drop(condvar.wait_while(state.lock(), |go| !*go)));
+            }
+            while condvar.stats().waits < 3 {
+                thread::yield_now();
+            }
+            // Wakes one for nothing, at least once.
+            while condvar.stats().spurious_wakeups == 0 {
+                condvar.notify_one();
+                thread::yield_now();
+            }
+            thread::sleep(Duration::from_millis(10));
+            let mut go = state.lock();
+            *go = true;
+            condvar.notify_all();
+        });
+        let stats = condvar.stats();
+        assert!(stats.waits >= 4);
+        assert!(stats.spurious_wakeups >= 1);
+        assert!(stats.notifications >= 2);
+        // Unless a waiter wasn't parked yet, one was woken and the other
+        // two were moved to the mutex.
+        assert!(stats.requeued <= 2);
+
+        condvar.reset_stats();
+        assert_eq!(condvar.stats(), CondvarStats::default());
+    }
+
+    #[test]
+    fn test_condvar_timed_waits() {
+        let state = Mutex::new(false);
+        let condvar = Condvar::new();
+        thread::scope(|s| {
+            let waiter = s.spawn(|| {
+                let g = state.lock();
+                condvar
+                    .wait_timeout_while(g, Duration::from_secs(10), |go| !*go)
+                    .1
+            });
+            while condvar.stats().waits == 0 {
+                thread::yield_now();
+            }
+            while condvar.stats().spurious_wakeups == 0 {
+                condvar.notify_one();
+                thread::yield_now();
+            }
+            *state.lock() = true;
+            condvar.notify_one();
+            assert!(!waiter.join().unwrap());
+        });
+        let (_, timed_out) = condvar.wait_timeout(state.lock(), Duration::from_millis(5));
+        assert!(timed_out);
+        let stats = condvar.stats();
+        assert!(stats.waits >= 3);
+        assert!(stats.spurious_wakeups >= 1);
+    }
+
+    #[test]
+    fn test_arc() {
+        let mut a = Arc::new(0);
+        let clones: Vec<_> = (0..3).map(|_| a.clone()).collect();
+        assert!(Arc::get_mut(&mut a).is_none());
+        drop(clones);
+        let weak = Arc::downgrade(&a);
+        assert!(Arc::get_mut(&mut a).is_none());
+        let stats = Arc::stats(&a);
+        assert_eq!((stats.failed_get_muts, stats.peak_strong), (2, 4));
+        assert!(stats.abort_proximity() < 1e-9);
+
+        const UPGRADES: usize = if cfg!(miri) { 20 } else { 10_000 };
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..UPGRADES {
+                        drop(weak.upgrade().unwrap());
+                    }
+                });
+            }
+        });
+        // How many upgrades failed depends on timing, and even a single
+        // thread can fail a compare_exchange_weak.
+        assert!((2..=5).contains(&Arc::stats(&a).peak_strong));
+
+        drop(weak);
+        assert!(Arc::get_mut(&mut a).is_some());
+        Arc::reset_stats(&a);
+        assert_eq!(Arc::stats(&a), ArcStats::default());
+    }
+}
@@ -0,0 +1,155 @@
+//! The atomics the model-checked primitives are built on.
+//!
+//! Normally these are just core's, but under `--cfg loom` they're loom's,
+//! so the `loom` tests can run the primitives under `loom::model`, which
+//! tries every interleaving and every outcome the memory orderings allow:
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" cargo test --release --lib loom
+//! ```
+//!
+//! With the `shuttle` feature they're shuttle's instead, for the randomized
+//! tests in `tests/shuttle.rs`, which reach the larger structures loom
+//! can't explore exhaustively:
+//!
+//! ```text
+//! cargo test --release --features shuttle --test shuttle
+//! ```
+//!
+//! `Arc`, the one-shot channels, `SpinLock`, `RawSpinRwLock` and the
+//! work-stealing deque are built on this.
+//!
+//! With the `portable-atomic` feature, the atomics come from the
+//! `portable-atomic` crate instead of core, everywhere but under loom and
+//! shuttle, for targets that lack some of them natively (see
+//! [`plain_atomic`]). On `thumbv6m`, which has no compare-and-swap at all:
+//!
+//! ```text
+//! cargo build --target thumbv6m-none-eabi --no-default-features \
+//!     --features portable-atomic,portable-atomic/critical-section
+//! ```
+
+#[cfg(all(loom, feature = "shuttle"))]
+compile_error!("`--cfg loom` and the `shuttle` feature can't be combined");
+
+// Only `SpinLock::try_lock_for` spins with this when not model checking.
+#[cfg(all(feature = "std", not(any(loom, feature = "shuttle"))))]
+pub(crate) use core::hint::spin_loop;
+#[cfg(loom)]
+pub(crate) use loom::hint::spin_loop;
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic;
+#[cfg(not(any(loom, feature = "shuttle")))]
+pub(crate) use plain_atomic as atomic;
+// Only the loom tests need this for now.
+#[cfg(all(test, loom))]
+pub(crate) use loom::thread;
+#[cfg(feature = "shuttle")]
+pub(crate) use shuttle::hint::spin_loop;
+#[cfg(feature = "shuttle")]
+pub(crate) use shuttle::sync::atomic;
+
+/// The atomics of everything loom and shuttle don't check: core's, or
+/// `portable-atomic`'s with that feature, which fills in what the target
+/// lacks, like `AtomicU64` on 32-bit targets, or compare-and-swap on
+/// `thumbv6m`. Its `Ordering` is core's, so that can come from either.
+pub(crate) mod plain_atomic {
+    #[cfg(not(feature = "portable-atomic"))]
+    pub(crate) use core::sync::atomic::*;
+    #[cfg(feature = "portable-atomic")]
+    pub(crate) use portable_atomic::*;
+}
+
+/// `atomic`, for the primitives that need to create their atomics in a
+/// const context, like `RawLock::INIT`. Loom's can't be, so only shuttle's
+/// replace these.
+pub(crate) mod const_atomic {
+    #[cfg(not(feature = "shuttle"))]
+    pub(crate) use super::plain_atomic::*;
+    #[cfg(feature = "shuttle")]
+    pub(crate) use shuttle::sync::atomic::*;
+}
+
+/// `loom::model`, but with a preemption bound (unless `LOOM_MAX_PREEMPTIONS`
+/// sets one), to keep the runs short.
+///
+/// Loom never finishes exploring a thread that spins until another one makes
+/// progress, so the tests stick to `try_lock` and friends.
+#[cfg(all(test, loom))]
+pub(crate) fn model(f: impl Fn() + Sync + Send + 'static) {
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound.get_or_insert(3);
+    builder.check(f);
+}
+
+/// `*a.get_mut()`, which loom's `AtomicBool` doesn't have.
+pub(crate) fn unsync_load(a: &mut atomic::AtomicBool) -> bool {
+    #[cfg(not(loom))]
+    return *a.get_mut();
+    // Safety: The mutable reference means there's no concurrent access.
+    #[cfg(loom)]
+    return unsafe { a.unsync_load() };
+}
+
+/// `unsync_load`, for an `AtomicU8`.
+pub(crate) fn unsync_load_u8(a: &mut atomic::AtomicU8) -> u8 {
+    #[cfg(not(loom))]
+    return *a.get_mut();
+    // Safety: The mutable reference means there's no concurrent access.
+    #[cfg(loom)]
+    return unsafe { a.unsync_load() };
+}
+
+/// Waits until `a` no longer holds `value`, watching it the platform's way
+/// (see `platform::backoff`), or with a single spin under loom and shuttle,
+/// whose atomics that can't watch.
+pub(crate) fn spin_while_eq(a: &atomic::AtomicBool, value: bool) {
+    #[cfg(not(any(loom, feature = "shuttle")))]
+    crate::platform::backoff::spin_while_eq(a, value);
+    #[cfg(any(loom, feature = "shuttle"))]
+    {
+        let _ = (a, value);
+        spin_loop();
+    }
+}
+
+/// Declares a `const fn`, except under loom, whose atomics can't be
+/// created in a const context.
+macro_rules! const_fn {
+    ($(#[$attr:meta])* $vis:vis fn $($rest:tt)*) => {
+        #[cfg(not(loom))]
+        $(#[$attr])* $vis const fn $($rest)*
+        #[cfg(loom)]
+        $(#[$attr])* $vis fn $($rest)*
+    };
+}
+
+pub(crate) use const_fn;
+
+/// A pause point for `chaos`, which compiles to nothing without the
+/// feature.
+macro_rules! chaos_point {
+    ($name:literal) => {
+        #[cfg(feature = "chaos")]
+        crate::chaos::point($name);
+    };
+}
+
+pub(crate) use chaos_point;
+
+/// Records an event for `trace`, which compiles to nothing without the
+/// feature. It's also a step for `replay`.
+macro_rules! trace_event {
+    ($op:ident, $addr:expr, $ordering:ident) => {
+        #[cfg(feature = "replay")]
+        crate::replay::point(stringify!($op), Some($addr));
+        #[cfg(feature = "trace")]
+        crate::trace::record(
+            crate::trace::Op::$op,
+            $addr,
+            core::sync::atomic::Ordering::$ordering,
+        );
+    };
+}
+
+pub(crate) use trace_event;
@@ -0,0 +1,118 @@
+//! Small configurations of the bigger structures, for model checking.
+//!
+//! Loom tries every interleaving, so it only gets through a structure with
+//! a handful of states, and shuttle's random schedules hit the interesting
+//! ones a lot sooner in a small structure too: a deque with two slots runs
+//! full and wraps around after a few pushes, where one with the usual
+//! capacity never does in a test that short. These are the same structures,
+//! only configured small, so a test of your own code built on them can
+//! explore it without the state space blowing up. Under
+//! `RUSTFLAGS="--cfg loom"`, this crate's lock-free structures are built on
+//! loom's atomics, and with the `shuttle` feature on shuttle's.
+
+use crate::lockfree::{self, Stealer, Worker};
+
+/// A work-stealing deque with room for `CAPACITY` items. That it's a power
+/// of two is checked when compiling.
+pub fn deque<T, const CAPACITY: usize>() -> (Worker<T>, Stealer<T>) {
+    const {
+        assert!(
+            CAPACITY.is_power_of_two(),
+            "capacity must be a power of two"
+        )
+    };
+    lockfree::deque(CAPACITY)
+}
+
+/// A deque with two slots: enough for the owner and a thief to each take a
+/// different item, and full after two pushes.
+pub fn tiny_deque<T>() -> (Worker<T>, Stealer<T>) {
+    deque::<T, 2>()
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::sync::Arc;
+
+    use crate::lockfree::Steal;
+    use crate::sync_shim::atomic::AtomicUsize;
+    use crate::sync_shim::atomic::Ordering::Relaxed;
+    use crate::sync_shim::thread;
+    use crate::testing::tiny_deque;
+
+    /// Fails if `push` stores `bottom` with less than Release, or `steal`
+    /// loads it with less than Acquire.
+    #[test]
+    fn loom_steal_sees_writes_before_push() {
+        crate::sync_shim::model(|| {
+            let (worker, stealer) = tiny_deque();
+            let written = Arc::new(AtomicUsize::new(0));
+            let thief = thread::spawn({
+                let written = written.clone();
+                move || {
+                    if let Steal::Success(()) = stealer.steal() {
+                        assert_eq!(written.load(Relaxed), 1);
+                    }
+                }
+            });
+            written.store(1, Relaxed);
+            worker.push(()).unwrap();
+            thief.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn loom_tiny_deque_takes_every_item_once() {
+        crate::sync_shim::model(|| {
+            let (worker, stealer) = tiny_deque();
+            worker.push(1).unwrap();
+            worker.push(2).unwrap();
+            assert_eq!(worker.push(3), Err(3));
+            // Two steals, so the thief can get to the item the owner pops,
+            // but no retries, since loom never finishes exploring those.
+            let thief = thread::spawn(move || {
+                (0..2)
+                    .filter_map(|_| match stealer.steal() {
+                        Steal::Success(i) => Some(i),
+                        Steal::Empty | Steal::Retry => None,
+                    })
+                    .collect::<Vec<_>>()
+            });
+            let mut taken = Vec::new();
+            while let Some(i) = worker.pop() {
+                taken.push(i);
+            }
+            taken.extend(thief.join().unwrap());
+            taken.sort_unstable();
+            assert_eq!(taken, [1, 2]);
+        });
+    }
+
+    /// A batch of both items races the owner popping the second one.
+    #[test]
+    fn loom_steal_batch_takes_every_item_once() {
+        crate::sync_shim::model(|| {
+            let (worker, stealer) = tiny_deque();
+            worker.push(1).unwrap();
+            worker.push(2).unwrap();
+            worker.push(3).unwrap_err();
+            let thief = thread::spawn(move || {
+                let (own, _) = tiny_deque();
+                let mut taken = Vec::new();
+                if let Steal::Success(_) = stealer.steal_batch(&own) {
+                    while let Some(i) = own.pop() {
+                        taken.push(i);
+                    }
+                }
+                taken
+            });
+            let mut taken = Vec::new();
+            while let Some(i) = worker.pop() {
+                taken.push(i);
+            }
+            taken.extend(thief.join().unwrap());
+            taken.sort_unstable();
+            assert_eq!(taken, [1, 2]);
+        });
+    }
+}
@@ -0,0 +1,281 @@
+//! Turning a trace into a happens-before graph, for Graphviz or Mermaid.
+//!
+//! Every event is a node, in a column per thread. Solid edges are program
+//! order: one thread's events one after the other. Dashed ones are
+//! synchronizes-with: from a releasing operation to the acquiring one that
+//! saw it, like a `send` to the `receive` that got its message, an unlock
+//! to the next lock, or every `Arc` drop to the last one. Happens-before
+//! is every path along both.
+//!
+//! ```ignore
+//! let dot = Graph::new(&trace::events()).to_dot();
+//! std::fs::write("trace.dot", dot)?; // dot -Tsvg trace.dot > trace.svg
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+
+use crate::trace::{Event, Op};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EdgeKind {
+    ProgramOrder,
+    SynchronizesWith,
+}
+
+/// An edge between two events, by their index in [`Graph::events`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Graph {
+    events: Vec<Event>,
+    edges: Vec<Edge>,
+}
+
+/// What each address's releases left for the acquires after them.
+#[derive(Default)]
+struct Released {
+    /// Sends not received yet, oldest first. The channels are FIFO.
+    sends: VecDeque<usize>,
+    /// The last unlock of a writer, and the read unlocks since.
+    unlock: Option<usize>,
+    read_unlocks: Vec<usize>,
+    /// Reference count decrements since the count was last zero.
+    decrements: Vec<usize>,
+}
+
+impl Graph {
+    /// The graph of `events`, which should be in trace order, as
+    /// `trace::events` returns them.
+    pub fn new(events: &[Event]) -> Self {
+        let mut edges = Vec::new();
+        let mut last_of_thread = HashMap::new();
+        let mut released = HashMap::<usize, Released>::new();
+        for (i, e) in events.iter().enumerate() {
+            if let Some(prev) = last_of_thread.insert(e.thread, i) {
+                edges.push(Edge {
+                    from: prev,
+                    to: i,
+                    kind: EdgeKind::ProgramOrder,
+                });
+            }
+            let r = released.entry(e.addr).or_default();
+            let from: Vec<usize> = match e.op {
+                Op::Send => {
+                    r.sends.push_back(i);
+                    Vec::new()
+                }
+                Op::Receive => r.sends.pop_front().into_iter().collect(),
+                Op::Unlock => {
+                    r.unlock = Some(i);
+                    r.read_unlocks.clear();
+                    Vec::new()
+                }
+                Op::ReadUnlock => {
+                    r.read_unlocks.push(i);
+                    Vec::new()
+                }
+                // A writer waits for the readers too.
+                Op::Lock => r
+                    .unlock
+                    .into_iter()
+                    .chain(r.read_unlocks.drain(..))
+                    .collect(),
+                Op::ReadLock => r.unlock.into_iter().collect(),
+                Op::RefIncrement => Vec::new(),
+                Op::RefDecrement => {
+                    r.decrements.push(i);
+                    Vec::new()
+                }
+                Op::RefZero => std::mem::take(&mut r.decrements),
+            };
+            // Within a thread, program order says it all already.
+            edges.extend(
+                from.into_iter()
+                    .filter(|&f| events[f].thread != e.thread)
+                    .map(|f| Edge {
+                        from: f,
+                        to: i,
+                        kind: EdgeKind::SynchronizesWith,
+                    }),
+            );
+        }
+        Self {
+            events: events.to_vec(),
+            edges,
+        }
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub fn edges(&self) -> &[Edge] {
+        &self.edges
+    }
+
+    /// Whether `a` happens before `b`, by a path of edges. Both are indices
+    /// in [`Graph::events`].
+    pub fn happens_before(&self, a: usize, b: usize) -> bool {
+        // Edges only go forward in the trace, so that's the order to look.
+        let mut reached = vec![false; self.events.len()];
+        reached[a] = true;
+        let mut edges: Vec<_> = self.edges.iter().collect();
+        edges.sort_unstable_by_key(|e| e.from);
+        for e in edges {
+            if reached[e.from] {
+                reached[e.to] = true;
+            }
+        }
+        a != b && reached[b]
+    }
+
+    fn threads(&self) -> Vec<u64> {
+        let mut threads: Vec<_> = self.events.iter().map(|e| e.thread).collect();
+        threads.sort_unstable();
+        threads.dedup();
+        threads
+    }
+
+    fn label(e: &Event) -> String {
+        format!("{} {:#x}\\n{:?}", e.op, e.addr, e.ordering)
+    }
+
+    /// The graph in Graphviz's DOT language.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph happens_before {\n    node [shape=box];\n");
+        for t in self.threads() {
+            let _ = writeln!(
+                out,
+                "    subgraph cluster_{t} {{\n        label=\"thread {t}\";"
+            );
+            for e in self.events.iter().filter(|e| e.thread == t) {
+                let _ = writeln!(out, "        e{} [label=\"{}\"];", e.seq, Self::label(e));
+            }
+            out.push_str("    }\n");
+        }
+        for edge in &self.edges {
+            let (from, to) = (&self.events[edge.from], &self.events[edge.to]);
+            let _ = match edge.kind {
+                EdgeKind::ProgramOrder => writeln!(out, "    e{} -> e{};", from.seq, to.seq),
+                EdgeKind::SynchronizesWith => writeln!(
+                    out,
+                    "    e{} -> e{} [style=dashed, color=red, label=\"{:?} → {:?}\"];",
+                    from.seq, to.seq, from.ordering, to.ordering
+                ),
+            };
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// The graph as a Mermaid flowchart.
+    pub fn to_mermaid(&self) -> String {
+        let mut out = String::from("flowchart TB\n");
+        for t in self.threads() {
+            let _ = writeln!(out, "    subgraph thread{t}[\"thread {t}\"]");
+            for e in self.events.iter().filter(|e| e.thread == t) {
+                let label = Self::label(e).replace("\\n", "<br>");
+                let _ = writeln!(out, "        e{}[\"{label}\"]", e.seq);
+            }
+            out.push_str("    end\n");
+        }
+        for edge in &self.edges {
+            let (from, to) = (&self.events[edge.from], &self.events[edge.to]);
+            let _ = match edge.kind {
+                EdgeKind::ProgramOrder => writeln!(out, "    e{} --> e{}", from.seq, to.seq),
+                EdgeKind::SynchronizesWith => writeln!(
+                    out,
+                    "    e{} -.->|\"{:?} → {:?}\"| e{}",
+                    from.seq, from.ordering, to.ordering, to.seq
+                ),
+            };
+        }
+        out
+    }
+}
+
+#[cfg(all(test, not(any(loom, feature = "shuttle"))))]
+mod tests {
+    use core::sync::atomic::Ordering::{self, Acquire, Relaxed, Release};
+    use std::time::Duration;
+
+    use crate::trace::graph::{Edge, EdgeKind, Graph};
+    use crate::trace::{Event, Op};
+
+    fn events(ops: &[(u64, Op, usize, Ordering)]) -> Vec<Event> {
+        ops.iter()
+            .enumerate()
+            .map(|(seq, &(thread, op, addr, ordering))| Event {
+                seq: seq as u64,
+                thread,
+                op,
+                addr,
+                ordering,
+                time: Duration::ZERO,
+            })
+            .collect()
+    }
+
+    fn sync_edges(graph: &Graph) -> Vec<(usize, usize)> {
+        graph
+            .edges()
+            .iter()
+            .filter(|e| e.kind == EdgeKind::SynchronizesWith)
+            .map(|e| (e.from, e.to))
+            .collect()
+    }
+
+    #[test]
+    fn test_send_synchronizes_with_receive() {
+        const CHANNEL: usize = 0x10;
+        const LOCK: usize = 0x20;
+        let graph = Graph::new(&events(&[
+            (0, Op::Lock, LOCK, Acquire),
+            (0, Op::Unlock, LOCK, Release),
+            (0, Op::Send, CHANNEL, Release),
+            (1, Op::Receive, CHANNEL, Acquire),
+            (1, Op::Lock, LOCK, Acquire),
+        ]));
+        assert_eq!(sync_edges(&graph), [(2, 3), (1, 4)]);
+        assert!(graph.edges().contains(&Edge {
+            from: 0,
+            to: 1,
+            kind: EdgeKind::ProgramOrder,
+        }));
+        assert!(graph.happens_before(0, 4));
+        assert!(!graph.happens_before(3, 2));
+
+        let dot = graph.to_dot();
+        assert!(dot.contains("e2 -> e3 [style=dashed, color=red, label=\"Release → Acquire\"]"));
+        assert!(dot.contains("label=\"thread 1\""));
+        let mermaid = graph.to_mermaid();
+        assert!(mermaid.contains("e2 -.->|\"Release → Acquire\"| e3"));
+        assert!(mermaid.contains("e0 --> e1"));
+    }
+
+    #[test]
+    fn test_writer_waits_for_readers_and_last_drop_for_all() {
+        const RWLOCK: usize = 0x10;
+        const ARC: usize = 0x20;
+        let graph = Graph::new(&events(&[
+            (0, Op::ReadLock, RWLOCK, Acquire),
+            (1, Op::ReadLock, RWLOCK, Acquire),
+            (0, Op::ReadUnlock, RWLOCK, Release),
+            (1, Op::ReadUnlock, RWLOCK, Release),
+            (2, Op::Lock, RWLOCK, Acquire),
+            (0, Op::RefIncrement, ARC, Relaxed),
+            (0, Op::RefDecrement, ARC, Release),
+            (1, Op::RefDecrement, ARC, Release),
+            (2, Op::RefDecrement, ARC, Release),
+            (2, Op::RefZero, ARC, Acquire),
+        ]));
+        assert_eq!(sync_edges(&graph), [(2, 4), (3, 4), (6, 9), (7, 9)]);
+        assert!(!graph.happens_before(5, 7));
+    }
+}
@@ -0,0 +1,319 @@
+//! A record of the synchronizing operations the primitives did, to see
+//! what happened before what.
+//!
+//! With the `trace` feature, the channels' sends and receives, the locks'
+//! acquires and releases, and `Arc`'s reference count changes each record
+//! an [`Event`]: which thread, what, on which address, with which memory
+//! ordering, and when. They go into a fixed-size ring in memory, without
+//! locking, and [`events`] returns what's in it, in order:
+//!
+//! ```ignore
+//! trace::clear();
+//! // ... run the code under test ...
+//! for event in trace::events() {
+//!     println!("{event}");
+//! }
+//! ```
+//!
+//! The order is the order in which the events took a sequence number,
+//! and the releasing side of an operation records before it releases,
+//! while the acquiring side records after it acquired. So an acquire
+//! always comes after the release it synchronized with, and the order is
+//! consistent with happens-before. The timestamps are only for people.
+//!
+//! The ring is shared by the whole process, so tests running at the same
+//! time show up in each other's traces. Filter by address or thread. It
+//! holds the last [`CAPACITY`] events, and the older ones are overwritten.
+//!
+//! [`graph`] draws a trace as a happens-before graph, for Graphviz or
+//! Mermaid.
+
+use core::cell::Cell;
+use core::fmt;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::sync_shim::plain_atomic::Ordering::{self, AcqRel, Acquire, Relaxed, Release, SeqCst};
+use crate::sync_shim::plain_atomic::{fence, AtomicU64};
+
+pub mod graph;
+
+/// How many events the ring holds. Fewer under Miri, which would take
+/// minutes to go around it.
+pub const CAPACITY: usize = if cfg!(miri) { 1 << 8 } else { 1 << 14 };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Op {
+    Send,
+    Receive,
+    Lock,
+    Unlock,
+    ReadLock,
+    ReadUnlock,
+    /// An `Arc` was cloned, or a `Weak` upgraded.
+    RefIncrement,
+    /// An `Arc` was dropped.
+    RefDecrement,
+    /// That was the last `Arc`, so the data is dropped. This is where the
+    /// earlier decrements are acquired.
+    RefZero,
+}
+
+const OPS: [Op; 9] = [
+    Op::Send,
+    Op::Receive,
+    Op::Lock,
+    Op::Unlock,
+    Op::ReadLock,
+    Op::ReadUnlock,
+    Op::RefIncrement,
+    Op::RefDecrement,
+    Op::RefZero,
+];
+
+const ORDERINGS: [Ordering; 5] = [Relaxed, Release, Acquire, AcqRel, SeqCst];
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Op::Send => "send",
+            Op::Receive => "receive",
+            Op::Lock => "lock",
+            Op::Unlock => "unlock",
+            Op::ReadLock => "read lock",
+            Op::ReadUnlock => "read unlock",
+            Op::RefIncrement => "ref +1",
+            Op::RefDecrement => "ref -1",
+            Op::RefZero => "ref zero",
+        };
+        f.pad(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    /// The position in the trace.
+    pub seq: u64,
+    /// The thread, numbered in the order they first recorded something.
+    /// See [`current_thread`].
+    pub thread: u64,
+    pub op: Op,
+    /// The address of the primitive, or of an `Arc`'s allocation.
+    pub addr: usize,
+    /// The ordering the operation synchronized with.
+    pub ordering: Ordering,
+    /// Since the first event of the process.
+    pub time: Duration,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{:<6} {:>10.3?} thread {:<3} {:<11} {:#x} {:?}",
+            self.seq, self.time, self.thread, self.op, self.addr, self.ordering
+        )
+    }
+}
+
+/// One event, as a seqlock: `state` is odd while it's being written, and
+/// otherwise the event's sequence number plus one, times two (or zero if
+/// it was never written).
+struct Slot {
+    state: AtomicU64,
+    thread: AtomicU64,
+    /// The op's index in `OPS`, and the ordering's in `ORDERINGS`, shifted
+    /// by 8.
+    op: AtomicU64,
+    addr: AtomicU64,
+    nanos: AtomicU64,
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            state: AtomicU64::new(0),
+            thread: AtomicU64::new(0),
+            op: AtomicU64::new(0),
+            addr: AtomicU64::new(0),
+            nanos: AtomicU64::new(0),
+        }
+    }
+
+    /// The event, unless it's being written, or was never written.
+    fn read(&self) -> Option<Event> {
+        let s = self.state.load(Acquire);
+        if s == 0 || s & 1 != 0 {
+            return None;
+        }
+        let thread = self.thread.load(Relaxed);
+        let op = self.op.load(Relaxed) as usize;
+        let addr = self.addr.load(Relaxed) as usize;
+        let nanos = self.nanos.load(Relaxed);
+        // Keeps the fields from being read after the state again.
+        fence(Acquire);
+        if self.state.load(Relaxed) != s {
+            return None;
+        }
+        Some(Event {
+            seq: (s >> 1) - 1,
+            thread,
+            op: OPS[op & 0xff],
+            addr,
+            ordering: ORDERINGS[op >> 8],
+            time: Duration::from_nanos(nanos),
+        })
+    }
+}
+
+static RING: [Slot; CAPACITY] = [const { Slot::new() }; CAPACITY];
+static NEXT_SEQ: AtomicU64 = AtomicU64::new(0);
+/// Events before this were cleared.
+static START: AtomicU64 = AtomicU64::new(0);
+static LOST: AtomicU64 = AtomicU64::new(0);
+static NEXT_THREAD: AtomicU64 = AtomicU64::new(0);
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+
+thread_local! {
+    static THREAD: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// The number the calling thread's events get.
+pub fn current_thread() -> u64 {
+    THREAD.with(|t| match t.get() {
+        Some(n) => n,
+        None => {
+            let n = NEXT_THREAD.fetch_add(1, Relaxed);
+            t.set(Some(n));
+            n
+        }
+    })
+}
+
+pub(crate) fn record(op: Op, addr: usize, ordering: Ordering) {
+    let nanos = EPOCH.get_or_init(Instant::now).elapsed().as_nanos() as u64;
+    let thread = current_thread();
+    let seq = NEXT_SEQ.fetch_add(1, Relaxed);
+    let slot = &RING[seq as usize % CAPACITY];
+    let done = (seq + 1) << 1;
+    let s = slot.state.load(Relaxed);
+    // Another writer is still at it, a whole lap ago, or already wrote a
+    // newer event here. Either way, this one doesn't fit.
+    if s & 1 != 0
+        || s >= done
+        || slot
+            .state
+            .compare_exchange(s, done | 1, Relaxed, Relaxed)
+            .is_err()
+    {
+        LOST.fetch_add(1, Relaxed);
+        return;
+    }
+    // Keeps the fields from being written before the state is odd.
+    fence(Release);
+    let op = OPS.iter().position(|&o| o == op).unwrap();
+    let ordering = ORDERINGS.iter().position(|&o| o == ordering).unwrap();
+    slot.thread.store(thread, Relaxed);
+    slot.op.store((op | ordering << 8) as u64, Relaxed);
+    slot.addr.store(addr as u64, Relaxed);
+    slot.nanos.store(nanos, Relaxed);
+    slot.state.store(done, Release);
+}
+
+/// The events since the last [`clear`] that are still in the ring, in
+/// order. Events still being recorded are left out.
+pub fn events() -> Vec<Event> {
+    let start = START.load(Relaxed);
+    let mut events: Vec<_> = RING
+        .iter()
+        .filter_map(Slot::read)
+        .filter(|e| e.seq >= start)
+        .collect();
+    events.sort_unstable_by_key(|e| e.seq);
+    events
+}
+
+/// Leaves the events recorded so far out of [`events`] from now on.
+pub fn clear() {
+    START.store(NEXT_SEQ.load(Relaxed), Relaxed);
+}
+
+/// How many events couldn't be recorded, because their place in the ring
+/// was still being written a lap earlier.
+pub fn lost() -> u64 {
+    LOST.load(Relaxed)
+}
+
+#[cfg(all(test, not(any(loom, feature = "shuttle"))))]
+mod tests {
+    use core::sync::atomic::Ordering::{Acquire, Release};
+    use std::thread;
+
+    use crate::channels::OneShotChannel;
+    use crate::locks::SpinLock;
+    use crate::trace::{clear, current_thread, events, record, Op, CAPACITY};
+
+    fn addr<T>(x: &T) -> usize {
+        core::ptr::from_ref(x).addr()
+    }
+
+    /// One test, as the second one fills the whole ring.
+    #[test]
+    fn test_trace() {
+        clear();
+        receive_comes_after_send();
+        ring_keeps_the_newest();
+    }
+
+    fn receive_comes_after_send() {
+        let channel = OneShotChannel::new();
+        let lock = SpinLock::new(0);
+        let receiver = thread::scope(|s| {
+            s.spawn(|| {
+                *lock.lock() += 1;
+                channel.send(1).unwrap();
+            });
+            loop {
+                if let Ok(message) = channel.receive() {
+                    *lock.lock() += message;
+                    return current_thread();
+                }
+                thread::yield_now();
+            }
+        });
+        let ours: Vec<_> = events()
+            .into_iter()
+            .filter(|e| e.addr == addr(&channel) || e.addr == addr(&lock))
+            .map(|e| (e.op, e.ordering, e.thread == receiver))
+            .collect();
+        assert_eq!(
+            ours,
+            [
+                (Op::Lock, Acquire, false),
+                (Op::Unlock, Release, false),
+                (Op::Send, Release, false),
+                (Op::Receive, Acquire, true),
+                (Op::Lock, Acquire, true),
+                (Op::Unlock, Release, true),
+            ]
+        );
+    }
+
+    fn ring_keeps_the_newest() {
+        let x = 0u8;
+        for _ in 0..CAPACITY + 10 {
+            record(Op::Send, addr(&x), Release);
+        }
+        let ours: Vec<_> = events()
+            .into_iter()
+            .filter(|e| e.addr == addr(&x))
+            .collect();
+        assert!(ours.len() <= CAPACITY);
+        assert!(ours.windows(2).all(|w| w[0].seq < w[1].seq));
+        let shown = ours.last().unwrap().to_string();
+        assert!(
+            shown.contains("send") && shown.contains("Release"),
+            "{shown}"
+        );
+    }
+}
@@ -0,0 +1,114 @@
+//! Randomized schedules for the structures that are too big for loom to
+//! explore exhaustively. Needs the `shuttle` feature, which swaps their
+//! atomics for shuttle's:
+//!
+//! ```text
+//! cargo test --release --features shuttle --test shuttle
+//! ```
+//!
+//! Shuttle only explores interleavings, not weak memory, so the orderings
+//! themselves are left to the loom tests.
+
+use shuttle::sync::atomic::AtomicUsize;
+use shuttle::sync::atomic::Ordering::Relaxed;
+use shuttle::sync::Arc;
+use shuttle::thread;
+
+use atomics_and_locks::lockfree::Steal;
+use atomics_and_locks::locks::{RawSpinRwLock, RwLock};
+use atomics_and_locks::testing::tiny_deque;
+
+const ITERATIONS: usize = 10_000;
+
+#[test]
+fn shuttle_rwlock_readers_never_see_half_a_write() {
+    shuttle::check_random(
+        || {
+            // Atomics rather than plain integers, so a reader that gets in
+            // while the writer is halfway has a point to be scheduled at.
+            let rwlock = Arc::new(RwLock::<_, RawSpinRwLock>::new((
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+            )));
+            let mut threads = Vec::new();
+            for _ in 0..2 {
+                let rwlock = rwlock.clone();
+                threads.push(thread::spawn(move || {
+                    for _ in 0..3 {
+                        let w = rwlock.write();
+                        w.0.store(w.0.load(Relaxed) + 1, Relaxed);
+                        w.1.store(w.1.load(Relaxed) + 1, Relaxed);
+                    }
+                }));
+            }
+            for _ in 0..2 {
+                let rwlock = rwlock.clone();
+                threads.push(thread::spawn(move || {
+                    for _ in 0..3 {
+                        let r = rwlock.read();
+                        assert_eq!(r.0.load(Relaxed), r.1.load(Relaxed));
+                    }
+                }));
+            }
+            for t in threads {
+                t.join().unwrap();
+            }
+            let r = rwlock.read();
+            assert_eq!((r.0.load(Relaxed), r.1.load(Relaxed)), (6, 6));
+        },
+        ITERATIONS,
+    );
+}
+
+#[test]
+fn shuttle_deque_every_item_is_taken_exactly_once() {
+    const ITEMS: usize = 8;
+    shuttle::check_random(
+        || {
+            // The owner also wraps around and runs into a full deque.
+            let (worker, stealer) = tiny_deque::<usize>();
+            let taken = Arc::new([(); ITEMS].map(|_| AtomicUsize::new(0)));
+            let remaining = Arc::new(AtomicUsize::new(ITEMS));
+            let thieves: Vec<_> = (0..2)
+                .map(|_| {
+                    let (stealer, taken, remaining) =
+                        (stealer.clone(), taken.clone(), remaining.clone());
+                    thread::spawn(move || {
+                        while remaining.load(Relaxed) > 0 {
+                            match stealer.steal() {
+                                Steal::Success(i) => {
+                                    taken[i].fetch_add(1, Relaxed);
+                                    remaining.fetch_sub(1, Relaxed);
+                                }
+                                Steal::Empty | Steal::Retry => thread::yield_now(),
+                            }
+                        }
+                    })
+                })
+                .collect();
+            let mut next = 0;
+            while next < ITEMS {
+                match worker.push(next) {
+                    Ok(()) => next += 1,
+                    Err(_) => {
+                        if let Some(i) = worker.pop() {
+                            taken[i].fetch_add(1, Relaxed);
+                            remaining.fetch_sub(1, Relaxed);
+                        }
+                    }
+                }
+            }
+            while let Some(i) = worker.pop() {
+                taken[i].fetch_add(1, Relaxed);
+                remaining.fetch_sub(1, Relaxed);
+            }
+            for t in thieves {
+                t.join().unwrap();
+            }
+            for count in taken.iter() {
+                assert_eq!(count.load(Relaxed), 1);
+            }
+        },
+        ITERATIONS,
+    );
+}